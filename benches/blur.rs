@@ -0,0 +1,100 @@
+// NOTE(erick): Times the separable blurs against the same Gaussian as a
+// custom kernel, which goes through the general convolution, on a
+// synthetic opaque image. Run with 'cargo bench --bench blur [SIZE]'.
+
+// NOTE(erick): Only some of the modules' functions are used here.
+#![allow(dead_code)]
+
+#[path = "../src/blur.rs"]
+mod blur;
+#[path = "../src/color.rs"]
+mod color;
+#[path = "../src/filter.rs"]
+mod filter;
+#[path = "../src/image.rs"]
+mod image;
+#[path = "../src/operation.rs"]
+mod operation;
+
+use std::env;
+use std::time::Instant;
+
+use image::Image;
+use operation::EdgeMode;
+use operation::Kernel;
+
+const DEFAULT_SIZE : u32 = 1024;
+const RADII : [f64; 8] = [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 100.0];
+
+// NOTE(erick): The general path grows with the square of the kernel,
+// past this radius it takes minutes.
+const MAX_GENERAL_RADIUS : f64 = 8.0;
+
+fn main() {
+    // NOTE(erick): cargo passes --bench to the benchmark.
+    let size = env::args().skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.parse::<u32>().expect("SIZE has to be a number"))
+        .unwrap_or(DEFAULT_SIZE);
+    let image = test_image(size);
+
+    println!("{}x{} image, times in milliseconds", size, size);
+    println!("{:>8} {:>10} {:>10} {:>10} {:>10}", "radius", "box", "gaussian", "general", "max diff");
+    for &radius in RADII.iter() {
+        let (box_time, _) = time(|| blur::box_blur(&image, radius as u32, EdgeMode::Clamp));
+        let (gaussian_time, gaussian) = time(|| blur::gaussian_blur(&image, radius, EdgeMode::Clamp));
+        if radius > MAX_GENERAL_RADIUS {
+            println!("{:>8} {:>10.1} {:>10.1} {:>10} {:>10}", radius, box_time, gaussian_time, "-", "-");
+            continue;
+        }
+
+        let kernel = Kernel::Custom(gaussian_weights(radius), 1.0, 0.0);
+        let (general_time, general) = time(|| filter::convolve(&image, &kernel, EdgeMode::Clamp));
+        let difference = gaussian.pixels.iter().zip(general.pixels.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap_or(0);
+        println!("{:>8} {:>10.1} {:>10.1} {:>10.1} {:>10}",
+                 radius, box_time, gaussian_time, general_time, difference);
+    }
+}
+
+fn time<F: Fn() -> Image>(blur: F) -> (f64, Image) {
+    let start = Instant::now();
+    let result = blur();
+    (start.elapsed().as_secs_f64() * 1000.0, result)
+}
+
+// NOTE(erick): Gradients with some noise on top, so neither path gets a
+// flat image.
+fn test_image(size: u32) -> Image {
+    let mut image = Image::new(size, size);
+    let mut state = 0x2545f491u32;
+    for y in 0 .. size {
+        for x in 0 .. size {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let noise = state & 0x3f;
+            image.set_pixel(x, y, [((x * 192 / size) + noise) as u8,
+                                   ((y * 192 / size) + noise) as u8,
+                                   (((x + y) * 96 / size) + noise) as u8,
+                                   255]);
+        }
+    }
+
+    image
+}
+
+// NOTE(erick): The 2D kernel of blur.rs's exact Gaussian.
+fn gaussian_weights(radius: f64) -> Vec<f64> {
+    let line = blur::gaussian_line(radius);
+    let mut weights = Vec::with_capacity(line.len() * line.len());
+    for y in line.iter() {
+        for x in line.iter() {
+            weights.push(x * y);
+        }
+    }
+
+    weights
+}
@@ -0,0 +1,245 @@
+use color::clamp_channel;
+use filter::edge_index;
+use image::Image;
+use operation::EdgeMode;
+
+// NOTE(erick): Up to this radius the Gaussian is an exact kernel, cut at
+// GAUSSIAN_EXTENT standard deviations. Above it three box blurs with
+// the same variance are close enough, and their cost doesn't depend on
+// the radius. Each box sees the edge mode again, so near the edges they
+// differ a bit more from the exact kernel.
+const EXACT_GAUSSIAN_LIMIT : f64 = 3.0;
+const GAUSSIAN_EXTENT : f64 = 3.0;
+const BOX_PASSES : usize = 3;
+
+// NOTE(erick): Columns are copied out and blurred a few at a time, so
+// walking down a column doesn't jump a whole row for every pixel.
+const COLUMN_BLOCK : usize = 64;
+
+enum Pass {
+    Box(usize),
+    Kernel(Vec<f64>),
+}
+
+pub fn box_blur(image: &Image, radius: u32, edge: EdgeMode) -> Image {
+    separable(image, &[Pass::Box(radius as usize)], edge)
+}
+
+pub fn gaussian_blur(image: &Image, radius: f64, edge: EdgeMode) -> Image {
+    if radius <= EXACT_GAUSSIAN_LIMIT {
+        return separable(image, &[Pass::Kernel(gaussian_line(radius))], edge);
+    }
+
+    let passes = box_radii(radius).into_iter().map(Pass::Box).collect::<Vec<_> >();
+    separable(image, passes.as_slice(), edge)
+}
+
+// NOTE(erick): One dimension of the exact Gaussian, normalized. The 2D
+// kernel is the line times itself.
+pub fn gaussian_line(radius: f64) -> Vec<f64> {
+    let extent = (radius * GAUSSIAN_EXTENT).ceil().max(1.0) as i64;
+    let line = (-extent ..= extent)
+        .map(|x| (-((x * x) as f64) / (2.0 * radius * radius)).exp())
+        .collect::<Vec<_> >();
+
+    let total = line.iter().sum::<f64>();
+    line.iter().map(|weight| weight / total).collect()
+}
+
+// NOTE(erick): Box widths whose variances add up to the Gaussian's, from
+// "Fast Almost-Gaussian Filtering" (Kovesi). Some passes use the odd
+// width below the ideal one and the rest the odd width above it.
+fn box_radii(radius: f64) -> Vec<usize> {
+    let passes = BOX_PASSES as f64;
+    let variance = radius * radius;
+    let ideal = (12.0 * variance / passes + 1.0).sqrt();
+    let mut lower = ideal.floor() as usize;
+    if lower.is_multiple_of(2) {
+        lower -= 1;
+    }
+
+    let width = lower as f64;
+    let lower_count = ((12.0 * variance - passes * width * width -
+                        4.0 * passes * width - 3.0 * passes) /
+                       (-4.0 * width - 4.0)).round() as usize;
+    (0 .. BOX_PASSES)
+        .map(|pass| if pass < lower_count { lower } else { lower + 2 })
+        .map(|width| (width - 1) / 2)
+        .collect()
+}
+
+// NOTE(erick): Rows are blurred first, then columns. Colors are weighted
+// by their alpha during each direction so transparent pixels don't bleed
+// black into their neighbours, and the result of the rows is stored
+// like any other image in between.
+fn separable(image: &Image, passes: &[Pass], edge: EdgeMode) -> Image {
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let mut result = image.clone();
+    let mut padded = Vec::new();
+
+    let mut line = vec![0.0; width * 4];
+    for row in result.pixels.chunks_mut(width * 4) {
+        premultiply(row, &mut line);
+        for pass in passes {
+            blur_line(&mut line, 4, pass, edge, &mut padded);
+        }
+        unpremultiply(&line, row);
+    }
+
+    let mut block = vec![0.0; height * COLUMN_BLOCK * 4];
+    for first_column in (0 .. width).step_by(COLUMN_BLOCK) {
+        let lanes = COLUMN_BLOCK.min(width - first_column) * 4;
+        let block = &mut block[.. height * lanes];
+        for (y, values) in block.chunks_mut(lanes).enumerate() {
+            let start = (y * width + first_column) * 4;
+            premultiply(&result.pixels[start .. start + lanes], values);
+        }
+        for pass in passes {
+            blur_line(block, lanes, pass, edge, &mut padded);
+        }
+        for (y, values) in block.chunks(lanes).enumerate() {
+            let start = (y * width + first_column) * 4;
+            unpremultiply(values, &mut result.pixels[start .. start + lanes]);
+        }
+    }
+
+    result
+}
+
+fn premultiply(pixels: &[u8], values: &mut [f64]) {
+    for (pixel, values) in pixels.chunks(4).zip(values.chunks_mut(4)) {
+        let coverage = pixel[3] as f64 / 255.0;
+        values[0] = pixel[0] as f64 * coverage;
+        values[1] = pixel[1] as f64 * coverage;
+        values[2] = pixel[2] as f64 * coverage;
+        values[3] = pixel[3] as f64;
+    }
+}
+
+fn unpremultiply(values: &[f64], pixels: &mut [u8]) {
+    for (values, pixel) in values.chunks(4).zip(pixels.chunks_mut(4)) {
+        let coverage = values[3] / 255.0;
+        for channel in 0 .. 3 {
+            pixel[channel] = if coverage > 0.0 { clamp_channel(values[channel] / coverage) } else { 0 };
+        }
+        pixel[3] = clamp_channel(values[3]);
+    }
+}
+
+// NOTE(erick): Blurs a line of samples with 'lanes' values each, which
+// are several pixels side by side for columns. The line is copied with
+// the neighbours the edge mode gives on both sides, then a box is a
+// running sum and a kernel a plain weighted sum.
+fn blur_line(line: &mut [f64], lanes: usize, pass: &Pass, edge: EdgeMode, padded: &mut Vec<f64>) {
+    let length = line.len() / lanes;
+    let radius = match pass {
+        &Pass::Box(radius)         => radius,
+        Pass::Kernel(weights) => weights.len() / 2,
+    };
+
+    padded.clear();
+    for position in 0 .. length + 2 * radius {
+        let index = edge_index(position as i64 - radius as i64, length as i64, edge);
+        match index {
+            Some(index) => padded.extend_from_slice(&line[index * lanes .. (index + 1) * lanes]),
+            None        => padded.extend((0 .. lanes).map(|_| 0.0)),
+        }
+    }
+
+    match pass {
+        &Pass::Box(radius) => {
+            let size = 2 * radius + 1;
+            let scale = 1.0 / size as f64;
+            let mut sums = vec![0.0; lanes];
+            for samples in padded.chunks(lanes).take(size) {
+                for (sum, sample) in sums.iter_mut().zip(samples) {
+                    *sum += sample;
+                }
+            }
+
+            for (position, output) in line.chunks_mut(lanes).enumerate() {
+                if position > 0 {
+                    let entering = &padded[(position + size - 1) * lanes ..][.. lanes];
+                    let leaving = &padded[(position - 1) * lanes ..][.. lanes];
+                    for lane in 0 .. lanes {
+                        sums[lane] += entering[lane] - leaving[lane];
+                    }
+                }
+                for (value, sum) in output.iter_mut().zip(sums.iter()) {
+                    *value = sum * scale;
+                }
+            }
+        },
+        Pass::Kernel(weights) => {
+            for (position, output) in line.chunks_mut(lanes).enumerate() {
+                for value in output.iter_mut() {
+                    *value = 0.0;
+                }
+                for (offset, weight) in weights.iter().enumerate() {
+                    let samples = &padded[(position + offset) * lanes ..][.. lanes];
+                    for (value, sample) in output.iter_mut().zip(samples) {
+                        *value += weight * sample;
+                    }
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Image {
+        let mut image = Image::new(3, 3);
+        for y in 0 .. 3 {
+            for x in 0 .. 3 {
+                let value = (9 * (3 * y + x)) as u8;
+                image.set_pixel(x, y, [value, 255 - value, 0, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn box_blur_grid() {
+        let result = box_blur(&grid(), 1, EdgeMode::Clamp);
+        assert_eq!(result.pixel(1, 1), [36, 219, 0, 255]);
+        assert_eq!(result.pixel(0, 0), [12, 243, 0, 255]);
+        assert_eq!(result.pixel(2, 1), [42, 213, 0, 255]);
+
+        let result = box_blur(&grid(), 1, EdgeMode::Wrap);
+        assert_eq!(result.pixel(0, 0), [36, 219, 0, 255]);
+
+        // NOTE(erick): Outside is transparent, so the corner fades rather
+        // than darkens. The color is 18 give or take the rounding of the
+        // rows in between.
+        let result = box_blur(&grid(), 1, EdgeMode::Constant);
+        assert_eq!(result.pixel(0, 0), [19, 237, 0, 113]);
+        assert_eq!(result.pixel(1, 1), [36, 219, 0, 255]);
+    }
+
+    #[test]
+    fn gaussian() {
+        for &radius in [0.5, 2.0, 3.0].iter() {
+            let line = gaussian_line(radius);
+            assert_eq!(line.len() % 2, 1);
+            assert!((line.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!(line.iter().zip(line.iter().rev()).all(|(a, b)| a == b));
+        }
+
+        // NOTE(erick): The boxes have to add up to the Gaussian's variance.
+        for &radius in [4.0, 10.0, 37.5].iter() {
+            let variance = box_radii(radius).iter()
+                .map(|&r| ((2 * r + 1) * (2 * r + 1) - 1) as f64 / 12.0)
+                .sum::<f64>();
+            assert!((variance - radius * radius).abs() < radius, "radius {}", radius);
+        }
+
+        let flat = Image { width: 5, height: 4, pixels: vec![77; 5 * 4 * 4] };
+        for &radius in [1.0, 8.0].iter() {
+            assert!(gaussian_blur(&flat, radius, EdgeMode::Mirror).pixels == flat.pixels);
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use image::Image;
+use quantize::Palette;
+
+// NOTE(erick): Reference:
+// https://docs.microsoft.com/en-us/windows/win32/gdi/bitmap-storage
+
+const FILE_HEADER_SIZE : usize = 14;
+
+const BI_RGB            : u32 = 0;
+const BI_RLE8           : u32 = 1;
+const BI_RLE4           : u32 = 2;
+const BI_BITFIELDS      : u32 = 3;
+const BI_ALPHABITFIELDS : u32 = 6;
+
+// NOTE(erick): A few bytes of RLE codes can skip whole rows, so the data
+// says nothing about the size. RLE images are checked against this
+// before they are allocated.
+const MAX_PIXELS : usize = 400_000_000;
+
+pub fn is_bmp(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && &bytes[0 .. 2] == b"BM"
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    if offset + 2 > bytes.len() {
+        return Err("BMP: unexpected end of file".to_string());
+    }
+    Ok(bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8)
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    if offset + 4 > bytes.len() {
+        return Err("BMP: unexpected end of file".to_string());
+    }
+    Ok(bytes[offset] as u32 | (bytes[offset + 1] as u32) << 8 |
+       (bytes[offset + 2] as u32) << 16 | (bytes[offset + 3] as u32) << 24)
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    top_down: bool,
+    bits_per_pixel: u16,
+    compression: u32,
+    masks: [u32; 4],
+    palette: Vec<[u8; 4]>,
+    pixel_offset: usize,
+}
+
+fn read_header(bytes: &[u8]) -> Result<Header, String> {
+    if !is_bmp(bytes) {
+        return Err("BMP: bad signature".to_string());
+    }
+
+    let pixel_offset = read_u32(bytes, 10)? as usize;
+    let info_size = read_u32(bytes, FILE_HEADER_SIZE)? as usize;
+    let info = FILE_HEADER_SIZE;
+
+    let width;
+    let height;
+    let top_down;
+    let bits_per_pixel;
+    let mut compression = BI_RGB;
+    let mut colors_used = 0;
+    let palette_entry_size;
+
+    if info_size == 12 {
+        // NOTE(erick): OS/2 BITMAPCOREHEADER.
+        width = read_u16(bytes, info + 4)? as i64;
+        height = read_u16(bytes, info + 6)? as i64;
+        top_down = false;
+        bits_per_pixel = read_u16(bytes, info + 10)?;
+        palette_entry_size = 3;
+    } else if info_size >= 40 {
+        width = read_u32(bytes, info + 4)? as i32 as i64;
+        let signed_height = read_u32(bytes, info + 8)? as i32 as i64;
+        top_down = signed_height < 0;
+        height = signed_height.abs();
+        bits_per_pixel = read_u16(bytes, info + 14)?;
+        compression = read_u32(bytes, info + 16)?;
+        colors_used = read_u32(bytes, info + 32)? as usize;
+        palette_entry_size = 4;
+    } else {
+        return Err(format!("BMP: unsupported header size {}", info_size));
+    }
+
+    if width <= 0 || height <= 0 || width > 0x00ff_ffff || height > 0x00ff_ffff {
+        return Err("BMP: invalid dimensions".to_string());
+    }
+
+    let mut masks = match bits_per_pixel {
+        16 => [0x7c00, 0x03e0, 0x001f, 0],
+        24 | 32 => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0],
+        _  => [0, 0, 0, 0],
+    };
+
+    let mut palette_offset = info + info_size;
+    if compression == BI_BITFIELDS || compression == BI_ALPHABITFIELDS {
+        // NOTE(erick): With a plain BITMAPINFOHEADER the masks come right
+        // after it, newer headers carry them inside.
+        let mask_offset = info + 40;
+        let mask_count = if compression == BI_ALPHABITFIELDS || info_size >= 56 { 4 } else { 3 };
+        for (i, mask) in masks.iter_mut().enumerate().take(mask_count) {
+            *mask = read_u32(bytes, mask_offset + i * 4)?;
+        }
+        if info_size == 40 {
+            palette_offset += mask_count * 4;
+        }
+    } else if info_size >= 56 && bits_per_pixel == 32 {
+        // NOTE(erick): V4/V5 headers may declare alpha even for BI_RGB.
+        masks[3] = read_u32(bytes, info + 52)?;
+    }
+
+    let mut palette = Vec::new();
+    if bits_per_pixel <= 8 {
+        let max_colors = 1usize << bits_per_pixel;
+        let color_count = if colors_used == 0 || colors_used > max_colors {
+            max_colors
+        } else {
+            colors_used
+        };
+
+        for i in 0 .. color_count {
+            let offset = palette_offset + i * palette_entry_size;
+            if offset + 3 > bytes.len() {
+                return Err("BMP: truncated palette".to_string());
+            }
+            palette.push([bytes[offset + 2], bytes[offset + 1], bytes[offset], 255]);
+        }
+    }
+
+    Ok(Header {
+        width: width as u32,
+        height: height as u32,
+        top_down,
+        bits_per_pixel,
+        compression,
+        masks,
+        palette,
+        pixel_offset,
+    })
+}
+
+// NOTE(erick): Only looks at the headers, so it works on a truncated file.
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if !is_bmp(bytes) {
+        return None;
+    }
+
+    let info_size = read_u32(bytes, FILE_HEADER_SIZE).ok()?;
+    let info = FILE_HEADER_SIZE;
+    if info_size == 12 {
+        let width = read_u16(bytes, info + 4).ok()? as u32;
+        let height = read_u16(bytes, info + 6).ok()? as u32;
+        return Some((width, height));
+    }
+
+    let width = read_u32(bytes, info + 4).ok()? as i32;
+    let height = read_u32(bytes, info + 8).ok()? as i32;
+    if width <= 0 || height == 0 {
+        return None;
+    }
+
+    Some((width as u32, height.unsigned_abs()))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    let header = read_header(bytes)?;
+    if header.pixel_offset > bytes.len() {
+        return Err("BMP: pixel data out of bounds".to_string());
+    }
+
+    match header.compression {
+        BI_RLE8 | BI_RLE4 => decode_rle(bytes, &header),
+        BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS => decode_uncompressed(bytes, &header),
+        _ => Err(format!("BMP: unsupported compression {}", header.compression)),
+    }
+}
+
+// NOTE(erick): ICO and CUR files embed bitmaps without the file header,
+// with the height doubled to make room for the 1 bit AND mask after the
+// pixels. 32 bit ones keep alpha in the fourth byte and only fall back
+// to the mask when that alpha is all zero.
+pub fn decode_icon_bitmap(dib: &[u8]) -> Result<Image, String> {
+    let info_size = read_u32(dib, 0)? as usize;
+    if info_size < 40 {
+        return Err(format!("ICO: unsupported bitmap header size {}", info_size));
+    }
+
+    let bits_per_pixel = read_u16(dib, 14)? as usize;
+    let compression = read_u32(dib, 16)?;
+    let colors_used = read_u32(dib, 32)? as usize;
+    let mut pixel_offset = FILE_HEADER_SIZE + info_size;
+    if bits_per_pixel <= 8 {
+        let max_colors = 1usize << bits_per_pixel;
+        let color_count = if colors_used == 0 || colors_used > max_colors {
+            max_colors
+        } else {
+            colors_used
+        };
+        pixel_offset += color_count * 4;
+    }
+    if compression == BI_BITFIELDS && info_size == 40 {
+        pixel_offset += 12;
+    }
+
+    let mut bytes = Vec::with_capacity(FILE_HEADER_SIZE + dib.len());
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&((FILE_HEADER_SIZE + dib.len()) as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0, 0, 0, 0]);
+    bytes.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+    bytes.extend_from_slice(dib);
+
+    let height = (read_u32(dib, 8)? as i32 / 2).to_le_bytes();
+    bytes[FILE_HEADER_SIZE + 8 .. FILE_HEADER_SIZE + 12].copy_from_slice(&height);
+
+    let mut header = read_header(&bytes)?;
+    if bits_per_pixel == 32 && compression == BI_RGB {
+        header.masks[3] = 0xff00_0000;
+    }
+    let mut image = decode_uncompressed(&bytes, &header)?;
+
+    let uses_alpha = bits_per_pixel == 32 && image.pixels.chunks(4).any(|pixel| pixel[3] != 0);
+    if uses_alpha {
+        return Ok(image);
+    }
+
+    let width = header.width as usize;
+    let rows = header.height as usize;
+    let mask_offset = pixel_offset + (bits_per_pixel * width).div_ceil(32) * 4 * rows;
+    let mask_row_size = width.div_ceil(32) * 4;
+    for row in 0 .. rows {
+        let y = rows - 1 - row;
+        for x in 0 .. width {
+            let mask_byte = bytes.get(mask_offset + row * mask_row_size + x / 8).cloned().unwrap_or(0);
+            let transparent = mask_byte & (0x80 >> (x % 8)) != 0;
+            let index = (y * width + x) * 4;
+            image.pixels[index + 3] = if transparent { 0 } else { 255 };
+        }
+    }
+
+    Ok(image)
+}
+
+#[inline]
+fn palette_color(header: &Header, index: usize) -> [u8; 4] {
+    if index < header.palette.len() {
+        header.palette[index]
+    } else {
+        [0, 0, 0, 255]
+    }
+}
+
+// NOTE(erick): Scales the masked bits to the full 0..255 range.
+#[inline]
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let bits = (mask >> shift).count_ones();
+    let max = (1u64 << bits) - 1;
+    let channel = ((value & mask) >> shift) as u64;
+
+    (channel * 255 / max) as u8
+}
+
+fn decode_uncompressed(bytes: &[u8], header: &Header) -> Result<Image, String> {
+    let bits_per_pixel = header.bits_per_pixel as usize;
+    match bits_per_pixel {
+        1 | 2 | 4 | 8 | 16 | 24 | 32 => { },
+        _ => return Err(format!("BMP: unsupported bit depth {}", bits_per_pixel)),
+    }
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let row_size = (bits_per_pixel * width).div_ceil(32) * 4;
+    if header.pixel_offset + row_size * height > bytes.len() {
+        return Err("BMP: truncated pixel data".to_string());
+    }
+
+    let has_alpha = header.masks[3] != 0;
+    let mut image = Image::new(header.width, header.height);
+    for row in 0 .. height {
+        let y = if header.top_down { row } else { height - 1 - row };
+        let data = &bytes[header.pixel_offset + row * row_size ..];
+
+        for x in 0 .. width {
+            let pixel = match bits_per_pixel {
+                1 | 2 | 4 | 8 => {
+                    let bit_offset = x * bits_per_pixel;
+                    let byte = data[bit_offset / 8];
+                    let shift = 8 - bits_per_pixel - bit_offset % 8;
+                    let index = (byte >> shift) & ((1u16 << bits_per_pixel) - 1) as u8;
+                    palette_color(header, index as usize)
+                },
+                16 | 32 => {
+                    let value = if bits_per_pixel == 16 {
+                        data[x * 2] as u32 | (data[x * 2 + 1] as u32) << 8
+                    } else {
+                        data[x * 4] as u32 | (data[x * 4 + 1] as u32) << 8 |
+                        (data[x * 4 + 2] as u32) << 16 | (data[x * 4 + 3] as u32) << 24
+                    };
+                    [extract_channel(value, header.masks[0]),
+                     extract_channel(value, header.masks[1]),
+                     extract_channel(value, header.masks[2]),
+                     if has_alpha { extract_channel(value, header.masks[3]) } else { 255 }]
+                },
+                _ => [data[x * 3 + 2], data[x * 3 + 1], data[x * 3], 255],
+            };
+
+            image.set_pixel(x as u32, y as u32, pixel);
+        }
+    }
+
+    Ok(image)
+}
+
+// NOTE(erick): RLE bitmaps are always stored bottom-up. Pixels skipped
+// by delta or end-of-line codes keep the first palette color.
+fn decode_rle(bytes: &[u8], header: &Header) -> Result<Image, String> {
+    let is_rle4 = header.compression == BI_RLE4;
+    if (is_rle4 && header.bits_per_pixel != 4) || (!is_rle4 && header.bits_per_pixel != 8) {
+        return Err("BMP: RLE compression with wrong bit depth".to_string());
+    }
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    if width * height > MAX_PIXELS {
+        return Err(format!("BMP: {}x{} is too big", width, height));
+    }
+
+    let mut image = Image::new(header.width, header.height);
+    let background = palette_color(header, 0);
+    for y in 0 .. header.height {
+        for x in 0 .. header.width {
+            image.set_pixel(x, y, background);
+        }
+    }
+
+    let put = |image: &mut Image, x: usize, row: usize, index: u8| {
+        if x < width && row < height {
+            image.set_pixel(x as u32, (height - 1 - row) as u32, palette_color(header, index as usize));
+        }
+    };
+
+    let data = &bytes[header.pixel_offset ..];
+    let mut position = 0;
+    let mut x = 0;
+    let mut row = 0;
+    while position + 1 < data.len() && row < height {
+        let count = data[position] as usize;
+        let value = data[position + 1];
+        position += 2;
+
+        if count > 0 {
+            for i in 0 .. count {
+                let index = if is_rle4 {
+                    if i % 2 == 0 { value >> 4 } else { value & 0x0f }
+                } else {
+                    value
+                };
+                put(&mut image, x, row, index);
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            0 => { x = 0; row += 1; },
+            1 => { break; },
+            2 => {
+                if position + 1 >= data.len() {
+                    return Err("BMP: truncated RLE delta".to_string());
+                }
+                x += data[position] as usize;
+                row += data[position + 1] as usize;
+                position += 2;
+            },
+            literal_count => {
+                let literal_count = literal_count as usize;
+                let byte_count = if is_rle4 { literal_count.div_ceil(2) } else { literal_count };
+                if position + byte_count > data.len() {
+                    return Err("BMP: truncated RLE run".to_string());
+                }
+
+                for i in 0 .. literal_count {
+                    let index = if is_rle4 {
+                        let byte = data[position + i / 2];
+                        if i % 2 == 0 { byte >> 4 } else { byte & 0x0f }
+                    } else {
+                        data[position + i]
+                    };
+                    put(&mut image, x, row, index);
+                    x += 1;
+                }
+
+                // NOTE(erick): Literal runs are padded to 16 bits.
+                position += byte_count + byte_count % 2;
+            },
+        }
+    }
+
+    Ok(image)
+}
+
+// NOTE(erick): 24-bit for opaque images. Anything with transparency is
+// written as 32-bit BI_BITFIELDS with a V4 header, the oldest header
+// that can describe an alpha mask.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let has_alpha = image.pixels.chunks(4).any(|pixel| pixel[3] != 255);
+    let bits_per_pixel: usize = if has_alpha { 32 } else { 24 };
+    let info_size: usize = if has_alpha { 108 } else { 40 };
+
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let row_size = (bits_per_pixel * width).div_ceil(32) * 4;
+    let pixel_offset = FILE_HEADER_SIZE + info_size;
+    let file_size = pixel_offset + row_size * height;
+
+    let mut output = Vec::with_capacity(file_size);
+    output.extend_from_slice(b"BM");
+    output.extend_from_slice(&(file_size as u32).to_le_bytes());
+    output.extend_from_slice(&[0, 0, 0, 0]);
+    output.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+
+    output.extend_from_slice(&(info_size as u32).to_le_bytes());
+    output.extend_from_slice(&(width as i32).to_le_bytes());
+    output.extend_from_slice(&(height as i32).to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&(bits_per_pixel as u16).to_le_bytes());
+    let compression = if has_alpha { BI_BITFIELDS } else { BI_RGB };
+    output.extend_from_slice(&compression.to_le_bytes());
+    output.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+    // NOTE(erick): 2835 pixels per meter is 72 DPI.
+    output.extend_from_slice(&2835u32.to_le_bytes());
+    output.extend_from_slice(&2835u32.to_le_bytes());
+    output.extend_from_slice(&[0; 8]);
+
+    if has_alpha {
+        for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000].iter() {
+            output.extend_from_slice(&mask.to_le_bytes());
+        }
+        // NOTE(erick): LCS_sRGB, then the unused endpoints and gammas.
+        output.extend_from_slice(b"BGRs");
+        output.extend_from_slice(&[0; 48]);
+    }
+
+    for row in (0 .. height).rev() {
+        let start = output.len();
+        for x in 0 .. width {
+            let pixel = image.pixel(x as u32, row as u32);
+            output.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            if has_alpha {
+                output.push(pixel[3]);
+            }
+        }
+        while output.len() - start < row_size {
+            output.push(0);
+        }
+    }
+
+    output
+}
+
+// NOTE(erick): A palette of at most 2^bits colors with the indices
+// packed from the high bits of each byte. The palette has no alpha, so
+// transparent pixels keep their color.
+pub fn encode_indexed(image: &Image, bits: u32) -> Vec<u8> {
+    let mut palette = Palette::build(&image.pixels, 1 << bits);
+    if palette.colors.is_empty() {
+        palette.colors.push([0, 0, 0]);
+    }
+
+    let bits = bits as usize;
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let info_size = 40;
+    let row_size = (bits * width).div_ceil(32) * 4;
+    let pixel_offset = FILE_HEADER_SIZE + info_size + palette.colors.len() * 4;
+    let file_size = pixel_offset + row_size * height;
+
+    let mut output = Vec::with_capacity(file_size);
+    output.extend_from_slice(b"BM");
+    output.extend_from_slice(&(file_size as u32).to_le_bytes());
+    output.extend_from_slice(&[0, 0, 0, 0]);
+    output.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+
+    output.extend_from_slice(&(info_size as u32).to_le_bytes());
+    output.extend_from_slice(&(width as i32).to_le_bytes());
+    output.extend_from_slice(&(height as i32).to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&(bits as u16).to_le_bytes());
+    output.extend_from_slice(&BI_RGB.to_le_bytes());
+    output.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+    output.extend_from_slice(&2835u32.to_le_bytes());
+    output.extend_from_slice(&2835u32.to_le_bytes());
+    output.extend_from_slice(&(palette.colors.len() as u32).to_le_bytes());
+    output.extend_from_slice(&[0; 4]);
+
+    for color in palette.colors.iter() {
+        output.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+
+    for row in (0 .. height).rev() {
+        let mut packed = vec![0u8; row_size];
+        for x in 0 .. width {
+            let pixel = image.pixel(x as u32, row as u32);
+            let index = palette.index([pixel[0], pixel[1], pixel[2]]);
+            let bit = x * bits;
+            packed[bit / 8] |= index << (8 - bits - bit % 8);
+        }
+        output.extend_from_slice(&packed);
+    }
+
+    output
+}
+
+// NOTE(erick): The bitmap of an ICO or CUR entry: 32 bit BGRA with the
+// AND mask marking the fully transparent pixels, for programs that
+// ignore alpha.
+pub fn encode_icon_bitmap(image: &Image) -> Vec<u8> {
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let mask_row_size = width.div_ceil(32) * 4;
+
+    let mut output = Vec::with_capacity(40 + (width * 4 + mask_row_size) * height);
+    output.extend_from_slice(&40u32.to_le_bytes());
+    output.extend_from_slice(&(width as i32).to_le_bytes());
+    output.extend_from_slice(&(height as i32 * 2).to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&32u16.to_le_bytes());
+    output.extend_from_slice(&BI_RGB.to_le_bytes());
+    output.extend_from_slice(&((width * 4 * height) as u32).to_le_bytes());
+    output.extend_from_slice(&[0; 16]);
+
+    for row in (0 .. height).rev() {
+        for x in 0 .. width {
+            let pixel = image.pixel(x as u32, row as u32);
+            output.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+
+    for row in (0 .. height).rev() {
+        let mut mask = vec![0u8; mask_row_size];
+        for x in 0 .. width {
+            if image.pixel(x as u32, row as u32)[3] == 0 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        output.extend_from_slice(&mask);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK : [u8; 4] = [0, 0, 0, 255];
+    const RED   : [u8; 4] = [255, 0, 0, 255];
+    const GREEN : [u8; 4] = [0, 255, 0, 255];
+    const BLUE  : [u8; 4] = [0, 0, 255, 255];
+
+    fn sample_image(width: u32, height: u32, alpha: bool) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let a = if alpha { ((x + y) * 17) as u8 } else { 255 };
+                image.set_pixel(x, y, [(x * 40) as u8, (y * 30) as u8, (x ^ y) as u8, a]);
+            }
+        }
+        image
+    }
+
+    // NOTE(erick): A BITMAPINFOHEADER file with the black, red, green and
+    // blue palette and 'data' as the pixels.
+    fn file_with_data(width: i32, height: i32, bits: u16, compression: u32,
+                      data: &[u8]) -> Vec<u8> {
+        let pixel_offset = FILE_HEADER_SIZE + 40 + 4 * 4;
+        let mut output = b"BM".to_vec();
+        output.extend_from_slice(&((pixel_offset + data.len()) as u32).to_le_bytes());
+        output.extend_from_slice(&[0; 4]);
+        output.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+        output.extend_from_slice(&40u32.to_le_bytes());
+        output.extend_from_slice(&width.to_le_bytes());
+        output.extend_from_slice(&height.to_le_bytes());
+        output.extend_from_slice(&1u16.to_le_bytes());
+        output.extend_from_slice(&bits.to_le_bytes());
+        output.extend_from_slice(&compression.to_le_bytes());
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        output.extend_from_slice(&[0; 8]);
+        output.extend_from_slice(&4u32.to_le_bytes());
+        output.extend_from_slice(&[0; 4]);
+        for color in [BLACK, RED, GREEN, BLUE].iter() {
+            output.extend_from_slice(&[color[2], color[1], color[0], 0]);
+        }
+        output.extend_from_slice(data);
+        output
+    }
+
+    fn rows(image: &Image) -> Vec<Vec<[u8; 4]>> {
+        (0 .. image.height).map(|y| (0 .. image.width).map(|x| image.pixel(x, y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for &alpha in [false, true].iter() {
+            let image = sample_image(13, 7, alpha);
+            let decoded = decode(&encode(&image)).unwrap();
+            assert_eq!((decoded.width, decoded.height), (13, 7));
+            assert_eq!(decoded.pixels, image.pixels);
+        }
+    }
+
+    #[test]
+    fn indexed_round_trip() {
+        let mut image = Image::new(11, 3);
+        for (index, pixel) in image.pixels.chunks_mut(4).enumerate() {
+            pixel.copy_from_slice(if index % 3 == 0 { &RED } else { &BLUE });
+        }
+        for &bits in [1, 4, 8].iter() {
+            let file = encode_indexed(&image, bits);
+            assert_eq!(read_u16(&file, FILE_HEADER_SIZE + 14).unwrap(), bits as u16);
+            assert_eq!(decode(&file).unwrap().pixels, image.pixels);
+        }
+    }
+
+    #[test]
+    fn rle8() {
+        let data = [3, 1, 1, 2, 0, 0,
+                    0, 3, 1, 2, 3, 0, 0, 1];
+        let image = decode(&file_with_data(4, 2, 8, BI_RLE8, &data)).unwrap();
+        assert_eq!(rows(&image), vec![vec![RED, GREEN, BLUE, BLACK],
+                                      vec![RED, RED, RED, GREEN]]);
+
+        let delta = [0, 2, 2, 1, 1, 3, 0, 1];
+        let image = decode(&file_with_data(4, 2, 8, BI_RLE8, &delta)).unwrap();
+        assert_eq!(rows(&image), vec![vec![BLACK, BLACK, BLUE, BLACK],
+                                      vec![BLACK, BLACK, BLACK, BLACK]]);
+    }
+
+    #[test]
+    fn rle4() {
+        let data = [0, 3, 0x12, 0x30, 2, 0x10, 0, 1];
+        let image = decode(&file_with_data(5, 1, 4, BI_RLE4, &data)).unwrap();
+        assert_eq!(rows(&image), vec![vec![RED, GREEN, BLUE, RED, BLACK]]);
+
+        assert!(decode(&file_with_data(5, 1, 8, BI_RLE4, &data)).is_err());
+        assert!(decode(&file_with_data(5, 1, 4, BI_RLE4, &data[.. 3])).is_err());
+    }
+
+    #[test]
+    fn bad_headers() {
+        let pixels = [0; 16];
+        assert!(decode(&file_with_data(2, 2, 8, BI_RGB, &pixels)).is_ok());
+        assert!(decode(&file_with_data(0, 2, 8, BI_RGB, &pixels)).is_err());
+        assert!(decode(&file_with_data(2, 2, 3, BI_RGB, &pixels)).is_err());
+        assert!(decode(&file_with_data(2, 2, 8, 9, &pixels)).is_err());
+        assert!(decode(&file_with_data(200, 2, 8, BI_RGB, &pixels)).is_err());
+        assert!(decode(&file_with_data(100000, 100000, 8, BI_RLE8, &[0, 1])).is_err());
+
+        let mut file = file_with_data(2, 2, 8, BI_RGB, &pixels);
+        file[FILE_HEADER_SIZE] = 20;
+        assert!(decode(&file).is_err());
+        assert!(decode(&file[.. FILE_HEADER_SIZE + 40 + 6]).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let file = encode(&sample_image(9, 5, true));
+        for length in 0 .. file.len() {
+            assert!(decode(&file[.. length]).is_err());
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use std::fs::read_dir;
+use std::path::Path;
+use std::path::PathBuf;
+
+use ncurses::*;
+use ncurses::CURSOR_VISIBILITY::CURSOR_INVISIBLE;
+
+use codec;
+use image::Image;
+use keys;
+use keys::Action;
+use keys::KEY_BACKSPACE;
+use keys::KEY_DOWN;
+use keys::KEY_END;
+use keys::KEY_HOME;
+use keys::KEY_LEFT;
+use keys::KEY_NPAGE;
+use keys::KEY_PPAGE;
+use keys::KEY_RIGHT;
+use keys::KEY_TAB;
+use keys::KEY_UP;
+use mouse;
+use theme;
+use theme::HIGHLIGHT_COLOR;
+use theme::QUESTION_COLOR;
+
+const KEY_SPACE  : i32 = ' ' as i32;
+const KEY_PERIOD : i32 = '.' as i32;
+
+pub enum BrowseResult {
+    Selected(Vec<PathBuf>),
+    EditPath(String),
+    Cancelled,
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    is_directory: bool,
+    size: u64,
+    // NOTE(erick): Read lazily, only for the entries that get drawn.
+    dimensions: Option<Option<(u32, u32)>>,
+}
+
+// NOTE(erick): Enough for the header of every format we read.
+const HEADER_READ_LIMIT : u64 = 64 * 1024;
+
+// NOTE(erick): Thumbnails are decoded on every selection change, so big
+// files would freeze the browser. The size in the header is checked
+// first, it's cheap.
+const THUMBNAIL_SIZE_LIMIT  : u64 = 4 * 1024 * 1024;
+const THUMBNAIL_PIXEL_LIMIT : u64 = 4 * 1024 * 1024;
+
+const THUMBNAIL_RAMP : &[u8] = b" .:-=+*#%@";
+
+struct Browser {
+    directory: PathBuf,
+    entries: Vec<Entry>,
+    selected: usize,
+    first_visible: usize,
+    marked: Vec<PathBuf>,
+    show_hidden: bool,
+    show_all_files: bool,
+    allow_multiple: bool,
+    thumbnail_path: Option<PathBuf>,
+    thumbnail: Option<Image>,
+}
+
+impl Entry {
+    fn dimensions(&mut self) -> Option<(u32, u32)> {
+        if self.dimensions.is_none() {
+            let bytes = codec::read_bytes(&self.path, Some(HEADER_READ_LIMIT));
+            self.dimensions = Some(bytes.ok().and_then(|bytes| codec::dimensions(&bytes)));
+        }
+
+        self.dimensions.unwrap()
+    }
+}
+
+impl Browser {
+    fn read_directory(&mut self) {
+        self.entries.clear();
+        self.selected = 0;
+        self.first_visible = 0;
+
+        if self.directory.parent().is_some() {
+            self.entries.push(Entry {
+                path: self.directory.parent().unwrap().to_path_buf(),
+                name: "..".to_string(),
+                is_directory: true,
+                size: 0,
+                dimensions: Some(None),
+            });
+        }
+
+        let dir_iterator = read_dir(&self.directory);
+        if dir_iterator.is_err() {
+            return;
+        }
+
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        for dir in dir_iterator.unwrap() {
+            if dir.is_err() { continue; }
+
+            let dir = dir.unwrap();
+            let path = dir.path();
+            let name = dir.file_name().to_string_lossy().into_owned();
+            if !self.show_hidden && name.starts_with('.') { continue; }
+
+            // NOTE(erick): metadata() follows symlinks, so links to
+            // directories are listed as directories.
+            let meta_data = std::fs::metadata(&path);
+            if meta_data.is_err() { continue; }
+
+            let meta_data = meta_data.unwrap();
+            if meta_data.is_dir() {
+                directories.push(Entry {
+                    path, name, is_directory: true, size: 0, dimensions: Some(None),
+                });
+            } else if self.show_all_files || codec::has_supported_extension(&path) {
+                files.push(Entry {
+                    path, name, is_directory: false, size: meta_data.len(), dimensions: None,
+                });
+            }
+        }
+
+        directories.sort_by(|a, b| a.name.cmp(&b.name));
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        self.entries.append(&mut directories);
+        self.entries.append(&mut files);
+    }
+
+    fn change_directory(&mut self, directory: PathBuf) {
+        let previous = self.directory.clone();
+        self.directory = directory;
+        self.read_directory();
+
+        // NOTE(erick): Going up keeps the directory we came from selected.
+        let came_from = self.entries.iter().position(|entry| entry.path == previous);
+        if let Some(index) = came_from {
+            self.selected = index;
+        }
+    }
+
+    fn move_selection(&mut self, increment: isize) {
+        if self.entries.is_empty() { return; }
+
+        let last = self.entries.len() as isize - 1;
+        let selected = (self.selected as isize + increment).max(0).min(last);
+        self.selected = selected as usize;
+    }
+
+    fn toggle_mark(&mut self) {
+        if !self.allow_multiple || self.entries.is_empty() { return; }
+
+        let entry = &self.entries[self.selected];
+        if entry.is_directory { return; }
+
+        let position = self.marked.iter().position(|path| *path == entry.path);
+        if let Some(position) = position {
+            self.marked.remove(position);
+        } else {
+            self.marked.push(entry.path.clone());
+        }
+    }
+
+    fn update_thumbnail(&mut self) {
+        let path = if !self.entries.is_empty() && !self.entries[self.selected].is_directory {
+            Some(self.entries[self.selected].path.clone())
+        } else {
+            None
+        };
+
+        if path == self.thumbnail_path { return; }
+
+        self.thumbnail = None;
+        if let Some(ref path) = path {
+            let entry = &mut self.entries[self.selected];
+            let is_small = entry.size <= THUMBNAIL_SIZE_LIMIT &&
+                entry.dimensions().is_some_and(|(width, height)| {
+                    width as u64 * height as u64 <= THUMBNAIL_PIXEL_LIMIT
+                });
+            if is_small {
+                self.thumbnail = codec::read_file(path).ok();
+            }
+        }
+        self.thumbnail_path = path;
+    }
+}
+
+pub fn browse(screen_height: i32, screen_width: i32,
+              start_directory: PathBuf, allow_multiple: bool) -> BrowseResult {
+    let window = newwin(screen_height, screen_width, 0, 0);
+    defer! {{ delwin(window); }}
+
+    let old_cursor = curs_set(CURSOR_INVISIBLE);
+    defer! {
+        if let Some(cursor) = old_cursor {
+            curs_set(cursor);
+        }
+    }
+
+    let mut browser = Browser {
+        directory: start_directory,
+        entries: Vec::new(),
+        selected: 0,
+        first_visible: 0,
+        marked: Vec::new(),
+        show_hidden: false,
+        show_all_files: false,
+        allow_multiple,
+        thumbnail_path: None,
+        thumbnail: None,
+    };
+    browser.read_directory();
+
+    // NOTE(erick): The first line shows the directory, the last one the keys.
+    let list_rows = (screen_height - 2).max(1) as usize;
+    let list_width = if screen_width >= 60 { screen_width * 3 / 5 } else { screen_width };
+
+    loop {
+        if browser.selected < browser.first_visible {
+            browser.first_visible = browser.selected;
+        }
+        if browser.selected >= browser.first_visible + list_rows {
+            browser.first_visible = browser.selected + 1 - list_rows;
+        }
+
+        browser.update_thumbnail();
+
+        theme::clear_window(window);
+        wprint_header(window, &browser);
+        wprint_entries(window, &mut browser, list_rows, list_width);
+        let thumbnail = browser.thumbnail.as_ref().filter(|_| list_width < screen_width);
+        if let Some(thumbnail) = thumbnail {
+            wprint_thumbnail(window, thumbnail,
+                             1, list_width + 1,
+                             list_rows as i32, screen_width - list_width - 1);
+        }
+        wprint_footer(window, screen_height - 1, allow_multiple);
+        wrefresh(window);
+
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => {
+                if browser.entries.is_empty() { continue; }
+
+                let entry = &browser.entries[browser.selected];
+                if entry.is_directory {
+                    let directory = entry.path.clone();
+                    browser.change_directory(directory);
+                } else if !browser.marked.is_empty() {
+                    return BrowseResult::Selected(browser.marked.clone());
+                } else {
+                    return BrowseResult::Selected(vec![entry.path.clone()]);
+                }
+            },
+            _ if keys::is(ch, Action::Cancel) => { return BrowseResult::Cancelled; },
+            _ if keys::is(ch, Action::Browse) => {
+                let mut path = browser.directory.to_string_lossy().into_owned();
+                if !path.ends_with('/') {
+                    path.push('/');
+                }
+                return BrowseResult::EditPath(path);
+            },
+            KEY_UP    => { browser.move_selection(-1); },
+            KEY_DOWN  => { browser.move_selection( 1); },
+            KEY_PPAGE => { browser.move_selection(-(list_rows as isize)); },
+            KEY_NPAGE => { browser.move_selection(list_rows as isize); },
+            KEY_HOME  => { browser.selected = 0; },
+            KEY_END   => { browser.move_selection(browser.entries.len() as isize); },
+            KEY_RIGHT if !browser.entries.is_empty() &&
+                browser.entries[browser.selected].is_directory => {
+                let directory = browser.entries[browser.selected].path.clone();
+                browser.change_directory(directory);
+            },
+            KEY_LEFT | KEY_BACKSPACE => {
+                let parent = browser.directory.parent().map(|parent| parent.to_path_buf());
+                if let Some(parent) = parent {
+                    browser.change_directory(parent);
+                }
+            },
+            KEY_SPACE => {
+                browser.toggle_mark();
+                browser.move_selection(1);
+            },
+            KEY_TAB   => {
+                browser.show_all_files = !browser.show_all_files;
+                browser.read_directory();
+            },
+            KEY_PERIOD => {
+                browser.show_hidden = !browser.show_hidden;
+                browser.read_directory();
+            },
+            KEY_MOUSE => {
+                let event = mouse::get_mouse_event();
+                if event.is_none() { continue; }
+
+                let event = event.unwrap();
+                browser.move_selection(mouse::wheel_direction(&event));
+
+                let row = event.y - getbegy(window) - 1;
+                let is_click = event.bstate &
+                    (BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED) as mmask_t != 0;
+                if !is_click || row < 0 || row as usize >= list_rows || event.x >= list_width {
+                    continue;
+                }
+
+                let clicked = browser.first_visible + row as usize;
+                if clicked >= browser.entries.len() { continue; }
+                browser.selected = clicked;
+
+                if event.bstate & BUTTON1_DOUBLE_CLICKED as mmask_t != 0 {
+                    let entry = &browser.entries[clicked];
+                    if entry.is_directory {
+                        let directory = entry.path.clone();
+                        browser.change_directory(directory);
+                    } else {
+                        return BrowseResult::Selected(vec![entry.path.clone()]);
+                    }
+                }
+            },
+            _         => { },
+        }
+    }
+}
+
+fn wprint_header(window: WINDOW, browser: &Browser) {
+    wmove(window, 0, 0);
+    wattron(window, theme::attribute(QUESTION_COLOR));
+    wprintw(window, format!("{}  [{}{}]", browser.directory.display(),
+                            if browser.show_all_files { "all files" } else { "images" },
+                            if browser.show_hidden { ", hidden" } else { "" }).as_str());
+    if !browser.marked.is_empty() {
+        wprintw(window, format!("  {} marked", browser.marked.len()).as_str());
+    }
+    wattroff(window, theme::attribute(QUESTION_COLOR));
+}
+
+fn wprint_footer(window: WINDOW, line: i32, allow_multiple: bool) {
+    let bindings = keys::bindings();
+    let key_name = |action: Action| {
+        bindings.keys(action).first().map(|key| keys::key_name(*key)).unwrap_or_default()
+    };
+
+    let mut footer = format!("{}: open  {}: cancel  {}: type a path  Tab: filter  .: hidden",
+                             key_name(Action::Confirm), key_name(Action::Cancel),
+                             key_name(Action::Browse));
+    if allow_multiple {
+        footer.push_str("  Space: mark");
+    }
+
+    wmove(window, line, 0);
+    wprintw(window, footer.as_str());
+}
+
+fn wprint_entries(window: WINDOW, browser: &mut Browser, rows: usize, width: i32) {
+    let width = width as usize;
+    let details_width = 22;
+    let name_width = width.saturating_sub(details_width + 3).max(8);
+
+    let last = (browser.first_visible + rows).min(browser.entries.len());
+    for index in browser.first_visible .. last {
+        let entry = &mut browser.entries[index];
+        let mark = if browser.marked.contains(&entry.path) { "*" } else { " " };
+        let mut name = entry.name.clone();
+        if entry.is_directory { name.push('/'); }
+        let name = truncate(name.as_str(), name_width);
+
+        let details = if entry.is_directory {
+            String::new()
+        } else {
+            let dimensions = match entry.dimensions() {
+                Some((w, h)) => format!("{}x{}", w, h),
+                None         => String::new(),
+            };
+            format!("{:>8} {:>12}", human_size(entry.size), dimensions)
+        };
+
+        let line = format!("{} {:<name_width$} {}", mark, name, details,
+                           name_width = name_width);
+
+        let y = (index - browser.first_visible) as i32 + 1;
+        wmove(window, y, 0);
+        if index == browser.selected {
+            wattron(window, theme::attribute(HIGHLIGHT_COLOR));
+            wprintw(window, truncate(line.as_str(), width).as_str());
+            wattroff(window, theme::attribute(HIGHLIGHT_COLOR));
+        } else {
+            wprintw(window, truncate(line.as_str(), width).as_str());
+        }
+    }
+}
+
+// NOTE(erick): Terminal cells are about twice as tall as they are wide,
+// so each cell covers two image rows for every column.
+fn wprint_thumbnail(window: WINDOW, image: &Image,
+                    top: i32, left: i32, rows: i32, columns: i32) {
+    if image.width == 0 || image.height == 0 || rows <= 0 || columns <= 0 {
+        return;
+    }
+
+    let scale_x = columns as f64 / image.width as f64;
+    let scale_y = (rows * 2) as f64 / image.height as f64;
+    let scale = scale_x.min(scale_y);
+
+    let out_columns = ((image.width as f64 * scale) as i32).max(1);
+    let out_rows = ((image.height as f64 * scale / 2.0) as i32).max(1);
+
+    for row in 0 .. out_rows {
+        let mut line = String::new();
+        for column in 0 .. out_columns {
+            let x0 = (column as u64 * image.width as u64 / out_columns as u64) as u32;
+            let x1 = ((column + 1) as u64 * image.width as u64 / out_columns as u64) as u32;
+            let y0 = (row as u64 * image.height as u64 / out_rows as u64) as u32;
+            let y1 = ((row + 1) as u64 * image.height as u64 / out_rows as u64) as u32;
+
+            let luma = average_luma(image, x0, x1.max(x0 + 1), y0, y1.max(y0 + 1));
+            let index = (luma * (THUMBNAIL_RAMP.len() - 1) as f64).round() as usize;
+            line.push(THUMBNAIL_RAMP[index] as char);
+        }
+
+        wmove(window, top + row, left);
+        wprintw(window, line.as_str());
+    }
+}
+
+// NOTE(erick): Samples at most 4x4 pixels per cell, which is plenty
+// for a preview and keeps huge images fast.
+fn average_luma(image: &Image, x0: u32, x1: u32, y0: u32, y1: u32) -> f64 {
+    let step_x = ((x1 - x0) / 4).max(1);
+    let step_y = ((y1 - y0) / 4).max(1);
+
+    let mut total = 0.0;
+    let mut count = 0;
+    let mut y = y0;
+    while y < y1.min(image.height) {
+        let mut x = x0;
+        while x < x1.min(image.width) {
+            let pixel = image.pixel(x, y);
+            let alpha = pixel[3] as f64 / 255.0;
+            let luma = 0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64;
+            total += luma * alpha / 255.0;
+            count += 1;
+            x += step_x;
+        }
+        y += step_y;
+    }
+
+    if count == 0 { 0.0 } else { total / count as f64 }
+}
+
+fn human_size(size: u64) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}{}", size, units[unit])
+    } else {
+        format!("{:.1}{}", value, units[unit])
+    }
+}
+
+fn truncate(string: &str, width: usize) -> String {
+    string.chars().take(width).collect()
+}
+
+pub fn start_directory(string: &str) -> PathBuf {
+    let path = Path::new(string);
+    if path.is_dir() {
+        return path.to_path_buf();
+    }
+
+    let parent = path.parent().filter(|parent| parent.is_dir());
+    if let Some(parent) = parent {
+        return parent.to_path_buf();
+    }
+
+    std::env::current_dir().unwrap_or_default()
+}
@@ -0,0 +1,322 @@
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bmp;
+use gif;
+use ico;
+use image::Image;
+use jpeg;
+use netpbm;
+use operation::SaveOptions;
+use operation::Subsampling;
+use png;
+use qoi;
+use tga;
+use tiff;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Bmp,
+    Png,
+    Pbm,
+    Pgm,
+    Ppm,
+    Pnm,
+    Pam,
+    Tga,
+    Qoi,
+    Gif,
+    Jpeg,
+    Tiff,
+    Ico,
+    Cur,
+}
+
+// NOTE(erick): Extensions only pick the encoder when saving and filter
+// the file browser. Files are always decoded by their signature.
+pub const SUPPORTED_EXTENSIONS : [(&str, Format); 16] = [
+    ("bmp", Format::Bmp),
+    ("png", Format::Png),
+    ("pbm", Format::Pbm),
+    ("pgm", Format::Pgm),
+    ("ppm", Format::Ppm),
+    ("pnm", Format::Pnm),
+    ("pam", Format::Pam),
+    ("tga", Format::Tga),
+    ("qoi", Format::Qoi),
+    ("gif", Format::Gif),
+    ("jpg", Format::Jpeg),
+    ("jpeg", Format::Jpeg),
+    ("tif", Format::Tiff),
+    ("tiff", Format::Tiff),
+    ("ico", Format::Ico),
+    ("cur", Format::Cur),
+];
+
+pub fn format_from_extension(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    SUPPORTED_EXTENSIONS.iter()
+        .find(|&&(supported, _)| supported == extension)
+        .map(|&(_, format)| format)
+}
+
+pub fn format_from_signature(bytes: &[u8]) -> Option<Format> {
+    if bmp::is_bmp(bytes) { return Some(Format::Bmp); }
+    if png::is_png(bytes) { return Some(Format::Png); }
+    if qoi::is_qoi(bytes) { return Some(Format::Qoi); }
+    if gif::is_gif(bytes) { return Some(Format::Gif); }
+    if jpeg::is_jpeg(bytes) { return Some(Format::Jpeg); }
+    if tiff::is_tiff(bytes) { return Some(Format::Tiff); }
+
+    match netpbm::magic(bytes) {
+        Some(1) | Some(4) => return Some(Format::Pbm),
+        Some(2) | Some(5) => return Some(Format::Pgm),
+        Some(3) | Some(6) => return Some(Format::Ppm),
+        Some(7)           => return Some(Format::Pam),
+        _                 => {},
+    }
+
+    if ico::is_cursor(bytes) { return Some(Format::Cur); }
+    if ico::is_ico(bytes) { return Some(Format::Ico); }
+
+    // NOTE(erick): TGA has no signature, only a header that has to make
+    // sense, so it goes last.
+    if tga::is_tga(bytes) { return Some(Format::Tga); }
+
+    None
+}
+
+pub fn has_supported_extension(path: &Path) -> bool {
+    format_from_extension(path).is_some()
+}
+
+// NOTE(erick): Enough to tell every format we read apart. The TGA
+// header is 18 bytes.
+pub const SIGNATURE_SIZE : u64 = 32;
+
+pub fn has_supported_signature(path: &Path) -> bool {
+    let bytes = read_bytes(path, Some(SIGNATURE_SIZE));
+    bytes.is_ok() && format_from_signature(bytes.unwrap().as_slice()).is_some()
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    match format_from_signature(bytes) {
+        Some(Format::Bmp)  => bmp::decode(bytes),
+        Some(Format::Png)  => png::decode(bytes),
+        Some(Format::Tga)  => tga::decode(bytes),
+        Some(Format::Qoi)  => qoi::decode(bytes),
+        Some(Format::Gif)  => gif::decode(bytes),
+        Some(Format::Jpeg) => jpeg::decode(bytes),
+        Some(Format::Tiff) => tiff::decode(bytes),
+        Some(Format::Ico)  => ico::decode(bytes),
+        Some(Format::Cur)  => ico::decode(bytes),
+        Some(_)            => netpbm::decode(bytes),
+        None               => Err("unknown file format".to_string()),
+    }
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match format_from_signature(bytes) {
+        Some(Format::Bmp)  => bmp::dimensions(bytes),
+        Some(Format::Png)  => png::dimensions(bytes),
+        Some(Format::Tga)  => tga::dimensions(bytes),
+        Some(Format::Qoi)  => qoi::dimensions(bytes),
+        Some(Format::Gif)  => gif::dimensions(bytes),
+        Some(Format::Jpeg) => jpeg::dimensions(bytes),
+        Some(Format::Tiff) => tiff::dimensions(bytes),
+        Some(Format::Ico)  => ico::dimensions(bytes),
+        Some(Format::Cur)  => ico::dimensions(bytes),
+        Some(_)            => netpbm::dimensions(bytes),
+        None               => None,
+    }
+}
+
+pub fn encode(image: &Image, format: Format, options: &SaveOptions) -> Vec<u8> {
+    match format {
+        Format::Bmp  => {
+            if let Some(bits) = options.palette_bits {
+                bmp::encode_indexed(image, bits)
+            } else {
+                bmp::encode(image)
+            }
+        },
+        Format::Png  => png::encode(image),
+        Format::Pbm  => encode_netpbm(image, netpbm::Kind::Bitmap, options),
+        Format::Pgm  => encode_netpbm(image, netpbm::Kind::Graymap, options),
+        Format::Ppm  => encode_netpbm(image, netpbm::Kind::Pixmap, options),
+        Format::Pnm  => encode_netpbm(image, netpbm::Kind::Anymap, options),
+        Format::Pam  => encode_netpbm(image, netpbm::Kind::Arbitrary, options),
+        Format::Tga  => tga::encode(image),
+        Format::Qoi  => qoi::encode(image),
+        Format::Gif  => gif::encode(image),
+        Format::Jpeg => jpeg::encode(image, options.quality,
+                                     options.subsampling == Subsampling::Chroma420),
+        Format::Tiff => tiff::encode(image),
+        Format::Ico  => ico::encode(image, false),
+        Format::Cur  => ico::encode(image, true),
+    }
+}
+
+fn encode_netpbm(image: &Image, kind: netpbm::Kind, options: &SaveOptions) -> Vec<u8> {
+    let plain = options.plain.unwrap_or(netpbm::options().plain);
+    netpbm::encode(image, kind, plain, options.sample_bits)
+}
+
+pub fn read_file(path: &Path) -> Result<Image, String> {
+    let bytes = read_bytes(path, None)?;
+    decode(bytes.as_slice())
+}
+
+// NOTE(erick): Only GIFs, TIFFs and icons have more than one frame, the
+// pages of a TIFF and the sizes of an icon are its frames. Everything
+// else is a single image.
+pub fn frame_count(bytes: &[u8]) -> Result<usize, String> {
+    match format_from_signature(bytes) {
+        Some(Format::Gif)  => gif::frame_count(bytes),
+        Some(Format::Tiff) => tiff::page_count(bytes),
+        Some(Format::Ico)  => ico::image_count(bytes),
+        Some(Format::Cur)  => ico::image_count(bytes),
+        Some(_)            => Ok(1),
+        None               => Err("unknown file format".to_string()),
+    }
+}
+
+pub fn read_frame(path: &Path, frame: usize) -> Result<Image, String> {
+    let bytes = read_bytes(path, None)?;
+    match format_from_signature(bytes.as_slice()) {
+        Some(Format::Gif)  => gif::decode_frame(bytes.as_slice(), frame),
+        Some(Format::Tiff) => tiff::decode_page(bytes.as_slice(), frame),
+        Some(Format::Ico)  => ico::decode_image(bytes.as_slice(), frame),
+        Some(Format::Cur)  => ico::decode_image(bytes.as_slice(), frame),
+        _ if frame == 0    => decode(bytes.as_slice()),
+        _                  => Err(format!("{} has a single frame", path.display())),
+    }
+}
+
+pub fn write_file(path: &Path, image: &Image, options: &SaveOptions) -> Result<(), String> {
+    let format = format_from_extension(path);
+    if format.is_none() {
+        return Err(format!("unknown format for {}", path.display()));
+    }
+
+    let format = format.unwrap();
+    if is_icon(Some(format)) {
+        check_icon_size(path, image)?;
+    }
+    check_size(path, image, format)?;
+
+    write_bytes(path, encode(image, format, options).as_slice())
+}
+
+pub fn is_icon(format: Option<Format>) -> bool {
+    format == Some(Format::Ico) || format == Some(Format::Cur)
+}
+
+pub fn is_netpbm(format: Option<Format>) -> bool {
+    format == Some(Format::Pbm) || format == Some(Format::Pgm) || format == Some(Format::Ppm) ||
+        format == Some(Format::Pnm) || format == Some(Format::Pam)
+}
+
+fn check_icon_size(path: &Path, image: &Image) -> Result<(), String> {
+    if image.width > ico::MAX_SIZE || image.height > ico::MAX_SIZE {
+        return Err(format!("{}: icons can be at most {}x{}, this image is {}x{}",
+                           path.display(), ico::MAX_SIZE, ico::MAX_SIZE,
+                           image.width, image.height));
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Largest width and height a format can store, None when it
+// takes anything that fits in memory.
+fn max_size(format: Format) -> Option<u32> {
+    match format {
+        Format::Tga  => Some(tga::MAX_SIZE),
+        Format::Gif  => Some(gif::MAX_SIZE),
+        Format::Jpeg => Some(jpeg::MAX_SIZE),
+        _            => None,
+    }
+}
+
+fn check_size(path: &Path, image: &Image, format: Format) -> Result<(), String> {
+    let max_size = max_size(format);
+    if let Some(max_size) = max_size.filter(|&size| image.width > size || image.height > size) {
+        return Err(format!("{}: the format can store at most {}x{}, this image is {}x{}",
+                           path.display(), max_size, max_size, image.width, image.height));
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Every image is one size of the icon, in the given order.
+pub fn write_icon(path: &Path, images: &[&Image]) -> Result<(), String> {
+    let format = format_from_extension(path);
+    if !is_icon(format) {
+        return Err(format!("{}: only ICO and CUR files can have several sizes", path.display()));
+    }
+
+    for image in images.iter() {
+        check_icon_size(path, image)?;
+    }
+
+    let bytes = ico::encode_images(images, format == Some(Format::Cur));
+    write_bytes(path, bytes.as_slice())
+}
+
+pub fn has_frames(format: Option<Format>) -> bool {
+    format == Some(Format::Gif) || format == Some(Format::Tiff)
+}
+
+// NOTE(erick): Frames are (image, delay in milliseconds). TIFF pages
+// have no timing, so the delays and loops only matter for GIFs.
+pub fn write_animation(path: &Path, frames: &[(&Image, u32)], loops: u16) -> Result<(), String> {
+    let format = format_from_extension(path);
+    if let Some(format) = format {
+        for &(image, _) in frames.iter() {
+            check_size(path, image, format)?;
+        }
+    }
+
+    let bytes = match format {
+        Some(Format::Gif)  => gif::encode_animation(frames, Some(loops)),
+        Some(Format::Tiff) => {
+            let images = frames.iter().map(|&(image, _)| image).collect::<Vec<_> >();
+            tiff::encode_pages(images.as_slice())
+        },
+        _ => return Err(format!("{}: only GIF and TIFF files can have several frames",
+                                path.display())),
+    };
+
+    write_bytes(path, bytes.as_slice())
+}
+
+fn write_bytes(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let file = File::create(path);
+    if file.is_err() || file.unwrap().write_all(bytes).is_err() {
+        return Err(format!("cannot write {}", path.display()));
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Headers are small, so 'limit' lets callers that only
+// want the dimensions avoid reading whole files.
+pub fn read_bytes(path: &Path, limit: Option<u64>) -> Result<Vec<u8>, String> {
+    let file = File::open(path);
+    if file.is_err() {
+        return Err(format!("cannot open {}", path.display()));
+    }
+
+    let mut bytes = Vec::new();
+    let result = match limit {
+        Some(limit) => file.unwrap().take(limit).read_to_end(&mut bytes),
+        None        => file.unwrap().read_to_end(&mut bytes),
+    };
+    if result.is_err() {
+        return Err(format!("cannot read {}", path.display()));
+    }
+
+    Ok(bytes)
+}
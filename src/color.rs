@@ -0,0 +1,320 @@
+use std::f64::consts::FRAC_PI_4;
+
+use image::Image;
+use operation::Adjustment;
+use operation::Channels;
+use operation::Levels;
+use operation::Mixer;
+
+const REC601_WEIGHTS : [f64; 3] = [0.299, 0.587, 0.114];
+const REC709_WEIGHTS : [f64; 3] = [0.2126, 0.7152, 0.0722];
+const AVERAGE_WEIGHTS : [f64; 3] = [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0];
+
+#[inline]
+pub fn clamp_channel(value: f64) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// NOTE(erick): Rec.601 gray value of an RGBA pixel, alpha is ignored.
+pub fn luma(pixel: &[u8]) -> u8 {
+    clamp_channel(REC601_WEIGHTS[0] * pixel[0] as f64 +
+                  REC601_WEIGHTS[1] * pixel[1] as f64 +
+                  REC601_WEIGHTS[2] * pixel[2] as f64)
+}
+
+// NOTE(erick): The gray presets are matrices with the same weights in
+// the three color rows that leave alpha alone.
+fn gray_matrix(weights: [f64; 3]) -> [[f64; 4]; 4] {
+    let row = [weights[0], weights[1], weights[2], 0.0];
+    [row, row, row, [0.0, 0.0, 0.0, 1.0]]
+}
+
+pub fn mix(image: &Image, mixer: &Mixer) -> Image {
+    let matrix = match *mixer {
+        Mixer::Rec601         => gray_matrix(REC601_WEIGHTS),
+        Mixer::Rec709         => gray_matrix(REC709_WEIGHTS),
+        Mixer::Average        => gray_matrix(AVERAGE_WEIGHTS),
+        Mixer::Matrix(matrix) => matrix,
+        Mixer::Desaturate     => return desaturate(image),
+    };
+
+    let mut result = image.clone();
+    for pixel in result.pixels.chunks_mut(4) {
+        let input = [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64, pixel[3] as f64];
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = row[0] * input[0] + row[1] * input[1] +
+                        row[2] * input[2] + row[3] * input[3];
+            pixel[channel] = clamp_channel(value);
+        }
+    }
+
+    result
+}
+
+fn desaturate(image: &Image) -> Image {
+    let mut result = image.clone();
+    for pixel in result.pixels.chunks_mut(4) {
+        let high = pixel[0].max(pixel[1]).max(pixel[2]) as u32;
+        let low = pixel[0].min(pixel[1]).min(pixel[2]) as u32;
+        let lightness = (high + low).div_ceil(2) as u8;
+        pixel[0] = lightness;
+        pixel[1] = lightness;
+        pixel[2] = lightness;
+    }
+
+    result
+}
+
+pub fn affected_channels(channels: Channels) -> [bool; 4] {
+    match channels {
+        Channels::All   => [true, true, true, false],
+        Channels::Red   => [true, false, false, false],
+        Channels::Green => [false, true, false, false],
+        Channels::Blue  => [false, false, true, false],
+        Channels::Alpha => [false, false, false, true],
+    }
+}
+
+// NOTE(erick): Per channel adjustments only depend on the input value,
+// so they are computed once for the 256 values.
+fn apply_table(image: &Image, channels: Channels, table: &[u8; 256]) -> Image {
+    let affected = affected_channels(channels);
+    let mut result = image.clone();
+    for pixel in result.pixels.chunks_mut(4) {
+        for channel in 0 .. 4 {
+            if affected[channel] {
+                pixel[channel] = table[pixel[channel] as usize];
+            }
+        }
+    }
+
+    result
+}
+
+fn build_table<F: Fn(f64) -> f64>(curve: F) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = clamp_channel(curve(value as f64 / 255.0).clamp(0.0, 1.0) * 255.0);
+    }
+    table
+}
+
+// NOTE(erick): Same as the old GIMP brightness-contrast: brightness moves
+// values towards black or white, contrast rotates the line through the
+// middle gray, up to a threshold at 100.
+pub fn adjust(image: &Image, channels: Channels, adjustment: &Adjustment) -> Image {
+    let brightness = adjustment.brightness / 100.0;
+    let slope = ((adjustment.contrast / 100.0 + 1.0) * FRAC_PI_4).tan();
+    let table = build_table(|value| {
+        let value = if brightness < 0.0 {
+            value * (1.0 + brightness)
+        } else {
+            value + (1.0 - value) * brightness
+        };
+        let value = ((value - 0.5) * slope + 0.5).clamp(0.0, 1.0);
+        value.powf(1.0 / adjustment.gamma)
+    });
+
+    apply_table(image, channels, &table)
+}
+
+pub fn levels(image: &Image, channels: Channels, levels: &Levels) -> Image {
+    let black = levels.input_black as f64 / 255.0;
+    let white = levels.input_white as f64 / 255.0;
+    let output_black = levels.output_black as f64 / 255.0;
+    let output_white = levels.output_white as f64 / 255.0;
+    let table = build_table(|value| {
+        let value = ((value - black) / (white - black)).clamp(0.0, 1.0);
+        let value = value.powf(1.0 / levels.midtone);
+        output_black + value * (output_white - output_black)
+    });
+
+    apply_table(image, channels, &table)
+}
+
+// NOTE(erick): Monotone cubic (Fritsch-Carlson) through the points, so
+// the curve never overshoots between them. Points are sorted by input
+// and there are at least two. Inputs outside them keep the end values.
+pub fn curve_table(points: &[(u8, u8)]) -> [u8; 256] {
+    let xs = points.iter().map(|&(x, _)| x as f64).collect::<Vec<_> >();
+    let ys = points.iter().map(|&(_, y)| y as f64).collect::<Vec<_> >();
+    let count = points.len();
+
+    let deltas = (0 .. count - 1)
+        .map(|k| (ys[k + 1] - ys[k]) / (xs[k + 1] - xs[k]))
+        .collect::<Vec<_> >();
+
+    let mut slopes = vec![0.0; count];
+    slopes[0] = deltas[0];
+    slopes[count - 1] = deltas[count - 2];
+    for k in 1 .. count - 1 {
+        if deltas[k - 1] * deltas[k] > 0.0 {
+            slopes[k] = (deltas[k - 1] + deltas[k]) / 2.0;
+        }
+    }
+    for k in 0 .. count - 1 {
+        if deltas[k] == 0.0 {
+            slopes[k] = 0.0;
+            slopes[k + 1] = 0.0;
+            continue;
+        }
+
+        let a = slopes[k] / deltas[k];
+        let b = slopes[k + 1] / deltas[k];
+        let length = a * a + b * b;
+        if length > 9.0 {
+            let scale = 3.0 / length.sqrt();
+            slopes[k] = scale * a * deltas[k];
+            slopes[k + 1] = scale * b * deltas[k];
+        }
+    }
+
+    let mut table = [0u8; 256];
+    let mut k = 0;
+    for (x, entry) in table.iter_mut().enumerate() {
+        let x = x as f64;
+        if x <= xs[0] {
+            *entry = points[0].1;
+            continue;
+        }
+        if x >= xs[count - 1] {
+            *entry = points[count - 1].1;
+            continue;
+        }
+
+        while x > xs[k + 1] {
+            k += 1;
+        }
+
+        let h = xs[k + 1] - xs[k];
+        let t = (x - xs[k]) / h;
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let value = (2.0 * t3 - 3.0 * t2 + 1.0) * ys[k] +
+                    (t3 - 2.0 * t2 + t) * h * slopes[k] +
+                    (-2.0 * t3 + 3.0 * t2) * ys[k + 1] +
+                    (t3 - t2) * h * slopes[k + 1];
+        *entry = clamp_channel(value);
+    }
+
+    table
+}
+
+pub fn curves(image: &Image, channels: Channels, points: &[(u8, u8)]) -> Image {
+    apply_table(image, channels, &curve_table(points))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_pixel(pixel: [u8; 4]) -> Image {
+        let mut image = Image::new(1, 1);
+        image.set_pixel(0, 0, pixel);
+        image
+    }
+
+    #[test]
+    fn gray_mixers() {
+        let image = single_pixel([255, 0, 0, 128]);
+        assert_eq!(mix(&image, &Mixer::Rec601).pixel(0, 0), [76, 76, 76, 128]);
+        assert_eq!(mix(&image, &Mixer::Rec709).pixel(0, 0), [54, 54, 54, 128]);
+        assert_eq!(mix(&image, &Mixer::Average).pixel(0, 0), [85, 85, 85, 128]);
+
+        let image = single_pixel([200, 100, 51, 255]);
+        assert_eq!(mix(&image, &Mixer::Desaturate).pixel(0, 0), [126, 126, 126, 255]);
+        assert_eq!(luma(&[200, 100, 51, 255]), 124);
+    }
+
+    #[test]
+    fn matrix_mixer() {
+        let image = single_pixel([200, 100, 50, 255]);
+        let swap = [[0.0, 0.0, 1.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 0.0, 0.0, 0.5]];
+        assert_eq!(mix(&image, &Mixer::Matrix(swap)).pixel(0, 0), [50, 100, 200, 128]);
+
+        let overflow = [[2.0, 0.0, 0.0, 0.0],
+                        [0.0, -1.0, 0.0, 0.0],
+                        [0.0, 0.0, 1.0, 0.0],
+                        [0.0, 0.0, 0.0, 1.0]];
+        assert_eq!(mix(&image, &Mixer::Matrix(overflow)).pixel(0, 0), [255, 0, 50, 255]);
+    }
+
+    fn ramp() -> Image {
+        let mut image = Image::new(256, 1);
+        for (value, pixel) in image.pixels.chunks_mut(4).enumerate() {
+            pixel.copy_from_slice(&[value as u8, value as u8, 255 - value as u8, value as u8]);
+        }
+        image
+    }
+
+    #[test]
+    fn neutral_adjustments() {
+        let image = ramp();
+        let adjustment = Adjustment { brightness: 0.0, contrast: 0.0, gamma: 1.0 };
+        assert!(adjust(&image, Channels::All, &adjustment).pixels == image.pixels);
+
+        let identity = Levels { input_black: 0, input_white: 255, midtone: 1.0,
+                                output_black: 0, output_white: 255 };
+        assert!(levels(&image, Channels::All, &identity).pixels == image.pixels);
+    }
+
+    #[test]
+    fn adjustments() {
+        let image = single_pixel([100, 100, 100, 100]);
+
+        let brighter = Adjustment { brightness: 50.0, contrast: 0.0, gamma: 1.0 };
+        assert_eq!(adjust(&image, Channels::All, &brighter).pixel(0, 0), [178, 178, 178, 100]);
+
+        let darker = Adjustment { brightness: -100.0, contrast: 0.0, gamma: 1.0 };
+        assert_eq!(adjust(&image, Channels::Red, &darker).pixel(0, 0), [0, 100, 100, 100]);
+
+        let threshold = Adjustment { brightness: 0.0, contrast: 100.0, gamma: 1.0 };
+        let result = adjust(&ramp(), Channels::Green, &threshold);
+        assert_eq!(result.pixel(127, 0)[1], 0);
+        assert_eq!(result.pixel(128, 0)[1], 255);
+        assert_eq!(result.pixel(128, 0)[0], 128);
+
+        let gamma = Adjustment { brightness: 0.0, contrast: 0.0, gamma: 2.0 };
+        assert_eq!(adjust(&image, Channels::Alpha, &gamma).pixel(0, 0), [100, 100, 100, 160]);
+    }
+
+    #[test]
+    fn level_ranges() {
+        let stretch = Levels { input_black: 50, input_white: 150, midtone: 1.0,
+                               output_black: 0, output_white: 255 };
+        let result = levels(&ramp(), Channels::All, &stretch);
+        assert_eq!(result.pixel(50, 0), [0, 0, 255, 50]);
+        assert_eq!(result.pixel(125, 0), [191, 191, 204, 125]);
+        assert_eq!(result.pixel(150, 0), [255, 255, 140, 150]);
+
+        let invert = Levels { input_black: 0, input_white: 255, midtone: 1.0,
+                              output_black: 255, output_white: 0 };
+        let result = levels(&single_pixel([0, 55, 255, 255]), Channels::All, &invert);
+        assert_eq!(result.pixel(0, 0), [255, 200, 0, 255]);
+    }
+
+    #[test]
+    fn curve_tables() {
+        let identity = curve_table(&[(0, 0), (255, 255)]);
+        assert!((0 .. 256).all(|value| identity[value] as usize == value));
+
+        let inverted = curve_table(&[(0, 255), (255, 0)]);
+        assert!((0 .. 256).all(|value| inverted[value] as usize == 255 - value));
+
+        let clipped = curve_table(&[(64, 10), (192, 240)]);
+        assert_eq!((clipped[0], clipped[64], clipped[192], clipped[255]), (10, 10, 240, 240));
+
+        // NOTE(erick): A plain cubic spline would dip below 40 between the
+        // flat part and the jump.
+        let steps = curve_table(&[(0, 40), (100, 40), (120, 250), (255, 255)]);
+        assert!(steps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(steps[50], 40);
+        assert_eq!(steps[120], 250);
+
+        let image = curves(&single_pixel([10, 20, 30, 40]), Channels::Blue, &[(0, 255), (255, 0)]);
+        assert_eq!(image.pixel(0, 0), [10, 20, 225, 40]);
+    }
+}
@@ -0,0 +1,1013 @@
+use std::path::PathBuf;
+
+use codec;
+use codec::Format;
+use completion::expand_path;
+use operation::Adjustment;
+use operation::Channels;
+use operation::ColorPalette;
+use operation::Direction;
+use operation::Dither;
+use operation::EdgeMode;
+use operation::IDENTITY_MATRIX;
+use operation::Kernel;
+use operation::Levels;
+use operation::Mixer;
+use operation::Morphology;
+use operation::Operation;
+use operation::points_string;
+use operation::SaveOptions;
+use operation::Shape;
+use operation::Subsampling;
+use operation::Threshold;
+use operation::Window;
+
+// NOTE(erick): Commands typed in the minibuffer after ':'. A pipeline
+// file is just a list of these commands, one per line, so loading a
+// pipeline is the same as typing its lines again.
+pub enum Command {
+    Open(PathBuf),
+    Save(usize, PathBuf, SaveOptions),
+    Merge(usize, usize, Direction),
+    Crop(usize, u32, u32, i32, i32),
+    Frame(PathBuf, usize),
+    Animate(PathBuf, u16, Vec<(usize, u32)>),
+    Icon(PathBuf, Vec<usize>),
+    Mix(usize, Mixer),
+    Adjust(usize, Channels, Adjustment),
+    Levels(usize, Channels, Levels),
+    Curves(usize, Channels, Vec<(u8, u8)>),
+    Convolve(usize, Kernel, EdgeMode),
+    Median(usize, Channels, Window),
+    Morphology(usize, Channels, Morphology, Window),
+    Threshold(usize, Threshold),
+    Quantize(usize, ColorPalette, Dither),
+    Write(PathBuf),
+    Source(PathBuf),
+    Quit,
+}
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS : [CommandInfo; 19] = [
+    CommandInfo { name: "adjust",  usage: "adjust OP CH BRIGHT CONTRAST GAMMA",
+                  description: "Change brightness, contrast (-100 to 100) and gamma" },
+    CommandInfo { name: "animate", usage: "animate FILE LOOPS OP[:MS]...",
+                  description: "Save operations as GIF frames or TIFF pages" },
+    CommandInfo { name: "convolve", usage: "convolve OP EDGE KERNEL [ARGS...]",
+                  description: "Filter with box, gauss, sharpen, unsharp, emboss, sobel, \
+                                prewitt, laplacian or kernel" },
+    CommandInfo { name: "crop",    usage: "crop OP X0 Y0 WIDTH HEIGHT",
+                  description: "Crop the result of an operation" },
+    CommandInfo { name: "curves",  usage: "curves OP CH IN:OUT IN:OUT...",
+                  description: "Map the channels through a curve, CH is rgb|r|g|b|a" },
+    CommandInfo { name: "frame",   usage: "frame FILE N",
+                  description: "Open one frame of an animated image" },
+    CommandInfo { name: "icon",    usage: "icon FILE OP...",
+                  description: "Save operations as the sizes of an ICO or CUR file" },
+    CommandInfo { name: "levels",  usage: "levels OP CH IN0 IN1 MID [OUT0 OUT1]",
+                  description: "Stretch input levels to output levels, CH is rgb|r|g|b|a" },
+    CommandInfo { name: "median",  usage: "median OP CH SHAPE RADIUS",
+                  description: "Median filter, SHAPE is square|cross|disk" },
+    CommandInfo { name: "merge",   usage: "merge OP0 OP1 h|v",
+                  description: "Merge two operations horizontally or vertically" },
+    CommandInfo { name: "mix",     usage: "mix OP PRESET|M11 M12...",
+                  description: "Convert to gray (601, 709, avg, desat) or mix with a 3x3/4x4 matrix" },
+    CommandInfo { name: "morph",   usage: "morph OP CH KIND SHAPE RADIUS",
+                  description: "Erode (min), dilate (max), open, close or gradient" },
+    CommandInfo { name: "open",    usage: "open FILE",
+                  description: "Open an image file" },
+    CommandInfo { name: "q",       usage: "q",
+                  description: "Quit climp" },
+    CommandInfo { name: "quantize", usage: "quantize OP DITHER PALETTE [COUNT|FILE]",
+                  description: "Reduce the colors to median N, octree N, web, gray N or file F" },
+    CommandInfo { name: "save",    usage: "save OP FILE [QUALITY [420|444]|BITS|plain|raw [8|16]]",
+                  description: "Save the result of an operation, BITS is 1, 4 or 8 for BMP" },
+    CommandInfo { name: "source",  usage: "source FILE",
+                  description: "Load a pipeline file" },
+    CommandInfo { name: "threshold", usage: "threshold OP LEVEL|otsu|mean|gauss [SIZE OFFSET]",
+                  description: "Turn to black and white at a level or around each pixel" },
+    CommandInfo { name: "w",       usage: "w FILE",
+                  description: "Write the pipeline to a file" },
+];
+
+fn usage(name: &str) -> String {
+    let info = COMMANDS.iter().find(|info| info.name == name);
+    match info {
+        Some(info) => format!("usage: {}", info.usage),
+        None       => "unknown command".to_string(),
+    }
+}
+
+// NOTE(erick): Operation indices are typed as they are shown in the
+// operations window, that is, starting from 1.
+pub fn parse_command(line: &str, operations_count: usize) -> Result<Command, String> {
+    let tokens = tokenize(line)?;
+    if tokens.is_empty() {
+        return Err("empty command".to_string());
+    }
+
+    let name = tokens[0].as_str();
+    let args = &tokens[1 ..];
+    let expected_args = match name {
+        "open" | "w" | "source" => 1,
+        "icon" | "mix"          => 2,
+        "save" | "frame"        => 2,
+        "threshold"             => 2,
+        "merge" | "animate"     => 3,
+        "convolve" | "quantize" => 3,
+        "curves" | "median"     => 4,
+        "crop" | "adjust"       => 5,
+        "levels" | "morph"      => 5,
+        "q"                     => 0,
+        _ => return Err(format!("unknown command: {}", name)),
+    };
+
+    // NOTE(erick): animate and icon take as many operations as needed
+    // after the first, curves as many points as needed and convolve as
+    // many weights as needed. save may be followed by the encoder
+    // options, mix by a whole matrix, levels by the output levels,
+    // threshold by the window size and offset and quantize by the color
+    // count or the palette file.
+    let max_args = match name {
+        "animate"  => usize::MAX,
+        "icon"     => usize::MAX,
+        "curves"   => usize::MAX,
+        "convolve" => usize::MAX,
+        "save"    => 4,
+        "mix"     => 17,
+        "levels"  => 7,
+        "threshold" => 4,
+        "quantize" => 4,
+        _         => expected_args,
+    };
+    if args.len() < expected_args || args.len() > max_args {
+        return Err(usage(name));
+    }
+
+    let command = match name {
+        "open"   => Command::Open(PathBuf::from(expand_path(&args[0]))),
+        "w"      => Command::Write(PathBuf::from(expand_path(&args[0]))),
+        "source" => Command::Source(PathBuf::from(expand_path(&args[0]))),
+        "q"      => Command::Quit,
+        "save"   => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let path = PathBuf::from(expand_path(&args[1]));
+            let mut options = SaveOptions::default();
+            // NOTE(erick): BMP takes the palette bits where JPEG takes the
+            // quality.
+            if codec::format_from_extension(&path) == Some(Format::Bmp) {
+                if args.len() > 3 {
+                    return Err(usage(name));
+                }
+                if args.len() > 2 {
+                    options.palette_bits = Some(parse_palette_bits(&args[2])?);
+                }
+                return Ok(Command::Save(op, path, options));
+            }
+            // NOTE(erick): Netpbm takes plain or raw and the sample bits,
+            // in any order.
+            let format = codec::format_from_extension(&path);
+            if codec::is_netpbm(format) {
+                for arg in args[2 ..].iter() {
+                    match arg.as_str() {
+                        "plain" if format == Some(Format::Pam) => {
+                            return Err("PAM has no plain version".to_string());
+                        },
+                        "plain" if options.plain.is_none() => { options.plain = Some(true); },
+                        "raw" if options.plain.is_none()   => { options.plain = Some(false); },
+                        "8" | "16" if format == Some(Format::Pbm) => {
+                            return Err("PBM has no sample bits".to_string());
+                        },
+                        "8"  => { options.sample_bits = 8; },
+                        "16" => { options.sample_bits = 16; },
+                        _    => return Err(usage(name)),
+                    }
+                }
+                return Ok(Command::Save(op, path, options));
+            }
+
+            // NOTE(erick): Only JPEG has a quality and chroma subsampling.
+            if args.len() > 2 && format != Some(Format::Jpeg) {
+                return Err(usage(name));
+            }
+            if args.len() > 2 {
+                options.quality = parse_quality(&args[2])?;
+            }
+            if args.len() > 3 {
+                options.subsampling = match args[3].as_str() {
+                    "420" | "4:2:0" => Subsampling::Chroma420,
+                    "444" | "4:4:4" => Subsampling::Chroma444,
+                    _ => return Err(format!("invalid subsampling: {}", args[3])),
+                };
+            }
+            Command::Save(op, path, options)
+        },
+        "merge"  => {
+            let op0 = parse_operation_index(&args[0], operations_count)?;
+            let op1 = parse_operation_index(&args[1], operations_count)?;
+            let direction = match args[2].to_lowercase().as_str() {
+                "h" | "hor" | "horizontal" => Direction::Horizontal,
+                "v" | "ver" | "vertical"   => Direction::Vertical,
+                _ => return Err(format!("invalid direction: {}", args[2])),
+            };
+            Command::Merge(op0, op1, direction)
+        },
+        "crop"   => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let x0 = parse_number::<u32>(&args[1], "X0")?;
+            let y0 = parse_number::<u32>(&args[2], "Y0")?;
+            let width = parse_number::<i32>(&args[3], "WIDTH")?;
+            let height = parse_number::<i32>(&args[4], "HEIGHT")?;
+            Command::Crop(op, x0, y0, width, height)
+        },
+        "frame"  => {
+            let frame = parse_number::<usize>(&args[1], "N")?;
+            if frame == 0 {
+                return Err("frames start at 1".to_string());
+            }
+            Command::Frame(PathBuf::from(expand_path(&args[0])), frame - 1)
+        },
+        "animate" => {
+            let loops = parse_number::<u16>(&args[1], "LOOPS")?;
+            let mut frames = Vec::new();
+            for arg in &args[2 ..] {
+                frames.push(parse_animation_frame(arg, operations_count)?);
+            }
+            Command::Animate(PathBuf::from(expand_path(&args[0])), loops, frames)
+        },
+        "icon"   => {
+            let mut ops = Vec::new();
+            for arg in &args[1 ..] {
+                ops.push(parse_operation_index(arg, operations_count)?);
+            }
+            Command::Icon(PathBuf::from(expand_path(&args[0])), ops)
+        },
+        "mix"    => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            Command::Mix(op, parse_mixer(&args[1 ..])?)
+        },
+        "adjust" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let channels = parse_channels(&args[1])?;
+            let adjustment = Adjustment {
+                brightness: parse_in_range(&args[2], "BRIGHT", MIN_PERCENT, MAX_PERCENT)?,
+                contrast: parse_in_range(&args[3], "CONTRAST", MIN_PERCENT, MAX_PERCENT)?,
+                gamma: parse_in_range(&args[4], "GAMMA", MIN_GAMMA, MAX_GAMMA)?,
+            };
+            Command::Adjust(op, channels, adjustment)
+        },
+        "levels" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let channels = parse_channels(&args[1])?;
+            let mut levels = Levels {
+                input_black: parse_number::<u8>(&args[2], "IN0")?,
+                input_white: parse_number::<u8>(&args[3], "IN1")?,
+                midtone: parse_in_range(&args[4], "MID", MIN_GAMMA, MAX_GAMMA)?,
+                output_black: 0,
+                output_white: 255,
+            };
+            if levels.input_black >= levels.input_white {
+                return Err("IN0 has to be below IN1".to_string());
+            }
+            if args.len() > 5 {
+                if args.len() < 7 {
+                    return Err(usage(name));
+                }
+                levels.output_black = parse_number::<u8>(&args[5], "OUT0")?;
+                levels.output_white = parse_number::<u8>(&args[6], "OUT1")?;
+            }
+            Command::Levels(op, channels, levels)
+        },
+        "curves" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let channels = parse_channels(&args[1])?;
+            let mut points = Vec::new();
+            for arg in &args[2 ..] {
+                points.push(parse_curve_point(arg)?);
+            }
+            points.sort();
+            for pair in points.windows(2) {
+                if pair[0].0 == pair[1].0 {
+                    return Err(format!("two points at IN {}", pair[0].0));
+                }
+            }
+            Command::Curves(op, channels, points)
+        },
+        "convolve" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let edge = parse_edge_mode(&args[1])?;
+            Command::Convolve(op, parse_kernel(&args[2 ..])?, edge)
+        },
+        "median" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let channels = parse_channels(&args[1])?;
+            Command::Median(op, channels, parse_window(&args[2], &args[3])?)
+        },
+        "morph"  => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let channels = parse_channels(&args[1])?;
+            let morphology = match args[2].to_lowercase().as_str() {
+                "erode" | "min"  => Morphology::Erode,
+                "dilate" | "max" => Morphology::Dilate,
+                "open"           => Morphology::Open,
+                "close"          => Morphology::Close,
+                "gradient"       => Morphology::Gradient,
+                _ => return Err(format!("invalid KIND: {}", args[2])),
+            };
+            Command::Morphology(op, channels, morphology, parse_window(&args[3], &args[4])?)
+        },
+        "threshold" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            Command::Threshold(op, parse_threshold(&args[1 ..])?)
+        },
+        "quantize" => {
+            let op = parse_operation_index(&args[0], operations_count)?;
+            let dither = parse_dither(&args[1])?;
+            Command::Quantize(op, parse_color_palette(&args[2 ..])?, dither)
+        },
+        _ => unreachable!(),
+    };
+
+    Ok(command)
+}
+
+// NOTE(erick): Operation numbers in a pipeline file count from the first
+// line of the file. Sourcing it into a session that already has
+// operations moves them past those.
+pub fn offset_operations(command: Command, first_operation: usize) -> Command {
+    let offset = |op: usize| op + first_operation;
+    match command {
+        Command::Save(op, path, options)       => Command::Save(offset(op), path, options),
+        Command::Merge(op0, op1, direction)
+            => Command::Merge(offset(op0), offset(op1), direction),
+        Command::Crop(op, x0, y0, w, h)        => Command::Crop(offset(op), x0, y0, w, h),
+        Command::Animate(path, loops, frames)  => {
+            let frames = frames.into_iter().map(|(op, delay)| (offset(op), delay)).collect();
+            Command::Animate(path, loops, frames)
+        },
+        Command::Icon(path, ops)               => {
+            Command::Icon(path, ops.into_iter().map(offset).collect())
+        },
+        Command::Mix(op, mixer)                => Command::Mix(offset(op), mixer),
+        Command::Adjust(op, channels, adjustment)
+            => Command::Adjust(offset(op), channels, adjustment),
+        Command::Levels(op, channels, levels)  => Command::Levels(offset(op), channels, levels),
+        Command::Curves(op, channels, points)  => Command::Curves(offset(op), channels, points),
+        Command::Convolve(op, kernel, edge)    => Command::Convolve(offset(op), kernel, edge),
+        Command::Median(op, channels, window)  => Command::Median(offset(op), channels, window),
+        Command::Morphology(op, channels, morphology, window)
+            => Command::Morphology(offset(op), channels, morphology, window),
+        Command::Threshold(op, threshold)      => Command::Threshold(offset(op), threshold),
+        Command::Quantize(op, palette, dither) => Command::Quantize(offset(op), palette, dither),
+        other                                  => other,
+    }
+}
+
+fn parse_operation_index(token: &str, operations_count: usize) -> Result<usize, String> {
+    let index = parse_number::<usize>(token, "OP")?;
+    if index == 0 || index > operations_count {
+        return Err(format!("no operation {}", index));
+    }
+
+    Ok(index - 1)
+}
+
+// NOTE(erick): OP or OP:MS, frames without a delay last 100ms.
+pub const DEFAULT_FRAME_DELAY : u32 = 100;
+
+fn parse_animation_frame(token: &str, operations_count: usize) -> Result<(usize, u32), String> {
+    let colon_index = token.find(':');
+    if colon_index.is_none() {
+        return Ok((parse_operation_index(token, operations_count)?, DEFAULT_FRAME_DELAY));
+    }
+
+    let colon_index = colon_index.unwrap();
+    let op = parse_operation_index(&token[.. colon_index], operations_count)?;
+    let delay = parse_number::<u32>(&token[colon_index + 1 ..], "MS")?;
+    Ok((op, delay))
+}
+
+// NOTE(erick): A preset name or the coefficients of the matrix, row by
+// row. Nine of them leave alpha alone.
+pub fn parse_mixer(tokens: &[String]) -> Result<Mixer, String> {
+    if tokens.len() == 1 {
+        return match tokens[0].to_lowercase().as_str() {
+            "601" | "rec601" | "rec.601" => Ok(Mixer::Rec601),
+            "709" | "rec709" | "rec.709" => Ok(Mixer::Rec709),
+            "avg" | "average"            => Ok(Mixer::Average),
+            "desat" | "desaturate"       => Ok(Mixer::Desaturate),
+            _ => Err(format!("invalid preset: {}", tokens[0])),
+        };
+    }
+
+    parse_matrix(tokens)
+}
+
+pub fn parse_matrix(tokens: &[String]) -> Result<Mixer, String> {
+    let size = match tokens.len() {
+        9  => 3,
+        16 => 4,
+        _  => return Err("the matrix needs 9 or 16 coefficients".to_string()),
+    };
+
+    let mut matrix = IDENTITY_MATRIX;
+    for (index, token) in tokens.iter().enumerate() {
+        let coefficient = parse_number::<f64>(token, "COEFFICIENT")?;
+        if !coefficient.is_finite() {
+            return Err(format!("invalid COEFFICIENT: {}", token));
+        }
+        matrix[index / size][index % size] = coefficient;
+    }
+
+    Ok(Mixer::Matrix(matrix))
+}
+
+// NOTE(erick): The inverse of parse_mixer, without the alpha row and
+// column when they don't change anything.
+pub fn mixer_arguments(mixer: &Mixer) -> String {
+    let matrix = match *mixer {
+        Mixer::Rec601         => return "601".to_string(),
+        Mixer::Rec709         => return "709".to_string(),
+        Mixer::Average        => return "avg".to_string(),
+        Mixer::Desaturate     => return "desat".to_string(),
+        Mixer::Matrix(matrix) => matrix,
+    };
+
+    let keeps_alpha = matrix[3] == IDENTITY_MATRIX[3] &&
+        matrix.iter().take(3).all(|row| row[3] == 0.0);
+    let size = if keeps_alpha { 3 } else { 4 };
+    matrix.iter().take(size)
+        .flat_map(|row| row.iter().take(size))
+        .map(|coefficient| coefficient.to_string())
+        .collect::<Vec<_> >()
+        .join(" ")
+}
+
+pub fn parse_channels(token: &str) -> Result<Channels, String> {
+    match token.to_lowercase().as_str() {
+        "rgb" | "all" => Ok(Channels::All),
+        "r" | "red"   => Ok(Channels::Red),
+        "g" | "green" => Ok(Channels::Green),
+        "b" | "blue"  => Ok(Channels::Blue),
+        "a" | "alpha" => Ok(Channels::Alpha),
+        _ => Err(format!("invalid channels: {}", token)),
+    }
+}
+
+fn parse_curve_point(token: &str) -> Result<(u8, u8), String> {
+    let colon_index = token.find(':');
+    if colon_index.is_none() {
+        return Err(format!("invalid point: {}", token));
+    }
+
+    let colon_index = colon_index.unwrap();
+    let input = parse_number::<u8>(&token[.. colon_index], "IN")?;
+    let output = parse_number::<u8>(&token[colon_index + 1 ..], "OUT")?;
+    Ok((input, output))
+}
+
+pub fn parse_edge_mode(token: &str) -> Result<EdgeMode, String> {
+    match token.to_lowercase().as_str() {
+        "clamp"              => Ok(EdgeMode::Clamp),
+        "wrap"               => Ok(EdgeMode::Wrap),
+        "mirror"             => Ok(EdgeMode::Mirror),
+        "constant" | "const" => Ok(EdgeMode::Constant),
+        _ => Err(format!("invalid edge mode: {}", token)),
+    }
+}
+
+pub const MAX_BOX_RADIUS : u32 = 100;
+pub const MIN_RADIUS : f64 = 0.1;
+pub const MAX_RADIUS : f64 = 100.0;
+pub const MAX_AMOUNT : f64 = 10.0;
+pub const MIN_KERNEL_SIZE : usize = 3;
+pub const MAX_KERNEL_SIZE : usize = 15;
+
+// NOTE(erick): A preset name followed by its parameters: box RADIUS,
+// gauss RADIUS, unsharp RADIUS AMOUNT or kernel DIVISOR BIAS WEIGHT...
+pub fn parse_kernel(tokens: &[String]) -> Result<Kernel, String> {
+    let name = tokens[0].to_lowercase();
+    let args = &tokens[1 ..];
+    let expected_args = match name.as_str() {
+        "box" | "gauss" | "gaussian" => 1,
+        "unsharp"                    => 2,
+        "kernel"                     => 3,
+        _                            => 0,
+    };
+    if args.len() < expected_args || (name != "kernel" && args.len() > expected_args) {
+        return Err(usage("convolve"));
+    }
+
+    let kernel = match name.as_str() {
+        "box"                => {
+            let radius = parse_number::<u32>(&args[0], "RADIUS")?;
+            if !(1 ..= MAX_BOX_RADIUS).contains(&radius) {
+                return Err(format!("RADIUS goes from 1 to {}", MAX_BOX_RADIUS));
+            }
+            Kernel::BoxBlur(radius)
+        },
+        "gauss" | "gaussian" => {
+            Kernel::Gaussian(parse_in_range(&args[0], "RADIUS", MIN_RADIUS, MAX_RADIUS)?)
+        },
+        "sharpen"            => Kernel::Sharpen,
+        "unsharp"            => {
+            let radius = parse_in_range(&args[0], "RADIUS", MIN_RADIUS, MAX_RADIUS)?;
+            let amount = parse_in_range(&args[1], "AMOUNT", 0.0, MAX_AMOUNT)?;
+            Kernel::UnsharpMask(radius, amount)
+        },
+        "emboss"             => Kernel::Emboss,
+        "sobel"              => Kernel::Sobel,
+        "prewitt"            => Kernel::Prewitt,
+        "laplacian"          => Kernel::Laplacian,
+        "kernel"             => {
+            let divisor = parse_finite(&args[0], "DIVISOR")?;
+            let bias = parse_finite(&args[1], "BIAS")?;
+            Kernel::Custom(parse_kernel_weights(&args[2 ..])?, divisor, bias)
+        },
+        _ => return Err(format!("invalid kernel: {}", tokens[0])),
+    };
+
+    Ok(kernel)
+}
+
+// NOTE(erick): The weights of a square kernel with an odd size, row by
+// row.
+pub fn parse_kernel_weights(tokens: &[String]) -> Result<Vec<f64>, String> {
+    let size = (tokens.len() as f64).sqrt() as usize;
+    if size * size != tokens.len() || size.is_multiple_of(2) ||
+        !(MIN_KERNEL_SIZE ..= MAX_KERNEL_SIZE).contains(&size) {
+        return Err(format!("the kernel needs 9, 25, 49... up to {} weights",
+                           MAX_KERNEL_SIZE * MAX_KERNEL_SIZE));
+    }
+
+    let mut weights = Vec::with_capacity(tokens.len());
+    for token in tokens.iter() {
+        weights.push(parse_finite(token, "WEIGHT")?);
+    }
+
+    Ok(weights)
+}
+
+fn kernel_arguments(kernel: &Kernel) -> String {
+    match *kernel {
+        Kernel::BoxBlur(radius)             => format!("box {}", radius),
+        Kernel::Gaussian(radius)            => format!("gauss {}", radius),
+        Kernel::Sharpen                     => "sharpen".to_string(),
+        Kernel::UnsharpMask(radius, amount) => format!("unsharp {} {}", radius, amount),
+        Kernel::Emboss                      => "emboss".to_string(),
+        Kernel::Sobel                       => "sobel".to_string(),
+        Kernel::Prewitt                     => "prewitt".to_string(),
+        Kernel::Laplacian                   => "laplacian".to_string(),
+        Kernel::Custom(ref weights, divisor, bias) => {
+            let weights = weights.iter().map(|weight| weight.to_string()).collect::<Vec<_> >();
+            format!("kernel {} {} {}", divisor, bias, weights.join(" "))
+        },
+    }
+}
+
+fn edge_argument(edge: EdgeMode) -> &'static str {
+    match edge {
+        EdgeMode::Clamp    => "clamp",
+        EdgeMode::Wrap     => "wrap",
+        EdgeMode::Mirror   => "mirror",
+        EdgeMode::Constant => "constant",
+    }
+}
+
+pub const MAX_WINDOW_RADIUS : u32 = 50;
+
+fn parse_window(shape: &str, radius: &str) -> Result<Window, String> {
+    let shape = match shape.to_lowercase().as_str() {
+        "square" => Shape::Square,
+        "cross"  => Shape::Cross,
+        "disk"   => Shape::Disk,
+        _ => return Err(format!("invalid SHAPE: {}", shape)),
+    };
+
+    let radius = parse_number::<u32>(radius, "RADIUS")?;
+    if !(1 ..= MAX_WINDOW_RADIUS).contains(&radius) {
+        return Err(format!("RADIUS goes from 1 to {}", MAX_WINDOW_RADIUS));
+    }
+
+    Ok(Window { shape, radius })
+}
+
+fn window_arguments(window: &Window) -> String {
+    let shape = match window.shape {
+        Shape::Square => "square",
+        Shape::Cross  => "cross",
+        Shape::Disk   => "disk",
+    };
+    format!("{} {}", shape, window.radius)
+}
+
+pub const MAX_BLOCK_SIZE : u32 = 201;
+pub const MAX_OFFSET : f64 = 255.0;
+
+// NOTE(erick): A level for a fixed threshold, otsu, or mean and gauss
+// followed by the odd window size and the offset.
+fn parse_threshold(tokens: &[String]) -> Result<Threshold, String> {
+    let name = tokens[0].to_lowercase();
+    let is_adaptive = name == "mean" || name == "gauss" || name == "gaussian";
+    if tokens.len() != if is_adaptive { 3 } else { 1 } {
+        return Err(usage("threshold"));
+    }
+
+    if !is_adaptive {
+        if name == "otsu" {
+            return Ok(Threshold::Otsu(None));
+        }
+        return Ok(Threshold::Fixed(parse_number::<u8>(&tokens[0], "LEVEL")?));
+    }
+
+    let size = parse_number::<u32>(&tokens[1], "SIZE")?;
+    if !(3 ..= MAX_BLOCK_SIZE).contains(&size) || size.is_multiple_of(2) {
+        return Err(format!("SIZE is odd and goes from 3 to {}", MAX_BLOCK_SIZE));
+    }
+    let offset = parse_in_range(&tokens[2], "OFFSET", -MAX_OFFSET, MAX_OFFSET)?;
+
+    if name == "mean" {
+        Ok(Threshold::Mean(size, offset))
+    } else {
+        Ok(Threshold::Gaussian(size, offset))
+    }
+}
+
+fn threshold_arguments(threshold: &Threshold) -> String {
+    match *threshold {
+        Threshold::Fixed(level)           => level.to_string(),
+        Threshold::Otsu(_)                => "otsu".to_string(),
+        Threshold::Mean(size, offset)     => format!("mean {} {}", size, offset),
+        Threshold::Gaussian(size, offset) => format!("gauss {} {}", size, offset),
+    }
+}
+
+pub const MIN_COLORS : u32 = 2;
+pub const MAX_COLORS : u32 = 256;
+
+fn parse_dither(token: &str) -> Result<Dither, String> {
+    match token.to_lowercase().as_str() {
+        "none"         => Ok(Dither::None),
+        "bayer2"       => Ok(Dither::Bayer(2)),
+        "bayer4"       => Ok(Dither::Bayer(4)),
+        "bayer8"       => Ok(Dither::Bayer(8)),
+        "floyd" | "fs" => Ok(Dither::FloydSteinberg),
+        "atkinson"     => Ok(Dither::Atkinson),
+        "sierra"       => Ok(Dither::Sierra),
+        _ => Err(format!("invalid dither: {} (none, bayer2, bayer4, bayer8, floyd, \
+                          atkinson, sierra)", token)),
+    }
+}
+
+fn dither_argument(dither: Dither) -> String {
+    match dither {
+        Dither::None           => "none".to_string(),
+        Dither::Bayer(size)    => format!("bayer{}", size),
+        Dither::FloydSteinberg => "floyd".to_string(),
+        Dither::Atkinson       => "atkinson".to_string(),
+        Dither::Sierra         => "sierra".to_string(),
+    }
+}
+
+// NOTE(erick): median, octree and gray take the number of colors, file
+// the path of a GPL, ACT or JASC palette.
+fn parse_color_palette(tokens: &[String]) -> Result<ColorPalette, String> {
+    let name = tokens[0].to_lowercase();
+    let takes_argument = name != "web";
+    if tokens.len() != if takes_argument { 2 } else { 1 } {
+        return Err(usage("quantize"));
+    }
+
+    if name == "file" {
+        return Ok(ColorPalette::File(PathBuf::from(expand_path(&tokens[1]))));
+    }
+
+    let count = if takes_argument { parse_color_count(&tokens[1])? } else { 0 };
+    match name.as_str() {
+        "median" => Ok(ColorPalette::MedianCut(count)),
+        "octree" => Ok(ColorPalette::Octree(count)),
+        "web"    => Ok(ColorPalette::WebSafe),
+        "gray"   => Ok(ColorPalette::Gray(count)),
+        _ => Err(format!("invalid palette: {} (median, octree, web, gray, file)", tokens[0])),
+    }
+}
+
+fn parse_color_count(token: &str) -> Result<u32, String> {
+    let count = parse_number::<u32>(token, "COUNT")?;
+    if !(MIN_COLORS ..= MAX_COLORS).contains(&count) {
+        return Err(format!("COUNT goes from {} to {}", MIN_COLORS, MAX_COLORS));
+    }
+
+    Ok(count)
+}
+
+fn color_palette_arguments(palette: &ColorPalette) -> String {
+    match palette {
+        &ColorPalette::MedianCut(count) => format!("median {}", count),
+        &ColorPalette::Octree(count)    => format!("octree {}", count),
+        &ColorPalette::WebSafe          => "web".to_string(),
+        &ColorPalette::Gray(count)      => format!("gray {}", count),
+        ColorPalette::File(path)   => {
+            format!("file {}", quote_argument(path.to_string_lossy().as_ref()))
+        },
+    }
+}
+
+pub const PALETTE_BITS : [u32; 3] = [1, 4, 8];
+
+fn parse_palette_bits(token: &str) -> Result<u32, String> {
+    let bits = parse_number::<u32>(token, "BITS")?;
+    if !PALETTE_BITS.contains(&bits) {
+        let choices = PALETTE_BITS.iter().map(|bits| bits.to_string()).collect::<Vec<_> >();
+        return Err(format!("BITS can be {}", choices.join(", ")));
+    }
+
+    Ok(bits)
+}
+
+pub const MIN_PERCENT : f64 = -100.0;
+pub const MAX_PERCENT : f64 = 100.0;
+pub const MIN_GAMMA : f64 = 0.1;
+pub const MAX_GAMMA : f64 = 10.0;
+
+fn parse_in_range(token: &str, name: &str, min: f64, max: f64) -> Result<f64, String> {
+    let value = parse_number::<f64>(token, name)?;
+    if !(min ..= max).contains(&value) {
+        return Err(format!("{} goes from {} to {}", name, min, max));
+    }
+
+    Ok(value)
+}
+
+pub const MIN_QUALITY : u32 = 1;
+pub const MAX_QUALITY : u32 = 100;
+
+fn parse_quality(token: &str) -> Result<u32, String> {
+    let quality = parse_number::<u32>(token, "QUALITY")?;
+    if !(MIN_QUALITY ..= MAX_QUALITY).contains(&quality) {
+        return Err(format!("quality goes from {} to {}", MIN_QUALITY, MAX_QUALITY));
+    }
+
+    Ok(quality)
+}
+
+fn parse_finite(token: &str, name: &str) -> Result<f64, String> {
+    let value = parse_number::<f64>(token, name)?;
+    if !value.is_finite() {
+        return Err(format!("invalid {}: {}", name, token));
+    }
+
+    Ok(value)
+}
+
+fn parse_number<T: ::std::str::FromStr>(token: &str, name: &str) -> Result<T, String> {
+    token.parse::<T>().map_err(|_| format!("invalid {}: {}", name, token))
+}
+
+// NOTE(erick): Splits on whitespace. Double quotes group words and
+// a backslash escapes the next character, so paths with spaces can
+// be typed (and written to pipeline files).
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                match chars.next() {
+                    Some(escaped) => current.push(escaped),
+                    None          => return Err("trailing backslash".to_string()),
+                }
+                in_token = true;
+            },
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    tokens.push(current.clone());
+                    current.clear();
+                    in_token = false;
+                }
+            },
+            c => {
+                current.push(c);
+                in_token = true;
+            },
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quote".to_string());
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+pub fn quote_argument(argument: &str) -> String {
+    let needs_quotes = argument.is_empty() ||
+        argument.chars().any(|c| c.is_whitespace() || c == '"' || c == '\\');
+    if !needs_quotes {
+        return argument.to_string();
+    }
+
+    let mut result = String::from("\"");
+    for c in argument.chars() {
+        if c == '"' || c == '\\' {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result.push('"');
+
+    result
+}
+
+// NOTE(erick): The inverse of parse_command. Used when writing pipeline files.
+pub fn operation_to_command(operation: &Operation, opened_files: &[PathBuf]) -> String {
+    let path_argument = |index: usize| {
+        quote_argument(opened_files[index].to_string_lossy().as_ref())
+    };
+
+    match *operation {
+        Operation::Open(file)
+            => format!("open {}", path_argument(file)),
+        Operation::Save(op, file, ref options) => {
+            let format = codec::format_from_extension(&opened_files[file]);
+            let palette_bits = options.palette_bits.filter(|_| format == Some(Format::Bmp));
+            if let Some(bits) = palette_bits {
+                return format!("save {} {} {}", op + 1, path_argument(file), bits);
+            }
+            if codec::is_netpbm(format) {
+                let mut command = format!("save {} {}", op + 1, path_argument(file));
+                if let Some(plain) = options.plain {
+                    command.push_str(if plain { " plain" } else { " raw" });
+                }
+                if options.sample_bits != 8 {
+                    command.push_str(format!(" {}", options.sample_bits).as_str());
+                }
+                return command;
+            }
+            if format != Some(Format::Jpeg) {
+                return format!("save {} {}", op + 1, path_argument(file));
+            }
+            let subsampling = match options.subsampling {
+                Subsampling::Chroma420 => "420",
+                Subsampling::Chroma444 => "444",
+            };
+            format!("save {} {} {} {}", op + 1, path_argument(file), options.quality, subsampling)
+        },
+        Operation::Merge(op0, op1, ref direction) => {
+            let direction = match *direction {
+                Direction::Horizontal => "h",
+                Direction::Vertical   => "v",
+            };
+            format!("merge {} {} {}", op0 + 1, op1 + 1, direction)
+        },
+        Operation::Crop(op, x0, y0, w, h)
+            => format!("crop {} {} {} {} {}", op + 1, x0, y0, w, h),
+        Operation::Frame(file, frame)
+            => format!("frame {} {}", path_argument(file), frame + 1),
+        Operation::Animate(ref frames, loops, file) => {
+            let frames = frames.iter()
+                .map(|&(op, delay)| format!("{}:{}", op + 1, delay))
+                .collect::<Vec<_> >();
+            format!("animate {} {} {}", path_argument(file), loops, frames.join(" "))
+        },
+        Operation::SaveIcon(ref ops, file) => {
+            let ops = ops.iter().map(|op| (op + 1).to_string()).collect::<Vec<_> >();
+            format!("icon {} {}", path_argument(file), ops.join(" "))
+        },
+        Operation::Mix(op, ref mixer)
+            => format!("mix {} {}", op + 1, mixer_arguments(mixer)),
+        Operation::Adjust(op, channels, ref adjustment)
+            => format!("adjust {} {} {} {} {}", op + 1, channels_argument(channels),
+                       adjustment.brightness, adjustment.contrast, adjustment.gamma),
+        Operation::Levels(op, channels, ref levels)
+            => format!("levels {} {} {} {} {} {} {}", op + 1, channels_argument(channels),
+                       levels.input_black, levels.input_white, levels.midtone,
+                       levels.output_black, levels.output_white),
+        Operation::Curves(op, channels, ref points)
+            => format!("curves {} {} {}", op + 1, channels_argument(channels),
+                       points_string(points)),
+        Operation::Convolve(op, ref kernel, edge)
+            => format!("convolve {} {} {}", op + 1, edge_argument(edge), kernel_arguments(kernel)),
+        Operation::Median(op, channels, ref window)
+            => format!("median {} {} {}", op + 1, channels_argument(channels),
+                       window_arguments(window)),
+        Operation::Morphology(op, channels, morphology, ref window) => {
+            let kind = match morphology {
+                Morphology::Erode    => "erode",
+                Morphology::Dilate   => "dilate",
+                Morphology::Open     => "open",
+                Morphology::Close    => "close",
+                Morphology::Gradient => "gradient",
+            };
+            format!("morph {} {} {} {}", op + 1, channels_argument(channels), kind,
+                    window_arguments(window))
+        },
+        Operation::Threshold(op, ref threshold)
+            => format!("threshold {} {}", op + 1, threshold_arguments(threshold)),
+        Operation::Quantize(op, ref palette, dither)
+            => format!("quantize {} {} {}", op + 1, dither_argument(dither),
+                       color_palette_arguments(palette)),
+    }
+}
+
+fn channels_argument(channels: Channels) -> &'static str {
+    match channels {
+        Channels::All   => "rgb",
+        Channels::Red   => "r",
+        Channels::Green => "g",
+        Channels::Blue  => "b",
+        Channels::Alpha => "a",
+    }
+}
+
+pub fn matching_command_names(prefix: &str) -> Vec<String> {
+    COMMANDS.iter()
+        .filter(|info| info.name.starts_with(prefix))
+        .map(|info| info.name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save_options(line: &str) -> Result<SaveOptions, String> {
+        match parse_command(line, 2)? {
+            Command::Save(_, _, options) => Ok(options),
+            _                            => panic!("not a save command"),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("  open  a.png ").unwrap(), vec!["open", "a.png"]);
+        assert_eq!(tokenize("open \"my file.png\"").unwrap(), vec!["open", "my file.png"]);
+        assert_eq!(tokenize("open my\\ file.png").unwrap(), vec!["open", "my file.png"]);
+        assert_eq!(tokenize("w \"\"").unwrap(), vec!["w", ""]);
+        assert!(tokenize("").unwrap().is_empty());
+        assert!(tokenize("open \"a.png").is_err());
+        assert!(tokenize("open a.png\\").is_err());
+
+        let argument = "a \"quoted\" \\ name";
+        assert_eq!(tokenize(quote_argument(argument).as_str()).unwrap(), vec![argument]);
+    }
+
+    #[test]
+    fn commands() {
+        match parse_command("crop 2 1 2 -3 4", 2).unwrap() {
+            Command::Crop(op, x0, y0, width, height) => {
+                assert_eq!((op, x0, y0, width, height), (1, 1, 2, -3, 4));
+            },
+            _ => panic!("not a crop command"),
+        }
+        match parse_command("merge 1 2 v", 2).unwrap() {
+            Command::Merge(0, 1, Direction::Vertical) => { },
+            _ => panic!("not a vertical merge"),
+        }
+
+        assert!(parse_command("", 2).is_err());
+        assert!(parse_command("fly 1", 2).is_err());
+        assert!(parse_command("merge 1 3 h", 2).is_err());
+        assert!(parse_command("merge 0 1 h", 2).is_err());
+        assert!(parse_command("merge 1 2 diagonal", 2).is_err());
+        assert!(parse_command("crop 1 0 0 10", 2).is_err());
+    }
+
+    #[test]
+    fn save() {
+        let options = save_options("save 1 a.jpg 75 444").unwrap();
+        assert_eq!(options.quality, 75);
+        assert!(options.subsampling == Subsampling::Chroma444);
+
+        let options = save_options("save 1 a.bmp 4").unwrap();
+        assert_eq!(options.palette_bits, Some(4));
+
+        let options = save_options("save 1 a.pgm 16 plain").unwrap();
+        assert_eq!((options.plain, options.sample_bits), (Some(true), 16));
+
+        assert!(save_options("save 1 a.jpg 0").is_err());
+        assert!(save_options("save 1 a.jpg 75 422").is_err());
+        assert!(save_options("save 1 a.png 75").is_err());
+        assert!(save_options("save 1 a.tga 75 444").is_err());
+        assert!(save_options("save 1 a.png 444").is_err());
+        assert!(save_options("save 1 a.bmp 4 444").is_err());
+        assert!(save_options("save 1 a.pbm 16").is_err());
+        assert!(save_options("save 1 a.pam plain").is_err());
+    }
+}
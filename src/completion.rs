@@ -0,0 +1,196 @@
+use std::env;
+use std::sync::OnceLock;
+
+use config::Section;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    // NOTE(erick): Insensitive unless the typed text has an uppercase letter.
+    Smart,
+}
+
+pub struct CompletionOptions {
+    pub case_mode: CaseMode,
+    pub fuzzy: bool,
+}
+
+impl CompletionOptions {
+    pub fn default() -> CompletionOptions {
+        CompletionOptions { case_mode: CaseMode::Smart, fuzzy: true }
+    }
+
+    // NOTE(erick): [completion]
+    //              case = smart | sensitive | insensitive
+    //              fuzzy = yes | no
+    pub fn from_section(section: Option<&Section>) -> Result<CompletionOptions, String> {
+        let mut result = CompletionOptions::default();
+        if section.is_none() {
+            return Ok(result);
+        }
+
+        for entry in section.unwrap().entries.iter() {
+            match (entry.name.as_str(), entry.value.as_str()) {
+                ("case", "smart")       => { result.case_mode = CaseMode::Smart; },
+                ("case", "sensitive")   => { result.case_mode = CaseMode::Sensitive; },
+                ("case", "insensitive") => { result.case_mode = CaseMode::Insensitive; },
+                ("fuzzy", "yes")        => { result.fuzzy = true; },
+                ("fuzzy", "no")         => { result.fuzzy = false; },
+                ("case", _) | ("fuzzy", _) => {
+                    return Err(format!("{}: invalid value '{}' for '{}'",
+                                       entry.line, entry.value, entry.name));
+                },
+                _ => return Err(format!("{}: unknown option '{}'", entry.line, entry.name)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+static COMPLETION_OPTIONS : OnceLock<CompletionOptions> = OnceLock::new();
+
+pub fn set_options(options: CompletionOptions) {
+    let _ = COMPLETION_OPTIONS.set(options);
+}
+
+pub fn options() -> &'static CompletionOptions {
+    COMPLETION_OPTIONS.get_or_init(CompletionOptions::default)
+}
+
+// NOTE(erick): Higher is better, None means the candidate doesn't match.
+// Prefix matches always rank above fuzzy ones, and fuzzy matches are
+// rewarded for consecutive characters and for starting words.
+pub fn match_score(candidate: &str, pattern: &str) -> Option<i64> {
+    let options = options();
+    let case_sensitive = match options.case_mode {
+        CaseMode::Sensitive   => true,
+        CaseMode::Insensitive => false,
+        CaseMode::Smart       => pattern.chars().any(|c| c.is_uppercase()),
+    };
+
+    let fold = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let candidate_chars = candidate.chars().map(fold).collect::<Vec<_> >();
+    let pattern_chars = pattern.chars().map(fold).collect::<Vec<_> >();
+
+    // NOTE(erick): Shorter candidates win ties, so 'a.bmp' comes before 'a.bmp.bak'.
+    let length_penalty = candidate_chars.len() as i64;
+
+    if candidate_chars.starts_with(&pattern_chars) {
+        return Some(1_000_000 - length_penalty);
+    }
+
+    if !options.fuzzy {
+        return None;
+    }
+
+    let mut score = 0;
+    let mut candidate_index = 0;
+    let mut previous_match: Option<usize> = None;
+    for pattern_char in pattern_chars.iter() {
+        let mut found = None;
+        while candidate_index < candidate_chars.len() {
+            if candidate_chars[candidate_index] == *pattern_char {
+                found = Some(candidate_index);
+                candidate_index += 1;
+                break;
+            }
+            candidate_index += 1;
+        }
+
+        let index = found?;
+        score += 10;
+        if previous_match.is_some() && previous_match.unwrap() + 1 == index {
+            score += 15;
+        } else if previous_match.is_some() {
+            score -= (index - previous_match.unwrap()) as i64;
+        }
+
+        let starts_word = index == 0 || "_-. /".contains(candidate_chars[index - 1]);
+        if starts_word {
+            score += 10;
+        }
+
+        previous_match = Some(index);
+    }
+
+    Some(score * 1000 - length_penalty)
+}
+
+// NOTE(erick): Expands a leading '~' and $VARIABLE or ${VARIABLE}
+// anywhere in the path. Unknown variables are left untouched.
+pub fn expand_path(path: &str) -> String {
+    let mut result = String::new();
+    let mut rest = path;
+
+    if rest == "~" || rest.starts_with("~/") {
+        if let Ok(home) = env::var("HOME") {
+            result.push_str(home.as_str());
+            rest = &rest[1 ..];
+        }
+    }
+
+    let chars = rest.chars().collect::<Vec<_> >();
+    let mut index = 0;
+    while index < chars.len() {
+        if chars[index] != '$' {
+            result.push(chars[index]);
+            index += 1;
+            continue;
+        }
+
+        let braced = index + 1 < chars.len() && chars[index + 1] == '{';
+        let name_start = if braced { index + 2 } else { index + 1 };
+        let mut name_end = name_start;
+        while name_end < chars.len() &&
+            (chars[name_end].is_ascii_alphanumeric() || chars[name_end] == '_') {
+            name_end += 1;
+        }
+
+        let closed = !braced || (name_end < chars.len() && chars[name_end] == '}');
+        let name = chars[name_start .. name_end].iter().collect::<String>();
+        let value = if !name.is_empty() && closed { env::var(&name).ok() } else { None };
+
+        if let Some(value) = value {
+            result.push_str(value.as_str());
+            index = if braced { name_end + 1 } else { name_end };
+        } else {
+            result.push('$');
+            index += 1;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores() {
+        assert!(match_score("a.bmp", "a.b").unwrap() > match_score("a.bmp.bak", "a.b").unwrap());
+        assert!(match_score("blur.png", "bl").unwrap() > match_score("a_blur.png", "bl").unwrap());
+        assert!(match_score("a_blur.png", "bl").unwrap() > match_score("table.png", "bl").unwrap());
+        assert!(match_score("Photo.png", "ph").is_some());
+        assert!(match_score("photo.png", "Ph").is_none());
+        assert!(match_score("photo.png", "gif").is_none());
+        assert!(match_score("photo.png", "").is_some());
+    }
+
+    #[test]
+    fn expansion() {
+        env::set_var("CLIMP_EXPAND_TEST", "/tmp/climp");
+        let home = env::var("HOME").unwrap();
+
+        assert_eq!(expand_path("~"), home);
+        assert_eq!(expand_path("~/a.png"), format!("{}/a.png", home));
+        assert_eq!(expand_path("a~/b"), "a~/b");
+        assert_eq!(expand_path("$CLIMP_EXPAND_TEST/a.png"), "/tmp/climp/a.png");
+        assert_eq!(expand_path("${CLIMP_EXPAND_TEST}a.png"), "/tmp/climpa.png");
+        assert_eq!(expand_path("${CLIMP_EXPAND_TEST/a.png"), "${CLIMP_EXPAND_TEST/a.png");
+        assert_eq!(expand_path("$CLIMP_UNSET_TEST/a"), "$CLIMP_UNSET_TEST/a");
+        assert_eq!(expand_path("cost$"), "cost$");
+    }
+}
@@ -0,0 +1,165 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+// NOTE(erick): The config file is a list of sections with 'name = value'
+// entries:
+//
+//     # Comments start with '#'
+//     [keys]
+//     quit = q, C-q
+//     cancel = Esc
+//
+// Each module interprets its own sections, this file only knows
+// where the file lives and how to split it.
+pub struct Entry {
+    pub name: String,
+    pub value: String,
+    pub line: usize,
+}
+
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+pub struct Config {
+    pub sections: Vec<Section>,
+}
+
+impl Config {
+    pub fn empty() -> Config {
+        Config { sections: Vec::new() }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+}
+
+// NOTE(erick): $XDG_CONFIG_HOME/climp, falling back to ~/.config/climp
+// as the XDG spec says.
+pub fn config_directory() -> Option<PathBuf> {
+    xdg_directory("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn xdg_directory(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+
+    let xdg_home = env::var(variable).ok().filter(|xdg_home| !xdg_home.is_empty());
+    if let Some(xdg_home) = xdg_home {
+        path.push(xdg_home);
+    } else {
+        let home = env::var("HOME");
+        if home.is_err() {
+            return None;
+        }
+        path.push(home.unwrap());
+        path.push(fallback);
+    }
+
+    path.push("climp");
+    Some(path)
+}
+
+pub fn config_file_path() -> Option<PathBuf> {
+    config_directory().map(|mut path| { path.push("config"); path })
+}
+
+// NOTE(erick): A missing config file is not an error, we just use
+// the built-in defaults.
+pub fn load() -> Result<Config, String> {
+    let path = config_file_path();
+    if path.is_none() {
+        return Ok(Config::empty());
+    }
+
+    let path = path.unwrap();
+    let file = File::open(&path);
+    if file.is_err() {
+        return Ok(Config::empty());
+    }
+
+    let mut contents = String::new();
+    if file.unwrap().read_to_string(&mut contents).is_err() {
+        return Err("cannot read file".to_string());
+    }
+
+    parse(contents.as_str())
+}
+
+pub fn parse(contents: &str) -> Result<Config, String> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    let mut line_number = 0;
+    for line in contents.lines() {
+        line_number += 1;
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                return Err(format!("{}: unterminated section header", line_number));
+            }
+
+            let name = line[1 .. line.len() - 1].split_whitespace()
+                .collect::<Vec<_> >().join(" ");
+            if name.is_empty() {
+                return Err(format!("{}: empty section name", line_number));
+            }
+
+            sections.push(Section { name, entries: Vec::new() });
+            continue;
+        }
+
+        let equals_index = line.find('=');
+        if equals_index.is_none() {
+            return Err(format!("{}: expected 'name = value'", line_number));
+        }
+
+        if sections.is_empty() {
+            return Err(format!("{}: entry outside of a section", line_number));
+        }
+
+        let equals_index = equals_index.unwrap();
+        let entry = Entry {
+            name: line[0 .. equals_index].trim().to_string(),
+            value: line[equals_index + 1 ..].trim().to_string(),
+            line: line_number,
+        };
+
+        sections.last_mut().unwrap().entries.push(entry);
+    }
+
+    Ok(Config { sections })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_and_entries() {
+        let config = parse("# comment\n\n[ keys ]\nquit = q\n  open =  o, C-o  \n[ui]\n").unwrap();
+        assert_eq!(config.sections.len(), 2);
+
+        let keys = config.section("keys").unwrap();
+        assert_eq!(keys.entries.len(), 2);
+        assert_eq!(keys.entries[1].name, "open");
+        assert_eq!(keys.entries[1].value, "o, C-o");
+        assert_eq!(keys.entries[1].line, 5);
+
+        assert!(config.section("ui").unwrap().entries.is_empty());
+        assert!(config.section("tiff").is_none());
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert_eq!(parse("[keys\n").err().unwrap(), "1: unterminated section header");
+        assert_eq!(parse("[ ]\n").err().unwrap(), "1: empty section name");
+        assert_eq!(parse("[keys]\nquit\n").err().unwrap(), "2: expected 'name = value'");
+        assert_eq!(parse("quit = q\n").err().unwrap(), "1: entry outside of a section");
+    }
+}
@@ -0,0 +1,213 @@
+use ncurses::*;
+use ncurses::CURSOR_VISIBILITY::CURSOR_INVISIBLE;
+
+use color;
+use keys;
+use keys::Action;
+use keys::KEY_DOWN;
+use keys::KEY_END;
+use keys::KEY_HOME;
+use keys::KEY_LEFT;
+use keys::KEY_NPAGE;
+use keys::KEY_PPAGE;
+use keys::KEY_RIGHT;
+use keys::KEY_TAB;
+use keys::KEY_UP;
+use operation::Channels;
+use theme;
+use theme::HIGHLIGHT_COLOR;
+use theme::QUESTION_COLOR;
+
+const KEY_ADD    : i32 = 'a' as i32;
+const KEY_REMOVE : i32 = 'd' as i32;
+
+const COARSE_STEP : i32 = 16;
+
+// NOTE(erick): Columns taken by the output labels on the left.
+const LABEL_WIDTH : i32 = 4;
+
+// NOTE(erick): Input goes right and output up, both from 0 to 255. The
+// title is the first line, the input axis and the keys the last two.
+struct Plot {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Plot {
+    fn column(&self, input: u8) -> i32 {
+        self.left + (input as i32 * (self.width - 1) + 127) / 255
+    }
+
+    fn row(&self, output: u8) -> i32 {
+        self.top + ((255 - output as i32) * (self.height - 1) + 127) / 255
+    }
+
+    fn input(&self, column: i32) -> u8 {
+        (((column - self.left) * 255 + (self.width - 1) / 2) / (self.width - 1)) as u8
+    }
+}
+
+// NOTE(erick): Returns the edited points, still sorted by input, or None
+// if the editor was cancelled.
+pub fn edit(screen_height: i32, screen_width: i32, channels: Channels,
+            initial_points: &[(u8, u8)]) -> Option<Vec<(u8, u8)>> {
+    let window = newwin(screen_height, screen_width, 0, 0);
+    defer! {{ delwin(window); }}
+
+    let old_cursor = curs_set(CURSOR_INVISIBLE);
+    defer! {
+        if let Some(cursor) = old_cursor {
+            curs_set(cursor);
+        }
+    }
+
+    let plot = Plot {
+        left: LABEL_WIDTH + 1,
+        top: 1,
+        width: (screen_width - LABEL_WIDTH - 2).max(2),
+        height: (screen_height - 3).max(2),
+    };
+
+    let mut points = initial_points.to_vec();
+    let mut selected = 0;
+    let mut message: Option<String> = None;
+    loop {
+        let table = color::curve_table(points.as_slice());
+
+        theme::clear_window(window);
+        wmove(window, 0, 0);
+        wattron(window, theme::attribute(QUESTION_COLOR));
+        let (input, output) = points[selected];
+        wprintw(window, format!("Curve ({}): point {} of {} at {} -> {}", channels,
+                                selected + 1, points.len(), input, output).as_str());
+        wattroff(window, theme::attribute(QUESTION_COLOR));
+
+        wprint_axes(window, &plot);
+        wprint_curve(window, &plot, &table);
+        for (index, &(input, output)) in points.iter().enumerate() {
+            wmove(window, plot.row(output), plot.column(input));
+            if index == selected {
+                wattron(window, theme::attribute(HIGHLIGHT_COLOR));
+                waddch(window, 'O' as chtype);
+                wattroff(window, theme::attribute(HIGHLIGHT_COLOR));
+            } else {
+                waddch(window, 'o' as chtype);
+            }
+        }
+
+        wmove(window, screen_height - 1, 0);
+        if message.is_some() {
+            wprintw(window, message.take().unwrap().as_str());
+        } else {
+            let bindings = keys::bindings();
+            let key_name = |action: Action| {
+                bindings.keys(action).first().map(|key| keys::key_name(*key)).unwrap_or_default()
+            };
+            wprintw(window, format!("{}: accept  {}: cancel  Tab: next point  \
+                                     arrows: move  a: add  d: remove",
+                                    key_name(Action::Confirm),
+                                    key_name(Action::Cancel)).as_str());
+        }
+        wrefresh(window);
+
+        let (mut dx, mut dy) = (0, 0);
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => { return Some(points); },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
+            KEY_TAB   => { selected = (selected + 1) % points.len(); },
+            KEY_LEFT  => { dx = -1; },
+            KEY_RIGHT => { dx =  1; },
+            KEY_UP    => { dy =  1; },
+            KEY_DOWN  => { dy = -1; },
+            KEY_HOME  => { dx = -COARSE_STEP; },
+            KEY_END   => { dx =  COARSE_STEP; },
+            KEY_PPAGE => { dy =  COARSE_STEP; },
+            KEY_NPAGE => { dy = -COARSE_STEP; },
+            KEY_ADD   => {
+                let result = add_point(&mut points, selected, &table);
+                match result {
+                    Ok(index)  => { selected = index; },
+                    Err(error) => { message = Some(error); },
+                }
+            },
+            KEY_REMOVE => {
+                if points.len() > 2 {
+                    points.remove(selected);
+                    selected = selected.min(points.len() - 1);
+                } else {
+                    message = Some("a curve needs at least two points".to_string());
+                }
+            },
+            _ => { },
+        }
+
+        if dx != 0 || dy != 0 {
+            // NOTE(erick): Points can't pass their neighbours, inputs stay
+            // sorted and unique.
+            let low = if selected == 0 { 0 } else { points[selected - 1].0 as i32 + 1 };
+            let high = if selected + 1 == points.len() { 255 } else { points[selected + 1].0 as i32 - 1 };
+            let (input, output) = points[selected];
+            points[selected] = ((input as i32 + dx).clamp(low, high) as u8,
+                                (output as i32 + dy).clamp(0, 255) as u8);
+        }
+    }
+}
+
+// NOTE(erick): The new point goes halfway to the next one (the previous
+// one for the last point) and on the curve, so nothing changes until
+// it is moved.
+fn add_point(points: &mut Vec<(u8, u8)>, selected: usize,
+             table: &[u8; 256]) -> Result<usize, String> {
+    let (first, second) = if selected + 1 == points.len() {
+        (selected - 1, selected)
+    } else {
+        (selected, selected + 1)
+    };
+
+    let low = points[first].0 as u32;
+    let high = points[second].0 as u32;
+    if high - low < 2 {
+        return Err("no room for another point here".to_string());
+    }
+
+    let input = ((low + high) / 2) as u8;
+    points.insert(second, (input, table[input as usize]));
+    Ok(second)
+}
+
+fn wprint_axes(window: WINDOW, plot: &Plot) {
+    wmove(window, plot.top, 0);
+    wprintw(window, format!("{:>width$}", 255, width = LABEL_WIDTH as usize).as_str());
+    wmove(window, plot.top + plot.height - 1, 0);
+    wprintw(window, format!("{:>width$}", 0, width = LABEL_WIDTH as usize).as_str());
+    for row in plot.top .. plot.top + plot.height {
+        mvwaddch(window, row, plot.left - 1, '|' as chtype);
+    }
+
+    let axis_row = plot.top + plot.height;
+    mvwaddch(window, axis_row, plot.left - 1, '+' as chtype);
+    for _ in 0 .. plot.width {
+        waddch(window, '-' as chtype);
+    }
+}
+
+// NOTE(erick): One sample per column, with the rows in between filled so
+// steep parts don't break into dots.
+fn wprint_curve(window: WINDOW, plot: &Plot, table: &[u8; 256]) {
+    let mut previous_row = None;
+    for column in plot.left .. plot.left + plot.width {
+        let row = plot.row(table[plot.input(column) as usize]);
+        let (from, to) = match previous_row {
+            Some(previous) if previous < row => (previous + 1, row),
+            Some(previous) if previous > row => (row, previous - 1),
+            _                                => (row, row),
+        };
+        for fill_row in from ..= to {
+            mvwaddch(window, fill_row, column, '*' as chtype);
+        }
+        previous_row = Some(row);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use color::clamp_channel;
+use image::Image;
+use operation::ColorPalette;
+use operation::Dither;
+use palette;
+use quantize;
+use quantize::Palette;
+
+// NOTE(erick): Where the error of a pixel goes, (dx, dy, share) with
+// dy going down. Atkinson only passes on 3/4 of it, which keeps more
+// contrast.
+const FLOYD_STEINBERG : [(i64, i64, f64); 4] = [
+    ( 1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0),
+];
+
+const ATKINSON : [(i64, i64, f64); 6] = [
+    ( 1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0),
+    ( 0, 2, 1.0 / 8.0),
+];
+
+const SIERRA : [(i64, i64, f64); 10] = [
+    ( 1, 0, 5.0 / 32.0), ( 2, 0, 3.0 / 32.0),
+    (-2, 1, 2.0 / 32.0), (-1, 1, 4.0 / 32.0), (0, 1, 5.0 / 32.0),
+    ( 1, 1, 4.0 / 32.0), ( 2, 1, 2.0 / 32.0),
+    (-1, 2, 2.0 / 32.0), ( 0, 2, 3.0 / 32.0), (1, 2, 2.0 / 32.0),
+];
+
+// NOTE(erick): Only the colors change, alpha is kept. A palette with no
+// colors (a fully transparent image) leaves the image as it is.
+pub fn quantize(image: &Image, colors: &ColorPalette, dither: Dither) -> Result<Image, String> {
+    let palette = match colors {
+        &ColorPalette::MedianCut(count) => Palette::build(&image.pixels, count as usize),
+        &ColorPalette::Octree(count)    => Palette::octree(&image.pixels, count as usize),
+        &ColorPalette::WebSafe          => Palette::from_colors(palette::web_safe()),
+        &ColorPalette::Gray(count)      => Palette::from_colors(palette::gray(count)),
+        ColorPalette::File(path)   => Palette::from_colors(palette::read_file(path)?),
+    };
+    if palette.colors.is_empty() {
+        return Ok(image.clone());
+    }
+
+    let result = match dither {
+        Dither::None           => ordered(image, &palette, 1),
+        Dither::Bayer(size)    => ordered(image, &palette, size as usize),
+        Dither::FloydSteinberg => diffuse(image, &palette, &FLOYD_STEINBERG),
+        Dither::Atkinson       => diffuse(image, &palette, &ATKINSON),
+        Dither::Sierra         => diffuse(image, &palette, &SIERRA),
+    };
+
+    Ok(result)
+}
+
+// NOTE(erick): Nearest colors found so far, most pixels share them.
+struct NearestColors<'a> {
+    palette: &'a Palette,
+    found: HashMap<[u8; 3], u8>,
+}
+
+impl<'a> NearestColors<'a> {
+    fn new(palette: &'a Palette) -> NearestColors<'a> {
+        NearestColors { palette, found: HashMap::new() }
+    }
+
+    fn get(&mut self, color: [u8; 3]) -> [u8; 3] {
+        let palette = self.palette;
+        let index = *self.found.entry(color).or_insert_with(|| palette.nearest(color));
+        palette.colors[index as usize]
+    }
+}
+
+// NOTE(erick): Thresholds from -0.5 to 0.5 for a size by size pattern,
+// the size being a power of two. Each size repeats the one before in
+// the order 0, 2, 3, 1.
+fn bayer_matrix(size: usize) -> Vec<f64> {
+    let mut matrix = vec![0];
+    let mut current = 1;
+    while current < size {
+        let next_size = current * 2;
+        let mut next = vec![0; next_size * next_size];
+        for y in 0 .. current {
+            for x in 0 .. current {
+                let value = 4 * matrix[y * current + x];
+                next[y * next_size + x] = value;
+                next[y * next_size + x + current] = value + 2;
+                next[(y + current) * next_size + x] = value + 3;
+                next[(y + current) * next_size + x + current] = value + 1;
+            }
+        }
+        matrix = next;
+        current = next_size;
+    }
+
+    let cells = (size * size) as f64;
+    matrix.iter().map(|&value| (value as f64 + 0.5) / cells - 0.5).collect()
+}
+
+// NOTE(erick): How far apart the colors of the palette are: the largest
+// channel difference to the closest other color, averaged. Evenly spaced
+// palettes get their step, 51 for web-safe.
+fn palette_spread(colors: &[[u8; 3]]) -> f64 {
+    let mut total = 0.0;
+    for (index, a) in colors.iter().enumerate() {
+        let closest = colors.iter().enumerate()
+            .filter(|&(other, b)| other != index && b != a)
+            .map(|(_, b)| (0 .. 3).map(|channel| (a[channel] as i32 - b[channel] as i32).abs())
+                 .max()
+                 .unwrap_or(0))
+            .min()
+            .unwrap_or(0);
+        total += closest as f64;
+    }
+
+    total / colors.len() as f64
+}
+
+// NOTE(erick): A size of 1 adds nothing, which is plain nearest color.
+fn ordered(image: &Image, palette: &Palette, size: usize) -> Image {
+    let matrix = bayer_matrix(size);
+    let spread = palette_spread(&palette.colors);
+    let mut nearest = NearestColors::new(palette);
+
+    let mut result = image.clone();
+    let width = image.width as usize;
+    for (index, pixel) in result.pixels.chunks_mut(4).enumerate() {
+        let (x, y) = (index % width, index / width);
+        let offset = matrix[(y % size) * size + x % size] * spread;
+        let color = nearest.get([clamp_channel(pixel[0] as f64 + offset),
+                                 clamp_channel(pixel[1] as f64 + offset),
+                                 clamp_channel(pixel[2] as f64 + offset)]);
+        pixel[.. 3].copy_from_slice(&color);
+    }
+
+    result
+}
+
+// NOTE(erick): Pixels are picked left to right, top to bottom. Transparent
+// pixels get a color but don't pass their error on, nobody sees it.
+fn diffuse(image: &Image, palette: &Palette, weights: &[(i64, i64, f64)]) -> Image {
+    let width = image.width as i64;
+    let height = image.height as i64;
+    let mut values = image.pixels.chunks(4)
+        .map(|pixel| [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64])
+        .collect::<Vec<_> >();
+    let mut nearest = NearestColors::new(palette);
+
+    let mut result = image.clone();
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let index = (y * width + x) as usize;
+            let value = values[index];
+            let color = nearest.get([clamp_channel(value[0]),
+                                     clamp_channel(value[1]),
+                                     clamp_channel(value[2])]);
+            let pixel = &mut result.pixels[index * 4 .. index * 4 + 4];
+            pixel[.. 3].copy_from_slice(&color);
+            if quantize::is_transparent(pixel) {
+                continue;
+            }
+
+            let mut error = [0.0; 3];
+            for channel in 0 .. 3 {
+                error[channel] = value[channel].clamp(0.0, 255.0) - color[channel] as f64;
+            }
+
+            for &(dx, dy, share) in weights.iter() {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || x >= width || y >= height {
+                    continue;
+                }
+
+                let neighbour = &mut values[(y * width + x) as usize];
+                for channel in 0 .. 3 {
+                    neighbour[channel] += error[channel] * share;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(width: u32, height: u32, pixel: [u8; 4]) -> Image {
+        let mut image = Image::new(width, height);
+        for chunk in image.pixels.chunks_mut(4) {
+            chunk.copy_from_slice(&pixel);
+        }
+        image
+    }
+
+    fn white_share(image: &Image) -> f64 {
+        let whites = image.pixels.chunks(4).filter(|pixel| pixel[0] == 255).count();
+        whites as f64 / (image.pixels.len() / 4) as f64
+    }
+
+    #[test]
+    fn bayer() {
+        let matrix = bayer_matrix(2);
+        assert_eq!(matrix, vec![-0.375, 0.125, 0.375, -0.125]);
+
+        let mut matrix = bayer_matrix(8);
+        assert_eq!(matrix.len(), 64);
+        matrix.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(matrix.windows(2).all(|pair| (pair[1] - pair[0] - 1.0 / 64.0).abs() < 1e-9));
+
+        assert_eq!(palette_spread(&palette::web_safe()), 51.0);
+        assert_eq!(palette_spread(&palette::gray(2)), 255.0);
+    }
+
+    // NOTE(erick): Mid gray between black and white has to come out as
+    // roughly half white pixels with every dither, and as one flat color
+    // without.
+    #[test]
+    fn two_grays() {
+        let image = flat(16, 16, [128, 128, 128, 200]);
+        let colors = ColorPalette::Gray(2);
+
+        let result = quantize(&image, &colors, Dither::None).unwrap();
+        assert!(result.pixels.chunks(4).all(|pixel| pixel == [255, 255, 255, 200]));
+
+        let dithers = [Dither::Bayer(2), Dither::Bayer(8), Dither::FloydSteinberg,
+                       Dither::Atkinson, Dither::Sierra];
+        for &dither in dithers.iter() {
+            let result = quantize(&image, &colors, dither).unwrap();
+            assert!(result.pixels.chunks(4).all(|pixel| {
+                (pixel[0] == 0 || pixel[0] == 255) && pixel[0] == pixel[2] && pixel[3] == 200
+            }));
+            assert!((white_share(&result) - 0.5).abs() < 0.05, "{}", dither);
+        }
+    }
+
+    #[test]
+    fn counted_palettes() {
+        let mut image = Image::new(32, 8);
+        for (index, pixel) in image.pixels.chunks_mut(4).enumerate() {
+            pixel.copy_from_slice(&[index as u8, (index * 7) as u8, (index * 13) as u8, 255]);
+        }
+
+        for colors in [ColorPalette::MedianCut(4), ColorPalette::Octree(4)].iter() {
+            let result = quantize(&image, colors, Dither::FloydSteinberg).unwrap();
+            let mut seen = result.pixels.chunks(4).map(|pixel| pixel.to_vec()).collect::<Vec<_> >();
+            seen.sort();
+            seen.dedup();
+            assert!(seen.len() <= 4, "{}", colors);
+        }
+
+        let transparent = Image::new(4, 4);
+        let result = quantize(&transparent, &ColorPalette::MedianCut(4), Dither::None).unwrap();
+        assert!(result.pixels == transparent.pixels);
+    }
+}
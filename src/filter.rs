@@ -0,0 +1,239 @@
+use blur;
+use color::clamp_channel;
+use image::Image;
+use operation::EdgeMode;
+use operation::Kernel;
+
+const SHARPEN : [f64; 9] = [ 0.0, -1.0,  0.0,
+                            -1.0,  5.0, -1.0,
+                             0.0, -1.0,  0.0];
+
+const EMBOSS : [f64; 9] = [-2.0, -1.0,  0.0,
+                           -1.0,  1.0,  1.0,
+                            0.0,  1.0,  2.0];
+
+const LAPLACIAN : [f64; 9] = [0.0,  1.0, 0.0,
+                              1.0, -4.0, 1.0,
+                              0.0,  1.0, 0.0];
+
+const SOBEL_X : [f64; 9] = [-1.0, 0.0, 1.0,
+                            -2.0, 0.0, 2.0,
+                            -1.0, 0.0, 1.0];
+
+const SOBEL_Y : [f64; 9] = [-1.0, -2.0, -1.0,
+                             0.0,  0.0,  0.0,
+                             1.0,  2.0,  1.0];
+
+const PREWITT_X : [f64; 9] = [-1.0, 0.0, 1.0,
+                              -1.0, 0.0, 1.0,
+                              -1.0, 0.0, 1.0];
+
+const PREWITT_Y : [f64; 9] = [-1.0, -1.0, -1.0,
+                               0.0,  0.0,  0.0,
+                               1.0,  1.0,  1.0];
+
+pub fn convolve(image: &Image, kernel: &Kernel, edge: EdgeMode) -> Image {
+    match *kernel {
+        Kernel::BoxBlur(radius)             => blur::box_blur(image, radius, edge),
+        Kernel::Gaussian(radius)            => blur::gaussian_blur(image, radius, edge),
+        Kernel::Sharpen                     => filter(image, &SHARPEN, 1.0, 0.0, edge),
+        Kernel::UnsharpMask(radius, amount) => unsharp_mask(image, radius, amount, edge),
+        Kernel::Emboss                      => filter(image, &EMBOSS, 1.0, 0.0, edge),
+        Kernel::Sobel                       => gradient(image, &SOBEL_X, &SOBEL_Y, edge),
+        Kernel::Prewitt                     => gradient(image, &PREWITT_X, &PREWITT_Y, edge),
+        Kernel::Laplacian                   => laplacian(image, edge),
+        Kernel::Custom(ref values, divisor, bias) => {
+            let divisor = if divisor != 0.0 { divisor } else { values.iter().sum() };
+            let divisor = if divisor != 0.0 { divisor } else { 1.0 };
+            filter(image, values.as_slice(), divisor, bias, edge)
+        },
+    }
+}
+
+// NOTE(erick): Maps a coordinate to the pixel it reads, None is outside
+// the image with constant edges. Mirror repeats the edge pixel, and both
+// wrap and mirror work with kernels bigger than the image.
+pub fn edge_index(position: i64, size: i64, edge: EdgeMode) -> Option<usize> {
+    if position >= 0 && position < size {
+        return Some(position as usize);
+    }
+
+    match edge {
+        EdgeMode::Clamp    => Some(position.clamp(0, size - 1) as usize),
+        EdgeMode::Wrap     => Some(position.rem_euclid(size) as usize),
+        EdgeMode::Mirror   => {
+            let period = 2 * size;
+            let position = position.rem_euclid(period);
+            Some(if position < size { position } else { period - 1 - position } as usize)
+        },
+        EdgeMode::Constant => None,
+    }
+}
+
+// NOTE(erick): Kernels are square with an odd size and are applied as
+// written, the top left weight goes with the top left neighbour. Each
+// pixel gets one sum of the color channels per kernel. This is the
+// general path, blurs have their own in blur.rs.
+fn correlate<F>(image: &Image, kernels: &[&[f64]], edge: EdgeMode, mut emit: F)
+    where F: FnMut(usize, &[[f64; 3]]) {
+    let size = (kernels[0].len() as f64).sqrt() as usize;
+    let radius = (size / 2) as i64;
+    let width = image.width as i64;
+    let height = image.height as i64;
+
+    let columns = (-radius .. width + radius)
+        .map(|x| edge_index(x, width, edge))
+        .collect::<Vec<_> >();
+    let rows = (-radius .. height + radius)
+        .map(|y| edge_index(y, height, edge))
+        .collect::<Vec<_> >();
+
+    let mut sums = vec![[0.0; 3]; kernels.len()];
+    for y in 0 .. height as usize {
+        for x in 0 .. width as usize {
+            for sum in sums.iter_mut() {
+                *sum = [0.0; 3];
+            }
+
+            for ky in 0 .. size {
+                let row = rows[y + ky];
+                if row.is_none() {
+                    continue;
+                }
+                let row_start = row.unwrap() * width as usize;
+
+                for kx in 0 .. size {
+                    let column = columns[x + kx];
+                    if column.is_none() {
+                        continue;
+                    }
+
+                    let offset = (row_start + column.unwrap()) * 4;
+                    let pixel = &image.pixels[offset .. offset + 4];
+                    for (sum, kernel) in sums.iter_mut().zip(kernels.iter()) {
+                        let weight = kernel[ky * size + kx];
+                        sum[0] += weight * pixel[0] as f64;
+                        sum[1] += weight * pixel[1] as f64;
+                        sum[2] += weight * pixel[2] as f64;
+                    }
+                }
+            }
+
+            emit(y * width as usize + x, &sums);
+        }
+    }
+}
+
+// NOTE(erick): Only blurs touch alpha, anything else would punch holes
+// in opaque images.
+fn filter(image: &Image, weights: &[f64], divisor: f64, bias: f64, edge: EdgeMode) -> Image {
+    let mut result = image.clone();
+    correlate(image, &[weights], edge, |index, sums| {
+        for (channel, &sum) in sums[0].iter().enumerate() {
+            result.pixels[index * 4 + channel] = clamp_channel(sum / divisor + bias);
+        }
+    });
+
+    result
+}
+
+fn gradient(image: &Image, horizontal: &[f64], vertical: &[f64], edge: EdgeMode) -> Image {
+    let mut result = image.clone();
+    correlate(image, &[horizontal, vertical], edge, |index, sums| {
+        for (channel, (&x, &y)) in sums[0].iter().zip(sums[1].iter()).enumerate() {
+            result.pixels[index * 4 + channel] = clamp_channel(x.hypot(y));
+        }
+    });
+
+    result
+}
+
+fn laplacian(image: &Image, edge: EdgeMode) -> Image {
+    let mut result = image.clone();
+    correlate(image, &[&LAPLACIAN], edge, |index, sums| {
+        for (channel, &sum) in sums[0].iter().enumerate() {
+            result.pixels[index * 4 + channel] = clamp_channel(sum.abs());
+        }
+    });
+
+    result
+}
+
+fn unsharp_mask(image: &Image, radius: f64, amount: f64, edge: EdgeMode) -> Image {
+    let blurred = blur::gaussian_blur(image, radius, edge);
+    let mut result = image.clone();
+    for (pixel, blurred) in result.pixels.chunks_mut(4).zip(blurred.pixels.chunks(4)) {
+        for channel in 0 .. 3 {
+            let value = pixel[channel] as f64;
+            let detail = value - blurred[channel] as f64;
+            pixel[channel] = clamp_channel(value + amount * detail);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(erick): A vertical edge, dark on the left half.
+    fn edge_image() -> Image {
+        let mut image = Image::new(4, 3);
+        for y in 0 .. 3 {
+            for x in 0 .. 4 {
+                let value = if x < 2 { 10 } else { 110 };
+                image.set_pixel(x, y, [value, value, value, 200]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn edge_indices() {
+        let cases = [(EdgeMode::Clamp,    [Some(0), Some(0), Some(2), Some(2)]),
+                     (EdgeMode::Wrap,     [Some(1), Some(2), Some(0), Some(1)]),
+                     (EdgeMode::Mirror,   [Some(1), Some(0), Some(2), Some(1)]),
+                     (EdgeMode::Constant, [None, None, None, None])];
+        for &(edge, expected) in cases.iter() {
+            let indices = [-2, -1, 3, 4].iter()
+                .map(|&position| edge_index(position, 3, edge))
+                .collect::<Vec<_> >();
+            assert_eq!(indices, expected);
+            assert_eq!(edge_index(1, 3, edge), Some(1));
+        }
+        assert_eq!(edge_index(-5, 2, EdgeMode::Mirror), Some(0));
+    }
+
+    #[test]
+    fn custom_kernels() {
+        let image = edge_image();
+        let identity = Kernel::Custom(vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0], 1.0, 0.0);
+        assert!(convolve(&image, &identity, EdgeMode::Constant).pixels == image.pixels);
+
+        // NOTE(erick): Reads the right neighbour, so the edge moves left.
+        let shift = Kernel::Custom(vec![0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0], 0.0, 5.0);
+        let result = convolve(&image, &shift, EdgeMode::Wrap);
+        let row = (0 .. 4).map(|x| result.pixel(x, 1)[0]).collect::<Vec<_> >();
+        assert_eq!(row, vec![15, 115, 115, 15]);
+        assert_eq!(result.pixel(0, 1)[3], 200);
+    }
+
+    #[test]
+    fn presets() {
+        let image = edge_image();
+        let flat = Image { width: 4, height: 3, pixels: vec![90; 4 * 3 * 4] };
+        assert!(convolve(&flat, &Kernel::Sharpen, EdgeMode::Clamp).pixels == flat.pixels);
+
+        let result = convolve(&flat, &Kernel::Laplacian, EdgeMode::Mirror);
+        assert_eq!(result.pixel(1, 1), [0, 0, 0, 90]);
+
+        let result = convolve(&image, &Kernel::Sobel, EdgeMode::Clamp);
+        assert_eq!(result.pixel(0, 1), [0, 0, 0, 200]);
+        assert_eq!(result.pixel(1, 1), [255, 255, 255, 200]);
+
+        let result = convolve(&image, &Kernel::Sharpen, EdgeMode::Clamp);
+        assert_eq!(result.pixel(1, 1)[0], 0);
+        assert_eq!(result.pixel(2, 1)[0], 210);
+    }
+}
@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+
+use image::Image;
+use quantize;
+use quantize::Palette;
+
+// NOTE(erick): Reference: https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+//
+// Frames are composited the way browsers do it: the canvas starts out
+// transparent, the background color is ignored and "restore to background"
+// clears to transparent.
+
+const TRAILER              : u8 = 0x3b;
+const EXTENSION            : u8 = 0x21;
+const IMAGE_DESCRIPTOR     : u8 = 0x2c;
+const GRAPHIC_CONTROL      : u8 = 0xf9;
+const APPLICATION          : u8 = 0xff;
+
+const DISPOSE_BACKGROUND   : u8 = 2;
+const DISPOSE_PREVIOUS     : u8 = 3;
+
+const MAX_CODE_SIZE        : u32 = 12;
+const MAX_CODES            : usize = 1 << MAX_CODE_SIZE;
+
+// NOTE(erick): The screen and image sizes are 16 bits.
+pub const MAX_SIZE         : u32 = 65535;
+
+// NOTE(erick): The screen size comes straight from the header, so the
+// canvas is checked against this before it is allocated.
+const MAX_PIXELS           : usize = 400_000_000;
+
+pub fn is_gif(bytes: &[u8]) -> bool {
+    bytes.len() >= 6 && (&bytes[0 .. 6] == b"GIF87a" || &bytes[0 .. 6] == b"GIF89a")
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if !is_gif(bytes) || bytes.len() < 10 {
+        return None;
+    }
+
+    Some((read_u16(bytes, 6) as u32, read_u16(bytes, 8) as u32))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.position + count > self.bytes.len() {
+            return Err("GIF: unexpected end of file".to_string());
+        }
+
+        let result = &self.bytes[self.position .. self.position + count];
+        self.position += count;
+        Ok(result)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn color_table(&mut self, packed: u8) -> Result<Vec<[u8; 3]>, String> {
+        let size = 2 << (packed & 0x07);
+        let bytes = self.take(size * 3)?;
+        Ok(bytes.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect())
+    }
+
+    // NOTE(erick): Extensions and image data are split in sub-blocks of at
+    // most 255 bytes, ended by an empty one.
+    fn sub_blocks(&mut self, output: Option<&mut Vec<u8>>) -> Result<(), String> {
+        let mut output = output;
+        loop {
+            let size = self.byte()? as usize;
+            if size == 0 {
+                return Ok(());
+            }
+
+            let data = self.take(size)?;
+            if let Some(ref mut output) = output {
+                output.extend_from_slice(data);
+            }
+        }
+    }
+}
+
+struct Descriptor {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+    interlaced: bool,
+    color_table: Option<Vec<[u8; 3]>>,
+}
+
+// NOTE(erick): Reads the header and calls 'visit' for every image in the
+// file with its color table, local or global, and the last graphic control
+// extension seen before it. 'data' is only filled in when 'want_data' is
+// set. Stops early if 'visit' returns false.
+fn walk<F>(bytes: &[u8], want_data: bool, mut visit: F) -> Result<(), String>
+    where F: FnMut(&Descriptor, Option<&Vec<[u8; 3]>>,
+                   Option<(u8, Option<u8>)>, &[u8]) -> Result<bool, String> {
+    if !is_gif(bytes) {
+        return Err("GIF: bad signature".to_string());
+    }
+
+    let mut reader = Reader { bytes, position: 6 };
+    let screen = reader.take(7)?;
+    let global_table = if screen[4] & 0x80 != 0 {
+        Some(reader.color_table(screen[4])?)
+    } else {
+        None
+    };
+
+    let mut control = None;
+    let mut data = Vec::new();
+    loop {
+        // NOTE(erick): A missing trailer is common enough to be accepted.
+        if reader.position == bytes.len() {
+            break;
+        }
+
+        match reader.byte()? {
+            TRAILER => { break; },
+            EXTENSION => {
+                let label = reader.byte()?;
+                if label == GRAPHIC_CONTROL {
+                    let mut block = Vec::new();
+                    reader.sub_blocks(Some(&mut block))?;
+                    if block.len() < 4 {
+                        return Err("GIF: bad graphic control extension".to_string());
+                    }
+                    let transparent = if block[0] & 0x01 != 0 { Some(block[3]) } else { None };
+                    control = Some(((block[0] >> 2) & 0x07, transparent));
+                } else {
+                    reader.sub_blocks(None)?;
+                }
+            },
+            IMAGE_DESCRIPTOR => {
+                let fields = reader.take(9)?;
+                let packed = fields[8];
+                let color_table = if packed & 0x80 != 0 {
+                    Some(reader.color_table(packed)?)
+                } else {
+                    None
+                };
+                let descriptor = Descriptor {
+                    left: read_u16(fields, 0) as usize,
+                    top: read_u16(fields, 2) as usize,
+                    width: read_u16(fields, 4) as usize,
+                    height: read_u16(fields, 6) as usize,
+                    interlaced: packed & 0x40 != 0,
+                    color_table,
+                };
+
+                data.clear();
+                data.push(reader.byte()?);
+                reader.sub_blocks(if want_data { Some(&mut data) } else { None })?;
+
+                let table = descriptor.color_table.as_ref().or(global_table.as_ref());
+                if !visit(&descriptor, table, control, data.as_slice())? {
+                    break;
+                }
+                control = None;
+            },
+            other => return Err(format!("GIF: unknown block 0x{:02x}", other)),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn frame_count(bytes: &[u8]) -> Result<usize, String> {
+    let mut count = 0;
+    walk(bytes, false, |_, _, _, _| { count += 1; Ok(true) })?;
+    Ok(count)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    decode_frame(bytes, 0)
+}
+
+// NOTE(erick): Earlier frames have to be decoded anyway because of the
+// compositing, only the one asked for is kept.
+pub fn decode_frame(bytes: &[u8], wanted: usize) -> Result<Image, String> {
+    let (width, height) = dimensions(bytes).ok_or("GIF: file too short".to_string())?;
+    if width == 0 || height == 0 || width as usize * height as usize > MAX_PIXELS {
+        return Err("GIF: invalid dimensions".to_string());
+    }
+
+    let mut canvas = Image::new(width, height);
+    let mut result = None;
+    let mut index = 0;
+    walk(bytes, true, |descriptor, table, control, data| {
+        let (disposal, transparent) = control.unwrap_or((0, None));
+        if table.is_none() {
+            return Err("GIF: frame without a color table".to_string());
+        }
+
+        let table = table.unwrap();
+        let previous = if disposal == DISPOSE_PREVIOUS { Some(canvas.clone()) } else { None };
+
+        let pixel_count = descriptor.width * descriptor.height;
+        let indices = lzw_decode(data, pixel_count)?;
+        for (i, &color_index) in indices.iter().enumerate() {
+            if Some(color_index) == transparent {
+                continue;
+            }
+
+            let row = interlaced_row(i / descriptor.width, descriptor.height, descriptor.interlaced);
+            let x = descriptor.left + i % descriptor.width;
+            let y = descriptor.top + row;
+            if x >= width as usize || y >= height as usize {
+                continue;
+            }
+
+            let color = table.get(color_index as usize).cloned().unwrap_or([0, 0, 0]);
+            canvas.set_pixel(x as u32, y as u32, [color[0], color[1], color[2], 255]);
+        }
+
+        if index == wanted {
+            result = Some(canvas.clone());
+            return Ok(false);
+        }
+
+        if disposal == DISPOSE_BACKGROUND {
+            let right = (descriptor.left + descriptor.width).min(width as usize);
+            let bottom = (descriptor.top + descriptor.height).min(height as usize);
+            for y in descriptor.top .. bottom {
+                for x in descriptor.left .. right {
+                    canvas.set_pixel(x as u32, y as u32, [0, 0, 0, 0]);
+                }
+            }
+        }
+        if let Some(previous) = previous {
+            canvas = previous;
+        }
+
+        index += 1;
+        Ok(true)
+    })?;
+
+    result.ok_or(format!("GIF: there is no frame {}", wanted + 1))
+}
+
+// NOTE(erick): Interlaced images store every 8th row starting at 0, then
+// every 8th starting at 4, every 4th starting at 2 and every 2nd
+// starting at 1. Maps the position in the file to the actual row.
+fn interlaced_row(row: usize, height: usize, interlaced: bool) -> usize {
+    if !interlaced {
+        return row;
+    }
+
+    let passes = [(0, 8), (4, 8), (2, 4), (1, 2)];
+    let mut remaining = row;
+    for &(start, step) in passes.iter() {
+        let rows_in_pass = if height > start { (height - start).div_ceil(step) } else { 0 };
+        if remaining < rows_in_pass {
+            return start + remaining * step;
+        }
+        remaining -= rows_in_pass;
+    }
+
+    row
+}
+
+// NOTE(erick): 'data' starts with the minimum code size. Truncated data
+// gives fewer indices and the missing pixels are left alone, which is
+// what every viewer does.
+fn lzw_decode(data: &[u8], pixel_count: usize) -> Result<Vec<u8>, String> {
+    let minimum_size = data[0] as u32;
+    if !(1 ..= 11).contains(&minimum_size) {
+        return Err(format!("GIF: invalid LZW code size {}", minimum_size));
+    }
+
+    let clear = 1usize << minimum_size;
+    let end = clear + 1;
+
+    let mut prefix = vec![0u16; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut length = vec![0u16; MAX_CODES];
+    for code in 0 .. clear {
+        suffix[code] = code as u8;
+        length[code] = 1;
+    }
+
+    // NOTE(erick): Every code is at least minimum_size + 1 bits and gives
+    // at most MAX_CODES indices, the frame size alone can't be trusted.
+    let code_count = (data.len() - 1) * 8 / (minimum_size as usize + 1);
+    let mut output = Vec::with_capacity(pixel_count.min(code_count.saturating_mul(MAX_CODES)));
+    let mut size = minimum_size + 1;
+    let mut next = clear + 2;
+    let mut previous: Option<usize> = None;
+
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut position = 1;
+    while output.len() < pixel_count {
+        while bit_count < size && position < data.len() {
+            bits |= (data[position] as u32) << bit_count;
+            bit_count += 8;
+            position += 1;
+        }
+        if bit_count < size {
+            break;
+        }
+
+        let code = (bits & ((1 << size) - 1)) as usize;
+        bits >>= size;
+        bit_count -= size;
+
+        if code == clear {
+            size = minimum_size + 1;
+            next = clear + 2;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        if previous.is_none() {
+            if code >= clear {
+                return Err("GIF: invalid LZW code".to_string());
+            }
+            output.push(code as u8);
+            previous = Some(code);
+            continue;
+        }
+
+        let previous_code = previous.unwrap();
+        if code > next || (code == next && next == MAX_CODES) {
+            return Err("GIF: invalid LZW code".to_string());
+        }
+
+        // NOTE(erick): The code being defined right now (KwKwK) starts and
+        // ends with the first index of the previous one.
+        let string_code = if code == next { previous_code } else { code };
+        let start = output.len();
+        let string_length = length[string_code] as usize;
+        output.resize(start + string_length, 0);
+        let mut current = string_code;
+        for i in (0 .. string_length).rev() {
+            output[start + i] = suffix[current];
+            current = prefix[current] as usize;
+        }
+        let first = output[start];
+        if code == next {
+            output.push(first);
+        }
+
+        if next < MAX_CODES {
+            prefix[next] = previous_code as u16;
+            suffix[next] = first;
+            length[next] = length[previous_code] + 1;
+            next += 1;
+            if next == 1 << size && size < MAX_CODE_SIZE {
+                size += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    output.truncate(pixel_count);
+    Ok(output)
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: usize, size: u32) {
+        self.bits |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8], minimum_size: u32) -> Vec<u8> {
+    let clear = 1usize << minimum_size;
+    let end = clear + 1;
+
+    let mut writer = BitWriter { bytes: Vec::new(), bits: 0, bit_count: 0 };
+    let mut table: HashMap<(usize, u8), usize> = HashMap::new();
+    let mut size = minimum_size + 1;
+    let mut next = clear + 2;
+
+    writer.write(clear, size);
+    if indices.is_empty() {
+        writer.write(end, size);
+        return writer.finish();
+    }
+
+    let mut current = indices[0] as usize;
+    for &index in &indices[1 ..] {
+        if let Some(&entry) = table.get(&(current, index)) {
+            current = entry;
+            continue;
+        }
+
+        writer.write(current, size);
+        table.insert((current, index), next);
+        next += 1;
+        if next > 1 << size && size < MAX_CODE_SIZE {
+            size += 1;
+        }
+
+        // NOTE(erick): Starting over when the table is full is simpler
+        // than deferred clears and costs little.
+        if next == MAX_CODES {
+            writer.write(clear, size);
+            table.clear();
+            size = minimum_size + 1;
+            next = clear + 2;
+        }
+        current = index as usize;
+    }
+
+    writer.write(current, size);
+    writer.write(end, size);
+    writer.finish()
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    encode_animation(&[(image, 0)], None)
+}
+
+// NOTE(erick): 'loops' is None for a still image, Some(0) to loop forever.
+// Every frame gets its own color table and is cleared before the next one
+// is drawn, so frames don't show through each other's transparent pixels.
+// The canvas fits the largest frame.
+pub fn encode_animation(frames: &[(&Image, u32)], loops: Option<u16>) -> Vec<u8> {
+    let width = frames.iter().map(|&(image, _)| image.width).max().unwrap_or(0);
+    let height = frames.iter().map(|&(image, _)| image.height).max().unwrap_or(0);
+
+    let mut output = Vec::new();
+    output.extend_from_slice(b"GIF89a");
+    output.extend_from_slice(&(width as u16).to_le_bytes());
+    output.extend_from_slice(&(height as u16).to_le_bytes());
+    output.extend_from_slice(&[0x70, 0, 0]);
+
+    if let Some(loops) = loops {
+        output.extend_from_slice(&[EXTENSION, APPLICATION, 11]);
+        output.extend_from_slice(b"NETSCAPE2.0");
+        output.extend_from_slice(&[3, 1]);
+        output.extend_from_slice(&loops.to_le_bytes());
+        output.push(0);
+    }
+
+    for &(image, delay) in frames {
+        write_frame(&mut output, image, delay, loops.is_some());
+    }
+
+    output.push(TRAILER);
+    output
+}
+
+fn write_frame(output: &mut Vec<u8>, image: &Image, delay: u32, animated: bool) {
+    let has_transparency = image.pixels.chunks(4).any(quantize::is_transparent);
+    let max_colors = if has_transparency { 255 } else { 256 };
+    let mut palette = Palette::build(&image.pixels, max_colors);
+
+    let transparent_index = palette.colors.len();
+    let color_count = transparent_index + has_transparency as usize;
+    let mut table_bits = 1;
+    while 1 << table_bits < color_count {
+        table_bits += 1;
+    }
+
+    let indices = image.pixels.chunks(4).map(|pixel| {
+        if quantize::is_transparent(pixel) {
+            transparent_index as u8
+        } else {
+            palette.index([pixel[0], pixel[1], pixel[2]])
+        }
+    }).collect::<Vec<_> >();
+
+    if animated || has_transparency {
+        let disposal = if animated { DISPOSE_BACKGROUND } else { 0 };
+        let centiseconds = ((delay + 5) / 10).min(u16::MAX as u32) as u16;
+        output.extend_from_slice(&[EXTENSION, GRAPHIC_CONTROL, 4,
+                                   disposal << 2 | has_transparency as u8]);
+        output.extend_from_slice(&centiseconds.to_le_bytes());
+        output.extend_from_slice(&[if has_transparency { transparent_index as u8 } else { 0 }, 0]);
+    }
+
+    output.push(IMAGE_DESCRIPTOR);
+    output.extend_from_slice(&[0, 0, 0, 0]);
+    output.extend_from_slice(&(image.width as u16).to_le_bytes());
+    output.extend_from_slice(&(image.height as u16).to_le_bytes());
+    output.push(0x80 | (table_bits - 1) as u8);
+    for i in 0 .. 1 << table_bits {
+        let color = palette.colors.get(i).cloned().unwrap_or([0, 0, 0]);
+        output.extend_from_slice(&color);
+    }
+
+    let minimum_size = (table_bits as u32).max(2);
+    output.push(minimum_size as u8);
+    let data = lzw_encode(&indices, minimum_size);
+    for block in data.chunks(255) {
+        output.push(block.len() as u8);
+        output.extend_from_slice(block);
+    }
+    output.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(erick): 256 colors at most, so nothing is lost to the palette.
+    fn sample_image(width: u32, height: u32, shift: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                image.set_pixel(x, y, [((x + shift) % 16 * 16) as u8, (y % 16 * 16) as u8, 50, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = sample_image(30, 20, 0);
+        let decoded = decode(&encode(&image)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (30, 20));
+        assert_eq!(decoded.pixels, image.pixels);
+    }
+
+    #[test]
+    fn transparency() {
+        let mut image = sample_image(8, 8, 0);
+        image.set_pixel(3, 4, [0, 0, 0, 0]);
+        let decoded = decode(&encode(&image)).unwrap();
+        assert_eq!(decoded.pixel(3, 4)[3], 0);
+        assert_eq!(decoded.pixel(4, 4), image.pixel(4, 4));
+    }
+
+    #[test]
+    fn animation() {
+        let first = sample_image(12, 10, 0);
+        let second = sample_image(12, 10, 5);
+        let file = encode_animation(&[(&first, 100), (&second, 200)], Some(0));
+        assert_eq!(frame_count(&file).unwrap(), 2);
+        assert_eq!(decode_frame(&file, 0).unwrap().pixels, first.pixels);
+        assert_eq!(decode_frame(&file, 1).unwrap().pixels, second.pixels);
+        assert!(decode_frame(&file, 2).is_err());
+    }
+
+    // NOTE(erick): Only the trailer can be missing.
+    #[test]
+    fn truncated() {
+        let file = encode(&sample_image(30, 20, 0));
+        for length in 0 .. file.len() - 1 {
+            assert!(decode(&file[.. length]).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_screen() {
+        let mut file = encode(&sample_image(4, 4, 0));
+        file[6 .. 10].copy_from_slice(&[0x60, 0xea, 0x60, 0xea]);
+        assert!(decode(&file).is_err());
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut file = encode(&sample_image(4, 4, 0));
+        // NOTE(erick): An opaque still image has nothing between the
+        // screen descriptor and the image descriptor.
+        assert_eq!(file[13], IMAGE_DESCRIPTOR);
+        file[18 .. 22].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let decoded = decode(&file).unwrap();
+        assert_eq!((decoded.width, decoded.height), (4, 4));
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = encode_animation(&[(&sample_image(12, 10, 0), 100),
+                                      (&sample_image(12, 10, 5), 100)], Some(0));
+        for index in 0 .. file.len() {
+            let mut corrupt = file.clone();
+            corrupt[index] ^= 0x10;
+            let _ = decode_frame(&corrupt, 1);
+        }
+    }
+}
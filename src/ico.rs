@@ -0,0 +1,235 @@
+use bmp;
+use image::Image;
+use png;
+
+// NOTE(erick): Reference:
+// https://learn.microsoft.com/en-us/previous-versions/ms997538(v=msdn.10)
+//
+// An ICONDIR followed by one 16 byte entry per image. Each image is
+// either a PNG file or a BMP without its file header. CUR files are the
+// same with the hotspot in place of the planes and bit count.
+
+const HEADER_SIZE : usize = 6;
+const ENTRY_SIZE  : usize = 16;
+
+const TYPE_ICON   : u16 = 1;
+const TYPE_CURSOR : u16 = 2;
+
+// NOTE(erick): Sizes are stored in a byte where 0 means 256.
+pub const MAX_SIZE : u32 = 256;
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    bytes[offset] as u32 | (bytes[offset + 1] as u32) << 8 |
+    (bytes[offset + 2] as u32) << 16 | (bytes[offset + 3] as u32) << 24
+}
+
+// NOTE(erick): The header is just zeros and small numbers, so the first
+// entry has to make sense too.
+pub fn is_ico(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE + ENTRY_SIZE || read_u16(bytes, 0) != 0 {
+        return false;
+    }
+
+    let kind = read_u16(bytes, 2);
+    let count = read_u16(bytes, 4) as usize;
+    if (kind != TYPE_ICON && kind != TYPE_CURSOR) || count == 0 {
+        return false;
+    }
+
+    let entry = HEADER_SIZE;
+    let size = read_u32(bytes, entry + 8) as usize;
+    let offset = read_u32(bytes, entry + 12) as usize;
+    if bytes[entry + 3] != 0 || size == 0 || offset < HEADER_SIZE + count * ENTRY_SIZE {
+        return false;
+    }
+
+    kind == TYPE_CURSOR || (read_u16(bytes, entry + 4) <= 1 && read_u16(bytes, entry + 6) <= 32)
+}
+
+pub fn is_cursor(bytes: &[u8]) -> bool {
+    is_ico(bytes) && read_u16(bytes, 2) == TYPE_CURSOR
+}
+
+#[inline]
+fn entry_size(value: u8) -> u32 {
+    if value == 0 { MAX_SIZE } else { value as u32 }
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if !is_ico(bytes) {
+        return None;
+    }
+
+    Some((entry_size(bytes[HEADER_SIZE]), entry_size(bytes[HEADER_SIZE + 1])))
+}
+
+pub fn image_count(bytes: &[u8]) -> Result<usize, String> {
+    if !is_ico(bytes) {
+        return Err("ICO: bad signature".to_string());
+    }
+
+    let count = read_u16(bytes, 4) as usize;
+    if bytes.len() < HEADER_SIZE + count * ENTRY_SIZE {
+        return Err("ICO: truncated directory".to_string());
+    }
+
+    Ok(count)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    decode_image(bytes, 0)
+}
+
+pub fn decode_image(bytes: &[u8], index: usize) -> Result<Image, String> {
+    let count = image_count(bytes)?;
+    if index >= count {
+        return Err(format!("ICO: image {} out of range, the file has {}", index + 1, count));
+    }
+
+    let entry = HEADER_SIZE + index * ENTRY_SIZE;
+    let size = read_u32(bytes, entry + 8) as usize;
+    let offset = read_u32(bytes, entry + 12) as usize;
+    if offset + size > bytes.len() {
+        return Err("ICO: image data out of bounds".to_string());
+    }
+
+    let data = &bytes[offset .. offset + size];
+    if png::is_png(data) {
+        png::decode(data)
+    } else {
+        bmp::decode_icon_bitmap(data)
+    }
+}
+
+pub fn encode(image: &Image, is_cursor: bool) -> Vec<u8> {
+    encode_images(&[image], is_cursor)
+}
+
+// NOTE(erick): 256 pixel images are stored as PNG like Windows does,
+// smaller ones as 32 bit bitmaps that every reader understands. Cursors
+// get their hotspot at the top left corner.
+pub fn encode_images(images: &[&Image], is_cursor: bool) -> Vec<u8> {
+    let data = images.iter().map(|image| {
+        if image.width >= MAX_SIZE || image.height >= MAX_SIZE {
+            png::encode(image)
+        } else {
+            bmp::encode_icon_bitmap(image)
+        }
+    }).collect::<Vec<_> >();
+
+    let mut output = Vec::new();
+    output.extend_from_slice(&0u16.to_le_bytes());
+    output.extend_from_slice(&(if is_cursor { TYPE_CURSOR } else { TYPE_ICON }).to_le_bytes());
+    output.extend_from_slice(&(images.len() as u16).to_le_bytes());
+
+    let mut offset = HEADER_SIZE + images.len() * ENTRY_SIZE;
+    for (image, data) in images.iter().zip(data.iter()) {
+        output.push(if image.width >= MAX_SIZE { 0 } else { image.width as u8 });
+        output.push(if image.height >= MAX_SIZE { 0 } else { image.height as u8 });
+        output.push(0);
+        output.push(0);
+        if is_cursor {
+            output.extend_from_slice(&[0, 0, 0, 0]);
+        } else {
+            output.extend_from_slice(&1u16.to_le_bytes());
+            output.extend_from_slice(&32u16.to_le_bytes());
+        }
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        output.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += data.len();
+    }
+
+    for data in data.iter() {
+        output.extend_from_slice(data);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(size: u32) -> Image {
+        let mut image = Image::new(size, size);
+        for y in 0 .. size {
+            for x in 0 .. size {
+                let alpha = if x == y { 0 } else { (255 - x) as u8 };
+                image.set_pixel(x, y, [x as u8, y as u8, (x ^ y) as u8, alpha]);
+            }
+        }
+        image
+    }
+
+    // NOTE(erick): An icon file holding just 'data'.
+    fn single_entry(data: &[u8], width: u8, height: u8) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 1, 0, 1, 0, width, height, 0, 0, 1, 0, 32, 0];
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&((HEADER_SIZE + ENTRY_SIZE) as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn multi_size_round_trip() {
+        let images = [test_image(16), test_image(33), test_image(256)];
+        let references = images.iter().collect::<Vec<_> >();
+        let bytes = encode_images(&references, false);
+
+        assert!(is_ico(&bytes) && !is_cursor(&bytes));
+        assert_eq!(image_count(&bytes).unwrap(), 3);
+        assert_eq!(dimensions(&bytes), Some((16, 16)));
+        for (index, image) in images.iter().enumerate() {
+            let decoded = decode_image(&bytes, index).unwrap();
+            assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+            assert!(decoded.pixels == image.pixels, "image {}", index);
+        }
+        assert!(decode_image(&bytes, 3).is_err());
+
+        let bytes = encode(&images[0], true);
+        assert!(is_cursor(&bytes));
+        assert!(decode(&bytes).unwrap().pixels == images[0].pixels);
+    }
+
+    #[test]
+    fn and_mask_only() {
+        let mut image = test_image(20);
+        for pixel in image.pixels.chunks_mut(4) {
+            pixel[3] = if pixel[3] == 0 { 0 } else { 255 };
+        }
+
+        // NOTE(erick): Old icons leave the alpha bytes at zero and only
+        // fill in the AND mask.
+        let mut data = bmp::encode_icon_bitmap(&image);
+        for pixel in data[40 .. 40 + 20 * 20 * 4].chunks_mut(4) {
+            pixel[3] = 0;
+        }
+
+        let decoded = decode(&single_entry(&data, 20, 20)).unwrap();
+        assert!(decoded.pixels == image.pixels);
+        assert_eq!(decoded.pixel(3, 3)[3], 0);
+        assert_eq!(decoded.pixel(3, 4)[3], 255);
+    }
+
+    #[test]
+    fn out_of_bounds_entry() {
+        let data = bmp::encode_icon_bitmap(&test_image(8));
+        let bytes = single_entry(&data, 8, 8);
+        assert!(decode(&bytes).is_ok());
+        assert!(decode(&bytes[.. bytes.len() - 1]).is_err());
+
+        let mut bad_offset = bytes.clone();
+        bad_offset[18 .. 22].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode(&bad_offset).is_err());
+
+        let mut bad_count = bytes.clone();
+        bad_count[4] = 9;
+        assert!(decode_image(&bad_count, 8).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+// NOTE(erick): Every codec decodes to and encodes from this format:
+// 8-bit RGBA, rows top to bottom, no padding between rows.
+#[derive(Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let index = (y as usize * self.width as usize + x as usize) * 4;
+        [self.pixels[index], self.pixels[index + 1],
+         self.pixels[index + 2], self.pixels[index + 3]]
+    }
+
+    #[inline]
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let index = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[index .. index + 4].copy_from_slice(&pixel);
+    }
+
+    // NOTE(erick): EXIF/TIFF orientation: 1 is already upright, 2 to 4
+    // mirror and rotate by 180, 5 to 8 swap the axes. Unknown values are
+    // treated as 1.
+    pub fn oriented(self, orientation: u16) -> Image {
+        if !(2 ..= 8).contains(&orientation) {
+            return self;
+        }
+
+        let (width, height) = (self.width, self.height);
+        let swaps_axes = orientation >= 5;
+        let mut result = if swaps_axes {
+            Image::new(height, width)
+        } else {
+            Image::new(width, height)
+        };
+
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let (to_x, to_y) = match orientation {
+                    2 => (width - 1 - x, y),
+                    3 => (width - 1 - x, height - 1 - y),
+                    4 => (x, height - 1 - y),
+                    5 => (y, x),
+                    6 => (height - 1 - y, x),
+                    7 => (height - 1 - y, width - 1 - x),
+                    _ => (y, width - 1 - x),
+                };
+                result.set_pixel(to_x, to_y, self.pixel(x, y));
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,1475 @@
+use std::sync::OnceLock;
+
+use image::Image;
+
+// NOTE(erick): Reference: ITU-T T.81 (the JPEG standard), JFIF 1.02 and
+// Adobe technical note 5116 for the APP14 marker.
+//
+// Every scan only fills in the coefficients, dequantization and the IDCT
+// happen once at the end. Baseline and progressive files go through the
+// same path that way.
+//
+// The encoder only writes baseline JFIF files with the example tables
+// from annex K, which is what most encoders do by default.
+
+const SOI   : u8 = 0xd8;
+const EOI   : u8 = 0xd9;
+const SOS   : u8 = 0xda;
+const DQT   : u8 = 0xdb;
+const DNL   : u8 = 0xdc;
+const DRI   : u8 = 0xdd;
+const DHT   : u8 = 0xc4;
+const SOF0  : u8 = 0xc0;
+const SOF1  : u8 = 0xc1;
+const SOF2  : u8 = 0xc2;
+const APP0  : u8 = 0xe0;
+const APP1  : u8 = 0xe1;
+const APP14 : u8 = 0xee;
+const RST0  : u8 = 0xd0;
+const RST7  : u8 = 0xd7;
+
+// NOTE(erick): The coefficients of a whole frame are kept until the end,
+// so the frame size is checked before they are allocated. Every block
+// takes at least one bit of scan data, and no image is bigger than
+// MAX_PIXELS.
+const MAX_PIXELS : usize = 400_000_000;
+
+// NOTE(erick): SOF stores the size in 16 bits.
+pub const MAX_SIZE : u32 = 65535;
+
+pub const ZIGZAG : [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+pub fn is_jpeg(bytes: &[u8]) -> bool {
+    bytes.len() >= 3 && bytes[0] == 0xff && bytes[1] == SOI && bytes[2] == 0xff
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+}
+
+// NOTE(erick): Calls 'visit' with every marker before the first scan and
+// the contents of its segment. Stops when 'visit' returns false.
+fn header_segments<F>(bytes: &[u8], mut visit: F) -> Result<(), String>
+    where F: FnMut(u8, &[u8]) -> Result<bool, String> {
+    if !is_jpeg(bytes) {
+        return Err("JPEG: bad signature".to_string());
+    }
+
+    let mut position = 2;
+    loop {
+        let (marker, segment, next) = next_segment(bytes, position)?;
+        if !visit(marker, segment)? || marker == SOS {
+            return Ok(());
+        }
+        position = next;
+    }
+}
+
+// NOTE(erick): Returns the marker at 'position', its segment without the
+// length and where the next marker starts. Markers may be preceded by
+// any number of 0xff fill bytes.
+fn next_segment(bytes: &[u8], position: usize) -> Result<(u8, &[u8], usize), String> {
+    let mut position = position;
+    if position >= bytes.len() || bytes[position] != 0xff {
+        return Err("JPEG: expected a marker".to_string());
+    }
+    while position < bytes.len() && bytes[position] == 0xff {
+        position += 1;
+    }
+    if position >= bytes.len() {
+        return Err("JPEG: unexpected end of file".to_string());
+    }
+
+    let marker = bytes[position];
+    position += 1;
+    if marker == EOI || (RST0 ..= RST7).contains(&marker) {
+        return Ok((marker, &bytes[position .. position], position));
+    }
+
+    if position + 2 > bytes.len() {
+        return Err("JPEG: unexpected end of file".to_string());
+    }
+    let length = read_u16(bytes, position) as usize;
+    if length < 2 || position + length > bytes.len() {
+        return Err("JPEG: bad segment length".to_string());
+    }
+
+    Ok((marker, &bytes[position + 2 .. position + length], position + length))
+}
+
+fn frame_size(segment: &[u8]) -> Result<(u32, u32), String> {
+    if segment.len() < 6 {
+        return Err("JPEG: bad frame header".to_string());
+    }
+    Ok((read_u16(segment, 3) as u32, read_u16(segment, 1) as u32))
+}
+
+// NOTE(erick): The orientation is the 0x0112 tag of the first IFD of the
+// TIFF structure inside the Exif APP1 segment.
+fn exif_orientation(segment: &[u8]) -> Option<u16> {
+    if segment.len() < 14 || &segment[0 .. 6] != b"Exif\0\0" {
+        return None;
+    }
+
+    let tiff = &segment[6 ..];
+    let big_endian = match &tiff[0 .. 2] {
+        b"MM" => true,
+        b"II" => false,
+        _     => return None,
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = tiff.get(offset .. offset + 2)?;
+        Some(if big_endian {
+            (bytes[0] as u16) << 8 | bytes[1] as u16
+        } else {
+            bytes[0] as u16 | (bytes[1] as u16) << 8
+        })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let high = u16_at(offset)? as u32;
+        let low = u16_at(offset + 2)? as u32;
+        Some(if big_endian { high << 16 | low } else { low << 16 | high })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let entry_count = u16_at(ifd)? as usize;
+    for i in 0 .. entry_count {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == 0x0112 {
+            return u16_at(entry + 8);
+        }
+    }
+
+    None
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut orientation = 1;
+    let mut size = None;
+    let result = header_segments(bytes, |marker, segment| {
+        match marker {
+            APP1 => { orientation = exif_orientation(segment).unwrap_or(orientation); },
+            SOF0 ..= 0xcf if marker != DHT && marker != 0xc8 && marker != 0xcc => {
+                size = Some(frame_size(segment)?);
+                return Ok(false);
+            },
+            _ => { },
+        }
+        Ok(true)
+    });
+
+    if result.is_err() || size.is_none() {
+        return None;
+    }
+
+    let (width, height) = size.unwrap();
+    if (5 ..= 8).contains(&orientation) {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+struct Huffman {
+    // NOTE(erick): Codes of up to 8 bits are found with a single lookup
+    // of the next 8 bits: length << 8 | symbol, 0 if the code is longer.
+    lookup: [u16; 256],
+    max_code: [i32; 17],
+    value_offset: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    // NOTE(erick): Codes are handed out in order, shorter first. Counts
+    // that need more codes of some length than there are left are
+    // rejected, they would write past the lookup table.
+    fn new(counts: &[u8], symbols: &[u8]) -> Result<Huffman, String> {
+        let mut huffman = Huffman {
+            lookup: [0; 256],
+            max_code: [-1; 17],
+            value_offset: [0; 17],
+            symbols: symbols.to_vec(),
+        };
+
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1 ..= 16 {
+            let count = counts[length - 1] as i32;
+            if code + count > 1 << length {
+                return Err("JPEG: invalid Huffman table".to_string());
+            }
+            huffman.value_offset[length] = index - code;
+            if count > 0 {
+                if length <= 8 {
+                    for i in 0 .. count {
+                        let first = ((code + i) << (8 - length)) as usize;
+                        let entry = (length as u16) << 8 | symbols[(index + i) as usize] as u16;
+                        for slot in first .. first + (1 << (8 - length)) {
+                            huffman.lookup[slot] = entry;
+                        }
+                    }
+                }
+                huffman.max_code[length] = code + count - 1;
+            }
+            code += count;
+            index += count;
+            code <<= 1;
+        }
+
+        Ok(huffman)
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    bits: u64,
+    count: u32,
+    // NOTE(erick): Once a marker shows up the data is over, zeros are fed
+    // from then on and the marker is left for the caller.
+    at_marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> BitReader<'a> {
+        BitReader { bytes, position, bits: 0, count: 0, at_marker: false }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let mut byte = 0;
+            if !self.at_marker && self.position < self.bytes.len() {
+                byte = self.bytes[self.position];
+                if byte == 0xff {
+                    let next = self.bytes.get(self.position + 1).cloned().unwrap_or(0xd9);
+                    if next == 0 {
+                        self.position += 2;
+                    } else {
+                        self.at_marker = true;
+                        byte = 0;
+                    }
+                } else {
+                    self.position += 1;
+                }
+            }
+
+            self.bits = self.bits << 8 | byte as u64;
+            self.count += 8;
+        }
+    }
+
+    #[inline]
+    fn peek(&mut self, count: u32) -> u32 {
+        if self.count < count {
+            self.fill();
+        }
+        ((self.bits >> (self.count - count)) & ((1 << count) - 1)) as u32
+    }
+
+    #[inline]
+    fn consume(&mut self, count: u32) {
+        self.count -= count;
+    }
+
+    #[inline]
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let result = self.peek(count);
+        self.consume(count);
+        result
+    }
+
+    #[inline]
+    fn bit(&mut self) -> bool {
+        self.bits(1) == 1
+    }
+
+    // NOTE(erick): 'count' bits holding a value in the JPEG magnitude
+    // encoding: values with the top bit clear are negative.
+    #[inline]
+    fn receive_extend(&mut self, count: u32) -> i32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = self.bits(count) as i32;
+        if value < 1 << (count - 1) {
+            value - (1 << count) + 1
+        } else {
+            value
+        }
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u8, String> {
+        let entry = huffman.lookup[self.peek(8) as usize];
+        if entry != 0 {
+            self.consume((entry >> 8) as u32);
+            return Ok(entry as u8);
+        }
+
+        let code = self.peek(16) as i32;
+        for length in 9 ..= 16 {
+            let prefix = code >> (16 - length);
+            if prefix <= huffman.max_code[length] {
+                self.consume(length as u32);
+                let index = (prefix + huffman.value_offset[length]) as usize;
+                return huffman.symbols.get(index).cloned()
+                    .ok_or("JPEG: bad Huffman code".to_string());
+            }
+        }
+
+        Err("JPEG: bad Huffman code".to_string())
+    }
+
+    // NOTE(erick): Drops the remaining bits and steps over the restart
+    // marker that should be next.
+    fn restart(&mut self) -> Result<(), String> {
+        self.bits = 0;
+        self.count = 0;
+        self.at_marker = false;
+
+        while self.position + 1 < self.bytes.len() && self.bytes[self.position] == 0xff &&
+            self.bytes[self.position + 1] == 0xff {
+            self.position += 1;
+        }
+        if self.position + 1 >= self.bytes.len() || self.bytes[self.position] != 0xff ||
+            !(RST0 ..= RST7).contains(&self.bytes[self.position + 1]) {
+            return Err("JPEG: missing restart marker".to_string());
+        }
+        self.position += 2;
+        Ok(())
+    }
+}
+
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    table: usize,
+    // NOTE(erick): Blocks covering the whole MCUs, the ones past the
+    // image edge are only used by interleaved scans.
+    blocks_wide: usize,
+    blocks_high: usize,
+    coefficients: Vec<i16>,
+    dc_table: usize,
+    ac_table: usize,
+    dc_prediction: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    max_horizontal: usize,
+    max_vertical: usize,
+    mcus_wide: usize,
+    mcus_high: usize,
+}
+
+struct Decoder {
+    frame: Option<Frame>,
+    quantization: [[u16; 64]; 4],
+    dc_tables: [Option<Huffman>; 4],
+    ac_tables: [Option<Huffman>; 4],
+    restart_interval: usize,
+    orientation: u16,
+    // NOTE(erick): -1 without an Adobe marker.
+    adobe_transform: i32,
+    end_of_band_run: u32,
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    if !is_jpeg(bytes) {
+        return Err("JPEG: bad signature".to_string());
+    }
+
+    let mut decoder = Decoder {
+        frame: None,
+        quantization: [[0; 64]; 4],
+        dc_tables: [None, None, None, None],
+        ac_tables: [None, None, None, None],
+        restart_interval: 0,
+        orientation: 1,
+        adobe_transform: -1,
+        end_of_band_run: 0,
+    };
+
+    let mut position = 2;
+    let mut scan_count = 0;
+    loop {
+        // NOTE(erick): Files cut short after some scans still decode to
+        // what's there, the way viewers show partial downloads.
+        if position >= bytes.len() && scan_count > 0 {
+            break;
+        }
+
+        let (marker, segment, next) = next_segment(bytes, position)?;
+        position = next;
+        match marker {
+            EOI => { break; },
+            DQT => { decoder.read_quantization_tables(segment)?; },
+            DHT => { decoder.read_huffman_tables(segment)?; },
+            DRI => {
+                if segment.len() < 2 {
+                    return Err("JPEG: bad restart interval".to_string());
+                }
+                decoder.restart_interval = read_u16(segment, 0) as usize;
+            },
+            SOF0 | SOF1 | SOF2 => {
+                decoder.read_frame(segment, marker == SOF2, bytes.len() - position)?;
+            },
+            0xc3 | 0xc5 ..= 0xc7 | 0xcb | 0xcd ..= 0xcf
+                => return Err("JPEG: lossless and hierarchical files are not supported".to_string()),
+            0xc9 | 0xca
+                => return Err("JPEG: arithmetic coding is not supported".to_string()),
+            DNL  => return Err("JPEG: DNL markers are not supported".to_string()),
+            APP1 => { decoder.orientation = exif_orientation(segment).unwrap_or(decoder.orientation); },
+            APP14 if segment.len() >= 12 && &segment[0 .. 5] == b"Adobe" => {
+                decoder.adobe_transform = segment[11] as i32;
+            },
+            SOS => {
+                position = decoder.read_scan(bytes, segment, position)?;
+                scan_count += 1;
+            },
+            _ => { },
+        }
+    }
+
+    if decoder.frame.is_none() {
+        return Err("JPEG: no frame".to_string());
+    }
+    if scan_count == 0 {
+        return Err("JPEG: no scan".to_string());
+    }
+
+    Ok(decoder.output()?.oriented(decoder.orientation))
+}
+
+impl Decoder {
+    fn read_quantization_tables(&mut self, segment: &[u8]) -> Result<(), String> {
+        let mut position = 0;
+        while position < segment.len() {
+            let precision = segment[position] >> 4;
+            let index = (segment[position] & 0x0f) as usize;
+            let size = if precision == 0 { 64 } else { 128 };
+            if index > 3 || position + 1 + size > segment.len() {
+                return Err("JPEG: bad quantization table".to_string());
+            }
+
+            for k in 0 .. 64 {
+                let value = if precision == 0 {
+                    segment[position + 1 + k] as u16
+                } else {
+                    read_u16(segment, position + 1 + 2 * k)
+                };
+                self.quantization[index][ZIGZAG[k]] = value;
+            }
+            position += 1 + size;
+        }
+
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self, segment: &[u8]) -> Result<(), String> {
+        let mut position = 0;
+        while position < segment.len() {
+            if position + 17 > segment.len() {
+                return Err("JPEG: bad Huffman table".to_string());
+            }
+
+            let class = segment[position] >> 4;
+            let index = (segment[position] & 0x0f) as usize;
+            let counts = &segment[position + 1 .. position + 17];
+            let total = counts.iter().map(|&count| count as usize).sum::<usize>();
+            if class > 1 || index > 3 || total > 256 || position + 17 + total > segment.len() {
+                return Err("JPEG: bad Huffman table".to_string());
+            }
+
+            let symbols = &segment[position + 17 .. position + 17 + total];
+            let table = Some(Huffman::new(counts, symbols)?);
+            if class == 0 {
+                self.dc_tables[index] = table;
+            } else {
+                self.ac_tables[index] = table;
+            }
+            position += 17 + total;
+        }
+
+        Ok(())
+    }
+
+    // NOTE(erick): 'remaining' is how many bytes of the file come after
+    // the frame header.
+    fn read_frame(&mut self, segment: &[u8], progressive: bool,
+                  remaining: usize) -> Result<(), String> {
+        if self.frame.is_some() {
+            return Err("JPEG: more than one frame".to_string());
+        }
+        if segment.len() < 6 {
+            return Err("JPEG: bad frame header".to_string());
+        }
+        if segment[0] != 8 {
+            return Err(format!("JPEG: {}-bit samples are not supported", segment[0]));
+        }
+
+        let (width, height) = frame_size(segment)?;
+        let component_count = segment[5] as usize;
+        if width == 0 || height == 0 {
+            return Err("JPEG: invalid dimensions".to_string());
+        }
+        let blocks = (width as usize).div_ceil(8) * (height as usize).div_ceil(8);
+        if width as usize * height as usize > MAX_PIXELS || blocks > remaining * 8 {
+            return Err(format!("JPEG: {}x{} is too big for the file", width, height));
+        }
+        if ![1, 3, 4].contains(&component_count) || segment.len() < 6 + component_count * 3 {
+            return Err("JPEG: bad frame header".to_string());
+        }
+
+        let mut components = Vec::new();
+        for i in 0 .. component_count {
+            let fields = &segment[6 + i * 3 ..];
+            let horizontal = (fields[1] >> 4) as usize;
+            let vertical = (fields[1] & 0x0f) as usize;
+            if !(1 ..= 4).contains(&horizontal) || !(1 ..= 4).contains(&vertical) || fields[2] > 3 {
+                return Err("JPEG: bad frame header".to_string());
+            }
+            components.push(Component {
+                id: fields[0],
+                horizontal,
+                vertical,
+                table: fields[2] as usize,
+                blocks_wide: 0,
+                blocks_high: 0,
+                coefficients: Vec::new(),
+                dc_table: 0,
+                ac_table: 0,
+                dc_prediction: 0,
+            });
+        }
+
+        let max_horizontal = components.iter().map(|c| c.horizontal).max().unwrap();
+        let max_vertical = components.iter().map(|c| c.vertical).max().unwrap();
+        let mcus_wide = (width as usize).div_ceil(8 * max_horizontal);
+        let mcus_high = (height as usize).div_ceil(8 * max_vertical);
+        for component in components.iter_mut() {
+            component.blocks_wide = mcus_wide * component.horizontal;
+            component.blocks_high = mcus_high * component.vertical;
+            component.coefficients = vec![0; component.blocks_wide * component.blocks_high * 64];
+        }
+
+        self.frame = Some(Frame {
+            width: width as usize,
+            height: height as usize,
+            progressive,
+            components,
+            max_horizontal,
+            max_vertical,
+            mcus_wide,
+            mcus_high,
+        });
+        Ok(())
+    }
+
+    // NOTE(erick): Returns where the marker after the scan data starts.
+    fn read_scan(&mut self, bytes: &[u8], segment: &[u8], data_start: usize) -> Result<usize, String> {
+        if self.frame.is_none() {
+            return Err("JPEG: scan before the frame header".to_string());
+        }
+
+        let count = *segment.first().unwrap_or(&0) as usize;
+        if count == 0 || count > 4 || segment.len() < 4 + count * 2 {
+            return Err("JPEG: bad scan header".to_string());
+        }
+
+        let mut scan_components = Vec::new();
+        {
+            let frame = self.frame.as_mut().unwrap();
+            for i in 0 .. count {
+                let id = segment[1 + i * 2];
+                let tables = segment[2 + i * 2];
+                let index = frame.components.iter().position(|c| c.id == id);
+                if index.is_none() || (tables >> 4) > 3 || (tables & 0x0f) > 3 {
+                    return Err("JPEG: bad scan header".to_string());
+                }
+
+                let component = &mut frame.components[index.unwrap()];
+                component.dc_table = (tables >> 4) as usize;
+                component.ac_table = (tables & 0x0f) as usize;
+                component.dc_prediction = 0;
+                scan_components.push(index.unwrap());
+            }
+        }
+
+        let parameters = &segment[1 + count * 2 ..];
+        let spectral_start = parameters[0] as usize;
+        let spectral_end = parameters[1] as usize;
+        let high_bit = (parameters[2] >> 4) as u32;
+        let low_bit = (parameters[2] & 0x0f) as u32;
+        let progressive = self.frame.as_ref().unwrap().progressive;
+        if !progressive && (spectral_start != 0 || spectral_end != 63 || high_bit != 0 || low_bit != 0) {
+            return Err("JPEG: bad scan header".to_string());
+        }
+        if spectral_end > 63 || spectral_start > spectral_end ||
+            (spectral_start == 0 && spectral_end != 0 && progressive) ||
+            (spectral_start > 0 && count != 1) || low_bit > 13 {
+            return Err("JPEG: bad progressive scan".to_string());
+        }
+
+        let scan = Scan { components: scan_components, spectral_start, spectral_end, high_bit, low_bit };
+        let mut reader = BitReader::new(bytes, data_start);
+        self.end_of_band_run = 0;
+        self.decode_scan(&scan, &mut reader)?;
+
+        // NOTE(erick): Padding or garbage may come before the next marker.
+        let mut position = reader.position;
+        loop {
+            if position + 1 >= bytes.len() {
+                return Ok(bytes.len());
+            }
+            let next = bytes[position + 1];
+            if bytes[position] == 0xff && next != 0 && !(RST0 ..= RST7).contains(&next) {
+                return Ok(position);
+            }
+            position += 1;
+        }
+    }
+
+    fn decode_scan(&mut self, scan: &Scan, reader: &mut BitReader) -> Result<(), String> {
+        let frame = self.frame.as_mut().unwrap();
+
+        // NOTE(erick): A scan with one component goes over its blocks in
+        // raster order and only the ones inside the image, every block is
+        // an MCU. Interleaved scans go over whole MCUs.
+        let single = scan.components.len() == 1;
+        let (units_wide, units_high) = if single {
+            let component = &frame.components[scan.components[0]];
+            ((frame.width * component.horizontal).div_ceil(8 * frame.max_horizontal),
+             (frame.height * component.vertical).div_ceil(8 * frame.max_vertical))
+        } else {
+            (frame.mcus_wide, frame.mcus_high)
+        };
+
+        let mut units_left = self.restart_interval;
+        for unit_y in 0 .. units_high {
+            for unit_x in 0 .. units_wide {
+                if self.restart_interval != 0 {
+                    if units_left == 0 {
+                        reader.restart()?;
+                        for &index in scan.components.iter() {
+                            frame.components[index].dc_prediction = 0;
+                        }
+                        self.end_of_band_run = 0;
+                        units_left = self.restart_interval;
+                    }
+                    units_left -= 1;
+                }
+
+                for &index in scan.components.iter() {
+                    let component = &mut frame.components[index];
+                    let (repeat_x, repeat_y) = if single {
+                        (1, 1)
+                    } else {
+                        (component.horizontal, component.vertical)
+                    };
+
+                    for block_y in 0 .. repeat_y {
+                        for block_x in 0 .. repeat_x {
+                            let x = unit_x * repeat_x + block_x;
+                            let y = unit_y * repeat_y + block_y;
+                            let offset = (y * component.blocks_wide + x) * 64;
+                            let dc_table = self.dc_tables[component.dc_table].as_ref();
+                            let ac_table = self.ac_tables[component.ac_table].as_ref();
+                            let block = &mut component.coefficients[offset .. offset + 64];
+                            decode_block(reader, scan, block, &mut component.dc_prediction,
+                                         dc_table, ac_table, &mut self.end_of_band_run,
+                                         frame.progressive)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn output(&self) -> Result<Image, String> {
+        let frame = self.frame.as_ref().unwrap();
+
+        let planes = frame.components.iter().map(|component| {
+            plane(component, &self.quantization[component.table])
+        }).collect::<Vec<_> >();
+
+        // NOTE(erick): Adobe's transform flag wins over the component ids,
+        // which some writers set to 'R', 'G' and 'B' for untransformed RGB.
+        let ids = frame.components.iter().map(|c| c.id).collect::<Vec<_> >();
+        let is_rgb = frame.components.len() == 3 &&
+            (self.adobe_transform == 0 || (self.adobe_transform == -1 && ids == b"RGB"));
+        let is_ycck = frame.components.len() == 4 && self.adobe_transform == 2;
+
+        let mut image = Image::new(frame.width as u32, frame.height as u32);
+        let mut samples = [0u8; 4];
+        for y in 0 .. frame.height {
+            for x in 0 .. frame.width {
+                for (i, component) in frame.components.iter().enumerate() {
+                    samples[i] = sample(&planes[i], component, frame, x, y);
+                }
+
+                let pixel = match frame.components.len() {
+                    1 => [samples[0], samples[0], samples[0], 255],
+                    3 if is_rgb => [samples[0], samples[1], samples[2], 255],
+                    3 => {
+                        let rgb = ycbcr_to_rgb(samples[0], samples[1], samples[2]);
+                        [rgb[0], rgb[1], rgb[2], 255]
+                    },
+                    _ => {
+                        // NOTE(erick): Adobe stores CMYK inverted, so after
+                        // the YCC conversion (if any) the channels already
+                        // are 255 - C and so on.
+                        let cmy = if is_ycck {
+                            let rgb = ycbcr_to_rgb(samples[0], samples[1], samples[2]);
+                            [255 - rgb[0], 255 - rgb[1], 255 - rgb[2]]
+                        } else {
+                            [samples[0], samples[1], samples[2]]
+                        };
+                        let k = samples[3] as u32;
+                        [(cmy[0] as u32 * k / 255) as u8, (cmy[1] as u32 * k / 255) as u8,
+                         (cmy[2] as u32 * k / 255) as u8, 255]
+                    },
+                };
+                image.set_pixel(x as u32, y as u32, pixel);
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+struct Scan {
+    components: Vec<usize>,
+    spectral_start: usize,
+    spectral_end: usize,
+    high_bit: u32,
+    low_bit: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_block(reader: &mut BitReader, scan: &Scan, block: &mut [i16],
+                dc_prediction: &mut i32,
+                dc_table: Option<&Huffman>, ac_table: Option<&Huffman>,
+                end_of_band_run: &mut u32, progressive: bool) -> Result<(), String> {
+    let missing_table = || "JPEG: scan uses an undefined Huffman table".to_string();
+
+    if scan.spectral_start == 0 {
+        if scan.high_bit == 0 {
+            let dc_table = dc_table.ok_or_else(missing_table)?;
+            let size = reader.decode(dc_table)? as u32;
+            if size > 16 {
+                return Err("JPEG: bad DC coefficient".to_string());
+            }
+            *dc_prediction += reader.receive_extend(size);
+            block[0] = (*dc_prediction << scan.low_bit) as i16;
+        } else if reader.bit() {
+            block[0] |= 1 << scan.low_bit;
+        }
+
+        if progressive {
+            return Ok(());
+        }
+    }
+
+    let ac_table = ac_table.ok_or_else(missing_table)?;
+    if !progressive {
+        let mut k = 1;
+        while k < 64 {
+            let symbol = reader.decode(ac_table)?;
+            let run = (symbol >> 4) as usize;
+            let size = (symbol & 0x0f) as u32;
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+
+            k += run;
+            if k > 63 {
+                return Err("JPEG: bad AC coefficient".to_string());
+            }
+            block[ZIGZAG[k]] = reader.receive_extend(size) as i16;
+            k += 1;
+        }
+        return Ok(());
+    }
+
+    if scan.high_bit == 0 {
+        decode_ac_first(reader, scan, block, ac_table, end_of_band_run)
+    } else {
+        decode_ac_refinement(reader, scan, block, ac_table, end_of_band_run)
+    }
+}
+
+fn decode_ac_first(reader: &mut BitReader, scan: &Scan, block: &mut [i16],
+                   ac_table: &Huffman, end_of_band_run: &mut u32) -> Result<(), String> {
+    if *end_of_band_run > 0 {
+        *end_of_band_run -= 1;
+        return Ok(());
+    }
+
+    let mut k = scan.spectral_start;
+    while k <= scan.spectral_end {
+        let symbol = reader.decode(ac_table)?;
+        let run = (symbol >> 4) as u32;
+        let size = (symbol & 0x0f) as u32;
+        if size == 0 {
+            if run < 15 {
+                *end_of_band_run = (1 << run) - 1 + reader.bits(run);
+                break;
+            }
+            k += 16;
+            continue;
+        }
+
+        k += run as usize;
+        if k > scan.spectral_end {
+            return Err("JPEG: bad AC coefficient".to_string());
+        }
+        block[ZIGZAG[k]] = (reader.receive_extend(size) * (1 << scan.low_bit)) as i16;
+        k += 1;
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Refinement scans send one more bit of every coefficient
+// that is already nonzero, interleaved with the new coefficients that
+// become nonzero at this bit. Runs count only the zero coefficients.
+fn decode_ac_refinement(reader: &mut BitReader, scan: &Scan, block: &mut [i16],
+                        ac_table: &Huffman, end_of_band_run: &mut u32) -> Result<(), String> {
+    let positive = 1i16 << scan.low_bit;
+    let negative = -1i16 << scan.low_bit;
+    let refine = |reader: &mut BitReader, coefficient: &mut i16| {
+        if reader.bit() && *coefficient & positive == 0 {
+            *coefficient += if *coefficient >= 0 { positive } else { negative };
+        }
+    };
+
+    let mut k = scan.spectral_start;
+    if *end_of_band_run == 0 {
+        while k <= scan.spectral_end {
+            let symbol = reader.decode(ac_table)?;
+            let mut run = (symbol >> 4) as i32;
+            let size = symbol & 0x0f;
+            let mut value = 0;
+            if size == 0 {
+                if run < 15 {
+                    *end_of_band_run = (1 << run) + reader.bits(run as u32);
+                    break;
+                }
+            } else {
+                if size != 1 {
+                    return Err("JPEG: bad refinement coefficient".to_string());
+                }
+                value = if reader.bit() { positive } else { negative };
+            }
+
+            while k <= scan.spectral_end {
+                let coefficient = &mut block[ZIGZAG[k]];
+                if *coefficient != 0 {
+                    refine(reader, coefficient);
+                } else {
+                    if run == 0 {
+                        if value != 0 {
+                            *coefficient = value;
+                        }
+                        k += 1;
+                        break;
+                    }
+                    run -= 1;
+                }
+                k += 1;
+            }
+        }
+    }
+
+    if *end_of_band_run > 0 {
+        while k <= scan.spectral_end {
+            let coefficient = &mut block[ZIGZAG[k]];
+            if *coefficient != 0 {
+                refine(reader, coefficient);
+            }
+            k += 1;
+        }
+        *end_of_band_run -= 1;
+    }
+
+    Ok(())
+}
+
+struct Plane {
+    width: usize,
+    height: usize,
+    samples: Vec<u8>,
+}
+
+fn plane(component: &Component, quantization: &[u16; 64]) -> Plane {
+    let width = component.blocks_wide * 8;
+    let height = component.blocks_high * 8;
+    let mut samples = vec![0u8; width * height];
+
+    let mut block = [0f32; 64];
+    for block_y in 0 .. component.blocks_high {
+        for block_x in 0 .. component.blocks_wide {
+            let offset = (block_y * component.blocks_wide + block_x) * 64;
+            let coefficients = &component.coefficients[offset .. offset + 64];
+            for i in 0 .. 64 {
+                block[i] = coefficients[i] as f32 * quantization[i] as f32;
+            }
+            inverse_dct(&mut block);
+
+            for y in 0 .. 8 {
+                let row = (block_y * 8 + y) * width + block_x * 8;
+                for x in 0 .. 8 {
+                    samples[row + x] = (block[y * 8 + x] + 128.0).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+
+    Plane { width, height, samples }
+}
+
+// NOTE(erick): Straight from the definition, one dimension at a time.
+// Blocks that only have a DC coefficient, which are most of them in
+// smooth areas, skip the work.
+fn inverse_dct(block: &mut [f32; 64]) {
+    if block[1 ..].iter().all(|&value| value == 0.0) {
+        let value = block[0] / 8.0;
+        block.iter_mut().for_each(|sample| *sample = value);
+        return;
+    }
+
+    let table = cosine_table();
+    let mut temporary = [0f32; 64];
+    for y in 0 .. 8 {
+        for x in 0 .. 8 {
+            let mut sum = 0.0;
+            for u in 0 .. 8 {
+                sum += table[x * 8 + u] * block[y * 8 + u];
+            }
+            temporary[y * 8 + x] = sum;
+        }
+    }
+    for x in 0 .. 8 {
+        for y in 0 .. 8 {
+            let mut sum = 0.0;
+            for v in 0 .. 8 {
+                sum += table[y * 8 + v] * temporary[v * 8 + x];
+            }
+            block[y * 8 + x] = sum;
+        }
+    }
+}
+
+// NOTE(erick): table[x * 8 + u] = C(u) / 2 * cos((2x + 1) u pi / 16)
+fn cosine_table() -> &'static [f32; 64] {
+    static TABLE : OnceLock<[f32; 64]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0f32; 64];
+        for x in 0 .. 8 {
+            for u in 0 .. 8 {
+                let scale = if u == 0 { 1.0 / 2f64.sqrt() } else { 1.0 };
+                let angle = (2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.0;
+                table[x * 8 + u] = (scale / 2.0 * angle.cos()) as f32;
+            }
+        }
+        table
+    })
+}
+
+// NOTE(erick): Subsampled components are interpolated linearly between
+// sample centers, the way libjpeg's "fancy" upsampling looks.
+#[inline]
+fn sample(plane: &Plane, component: &Component, frame: &Frame, x: usize, y: usize) -> u8 {
+    if component.horizontal == frame.max_horizontal && component.vertical == frame.max_vertical {
+        return plane.samples[y * plane.width + x];
+    }
+
+    let scale_x = component.horizontal as f32 / frame.max_horizontal as f32;
+    let scale_y = component.vertical as f32 / frame.max_vertical as f32;
+    let source_x = ((x as f32 + 0.5) * scale_x - 0.5).max(0.0);
+    let source_y = ((y as f32 + 0.5) * scale_y - 0.5).max(0.0);
+
+    let x0 = (source_x as usize).min(plane.width - 1);
+    let y0 = (source_y as usize).min(plane.height - 1);
+    let x1 = (x0 + 1).min(plane.width - 1);
+    let y1 = (y0 + 1).min(plane.height - 1);
+    let fraction_x = source_x - x0 as f32;
+    let fraction_y = source_y - y0 as f32;
+
+    let at = |x: usize, y: usize| plane.samples[y * plane.width + x] as f32;
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fraction_x;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fraction_x;
+    (top + (bottom - top) * fraction_y).round() as u8
+}
+
+#[inline]
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let y = y as f32;
+    let cb = cb as f32 - 128.0;
+    let cr = cr as f32 - 128.0;
+    let clamp = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    [clamp(y + 1.402 * cr),
+     clamp(y - 0.344136 * cb - 0.714136 * cr),
+     clamp(y + 1.772 * cb)]
+}
+
+// NOTE(erick): Annex K tables, in natural order. They are scaled by the
+// quality the same way libjpeg does, so quality numbers mean roughly the
+// same thing as in other programs.
+const LUMINANCE_QUANTIZATION : [u16; 64] = [
+    16,  11,  10,  16,  24,  40,  51,  61,
+    12,  12,  14,  19,  26,  58,  60,  55,
+    14,  13,  16,  24,  40,  57,  69,  56,
+    14,  17,  22,  29,  51,  87,  80,  62,
+    18,  22,  37,  56,  68, 109, 103,  77,
+    24,  35,  55,  64,  81, 104, 113,  92,
+    49,  64,  78,  87, 103, 121, 120, 101,
+    72,  92,  95,  98, 112, 100, 103,  99,
+];
+
+const CHROMINANCE_QUANTIZATION : [u16; 64] = [
+    17,  18,  24,  47,  99,  99,  99,  99,
+    18,  21,  26,  66,  99,  99,  99,  99,
+    24,  26,  56,  99,  99,  99,  99,  99,
+    47,  66,  99,  99,  99,  99,  99,  99,
+    99,  99,  99,  99,  99,  99,  99,  99,
+    99,  99,  99,  99,  99,  99,  99,  99,
+    99,  99,  99,  99,  99,  99,  99,  99,
+    99,  99,  99,  99,  99,  99,  99,  99,
+];
+
+// NOTE(erick): Annex K Huffman tables, as the code counts per length and
+// the symbols, the way DHT segments store them.
+const DC_LUMINANCE_COUNTS : [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMINANCE_COUNTS : [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_SYMBOLS : [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMINANCE_COUNTS : [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMINANCE_SYMBOLS : [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMINANCE_COUNTS : [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMINANCE_SYMBOLS : [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// NOTE(erick): Bits are written from the most significant one, and every
+// 0xff byte in the entropy coded data is followed by a 0x00 so it isn't
+// taken for a marker.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, length: u32) {
+        self.buffer = self.buffer << length | (value & ((1 << length) - 1));
+        self.count += length;
+        while self.count >= 8 {
+            let byte = (self.buffer >> (self.count - 8)) as u8;
+            self.bytes.push(byte);
+            if byte == 0xff {
+                self.bytes.push(0);
+            }
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: (u16, u8)) {
+        self.write(code.0 as u32, code.1 as u32);
+    }
+
+    // NOTE(erick): The last byte is padded with ones.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            let padding = 8 - self.count;
+            self.write((1 << padding) - 1, padding);
+        }
+        self.bytes
+    }
+}
+
+// NOTE(erick): (code, length) for every symbol, built the same way the
+// decoder does in Huffman::new.
+fn huffman_codes(counts: &[u8], symbols: &[u8]) -> [(u16, u8); 256] {
+    let mut codes = [(0u16, 0u8); 256];
+    let mut code = 0u16;
+    let mut index = 0;
+    for length in 1 ..= 16 {
+        for _ in 0 .. counts[length - 1] {
+            codes[symbols[index] as usize] = (code, length as u8);
+            code += 1;
+            index += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+fn scaled_quantization(table: &[u16; 64], quality: u32) -> [u16; 64] {
+    let quality = quality.clamp(1, 100);
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+
+    let mut scaled = [0u16; 64];
+    for i in 0 .. 64 {
+        scaled[i] = ((table[i] as u32 * scale + 50) / 100).clamp(1, 255) as u16;
+    }
+    scaled
+}
+
+fn write_segment(output: &mut Vec<u8>, marker: u8, contents: &[u8]) {
+    output.extend_from_slice(&[0xff, marker]);
+    output.extend_from_slice(&((contents.len() + 2) as u16).to_be_bytes());
+    output.extend_from_slice(contents);
+}
+
+// NOTE(erick): Full resolution sample planes, already centered around 0
+// the way the DCT wants them.
+struct SourcePlanes {
+    width: usize,
+    height: usize,
+    planes: Vec<Vec<f32>>,
+}
+
+impl SourcePlanes {
+    fn new(image: &Image, is_gray: bool) -> SourcePlanes {
+        let count = if is_gray { 1 } else { 3 };
+        let mut planes = vec![Vec::with_capacity(image.pixels.len() / 4); count];
+        for pixel in image.pixels.chunks(4) {
+            let r = pixel[0] as f32;
+            let g = pixel[1] as f32;
+            let b = pixel[2] as f32;
+            planes[0].push(0.299 * r + 0.587 * g + 0.114 * b - 128.0);
+            if !is_gray {
+                planes[1].push(-0.168736 * r - 0.331264 * g + 0.5 * b);
+                planes[2].push(0.5 * r - 0.418688 * g - 0.081312 * b);
+            }
+        }
+
+        SourcePlanes { width: image.width as usize, height: image.height as usize, planes }
+    }
+
+    // NOTE(erick): Reads the 8x8 block at (x, y) of a plane shrunk by
+    // 'scale' in both axes, averaging the samples it covers. Blocks past
+    // the edges repeat the last row and column.
+    fn block(&self, plane: usize, x: usize, y: usize, scale: usize, block: &mut [f32; 64]) {
+        let samples = &self.planes[plane];
+        for row in 0 .. 8 {
+            for column in 0 .. 8 {
+                let mut sum = 0.0;
+                for dy in 0 .. scale {
+                    let source_y = ((y + row) * scale + dy).min(self.height - 1);
+                    for dx in 0 .. scale {
+                        let source_x = ((x + column) * scale + dx).min(self.width - 1);
+                        sum += samples[source_y * self.width + source_x];
+                    }
+                }
+                block[row * 8 + column] = sum / (scale * scale) as f32;
+            }
+        }
+    }
+}
+
+// NOTE(erick): The transpose of inverse_dct, with the same table. The
+// result comes out quantized and in zigzag order.
+fn forward_dct(block: &[f32; 64], quantization: &[u16; 64], output: &mut [i32; 64]) {
+    let table = cosine_table();
+    let mut temporary = [0f32; 64];
+    for v in 0 .. 8 {
+        for x in 0 .. 8 {
+            let mut sum = 0.0;
+            for y in 0 .. 8 {
+                sum += table[y * 8 + v] * block[y * 8 + x];
+            }
+            temporary[v * 8 + x] = sum;
+        }
+    }
+
+    let mut coefficients = [0f32; 64];
+    for v in 0 .. 8 {
+        for u in 0 .. 8 {
+            let mut sum = 0.0;
+            for x in 0 .. 8 {
+                sum += table[x * 8 + u] * temporary[v * 8 + x];
+            }
+            coefficients[v * 8 + u] = sum;
+        }
+    }
+
+    for k in 0 .. 64 {
+        let index = ZIGZAG[k];
+        output[k] = (coefficients[index] / quantization[index] as f32).round() as i32;
+    }
+}
+
+// NOTE(erick): The number of bits a value needs, and those bits the way
+// the standard stores them: negative values as value - 1 in that size.
+#[inline]
+fn magnitude(value: i32) -> (u32, u32) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { value - 1 } else { value };
+    (bits as u32, size)
+}
+
+fn encode_block(writer: &mut BitWriter, coefficients: &[i32; 64], previous_dc: &mut i32,
+                dc_codes: &[(u16, u8); 256], ac_codes: &[(u16, u8); 256]) {
+    let (bits, size) = magnitude(coefficients[0] - *previous_dc);
+    *previous_dc = coefficients[0];
+    writer.write_code(dc_codes[size as usize]);
+    writer.write(bits, size);
+
+    let mut run = 0;
+    for &coefficient in &coefficients[1 ..] {
+        if coefficient == 0 {
+            run += 1;
+            continue;
+        }
+
+        while run > 15 {
+            writer.write_code(ac_codes[0xf0]);
+            run -= 16;
+        }
+        let (bits, size) = magnitude(coefficient);
+        writer.write_code(ac_codes[(run << 4 | size) as usize]);
+        writer.write(bits, size);
+        run = 0;
+    }
+
+    if run > 0 {
+        writer.write_code(ac_codes[0x00]);
+    }
+}
+
+// NOTE(erick): Alpha is dropped. Gray images are written with a single
+// component, the others as YCbCr with the chroma halved in both axes
+// when 'subsample_chroma' is set.
+pub fn encode(image: &Image, quality: u32, subsample_chroma: bool) -> Vec<u8> {
+    let is_gray = image.pixels.chunks(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let component_count = if is_gray { 1 } else { 3 };
+    let scale = if subsample_chroma && !is_gray { 2 } else { 1 };
+
+    let luminance = scaled_quantization(&LUMINANCE_QUANTIZATION, quality);
+    let chrominance = scaled_quantization(&CHROMINANCE_QUANTIZATION, quality);
+
+    let mut output = vec![0xff, SOI];
+    write_segment(&mut output, APP0, &[b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0]);
+
+    let mut tables = Vec::new();
+    for (index, table) in [&luminance, &chrominance].iter().take(component_count.min(2)).enumerate() {
+        tables.push(index as u8);
+        tables.extend(ZIGZAG.iter().map(|&i| table[i] as u8));
+    }
+    write_segment(&mut output, DQT, &tables);
+
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(image.height as u16).to_be_bytes());
+    frame.extend_from_slice(&(image.width as u16).to_be_bytes());
+    frame.push(component_count as u8);
+    for component in 0 .. component_count {
+        let sampling = if component == 0 { (scale * 16 + scale) as u8 } else { 0x11 };
+        frame.extend_from_slice(&[component as u8 + 1, sampling, component.min(1) as u8]);
+    }
+    write_segment(&mut output, SOF0, &frame);
+
+    let mut huffman_tables = Vec::new();
+    let table_specs : [(u8, &[u8], &[u8]); 4] = [
+        (0x00, &DC_LUMINANCE_COUNTS,   &DC_SYMBOLS),
+        (0x10, &AC_LUMINANCE_COUNTS,   &AC_LUMINANCE_SYMBOLS),
+        (0x01, &DC_CHROMINANCE_COUNTS, &DC_SYMBOLS),
+        (0x11, &AC_CHROMINANCE_COUNTS, &AC_CHROMINANCE_SYMBOLS),
+    ];
+    for &(class_and_index, counts, symbols) in table_specs.iter().take(component_count.min(2) * 2) {
+        huffman_tables.push(class_and_index);
+        huffman_tables.extend_from_slice(counts);
+        huffman_tables.extend_from_slice(symbols);
+    }
+    write_segment(&mut output, DHT, &huffman_tables);
+
+    let mut scan = vec![component_count as u8];
+    for component in 0 .. component_count {
+        let tables = if component == 0 { 0x00 } else { 0x11 };
+        scan.extend_from_slice(&[component as u8 + 1, tables]);
+    }
+    scan.extend_from_slice(&[0, 63, 0]);
+    write_segment(&mut output, SOS, &scan);
+
+    let dc_codes = [huffman_codes(&DC_LUMINANCE_COUNTS, &DC_SYMBOLS),
+                    huffman_codes(&DC_CHROMINANCE_COUNTS, &DC_SYMBOLS)];
+    let ac_codes = [huffman_codes(&AC_LUMINANCE_COUNTS, &AC_LUMINANCE_SYMBOLS),
+                    huffman_codes(&AC_CHROMINANCE_COUNTS, &AC_CHROMINANCE_SYMBOLS)];
+    let quantization = [&luminance, &chrominance];
+
+    let source = SourcePlanes::new(image, is_gray);
+    let mcu_size = 8 * scale;
+    let mcus_wide = source.width.div_ceil(mcu_size);
+    let mcus_high = source.height.div_ceil(mcu_size);
+
+    let mut writer = BitWriter { bytes: Vec::new(), buffer: 0, count: 0 };
+    let mut previous_dc = [0i32; 3];
+    let mut block = [0f32; 64];
+    let mut coefficients = [0i32; 64];
+    for mcu_y in 0 .. mcus_high {
+        for mcu_x in 0 .. mcus_wide {
+            for y in 0 .. scale {
+                for x in 0 .. scale {
+                    source.block(0, mcu_x * mcu_size + x * 8, mcu_y * mcu_size + y * 8, 1, &mut block);
+                    forward_dct(&block, quantization[0], &mut coefficients);
+                    encode_block(&mut writer, &coefficients, &mut previous_dc[0],
+                                 &dc_codes[0], &ac_codes[0]);
+                }
+            }
+
+            let chroma_dc = previous_dc.iter_mut().enumerate().take(component_count).skip(1);
+            for (component, dc) in chroma_dc {
+                source.block(component, mcu_x * 8, mcu_y * 8, scale, &mut block);
+                forward_dct(&block, quantization[1], &mut coefficients);
+                encode_block(&mut writer, &coefficients, dc, &dc_codes[1], &ac_codes[1]);
+            }
+        }
+    }
+
+    output.extend_from_slice(&writer.finish());
+    output.extend_from_slice(&[0xff, EOI]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_image(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                image.set_pixel(x, y, [(x * 255 / width) as u8, (y * 255 / height) as u8,
+                                       ((x + y) * 2) as u8, 255]);
+            }
+        }
+        image
+    }
+
+    // NOTE(erick): Offset of the first segment with this marker, walking
+    // the segments before the scan data.
+    fn find_segment(file: &[u8], marker: u8) -> usize {
+        let mut position = 2;
+        while file[position + 1] != marker {
+            position += 2 + read_u16(file, position + 2) as usize;
+        }
+        position
+    }
+
+    fn largest_error(a: &Image, b: &Image) -> i32 {
+        a.pixels.iter().zip(b.pixels.iter())
+            .map(|(&x, &y)| (x as i32 - y as i32).abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = sample_image(45, 29);
+        for &subsample in [false, true].iter() {
+            let decoded = decode(&encode(&image, 95, subsample)).unwrap();
+            assert_eq!((decoded.width, decoded.height), (45, 29));
+            assert!(largest_error(&image, &decoded) <= 16);
+        }
+    }
+
+    #[test]
+    fn gray_round_trip() {
+        let mut image = sample_image(16, 9);
+        for pixel in image.pixels.chunks_mut(4) {
+            pixel[1] = pixel[0];
+            pixel[2] = pixel[0];
+        }
+        let decoded = decode(&encode(&image, 95, true)).unwrap();
+        assert!(largest_error(&image, &decoded) <= 8);
+    }
+
+    // NOTE(erick): Missing scan data is filled in like other decoders do,
+    // but the headers have to be complete.
+    #[test]
+    fn truncated() {
+        let file = encode(&sample_image(40, 24), 90, true);
+        let scan = find_segment(&file, SOS);
+        for length in 0 .. file.len() {
+            let result = decode(&file[.. length]);
+            if length <= scan + 2 {
+                assert!(result.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = encode(&sample_image(40, 24), 90, true);
+        for index in 0 .. file.len() {
+            let mut corrupt = file.clone();
+            corrupt[index] ^= 0x10;
+            let _ = decode(&corrupt);
+        }
+    }
+
+    #[test]
+    fn over_subscribed_huffman_table() {
+        let mut file = encode(&sample_image(16, 16), 90, false);
+        let counts = find_segment(&file, DHT) + 5;
+        assert_eq!(&file[counts .. counts + 3], &[0, 1, 5]);
+        file[counts] = 3;
+        file[counts + 2] = 2;
+        assert!(decode(&file).is_err());
+    }
+
+    #[test]
+    fn frame_bigger_than_file() {
+        let mut file = encode(&sample_image(16, 16), 90, false);
+        let size = find_segment(&file, SOF0) + 5;
+        file[size .. size + 4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(decode(&file).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use std::sync::OnceLock;
+
+use config::Section;
+
+pub const KEY_TAB       : i32 = 0x09;
+pub const KEY_ENTER     : i32 = 0x0a;
+pub const KEY_BACKSPACE : i32 = 0x7f;
+pub const KEY_ESC       : i32 = 0x1b;
+pub const KEY_DOWN      : i32 = 0x102;
+pub const KEY_UP        : i32 = 0x103;
+pub const KEY_LEFT      : i32 = 0x104;
+pub const KEY_RIGHT     : i32 = 0x105;
+pub const KEY_HOME      : i32 = 0x106;
+pub const KEY_F0        : i32 = 0x108;
+pub const KEY_NPAGE     : i32 = 0x152;
+pub const KEY_PPAGE     : i32 = 0x153;
+pub const KEY_END       : i32 = 0x168;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Open,
+    Save,
+    Merge,
+    Crop,
+    Animate,
+    Icon,
+    Mix,
+    Adjust,
+    Levels,
+    Curves,
+    Convolve,
+    Median,
+    Morphology,
+    Threshold,
+    Quantize,
+    Command,
+    Run,
+    Quit,
+    Help,
+    Confirm,
+    Cancel,
+    Browse,
+    Places,
+}
+
+// NOTE(erick): Global actions are looked up in the main loop, prompt
+// actions inside the minibuffer prompts. A key may be bound once in
+// each context.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Global,
+    Prompt,
+}
+
+pub struct ActionInfo {
+    pub action: Action,
+    pub name: &'static str,
+    pub context: Context,
+    pub default_keys: &'static [&'static str],
+    pub description: &'static str,
+}
+
+pub const ACTIONS : [ActionInfo; 23] = [
+    ActionInfo { action: Action::Open,    name: "open",    context: Context::Global,
+                 default_keys: &["o"],     description: "Open an image file" },
+    ActionInfo { action: Action::Save,    name: "save",    context: Context::Global,
+                 default_keys: &["s"],     description: "Save the result to a file" },
+    ActionInfo { action: Action::Merge,   name: "merge",   context: Context::Global,
+                 default_keys: &["m"],     description: "Merge two operations side by side" },
+    ActionInfo { action: Action::Crop,    name: "crop",    context: Context::Global,
+                 default_keys: &["c"],     description: "Crop the result of an operation" },
+    ActionInfo { action: Action::Animate, name: "animate", context: Context::Global,
+                 default_keys: &["a"],     description: "Save operations as an animated GIF or multi-page TIFF" },
+    ActionInfo { action: Action::Icon,    name: "icon",    context: Context::Global,
+                 default_keys: &["i"],     description: "Save operations as the sizes of an icon" },
+    ActionInfo { action: Action::Mix,     name: "mix",     context: Context::Global,
+                 default_keys: &["x"],     description: "Convert to gray or mix the color channels" },
+    ActionInfo { action: Action::Adjust,  name: "adjust",  context: Context::Global,
+                 default_keys: &["b"],     description: "Change brightness, contrast and gamma" },
+    ActionInfo { action: Action::Levels,  name: "levels",  context: Context::Global,
+                 default_keys: &["l"],     description: "Adjust the input and output levels" },
+    ActionInfo { action: Action::Curves,  name: "curves",  context: Context::Global,
+                 default_keys: &["u"],     description: "Edit a tone curve for some channels" },
+    ActionInfo { action: Action::Convolve, name: "convolve", context: Context::Global,
+                 default_keys: &["v"],     description: "Blur, sharpen, emboss or find edges with a kernel" },
+    ActionInfo { action: Action::Median,  name: "median",  context: Context::Global,
+                 default_keys: &["n"],     description: "Remove specks with a median filter" },
+    ActionInfo { action: Action::Morphology, name: "morphology", context: Context::Global,
+                 default_keys: &["e"],     description: "Erode, dilate, open, close or take the gradient" },
+    ActionInfo { action: Action::Threshold, name: "threshold", context: Context::Global,
+                 default_keys: &["t"],     description: "Turn to black and white, globally or adaptively" },
+    ActionInfo { action: Action::Quantize, name: "quantize", context: Context::Global,
+                 default_keys: &["d"],     description: "Reduce the colors to a palette, with dithering" },
+    ActionInfo { action: Action::Command, name: "command", context: Context::Global,
+                 default_keys: &[":"],     description: "Type a command in the minibuffer" },
+    ActionInfo { action: Action::Run,     name: "run",     context: Context::Global,
+                 default_keys: &["r"],     description: "Run the operations and write the saved files" },
+    ActionInfo { action: Action::Help,    name: "help",    context: Context::Global,
+                 default_keys: &["?"],     description: "Show this help" },
+    ActionInfo { action: Action::Quit,    name: "quit",    context: Context::Global,
+                 default_keys: &["q"],     description: "Quit climp" },
+    ActionInfo { action: Action::Confirm, name: "confirm", context: Context::Prompt,
+                 default_keys: &["Enter"], description: "Accept the current prompt" },
+    ActionInfo { action: Action::Cancel,  name: "cancel",  context: Context::Prompt,
+                 default_keys: &["Esc"],   description: "Cancel the current prompt" },
+    ActionInfo { action: Action::Browse,  name: "browse",  context: Context::Prompt,
+                 default_keys: &["C-b"],   description: "Toggle the file browser in path prompts" },
+    ActionInfo { action: Action::Places,  name: "places",  context: Context::Prompt,
+                 default_keys: &["C-r"],   description: "Show bookmarks and recent files in path prompts" },
+];
+
+// NOTE(erick): Keys the prompts use for editing and navigation. They can't
+// be rebound, and binding confirm or cancel to one of them would make
+// that prompt unusable.
+pub const PROMPT_KEYS : [(i32, &str); 7] = [
+    (KEY_TAB,       "Complete a path or command name, or list the matches and go to the next one"),
+    (KEY_BACKSPACE, "Delete the last character"),
+    (KEY_UP,        "Previous entry or command, increase a number"),
+    (KEY_DOWN,      "Next entry or command, decrease a number"),
+    (KEY_LEFT,      "Previous option"),
+    (KEY_RIGHT,     "Next option"),
+    (0x20,          "Insert a space"),
+];
+
+// NOTE(erick): Fixed keys of the file browser. Confirm, cancel and browse
+// keep their bindings there.
+pub const BROWSER_KEYS : [(i32, &str); 11] = [
+    (KEY_UP,        "Previous entry"),
+    (KEY_DOWN,      "Next entry"),
+    (KEY_PPAGE,     "Previous page"),
+    (KEY_NPAGE,     "Next page"),
+    (KEY_HOME,      "First entry"),
+    (KEY_END,       "Last entry"),
+    (KEY_RIGHT,     "Enter the directory"),
+    (KEY_LEFT,      "Go to the parent directory"),
+    (0x20,          "Mark the file (Open only)"),
+    (KEY_TAB,       "Show all files or only images"),
+    ('.' as i32,    "Show or hide hidden files"),
+];
+
+// NOTE(erick): Fixed keys of the bookmarks and recent files list, on top
+// of the arrows and paging keys of the file browser.
+pub const PLACES_KEYS : [(i32, &str); 2] = [
+    ('a' as i32,    "Bookmark the directory of the prompt's path"),
+    ('d' as i32,    "Remove the selected entry"),
+];
+
+// NOTE(erick): Fixed keys of the curve editor, on top of confirm and
+// cancel.
+pub const CURVE_KEYS : [(i32, &str); 11] = [
+    (KEY_TAB,       "Select the next point"),
+    (KEY_LEFT,      "Move the point left"),
+    (KEY_RIGHT,     "Move the point right"),
+    (KEY_UP,        "Move the point up"),
+    (KEY_DOWN,      "Move the point down"),
+    (KEY_HOME,      "Move the point 16 left"),
+    (KEY_END,       "Move the point 16 right"),
+    (KEY_PPAGE,     "Move the point 16 up"),
+    (KEY_NPAGE,     "Move the point 16 down"),
+    ('a' as i32,    "Add a point after the selected one"),
+    ('d' as i32,    "Remove the selected point"),
+];
+
+pub struct KeyBindings {
+    bindings: Vec<(Action, i32)>,
+}
+
+impl KeyBindings {
+    pub fn default() -> KeyBindings {
+        let mut bindings = Vec::new();
+        for info in ACTIONS.iter() {
+            for key in info.default_keys {
+                bindings.push((info.action, parse_key(key).expect("Invalid default key")));
+            }
+        }
+
+        KeyBindings { bindings }
+    }
+
+    // NOTE(erick): Every action listed in the section replaces all of its
+    // default keys. Actions not listed keep their defaults.
+    pub fn from_section(section: Option<&Section>) -> Result<KeyBindings, String> {
+        let mut result = KeyBindings::default();
+        if section.is_none() {
+            return Ok(result);
+        }
+
+        for entry in section.unwrap().entries.iter() {
+            let info = ACTIONS.iter().find(|info| info.name == entry.name);
+            if info.is_none() {
+                return Err(format!("{}: unknown action '{}'", entry.line, entry.name));
+            }
+
+            let info = info.unwrap();
+            result.bindings.retain(|&(action, _)| action != info.action);
+
+            for key_name in entry.value.split(',') {
+                let key_name = key_name.trim();
+                let key = parse_key(key_name);
+                if key.is_none() {
+                    return Err(format!("{}: invalid key '{}'", entry.line, key_name));
+                }
+
+                let key = key.unwrap();
+                let is_reserved = PROMPT_KEYS.iter().any(|&(reserved, _)| reserved == key);
+                if info.context == Context::Prompt && is_reserved {
+                    return Err(format!("{}: '{}' is reserved inside prompts",
+                                       entry.line, key_name));
+                }
+
+                // NOTE(erick): Prompts insert printable characters as text, so a
+                // binding on one would never fire there.
+                let is_printable = (0x20 .. KEY_BACKSPACE).contains(&key);
+                if info.context == Context::Prompt && is_printable {
+                    return Err(format!("{}: '{}' would be typed inside prompts",
+                                       entry.line, key_name));
+                }
+
+                result.bindings.push((info.action, key));
+            }
+
+            let conflicts = result.check_conflicts();
+            if conflicts.is_err() {
+                return Err(format!("{}: {}", entry.line, conflicts.err().unwrap()));
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn check_conflicts(&self) -> Result<(), String> {
+        for (i, &(action0, key0)) in self.bindings.iter().enumerate() {
+            for &(action1, key1) in self.bindings[i + 1 ..].iter() {
+                if key0 == key1 && action0 != action1 &&
+                    action_info(action0).context == action_info(action1).context {
+                    return Err(format!("key '{}' is bound to both '{}' and '{}'",
+                                       key_name(key0),
+                                       action_info(action0).name,
+                                       action_info(action1).name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn action(&self, ch: i32, context: Context) -> Option<Action> {
+        self.bindings.iter()
+            .find(|&&(action, key)| key == ch && action_info(action).context == context)
+            .map(|&(action, _)| action)
+    }
+
+    pub fn is(&self, ch: i32, action: Action) -> bool {
+        self.bindings.iter().any(|&(bound_action, key)| bound_action == action && key == ch)
+    }
+
+    pub fn keys(&self, action: Action) -> Vec<i32> {
+        self.bindings.iter()
+            .filter(|&&(bound_action, _)| bound_action == action)
+            .map(|&(_, key)| key)
+            .collect()
+    }
+}
+
+pub fn action_info(action: Action) -> &'static ActionInfo {
+    ACTIONS.iter().find(|info| info.action == action).expect("Action without info")
+}
+
+static KEY_BINDINGS : OnceLock<KeyBindings> = OnceLock::new();
+
+// NOTE(erick): Like the ncurses state, the bindings are global. They are
+// set once at startup, before any prompt can ask for them.
+pub fn set_bindings(bindings: KeyBindings) {
+    let _ = KEY_BINDINGS.set(bindings);
+}
+
+pub fn bindings() -> &'static KeyBindings {
+    KEY_BINDINGS.get_or_init(KeyBindings::default)
+}
+
+#[inline]
+pub fn is(ch: i32, action: Action) -> bool {
+    bindings().is(ch, action)
+}
+
+// NOTE(erick): Accepts a single character ('q', ':'), a named key
+// ('Esc', 'F5') or a control combination ('C-x').
+pub fn parse_key(name: &str) -> Option<i32> {
+    let chars = name.chars().collect::<Vec<_> >();
+    if chars.len() == 1 {
+        let ch = chars[0] as i32;
+        if ch > 0x20 && ch < KEY_BACKSPACE {
+            return Some(ch);
+        }
+        return None;
+    }
+
+    if chars.len() == 3 && (name.starts_with("C-") || name.starts_with("c-")) {
+        let ch = chars[2].to_ascii_lowercase();
+        if ch.is_ascii_lowercase() {
+            return Some(ch as i32 - 'a' as i32 + 1);
+        }
+        return None;
+    }
+
+    let lowercase = name.to_lowercase();
+    match lowercase.as_str() {
+        "tab"       => return Some(KEY_TAB),
+        "enter"     => return Some(KEY_ENTER),
+        "backspace" => return Some(KEY_BACKSPACE),
+        "esc"       => return Some(KEY_ESC),
+        "space"     => return Some(0x20),
+        "down"      => return Some(KEY_DOWN),
+        "up"        => return Some(KEY_UP),
+        "left"      => return Some(KEY_LEFT),
+        "right"     => return Some(KEY_RIGHT),
+        "home"      => return Some(KEY_HOME),
+        "end"       => return Some(KEY_END),
+        "pgup"      => return Some(KEY_PPAGE),
+        "pgdn"      => return Some(KEY_NPAGE),
+        _           => { },
+    }
+
+    if let Some(number) = lowercase.strip_prefix('f') {
+        if let Ok(number) = number.parse::<i32>() {
+            if (1 ..= 12).contains(&number) {
+                return Some(KEY_F0 + number);
+            }
+        }
+    }
+
+    None
+}
+
+pub fn key_name(key: i32) -> String {
+    match key {
+        KEY_TAB       => return "Tab".to_string(),
+        KEY_ENTER     => return "Enter".to_string(),
+        KEY_BACKSPACE => return "Backspace".to_string(),
+        KEY_ESC       => return "Esc".to_string(),
+        0x20          => return "Space".to_string(),
+        KEY_DOWN      => return "Down".to_string(),
+        KEY_UP        => return "Up".to_string(),
+        KEY_LEFT      => return "Left".to_string(),
+        KEY_RIGHT     => return "Right".to_string(),
+        KEY_HOME      => return "Home".to_string(),
+        KEY_END       => return "End".to_string(),
+        KEY_PPAGE     => return "PgUp".to_string(),
+        KEY_NPAGE     => return "PgDn".to_string(),
+        _             => { },
+    }
+
+    if (1 ..= 26).contains(&key) {
+        return format!("C-{}", (key - 1 + 'a' as i32) as u8 as char);
+    }
+    if key > KEY_F0 && key <= KEY_F0 + 12 {
+        return format!("F{}", key - KEY_F0);
+    }
+    if key > 0x20 && key < KEY_BACKSPACE {
+        return (key as u8 as char).to_string();
+    }
+
+    format!("{:#x}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+
+    fn bindings(contents: &str) -> Result<KeyBindings, String> {
+        let config = config::parse(contents).unwrap();
+        KeyBindings::from_section(config.section("keys"))
+    }
+
+    #[test]
+    fn prompt_bindings() {
+        let result = bindings("[keys]\nconfirm = C-j\ncancel = C-g\n").unwrap();
+        assert_eq!(result.action(0x0a, Context::Prompt), Some(Action::Confirm));
+        assert_eq!(result.action(0x07, Context::Prompt), Some(Action::Cancel));
+        assert_eq!(result.action(KEY_ESC, Context::Prompt), None);
+
+        assert!(bindings("[keys]\nconfirm = y\n").is_err());
+        assert!(bindings("[keys]\ncancel = Enter, q\n").is_err());
+        assert!(bindings("[keys]\ncancel = Space\n").is_err());
+        assert!(bindings("[keys]\nconfirm = Tab\n").is_err());
+    }
+
+    #[test]
+    fn global_bindings() {
+        let result = bindings("[keys]\nquit = q, Q\n").unwrap();
+        assert_eq!(result.action('Q' as i32, Context::Global), Some(Action::Quit));
+        assert_eq!(result.action('Q' as i32, Context::Prompt), None);
+
+        assert!(bindings("[keys]\nquit = x\n").is_err());
+        assert!(bindings("[keys]\nquit = C-\n").is_err());
+        assert!(bindings("[keys]\nfly = q\n").is_err());
+    }
+}
@@ -3,13 +3,104 @@ extern crate scopeguard;
 extern crate ncurses;
 extern crate nix;
 
-use std::fmt::Display;
-use std::fmt::Formatter;
+mod blur;
+mod bmp;
+mod browser;
+mod codec;
+mod color;
+mod command;
+mod completion;
+mod config;
+mod curves;
+mod dither;
+mod filter;
+mod gif;
+mod ico;
+mod image;
+mod jpeg;
+mod keys;
+mod mouse;
+mod netpbm;
+mod operation;
+mod palette;
+mod pipeline;
+mod places;
+mod png;
+mod qoi;
+mod quantize;
+mod rank;
+mod tga;
+mod tiff;
+mod theme;
+mod threshold;
+mod zlib;
 
 use std::path::Path;
 use std::path::PathBuf;
 use std::fs::read_dir;
 use std::fs::canonicalize;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+
+use browser::BrowseResult;
+use places::PlacesResult;
+use codec::Format;
+use command::Command;
+use command::DEFAULT_FRAME_DELAY;
+use command::MAX_AMOUNT;
+use command::MAX_BLOCK_SIZE;
+use command::MAX_BOX_RADIUS;
+use command::MAX_COLORS;
+use command::MAX_GAMMA;
+use command::MAX_OFFSET;
+use command::MAX_PERCENT;
+use command::MAX_QUALITY;
+use command::MAX_RADIUS;
+use command::MAX_WINDOW_RADIUS;
+use command::MIN_COLORS;
+use command::MIN_GAMMA;
+use command::MIN_PERCENT;
+use command::MIN_QUALITY;
+use command::MIN_RADIUS;
+use completion::CompletionOptions;
+use keys::Action;
+use keys::Context;
+use keys::KeyBindings;
+use keys::KEY_TAB;
+use keys::KEY_BACKSPACE;
+use keys::KEY_DOWN;
+use keys::KEY_UP;
+use keys::KEY_LEFT;
+use keys::KEY_RIGHT;
+use netpbm::NetpbmOptions;
+use operation::Adjustment;
+use operation::Channels;
+use operation::ColorPalette;
+use operation::Direction;
+use operation::Dither;
+use operation::EdgeMode;
+use operation::Kernel;
+use operation::Levels;
+use operation::Mixer;
+use operation::Morphology;
+use operation::Operation;
+use operation::SaveOptions;
+use operation::Shape;
+use operation::Subsampling;
+use operation::Threshold;
+use operation::Window;
+use tga::TgaOptions;
+use tiff::TiffOptions;
+use mouse::clicked_row;
+use mouse::get_mouse_event;
+use mouse::wheel_direction;
+use theme::change_to_color;
+use theme::clear_window;
+use theme::NORMAL_COLOR;
+use theme::ERROR_COLOR;
+use theme::HIGHLIGHT_COLOR;
+use theme::QUESTION_COLOR;
 
 use std::char;
 use ncurses::*;
@@ -23,70 +114,14 @@ use nix::sys::signal::sigaction;
 
 use nix::sys::signal::SIGINT;
 
-#[allow(dead_code)]
-enum Direction {
-    Horizontal,
-    Vertical,
-}
-
-impl Display for Direction {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            &Direction::Horizontal => write!(f, "Hor"),
-            &Direction::Vertical   => write!(f, "Ver"),
-        }
-    }
-}
-
-#[allow(dead_code)]
-enum Operation {
-    Open(usize),
-    Save(usize, usize),
-    Merge(usize, usize, Direction),
-    Crop(usize, u32, u32, i32, i32),
-}
-
-impl Display for Operation {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        match self {
-            &Operation::Open(ref file)
-                => write!(f, "Open({})", file),
-            &Operation::Save(ref op, ref file)
-                => write!(f, "Save({}, {})", op, file),
-            &Operation::Crop(ref op, ref x0, ref y0, ref w, ref h)
-                => write!(f, "Crop({}, {}, {}, {}, {})", op, x0, y0, w, h),
-            &Operation::Merge(ref op0, ref op1, ref dir)
-                => write!(f, "Merge({}, {}, {})", op0, op1, dir),
-        }
-    }
-}
-
-const KEY_C         : i32 = 'c' as i32;
-const KEY_M         : i32 = 'm' as i32;
-const KEY_O         : i32 = 'o' as i32;
-const KEY_Q         : i32 = 'q' as i32;
-const KEY_S         : i32 = 's' as i32;
-const KEY_TAB       : i32 = 0x09;
-const KEY_ENTER     : i32 = 0x0a;
-const KEY_BACKSPACE : i32 = 0x7f;
-const KEY_ESC       : i32 = 0x1b;
-const KEY_DOWN      : i32 = 0x102;
-const KEY_UP        : i32 = 0x103;
-const KEY_LEFT      : i32 = 0x104;
-const KEY_RIGHT     : i32 = 0x105;
-
-extern fn stop_program(_: i32) {
+extern "C" fn stop_program(_: i32) {
     endwin();
     std::process::exit(0);
 }
 
-const NORMAL_COLOR    : i16 = 1;
-const ERROR_COLOR     : i16 = 2;
-const HIGHLIGHT_COLOR : i16 = 3;
-const QUESTION_COLOR  : i16 = 4;
-
 // Reference:
 // https://github.com/jeaye/ncurses-rs/blob/master/src/ncurses.rs
+#[allow(clippy::unnecessary_unwrap)]
 fn main() {
 
     /* Installing a SIGINT handler */
@@ -96,6 +131,48 @@ fn main() {
     #[allow(unused_must_use)]
     unsafe { sigaction(SIGINT, &sig_action); }
 
+    /* Loading the user configuration before taking over the terminal */
+    let config = config::load();
+    if config.is_err() {
+        config_error(config.err().unwrap());
+    }
+
+    let config = config.unwrap();
+    let bindings = KeyBindings::from_section(config.section("keys"));
+    if bindings.is_err() {
+        config_error(bindings.err().unwrap());
+    }
+    keys::set_bindings(bindings.unwrap());
+
+    let completion_options = CompletionOptions::from_section(config.section("completion"));
+    if completion_options.is_err() {
+        config_error(completion_options.err().unwrap());
+    }
+    completion::set_options(completion_options.unwrap());
+
+    let netpbm_options = NetpbmOptions::from_section(config.section("netpbm"));
+    if netpbm_options.is_err() {
+        config_error(netpbm_options.err().unwrap());
+    }
+    netpbm::set_options(netpbm_options.unwrap());
+
+    let tga_options = TgaOptions::from_section(config.section("tga"));
+    if tga_options.is_err() {
+        config_error(tga_options.err().unwrap());
+    }
+    tga::set_options(tga_options.unwrap());
+
+    let tiff_options = TiffOptions::from_section(config.section("tiff"));
+    if tiff_options.is_err() {
+        config_error(tiff_options.err().unwrap());
+    }
+    tiff::set_options(tiff_options.unwrap());
+
+    let theme = theme::from_config(&config);
+    if theme.is_err() {
+        config_error(theme.err().unwrap());
+    }
+
     /* Start ncurses. */
     initscr();
     raw();
@@ -103,10 +180,11 @@ fn main() {
     keypad(stdscr(), true);
     noecho();
 
-    init_pair(ERROR_COLOR, COLOR_WHITE, COLOR_RED);
-    init_pair(HIGHLIGHT_COLOR, COLOR_BLACK, COLOR_WHITE);
-    init_pair(NORMAL_COLOR, COLOR_WHITE, COLOR_BLACK);
-    init_pair(QUESTION_COLOR, COLOR_WHITE, COLOR_BLUE);
+    theme::apply(theme.unwrap());
+
+    let mouse_events = BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED |
+                       BUTTON4_PRESSED | BUTTON5_PRESSED;
+    mousemask(mouse_events as mmask_t, None);
 
     let screen_width  = getmaxx(stdscr());
     let mut screen_height = getmaxy(stdscr());
@@ -129,56 +207,122 @@ fn main() {
     // applied.
     let mut opened_files = Vec::new();
     let mut operations = Vec::new();
+    let mut command_history = Vec::new();
+    let mut first_visible_file = 0;
+    let mut first_visible_operation = 0;
+    let mut status_message: Option<(i16, String)> = None;
     loop {
         // wprint_strings(stdscr(), &opened_files);
         clear_window(minibuffer_window);
+        if status_message.is_some() {
+            let (color, message) = status_message.take().unwrap();
+            change_to_color(minibuffer_window, color);
+            wprintw(minibuffer_window, message.as_str());
+            wrefresh(minibuffer_window);
+        }
         clear_window(operations_window);
         clear_window(opened_files_window);
 
-        wprint_files(opened_files_window, &opened_files);
+        wprint_files(opened_files_window, &opened_files, first_visible_file);
         wrefresh(opened_files_window);
 
         wprint_operations(operations_window,
-                          &operations, &opened_files, -1);
+                          &operations, &opened_files, -1, first_visible_operation);
         wrefresh(operations_window);
 
         refresh();
 
-        let mut key_o_pressed = false;
-        let mut key_s_pressed = false;
-        let mut key_m_pressed = false;
-        let mut key_c_pressed = false;
+        let mut open_requested = false;
+        let mut save_requested = false;
+        let mut merge_requested = false;
+        let mut crop_requested = false;
+        let mut animate_requested = false;
+        let mut icon_requested = false;
+        let mut mix_requested = false;
+        let mut adjust_requested = false;
+        let mut levels_requested = false;
+        let mut curves_requested = false;
+        let mut convolve_requested = false;
+        let mut median_requested = false;
+        let mut morphology_requested = false;
+        let mut threshold_requested = false;
+        let mut quantize_requested = false;
+        let mut command_requested = false;
+        let mut run_requested = false;
+        let mut help_requested = false;
 
         let ch = getch();
-        match ch {
-            KEY_Q => { break; },
-            KEY_O => { key_o_pressed = true },
-            KEY_S => { key_s_pressed = true },
-            KEY_M => { key_m_pressed = true },
-            KEY_C => { key_c_pressed = true },
+        if ch == KEY_MOUSE {
+            let event = get_mouse_event();
+            if event.is_some() {
+                let event = event.unwrap();
+                let scroll = wheel_direction(&event);
+                if wenclose(opened_files_window, event.y, event.x) {
+                    first_visible_file = scroll_position(first_visible_file, scroll,
+                                                         opened_files.len(),
+                                                         opened_files_window);
+                }
+                if wenclose(operations_window, event.y, event.x) {
+                    first_visible_operation = scroll_position(first_visible_operation, scroll,
+                                                              operations.len(),
+                                                              operations_window);
+                }
+            }
+            continue;
+        }
+
+        match keys::bindings().action(ch, Context::Global) {
+            Some(Action::Quit)       => { break; },
+            Some(Action::Open)       => { open_requested = true },
+            Some(Action::Save)       => { save_requested = true },
+            Some(Action::Merge)      => { merge_requested = true },
+            Some(Action::Crop)       => { crop_requested = true },
+            Some(Action::Animate)    => { animate_requested = true },
+            Some(Action::Icon)       => { icon_requested = true },
+            Some(Action::Mix)        => { mix_requested = true },
+            Some(Action::Adjust)     => { adjust_requested = true },
+            Some(Action::Levels)     => { levels_requested = true },
+            Some(Action::Curves)     => { curves_requested = true },
+            Some(Action::Convolve)   => { convolve_requested = true },
+            Some(Action::Median)     => { median_requested = true },
+            Some(Action::Morphology) => { morphology_requested = true },
+            Some(Action::Threshold)  => { threshold_requested = true },
+            Some(Action::Quantize)   => { quantize_requested = true },
+            Some(Action::Command)    => { command_requested = true },
+            Some(Action::Run)        => { run_requested = true },
+            Some(Action::Help)       => { help_requested = true },
 
             _     => { },
         };
 
-        if key_o_pressed {
-            let new_file = open_file(minibuffer_window,
-                                     screen_height, screen_width, true);
-            if new_file.is_some() {
-                opened_files.push(new_file.unwrap());
-                operations.push(Operation::Open(opened_files.len() - 1));
+        if open_requested {
+            let new_files = open_file(minibuffer_window,
+                                      screen_height, screen_width, true);
+            if new_files.is_some() {
+                for new_file in new_files.unwrap() {
+                    operations.extend(open_operations(&new_file, opened_files.len()));
+                    opened_files.push(new_file);
+                }
             }
         }
 
-        if key_s_pressed {
-            let new_file = open_file(minibuffer_window,
-                                     screen_height, screen_width, false);
-            if new_file.is_some() {
+        if save_requested {
+            let new_files = open_file(minibuffer_window,
+                                      screen_height, screen_width, false);
+            let new_file = new_files.map(|mut files| files.remove(0));
+            let options = if new_file.is_some() {
+                get_save_options(minibuffer_window, new_file.as_ref().unwrap())
+            } else {
+                None
+            };
+
+            if options.is_some() {
                 opened_files.push(new_file.unwrap());
-                operations.push(Operation::Save(0, opened_files.len() - 1));
+                operations.push(Operation::Save(0, opened_files.len() - 1, options.unwrap()));
             }
         }
 
-        if key_m_pressed {
+        if merge_requested {
             let op = get_merge_operation(minibuffer_window, operations_window,
                                          &operations, &opened_files);
             if op.is_some() {
@@ -186,88 +330,1151 @@ fn main() {
             }
         }
 
-        if key_c_pressed {
+        if crop_requested {
             let op = get_crop_operation(minibuffer_window, operations_window,
                                         &operations, &opened_files);
             if op.is_some() {
                 operations.push(op.unwrap());
             }
         }
+
+        if mix_requested {
+            let op = get_mix_operation(minibuffer_window, operations_window,
+                                       &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if adjust_requested {
+            let op = get_adjust_operation(minibuffer_window, operations_window,
+                                          &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if levels_requested {
+            let op = get_levels_operation(minibuffer_window, operations_window,
+                                          &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if curves_requested {
+            let op = get_curves_operation(minibuffer_window, operations_window,
+                                          &operations, &opened_files,
+                                          screen_height, screen_width);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if convolve_requested {
+            let op = get_convolve_operation(minibuffer_window, operations_window,
+                                            &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if median_requested {
+            let op = get_median_operation(minibuffer_window, operations_window,
+                                          &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if morphology_requested {
+            let op = get_morphology_operation(minibuffer_window, operations_window,
+                                              &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if threshold_requested {
+            let op = get_threshold_operation(minibuffer_window, operations_window,
+                                             &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if quantize_requested {
+            let op = get_quantize_operation(minibuffer_window, operations_window,
+                                            &operations, &opened_files);
+            if op.is_some() {
+                operations.push(op.unwrap());
+            }
+        }
+
+        if animate_requested {
+            let animation = get_animation(minibuffer_window, operations_window,
+                                          &operations, &opened_files);
+            let new_files = if animation.is_some() {
+                open_file(minibuffer_window, screen_height, screen_width, false)
+            } else {
+                None
+            };
+
+            if new_files.is_some() {
+                let (frames, loops) = animation.unwrap();
+                let new_file = new_files.unwrap().remove(0);
+                if codec::has_frames(codec::format_from_extension(&new_file)) {
+                    opened_files.push(new_file);
+                    operations.push(Operation::Animate(frames, loops, opened_files.len() - 1));
+                } else {
+                    status_message = Some((ERROR_COLOR,
+                                           "only GIF and TIFF files can have several frames"
+                                           .to_string()));
+                }
+            }
+        }
+
+        if icon_requested {
+            let ops = get_icon_operations(minibuffer_window, operations_window,
+                                          &operations, &opened_files);
+            let new_files = if ops.is_some() {
+                open_file(minibuffer_window, screen_height, screen_width, false)
+            } else {
+                None
+            };
+
+            if new_files.is_some() {
+                let new_file = new_files.unwrap().remove(0);
+                if codec::is_icon(codec::format_from_extension(&new_file)) {
+                    opened_files.push(new_file);
+                    operations.push(Operation::SaveIcon(ops.unwrap(), opened_files.len() - 1));
+                } else {
+                    status_message = Some((ERROR_COLOR,
+                                           "only ICO and CUR files can have several sizes"
+                                           .to_string()));
+                }
+            }
+        }
+
+        if command_requested {
+            let should_quit = command_line(minibuffer_window, &mut command_history,
+                                           &mut operations, &mut opened_files);
+            if should_quit { break; }
+        }
+
+        if run_requested {
+            clear_window(minibuffer_window);
+            wprintw(minibuffer_window, "Running...");
+            wrefresh(minibuffer_window);
+
+            status_message = match pipeline::run(&mut operations, &opened_files) {
+                Ok(saved_count) => Some((NORMAL_COLOR,
+                                         format!("Done, {} file(s) written", saved_count))),
+                Err(message)    => Some((ERROR_COLOR, message)),
+            };
+        }
+
+        if help_requested {
+            show_help(screen_height, screen_width);
+        }
+    }
+
+    endwin();
+}
+
+// NOTE(erick): Messages that point to a line already start with its number.
+fn config_error(message: String) -> ! {
+    let path = config::config_file_path().unwrap_or_default();
+    let starts_with_line = message.starts_with(|c: char| c.is_ascii_digit());
+    let separator = if starts_with_line { ":" } else { ": " };
+    eprintln!("climp: {}{}{}", path.display(), separator, message);
+    std::process::exit(1);
+}
+
+#[allow(clippy::needless_borrow, clippy::question_mark)]
+fn get_merge_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                       operations: &Vec<Operation>,
+                       opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation0 = select_operation(minibuffer_window, operations_window,
+                                      &operations, &opened_files,
+                                      "Merge: (");
+    if operation0.is_none() { return None; }
+
+    let operation0 = operation0.unwrap();
+    let prompt = format!("Merge: ({}, ", operation0 + 1);
+    let operation1 = select_operation(minibuffer_window, operations_window,
+                                      &operations, &opened_files,
+                                      prompt.as_str());
+
+    if operation1.is_none() { return None; }
+
+    let operation1 = operation1.unwrap();
+    let direction = select_direction(minibuffer_window);
+
+    if direction.is_none() { return None; }
+
+    let direction = direction.unwrap();
+
+    let confirmation_prompt = format!("Merge({}, {}, {})",
+                                      operation0, operation1, direction);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Merge(operation0, operation1, direction))
+}
+
+#[allow(clippy::needless_borrow, clippy::question_mark)]
+fn get_crop_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                       operations: &Vec<Operation>,
+                       opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                      &operations, &opened_files,
+                                      "Merge: (");
+    if operation.is_none() { return None; }
+
+    let x0 = enter_u32(minibuffer_window, "X0: ", 0, u32::MAX, Some(0));
+    if x0.is_none() { return None; }
+
+    let y0 = enter_u32(minibuffer_window, "Y0: ", 0, u32::MAX, Some(0));
+    if y0.is_none() { return None; }
+
+    // NOTE(erick): Negative sizes go left of and above (X0, Y0).
+    let width = enter_i32(minibuffer_window, "WIDTH: ", -i32::MAX, i32::MAX, None);
+    if width.is_none() { return None; }
+
+    let height = enter_i32(minibuffer_window, "HEIGHT: ", -i32::MAX, i32::MAX, None);
+    if height.is_none() { return None; }
+
+    let operation = operation.unwrap();
+    let x0 = x0.unwrap();
+    let y0 = y0.unwrap();
+    let width = width.unwrap();
+    let height = height.unwrap();
+
+    let confirmation_prompt = format!("Crop({}, {}, {}, {}, {})",
+                                      operation,
+                                      x0, y0,
+                                      width, height);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Crop(operation, x0, y0, width, height))
+}
+
+fn get_mix_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                     operations: &Vec<Operation>,
+                     opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Mix: (")?;
+
+    let options = vec!['6', '7', 'A', 'D', 'M'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "6 = Rec.601, 7 = Rec.709, A = average, D = desaturate, \
+                                      M = matrix: ")?;
+
+    let mixer = match chosen {
+        '6' => Some(Mixer::Rec601),
+        '7' => Some(Mixer::Rec709),
+        'A' => Some(Mixer::Average),
+        'D' => Some(Mixer::Desaturate),
+        _   => enter_numbers(minibuffer_window, "Matrix (9 or 16 numbers, row by row): ",
+                             command::parse_matrix),
+    }?;
+
+
+    let confirmation_prompt = format!("Mix({}, {})", operation, mixer);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Mix(operation, mixer))
+}
+
+fn select_channels(minibuffer: WINDOW) -> Option<Channels> {
+    let options = vec!['*', 'R', 'G', 'B', 'A'];
+    let chosen = select_from_options(minibuffer, &options, "Channels (* = RGB): ")?;
+
+    match chosen {
+        'R' => Some(Channels::Red),
+        'G' => Some(Channels::Green),
+        'B' => Some(Channels::Blue),
+        'A' => Some(Channels::Alpha),
+        _   => Some(Channels::All),
+    }
+}
+
+fn get_adjust_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                        operations: &Vec<Operation>,
+                        opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Adjust: (")?;
+
+    let channels = select_channels(minibuffer_window)?;
+
+    let percent_range = format!("({} to {}): ", MIN_PERCENT, MAX_PERCENT);
+    let prompt = format!("Brightness {}", percent_range);
+    let brightness = enter_f64(minibuffer_window, prompt.as_str(),
+                               MIN_PERCENT, MAX_PERCENT, Some(0.0), 1.0)?;
+
+    let prompt = format!("Contrast {}", percent_range);
+    let contrast = enter_f64(minibuffer_window, prompt.as_str(),
+                             MIN_PERCENT, MAX_PERCENT, Some(0.0), 1.0)?;
+
+    let prompt = format!("Gamma ({} to {}): ", MIN_GAMMA, MAX_GAMMA);
+    let gamma = enter_f64(minibuffer_window, prompt.as_str(),
+                          MIN_GAMMA, MAX_GAMMA, Some(1.0), 0.1)?;
+
+    let adjustment = Adjustment {
+        brightness,
+        contrast,
+        gamma,
+    };
+
+    let confirmation_prompt = format!("Adjust({}, {}, {}, {}, {})", operation, channels,
+                                      adjustment.brightness, adjustment.contrast,
+                                      adjustment.gamma);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Adjust(operation, channels, adjustment))
+}
+
+fn get_levels_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                        operations: &Vec<Operation>,
+                        opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Levels: (")?;
+
+    let channels = select_channels(minibuffer_window)?;
+
+    let input_black = enter_u32(minibuffer_window, "Input black (0-254): ", 0, 254, Some(0))?;
+
+    // NOTE(erick): Input white has to be above input black.
+    let prompt = format!("Input white ({}-255): ", input_black + 1);
+    let input_white = enter_u32(minibuffer_window, prompt.as_str(),
+                                input_black + 1, 255, Some(255))?;
+
+    let prompt = format!("Midtone ({} to {}): ", MIN_GAMMA, MAX_GAMMA);
+    let midtone = enter_f64(minibuffer_window, prompt.as_str(),
+                            MIN_GAMMA, MAX_GAMMA, Some(1.0), 0.1)?;
+
+    let output_black = enter_u32(minibuffer_window, "Output black (0-255): ", 0, 255, Some(0))?;
+
+    let output_white = enter_u32(minibuffer_window, "Output white (0-255): ", 0, 255, Some(255))?;
+
+    let levels = Levels {
+        input_black: input_black as u8,
+        input_white: input_white as u8,
+        midtone,
+        output_black: output_black as u8,
+        output_white: output_white as u8,
+    };
+
+    let confirmation_prompt = format!("Levels({}, {}, {}, {}, {}, {}, {})", operation, channels,
+                                      levels.input_black, levels.input_white, levels.midtone,
+                                      levels.output_black, levels.output_white);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Levels(operation, channels, levels))
+}
+
+fn get_curves_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                        operations: &Vec<Operation>,
+                        opened_files: &Vec<PathBuf>,
+                        screen_height: i32, screen_width: i32) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Curves: (")?;
+
+    let channels = select_channels(minibuffer_window)?;
+
+    let points = curves::edit(screen_height, screen_width, channels, &[(0, 0), (255, 255)])?;
+
+    let confirmation_prompt = format!("Curves({}, {}, {})", operation, channels,
+                                      operation::points_string(&points));
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Curves(operation, channels, points))
+}
+
+fn get_convolve_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                          operations: &Vec<Operation>,
+                          opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Convolve: (")?;
+
+    let options = vec!['B', 'G', 'S', 'U', 'E', 'X', 'P', 'L', 'K'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "B = box, G = Gaussian, S = sharpen, U = unsharp, E = emboss, \
+                                      X = Sobel, P = Prewitt, L = Laplacian, K = kernel: ")?;
+
+    let kernel = match chosen {
+        'B' => {
+            let prompt = format!("Radius (1-{}): ", MAX_BOX_RADIUS);
+            enter_u32(minibuffer_window, prompt.as_str(), 1, MAX_BOX_RADIUS, Some(1))
+                .map(Kernel::BoxBlur)
+        },
+        'G' => enter_radius(minibuffer_window).map(Kernel::Gaussian),
+        'S' => Some(Kernel::Sharpen),
+        'U' => {
+            let radius = enter_radius(minibuffer_window);
+            let amount = if radius.is_some() {
+                let prompt = format!("Amount (0 to {}): ", MAX_AMOUNT);
+                enter_f64(minibuffer_window, prompt.as_str(), 0.0, MAX_AMOUNT, Some(1.0), 0.1)
+            } else {
+                None
+            };
+            amount.map(|amount| Kernel::UnsharpMask(radius.unwrap(), amount))
+        },
+        'E' => Some(Kernel::Emboss),
+        'X' => Some(Kernel::Sobel),
+        'P' => Some(Kernel::Prewitt),
+        'L' => Some(Kernel::Laplacian),
+        _   => enter_custom_kernel(minibuffer_window),
+    }?;
+
+    let options = vec!['C', 'W', 'M', '0'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "Edges (C = clamp, W = wrap, M = mirror, 0 = constant): ")?;
+
+    let edge = match chosen {
+        'W' => EdgeMode::Wrap,
+        'M' => EdgeMode::Mirror,
+        '0' => EdgeMode::Constant,
+        _   => EdgeMode::Clamp,
+    };
+
+
+    let confirmation_prompt = format!("Convolve({}, {}, {})", operation, kernel, edge);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Convolve(operation, kernel, edge))
+}
+
+fn enter_radius(minibuffer: WINDOW) -> Option<f64> {
+    let prompt = format!("Radius ({} to {}): ", MIN_RADIUS, MAX_RADIUS);
+    enter_f64(minibuffer, prompt.as_str(), MIN_RADIUS, MAX_RADIUS, Some(1.0), 0.5)
+}
+
+fn enter_custom_kernel(minibuffer: WINDOW) -> Option<Kernel> {
+    let divisor = enter_f64(minibuffer, "Divisor (0 = sum of the weights): ",
+                            -f64::MAX, f64::MAX, Some(1.0), 1.0)?;
+
+    let bias = enter_f64(minibuffer, "Bias: ", -f64::MAX, f64::MAX, Some(0.0), 1.0)?;
+
+    let weights = enter_numbers(minibuffer, "Kernel (9, 25, 49... numbers, row by row): ",
+                                command::parse_kernel_weights)?;
+
+    Some(Kernel::Custom(weights, divisor, bias))
+}
+
+fn select_window(minibuffer: WINDOW) -> Option<Window> {
+    let options = vec!['S', 'C', 'D'];
+    let chosen = select_from_options(minibuffer, &options,
+                                     "Window (S = square, C = cross, D = disk): ")?;
+
+    let shape = match chosen {
+        'C' => Shape::Cross,
+        'D' => Shape::Disk,
+        _   => Shape::Square,
+    };
+
+    let prompt = format!("Radius (1-{}): ", MAX_WINDOW_RADIUS);
+    let radius = enter_u32(minibuffer, prompt.as_str(), 1, MAX_WINDOW_RADIUS, Some(1))?;
+
+    Some(Window { shape, radius })
+}
+
+fn get_median_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                        operations: &Vec<Operation>,
+                        opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Median: (")?;
+
+    let channels = select_channels(minibuffer_window)?;
+
+    let window = select_window(minibuffer_window)?;
+
+
+    let confirmation_prompt = format!("Median({}, {}, {})", operation, channels, window);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Median(operation, channels, window))
+}
+
+fn get_morphology_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                            operations: &Vec<Operation>,
+                            opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Morphology: (")?;
+
+    let options = vec!['E', 'D', 'O', 'C', 'G'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "E = erode (min), D = dilate (max), O = open, C = close, \
+                                      G = gradient: ")?;
+
+    let morphology = match chosen {
+        'D' => Morphology::Dilate,
+        'O' => Morphology::Open,
+        'C' => Morphology::Close,
+        'G' => Morphology::Gradient,
+        _   => Morphology::Erode,
+    };
+
+    let channels = select_channels(minibuffer_window)?;
+
+    let window = select_window(minibuffer_window)?;
+
+
+    let confirmation_prompt = format!("{}({}, {}, {})", morphology, operation, channels, window);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Morphology(operation, channels, morphology, window))
+}
+
+fn get_threshold_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                           operations: &Vec<Operation>,
+                           opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Threshold: (")?;
+
+    let options = vec!['F', 'O', 'M', 'G'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "F = fixed, O = Otsu, M = adaptive mean, \
+                                      G = adaptive Gaussian: ")?;
+
+    let threshold = match chosen {
+        'F' => {
+            let level = enter_u32(minibuffer_window, "Level (0-255): ", 0, 255, Some(127))?;
+
+            Threshold::Fixed(level as u8)
+        },
+        'O' => Threshold::Otsu(None),
+        adaptive => {
+            let mut prompt = format!("Window size (3-{}): ", MAX_BLOCK_SIZE);
+            let size = loop {
+                let entered = enter_u32(minibuffer_window, prompt.as_str(),
+                                        3, MAX_BLOCK_SIZE, Some(15))?;
+
+                if !entered.is_multiple_of(2) {
+                    break entered;
+                }
+                prompt = format!("Window size has to be odd (3-{}): ", MAX_BLOCK_SIZE);
+            };
+
+            let prompt = format!("Offset ({}-{}): ", -MAX_OFFSET, MAX_OFFSET);
+            let offset = enter_f64(minibuffer_window, prompt.as_str(),
+                                   -MAX_OFFSET, MAX_OFFSET, Some(0.0), 1.0)?;
+
+            if adaptive == 'M' {
+                Threshold::Mean(size, offset)
+            } else {
+                Threshold::Gaussian(size, offset)
+            }
+        },
+    };
+
+
+    let confirmation_prompt = format!("Threshold({}, {})", operation, threshold);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Threshold(operation, threshold))
+}
+
+fn get_quantize_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                          operations: &Vec<Operation>,
+                          opened_files: &Vec<PathBuf>) -> Option<Operation> {
+    let operation = select_operation(minibuffer_window, operations_window,
+                                     operations, opened_files,
+                                     "Quantize: (")?;
+
+    let options = vec!['M', 'O', 'W', 'G', 'F'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "M = median cut, O = octree, W = web-safe, G = grays, \
+                                      F = palette file: ")?;
+
+    let color_count_prompt = format!("Colors ({}-{}): ", MIN_COLORS, MAX_COLORS);
+    let palette = match chosen {
+        'W' => ColorPalette::WebSafe,
+        'F' => {
+            let path = enter_palette_file(minibuffer_window)?;
+
+            ColorPalette::File(path)
+        },
+        'G' => {
+            let count = enter_u32(minibuffer_window, color_count_prompt.as_str(),
+                                  MIN_COLORS, MAX_COLORS, Some(4))?;
+
+            ColorPalette::Gray(count)
+        },
+        chosen => {
+            let count = enter_u32(minibuffer_window, color_count_prompt.as_str(),
+                                  MIN_COLORS, MAX_COLORS, Some(16))?;
+
+            if chosen == 'O' {
+                ColorPalette::Octree(count)
+            } else {
+                ColorPalette::MedianCut(count)
+            }
+        },
+    };
+
+    let options = vec!['N', '2', '4', '8', 'F', 'A', 'S'];
+    let chosen = select_from_options(minibuffer_window, &options,
+                                     "N = no dither, 2/4/8 = Bayer, F = Floyd-Steinberg, \
+                                      A = Atkinson, S = Sierra: ")?;
+
+    let dither = match chosen {
+        '2' => Dither::Bayer(2),
+        '4' => Dither::Bayer(4),
+        '8' => Dither::Bayer(8),
+        'F' => Dither::FloydSteinberg,
+        'A' => Dither::Atkinson,
+        'S' => Dither::Sierra,
+        _   => Dither::None,
+    };
+
+
+    let confirmation_prompt = format!("Quantize({}, {}, {})", operation, palette, dither);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(Operation::Quantize(operation, palette, dither))
+}
+
+// NOTE(erick): Operations are picked one at a time until the selection
+// is cancelled.
+fn select_operations(minibuffer_window: WINDOW, operations_window: WINDOW,
+                     operations: &Vec<Operation>,
+                     opened_files: &Vec<PathBuf>, name: &str) -> Vec<usize> {
+    let mut selected_ops: Vec<usize> = Vec::new();
+    loop {
+        let selected = selected_ops.iter().map(|op| format!("{}, ", op + 1)).collect::<String>();
+        let prompt = format!("{}: ({}", name, selected);
+        let operation = select_operation(minibuffer_window, operations_window,
+                                         operations, opened_files,
+                                         prompt.as_str());
+        if operation.is_none() { break; }
+
+        selected_ops.push(operation.unwrap());
+    }
+
+    selected_ops
+}
+
+// NOTE(erick): All the frames get the same delay, the animate command can
+// give each its own.
+fn get_animation(minibuffer_window: WINDOW, operations_window: WINDOW,
+                 operations: &Vec<Operation>,
+                 opened_files: &Vec<PathBuf>) -> Option<(Vec<(usize, u32)>, u16)> {
+    let frames = select_operations(minibuffer_window, operations_window,
+                                   operations, opened_files, "Animate");
+    if frames.is_empty() { return None; }
+
+    let delay = enter_u32(minibuffer_window, "Delay (ms): ",
+                          0, u32::MAX, Some(DEFAULT_FRAME_DELAY))?;
+
+    let loops = enter_u32(minibuffer_window, "Loops (0 forever): ",
+                          0, u16::MAX as u32, Some(0))?;
+
+    let loops = loops as u16;
+
+    let frame_list = frames.iter().map(|op| op.to_string()).collect::<Vec<_> >();
+    let confirmation_prompt = format!("Animate({}, {}, {})",
+                                      frame_list.join(" "), delay, loops);
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some((frames.into_iter().map(|op| (op, delay)).collect(), loops))
+}
+
+// NOTE(erick): One operation per size of the icon, usually 16, 32, 48
+// and 256 pixels wide.
+fn get_icon_operations(minibuffer_window: WINDOW, operations_window: WINDOW,
+                       operations: &Vec<Operation>,
+                       opened_files: &Vec<PathBuf>) -> Option<Vec<usize>> {
+    let ops = select_operations(minibuffer_window, operations_window,
+                                operations, opened_files, "Icon");
+    if ops.is_empty() { return None; }
+
+    let op_list = ops.iter().map(|op| op.to_string()).collect::<Vec<_> >();
+    let confirmation_prompt = format!("SaveIcon({})", op_list.join(" "));
+    let confirmation = get_confirmation(minibuffer_window,
+                                        confirmation_prompt.as_str());
+    if !confirmation { return None; }
+
+    Some(ops)
+}
+
+// NOTE(erick): Only asks for what the format of 'path' uses. Cancelling
+// any of the questions cancels the save.
+fn get_save_options(minibuffer_window: WINDOW, path: &Path) -> Option<SaveOptions> {
+    let mut options = SaveOptions::default();
+    let format = codec::format_from_extension(path);
+    if format == Some(Format::Bmp) {
+        let chosen = select_from_options(minibuffer_window, &vec!['T', '1', '4', '8'],
+                                         "Colors (T = true color, 1/4/8 = bits per pixel): ")?;
+
+        options.palette_bits = chosen.to_digit(10);
+        return Some(options);
+    }
+    if codec::is_netpbm(format) {
+        if format != Some(Format::Pam) {
+            // NOTE(erick): The config setting is selected first.
+            let choices = if netpbm::options().plain { vec!['p', 'b'] } else { vec!['b', 'p'] };
+            let chosen = select_from_options(minibuffer_window, &choices,
+                                             "Encoding (b = binary, p = plain): ")?;
+
+            options.plain = Some(chosen == 'p');
+        }
+        if format != Some(Format::Pbm) {
+            let chosen = select_from_options(minibuffer_window, &vec!['8', '6'],
+                                             "Sample bits (8 = 8-bit, 6 = 16-bit): ")?;
+
+            options.sample_bits = if chosen == '6' { 16 } else { 8 };
+        }
+        return Some(options);
+    }
+    if format != Some(Format::Jpeg) {
+        return Some(options);
+    }
+
+    let prompt = format!("Quality ({}-{}): ", MIN_QUALITY, MAX_QUALITY);
+    let quality = enter_u32(minibuffer_window, prompt.as_str(),
+                            MIN_QUALITY, MAX_QUALITY, Some(options.quality))?;
+
+    options.quality = quality;
+
+    let chosen = select_from_options(minibuffer_window, &vec!['2', '4'],
+                                     "Chroma (2 = 4:2:0, 4 = 4:4:4): ")?;
+
+    options.subsampling = match chosen {
+        '4' => Subsampling::Chroma444,
+        _   => Subsampling::Chroma420,
+    };
+
+    Some(options)
+}
+
+// NOTE(erick): Reads ex-style commands (':crop 3 0 0 100 100') until one
+// succeeds or the user gives up. Errors are shown inline and the command
+// is kept so it can be fixed. Returns true if the program should quit.
+fn command_line(minibuffer: WINDOW, history: &mut Vec<String>,
+                operations: &mut Vec<Operation>,
+                opened_files: &mut Vec<PathBuf>) -> bool {
+    let mut string = String::new();
+    let mut history_index = history.len();
+    let mut error_message: Option<String> = None;
+
+    loop {
+        wclear(minibuffer);
+        wmove(minibuffer, 0, 0);
+        wprintw(minibuffer, ":");
+        wprintw(minibuffer, string.as_str());
+        if let Some(ref error_message) = error_message {
+            wprintw(minibuffer, "  [");
+            wprintw(minibuffer, error_message.as_str());
+            wprintw(minibuffer, "]");
+            // NOTE(erick): Keep the cursor after the command, not the message.
+            wmove(minibuffer, 0, string.chars().count() as i32 + 1);
+        }
+        wrefresh(minibuffer);
+
+        change_to_color(minibuffer, NORMAL_COLOR);
+        error_message = None;
+
+        let mut auto_complete = false;
+        let mut done = false;
+
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => { done = true; },
+            _ if keys::is(ch, Action::Cancel)  => { return false; },
+            KEY_TAB       => { auto_complete = true; },
+            KEY_BACKSPACE => {
+                if string.is_empty() { return false; }
+                string.pop();
+            },
+            KEY_UP        => {
+                if history_index > 0 {
+                    history_index -= 1;
+                    string = history[history_index].clone();
+                }
+            },
+            KEY_DOWN      => {
+                if history_index < history.len() {
+                    history_index += 1;
+                }
+                if history_index == history.len() {
+                    string.clear();
+                } else {
+                    string = history[history_index].clone();
+                }
+            },
+            _             => {
+                if is_printable(ch) {
+                    string.push(get_char(ch));
+                }
+            },
+        };
+
+        if auto_complete {
+            let completed = select_completion(minibuffer, ":", &mut string,
+                                              &complete_command_line);
+            if !completed {
+                change_to_color(minibuffer, ERROR_COLOR);
+            }
+        }
+
+        if done {
+            if string.trim().is_empty() { return false; }
+
+            if history.last() != Some(&string) {
+                history.push(string.clone());
+            }
+            history_index = history.len();
+
+            let result = command::parse_command(string.as_str(), operations.len())
+                .and_then(|command| execute_command(command, operations, opened_files));
+            match result {
+                Ok(should_quit) => { return should_quit; },
+                Err(message)    => {
+                    change_to_color(minibuffer, ERROR_COLOR);
+                    error_message = Some(message);
+                },
+            }
+        }
+    }
+}
+
+fn execute_command(command: Command,
+                   operations: &mut Vec<Operation>,
+                   opened_files: &mut Vec<PathBuf>) -> Result<bool, String> {
+    match command {
+        Command::Open(path) => {
+            let path_string = path.to_string_lossy().into_owned();
+            let path_buf = handle_file_opening(&path_string, true);
+            if path_buf.is_err() {
+                return Err(format!("cannot open {}", path_string));
+            }
+            let _ = places::add_recent(path_buf.as_ref().unwrap());
+            operations.extend(open_operations(path_buf.as_ref().unwrap(), opened_files.len()));
+            opened_files.push(path_buf.unwrap());
+        },
+        Command::Frame(path, frame) => {
+            let path_string = path.to_string_lossy().into_owned();
+            let path_buf = handle_file_opening(&path_string, true);
+            if path_buf.is_err() {
+                return Err(format!("cannot open {}", path_string));
+            }
+            let _ = places::add_recent(path_buf.as_ref().unwrap());
+            opened_files.push(path_buf.unwrap());
+            operations.push(Operation::Frame(opened_files.len() - 1, frame));
+        },
+        Command::Save(op, path, options) => {
+            let path_string = path.to_string_lossy().into_owned();
+            let path_buf = handle_file_opening(&path_string, false);
+            if path_buf.is_err() {
+                return Err(format!("cannot save to {}", path_string));
+            }
+            let _ = places::add_recent(path_buf.as_ref().unwrap());
+            opened_files.push(path_buf.unwrap());
+            operations.push(Operation::Save(op, opened_files.len() - 1, options));
+        },
+        Command::Animate(path, loops, frames) => {
+            let path_string = path.to_string_lossy().into_owned();
+            let path_buf = handle_file_opening(&path_string, false);
+            if path_buf.is_err() {
+                return Err(format!("cannot save to {}", path_string));
+            }
+            if !codec::has_frames(codec::format_from_extension(path_buf.as_ref().unwrap())) {
+                return Err("only GIF and TIFF files can have several frames".to_string());
+            }
+            let _ = places::add_recent(path_buf.as_ref().unwrap());
+            opened_files.push(path_buf.unwrap());
+            operations.push(Operation::Animate(frames, loops, opened_files.len() - 1));
+        },
+        Command::Icon(path, ops) => {
+            let path_string = path.to_string_lossy().into_owned();
+            let path_buf = handle_file_opening(&path_string, false);
+            if path_buf.is_err() {
+                return Err(format!("cannot save to {}", path_string));
+            }
+            if !codec::is_icon(codec::format_from_extension(path_buf.as_ref().unwrap())) {
+                return Err("only ICO and CUR files can have several sizes".to_string());
+            }
+            let _ = places::add_recent(path_buf.as_ref().unwrap());
+            opened_files.push(path_buf.unwrap());
+            operations.push(Operation::SaveIcon(ops, opened_files.len() - 1));
+        },
+        Command::Mix(op, mixer) => {
+            operations.push(Operation::Mix(op, mixer));
+        },
+        Command::Adjust(op, channels, adjustment) => {
+            operations.push(Operation::Adjust(op, channels, adjustment));
+        },
+        Command::Levels(op, channels, levels) => {
+            operations.push(Operation::Levels(op, channels, levels));
+        },
+        Command::Curves(op, channels, points) => {
+            operations.push(Operation::Curves(op, channels, points));
+        },
+        Command::Convolve(op, kernel, edge) => {
+            operations.push(Operation::Convolve(op, kernel, edge));
+        },
+        Command::Median(op, channels, window) => {
+            operations.push(Operation::Median(op, channels, window));
+        },
+        Command::Morphology(op, channels, morphology, window) => {
+            operations.push(Operation::Morphology(op, channels, morphology, window));
+        },
+        Command::Threshold(op, threshold) => {
+            operations.push(Operation::Threshold(op, threshold));
+        },
+        Command::Quantize(op, palette, dither) => {
+            // NOTE(erick): The file is read again when the operation runs,
+            // this only catches typos early.
+            if let ColorPalette::File(ref path) = palette {
+                palette::read_file(path)?;
+            }
+            operations.push(Operation::Quantize(op, palette, dither));
+        },
+        Command::Merge(op0, op1, direction) => {
+            operations.push(Operation::Merge(op0, op1, direction));
+        },
+        Command::Crop(op, x0, y0, width, height) => {
+            operations.push(Operation::Crop(op, x0, y0, width, height));
+        },
+        Command::Write(path) => {
+            write_pipeline(&path, operations, opened_files)?;
+        },
+        Command::Source(path) => {
+            source_pipeline(&path, operations, opened_files)?;
+        },
+        Command::Quit => { return Ok(true); },
+    }
+
+    Ok(false)
+}
+
+// NOTE(erick): Animated files open as one operation per frame.
+fn open_operations(path: &Path, file_index: usize) -> Vec<Operation> {
+    let frame_count = codec::read_bytes(path, None).ok()
+        .and_then(|bytes| codec::frame_count(bytes.as_slice()).ok())
+        .unwrap_or(1);
+    if frame_count <= 1 {
+        return vec![Operation::Open(file_index)];
+    }
+
+    (0 .. frame_count).map(|frame| Operation::Frame(file_index, frame)).collect()
+}
+
+fn write_pipeline(path: &Path,
+                  operations: &[Operation],
+                  opened_files: &[PathBuf]) -> Result<(), String> {
+    let mut contents = String::new();
+    for operation in operations {
+        contents.push_str(command::operation_to_command(operation, opened_files).as_str());
+        contents.push('\n');
+    }
+
+    let file = File::create(path);
+    if file.is_err() {
+        return Err(format!("cannot write {}", path.display()));
+    }
+
+    let mut file = file.unwrap();
+    if file.write_all(contents.as_bytes()).is_err() {
+        return Err(format!("cannot write {}", path.display()));
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Runs every line of a pipeline file as if it had been typed.
+// If any line fails, the operations added so far are dropped so a broken
+// file doesn't leave a half-loaded pipeline behind.
+fn source_pipeline(path: &Path,
+                   operations: &mut Vec<Operation>,
+                   opened_files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let mut contents = String::new();
+    let file = File::open(path);
+    if file.is_err() || file.unwrap().read_to_string(&mut contents).is_err() {
+        return Err(format!("cannot read {}", path.display()));
+    }
+
+    let operations_len = operations.len();
+    let opened_files_len = opened_files.len();
+
+    let mut line_number = 0;
+    for line in contents.lines() {
+        line_number += 1;
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+
+        let result = command::parse_command(line, operations.len() - operations_len)
+            .map(|command| command::offset_operations(command, operations_len))
+            .and_then(|command| {
+                match command {
+                    Command::Write(_) | Command::Source(_) | Command::Quit
+                        => Err("not allowed in a pipeline file".to_string()),
+                    _   => execute_command(command, operations, opened_files),
+                }
+            });
+
+        if result.is_err() {
+            operations.truncate(operations_len);
+            opened_files.truncate(opened_files_len);
+            return Err(format!("{}:{}: {}", path.display(), line_number,
+                               result.err().unwrap()));
+        }
     }
 
-    endwin();
+    Ok(())
 }
 
-fn get_merge_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
-                       operations: &Vec<Operation>,
-                       opened_files: &Vec<PathBuf>) -> Option<Operation> {
-    let operation0 = select_operation(minibuffer_window, operations_window,
-                                      &operations, &opened_files,
-                                      "Merge: (");
-    if operation0.is_none() { return None; }
-
-    let operation0 = operation0.unwrap();
-    let prompt = format!("Merge: ({}, ", operation0 + 1);
-    let operation1 = select_operation(minibuffer_window, operations_window,
-                                      &operations, &opened_files,
-                                      prompt.as_str());
-
-    if operation1.is_none() { return None; }
+// NOTE(erick): The first word is completed against the command names,
+// anything after it is completed as a path.
+fn complete_command_line(line: &str) -> Option<Vec<String>> {
+    let last_space_index = line.rfind(' ');
+    if last_space_index.is_none() {
+        return Some(command::matching_command_names(line));
+    }
 
-    let operation1 = operation1.unwrap();
-    let direction = select_direction(minibuffer_window);
+    let last_space_index = last_space_index.unwrap();
+    let head = &line[0 .. last_space_index + 1];
+    let word = completion::expand_path(&line[last_space_index + 1 ..]);
 
-    if direction.is_none() { return None; }
+    // NOTE(erick): get_maximum_path_matching needs a directory to search,
+    // so relative paths are completed from the current one.
+    let is_relative = !word.contains('/');
+    let to_complete = if is_relative { format!("./{}", word) } else { word };
 
-    let direction = direction.unwrap();
+    let matches = get_maximum_path_matching(to_complete.as_str())?;
 
-    let confirmation_prompt = format!("Merge({}, {}, {})",
-                                      operation0, operation1, direction);
-    let confirmation = get_confirmation(minibuffer_window,
-                                        confirmation_prompt.as_str());
-    if !confirmation { return None; }
+    let result = matches.into_iter().map(|path| {
+        let path = if is_relative { path[2 ..].to_string() } else { path };
+        format!("{}{}", head, path)
+    }).collect();
 
-    Some(Operation::Merge(operation0, operation1, direction))
+    Some(result)
 }
 
-fn get_crop_operation(minibuffer_window: WINDOW, operations_window: WINDOW,
-                       operations: &Vec<Operation>,
-                       opened_files: &Vec<PathBuf>) -> Option<Operation> {
-    let operation = select_operation(minibuffer_window, operations_window,
-                                      &operations, &opened_files,
-                                      "Merge: (");
-    if operation.is_none() { return None; }
+// NOTE(erick): Built from the same tables the main loop and the command
+// line use, so rebinding a key or adding a command shows up here too.
+fn help_lines() -> Vec<String> {
+    let bindings = keys::bindings();
+    let key_names = |action: Action| {
+        bindings.keys(action).into_iter()
+            .map(keys::key_name).collect::<Vec<_> >().join(", ")
+    };
+
+    let mut lines = Vec::new();
+    lines.push("Keys:".to_string());
+    for info in keys::ACTIONS.iter().filter(|info| info.context == Context::Global) {
+        lines.push(format!("  {:<14} {}", key_names(info.action), info.description));
+    }
 
-    let x0 = enter_u32(minibuffer_window, "X0: ");
-    if x0.is_none() { return None; }
+    lines.push("".to_string());
+    lines.push("Inside prompts:".to_string());
+    for info in keys::ACTIONS.iter().filter(|info| info.context == Context::Prompt) {
+        lines.push(format!("  {:<14} {}", key_names(info.action), info.description));
+    }
+    for &(key, description) in keys::PROMPT_KEYS.iter() {
+        lines.push(format!("  {:<14} {}", keys::key_name(key), description));
+    }
 
-    let y0 = enter_u32(minibuffer_window, "Y0: ");
-    if y0.is_none() { return None; }
+    lines.push("".to_string());
+    lines.push(format!("File browser (press {} in a path prompt):", key_names(Action::Browse)));
+    for &(key, description) in keys::BROWSER_KEYS.iter() {
+        lines.push(format!("  {:<14} {}", keys::key_name(key), description));
+    }
 
+    lines.push("".to_string());
+    lines.push(format!("Bookmarks and recent files (press {} in a path prompt):",
+                       key_names(Action::Places)));
+    for &(key, description) in keys::PLACES_KEYS.iter() {
+        lines.push(format!("  {:<14} {}", keys::key_name(key), description));
+    }
 
-    let width = enter_u32(minibuffer_window, "WIDTH: ");
-    if width.is_none() { return None; }
+    lines.push("".to_string());
+    lines.push(format!("Curve editor (press {}):", key_names(Action::Curves)));
+    for &(key, description) in keys::CURVE_KEYS.iter() {
+        lines.push(format!("  {:<14} {}", keys::key_name(key), description));
+    }
 
+    lines.push("".to_string());
+    lines.push(format!("Commands (press {} first):", key_names(Action::Command)));
+    for info in command::COMMANDS.iter() {
+        lines.push(format!("  {:<28} {}", info.usage, info.description));
+    }
 
-    let height = enter_u32(minibuffer_window, "HEIGHT: ");
-    if height.is_none() { return None; }
+    lines
+}
 
+fn show_help(screen_height: i32, screen_width: i32) {
+    let window = newwin(screen_height, screen_width, 0, 0);
+    defer! {{ delwin(window); }}
 
-    let operation = operation.unwrap();
-    let x0 = x0.unwrap();
-    let y0 = y0.unwrap();
-    let width = width.unwrap() as i32;
-    let height = height.unwrap() as i32;
+    let old_cursor = curs_set(CURSOR_INVISIBLE);
+    defer! {
+        if let Some(old_cursor) = old_cursor {
+            curs_set(old_cursor);
+        }
+    }
 
-    let confirmation_prompt = format!("Crop({}, {}, {}, {}, {})",
-                                      operation,
-                                      x0, y0,
-                                      width, height);
-    let confirmation = get_confirmation(minibuffer_window,
-                                        confirmation_prompt.as_str());
-    if !confirmation { return None; }
+    let lines = help_lines();
+    let visible_lines = (screen_height - 1).max(1) as usize;
+    let mut first_line = 0;
+    loop {
+        clear_window(window);
+        wmove(window, 0, 0);
+        wattron(window, theme::attribute(QUESTION_COLOR));
+        wprintw(window, "Help: Up/Down scroll, any other key closes");
+        wattroff(window, theme::attribute(QUESTION_COLOR));
+
+        for (row, line) in lines.iter().skip(first_line).take(visible_lines).enumerate() {
+            wmove(window, row as i32 + 1, 0);
+            wprintw(window, line.as_str());
+        }
+        wrefresh(window);
 
-    Some(Operation::Crop(operation, x0, y0, width, height))
+        let ch = getch();
+        match ch {
+            KEY_UP   => {
+                first_line = first_line.saturating_sub(1);
+            },
+            KEY_DOWN => {
+                if first_line + visible_lines < lines.len() { first_line += 1; }
+            },
+            _        => { return; },
+        }
+    }
 }
 
 fn get_confirmation(minibuffer: WINDOW, prompt: &str) -> bool {
@@ -280,14 +1487,14 @@ fn get_confirmation(minibuffer: WINDOW, prompt: &str) -> bool {
     loop {
         let ch = getch();
         match ch {
-            KEY_ENTER => { return true; },
-            KEY_ESC   => { return false; },
-            KEY_Q     => { return false; },
+            _ if keys::is(ch, Action::Confirm) => { return true; },
+            _ if keys::is(ch, Action::Cancel)  => { return false; },
             _         => {  },
         }
     }
 }
 
+#[allow(clippy::len_zero, clippy::unnecessary_cast, clippy::unnecessary_unwrap)]
 fn select_operation(minibuffer: WINDOW, window: WINDOW,
                     operations: &Vec<Operation>,
                     opened_files: &Vec<PathBuf>,
@@ -307,21 +1514,48 @@ fn select_operation(minibuffer: WINDOW, window: WINDOW,
     wrefresh(minibuffer);
 
     let mut selected: isize = 0;
+    let mut first_visible = 0;
     loop {
         let mut selected_increment = 0;
 
+        // NOTE(erick): Scrolling just enough to keep the selection visible.
+        let rows = visible_rows(window);
+        if (selected as usize) < first_visible {
+            first_visible = selected as usize;
+        }
+        if selected as usize >= first_visible + rows {
+            first_visible = selected as usize + 1 - rows;
+        }
+
         clear_window(window);
         wprint_operations(window,
-                          operations, opened_files, selected as isize);
+                          operations, opened_files, selected as isize, first_visible);
         wrefresh(window);
 
         let ch = getch();
         match ch {
-            KEY_ENTER => { return Some(selected as usize); },
-            KEY_ESC   => { return None; },
-            KEY_Q     => { return None; },
+            _ if keys::is(ch, Action::Confirm) => { return Some(selected as usize); },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
             KEY_UP    => { selected_increment = -1; },
             KEY_DOWN  => { selected_increment =  1; },
+            KEY_MOUSE => {
+                let event = get_mouse_event();
+                if event.is_some() {
+                    let event = event.unwrap();
+                    selected_increment = wheel_direction(&event);
+
+                    let row = clicked_row(window, &event);
+                    if row.is_some() {
+                        let clicked = first_visible + row.unwrap();
+                        if clicked < operations.len() {
+                            selected = clicked as isize;
+                            if event.bstate & BUTTON1_DOUBLE_CLICKED as mmask_t != 0 {
+                                return Some(selected as usize);
+                            }
+                        }
+                    }
+                }
+            },
             _         => { },
         }
 
@@ -337,6 +1571,7 @@ fn select_operation(minibuffer: WINDOW, window: WINDOW,
     }
 }
 
+#[allow(clippy::question_mark)]
 fn select_direction(minibuffer: WINDOW) -> Option<Direction> {
     let options = vec!['H', 'V'];
     let chosen = select_from_options(minibuffer, &options, "Direction: ");
@@ -352,6 +1587,7 @@ fn select_direction(minibuffer: WINDOW) -> Option<Direction> {
     }
 }
 
+#[allow(clippy::explicit_counter_loop, clippy::len_zero, clippy::unnecessary_unwrap)]
 fn select_from_options(minibuffer: WINDOW,
                        options: &Vec<char>,
                        prompt: &str) -> Option<char> {
@@ -389,11 +1625,28 @@ fn select_from_options(minibuffer: WINDOW,
 
         let ch = getch();
         match ch {
-            KEY_ENTER => { return Some(options[selected as usize]); },
-            KEY_ESC   => { return None; },
-            KEY_Q     => { return None; },
+            _ if keys::is(ch, Action::Confirm) => { return Some(options[selected as usize]); },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
             KEY_LEFT  => { selected_increment = -1; },
             KEY_RIGHT => { selected_increment =  1; },
+            KEY_MOUSE => {
+                let event = get_mouse_event();
+                if event.is_some() {
+                    let event = event.unwrap();
+                    selected_increment = wheel_direction(&event);
+
+                    // NOTE(erick): Each option takes two columns, the
+                    // character and the space after it.
+                    let column = event.x - getbegx(minibuffer) -
+                        prompt.chars().count() as i32;
+                    let is_click = event.bstate &
+                        (BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED) as mmask_t != 0;
+                    if is_click && wenclose(minibuffer, event.y, event.x) &&
+                        column >= 0 && ((column / 2) as usize) < options.len() {
+                        return Some(options[(column / 2) as usize]);
+                    }
+                }
+            },
             _         => { },
         }
 
@@ -409,58 +1662,247 @@ fn select_from_options(minibuffer: WINDOW,
     }
 }
 
-fn enter_u32(minibuffer: WINDOW, prompt: &str) -> Option<u32> {
+// NOTE(erick): What a number prompt accepts. Only ranges that go below
+// zero take a sign and only non-integers take a decimal point. Up and
+// down change the value by 'step' without leaving the range.
+#[derive(Clone, Copy)]
+struct NumberBounds {
+    min: f64,
+    max: f64,
+    default: Option<f64>,
+    step: f64,
+    integer: bool,
+}
+
+fn format_number(value: f64, step: f64) -> String {
+    let step_string = step.to_string();
+    let decimals = step_string.find('.').map(|dot| step_string.len() - dot - 1).unwrap_or(0);
+    format!("{:.*}", decimals, value)
+}
+
+// NOTE(erick): The default is shown as if it had been typed, the first
+// key replaces it. Values out of range are refused and kept so they can
+// be fixed.
+fn enter_number(minibuffer: WINDOW, prompt: &str, bounds: NumberBounds) -> Option<f64> {
+    let mut string = bounds.default.map(|value| format_number(value, bounds.step))
+        .unwrap_or_default();
+    let mut is_default = bounds.default.is_some();
+    loop {
+        wclear(minibuffer);
+        wmove(minibuffer, 0, 0);
+        wprintw(minibuffer, prompt);
+        wprintw(minibuffer, string.as_str());
+        wrefresh(minibuffer);
+
+        change_to_color(minibuffer, NORMAL_COLOR);
+
+        let mut char_to_push = None;
+        let mut increment = 0.0;
+        let mut done = false;
+
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => { done = true; },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
+            KEY_BACKSPACE => { string.pop(); },
+            KEY_UP        => { increment =  bounds.step; },
+            KEY_DOWN      => { increment = -bounds.step; },
+            KEY_MOUSE     => { get_mouse_event(); },
+            _             => { char_to_push = Some(ch) },
+        };
+
+        if let Some(char_to_push) = char_to_push {
+            if is_default {
+                string.clear();
+            }
+
+            let char_to_push = get_char(char_to_push);
+            match char_to_push {
+                ch @ '0' ..= '9' => { string.push(ch); },
+                '-' if bounds.min < 0.0 && string.is_empty() => { string.push('-'); },
+                '.' if !bounds.integer && !string.contains('.') => { string.push('.'); },
+                _ => { change_to_color(minibuffer, ERROR_COLOR); },
+            }
+        }
+        is_default = false;
+
+        if increment != 0.0 {
+            let current = string.parse::<f64>().ok()
+                .or(bounds.default)
+                .unwrap_or(0.0_f64.clamp(bounds.min, bounds.max));
+            let value = ((current + increment) / bounds.step).round() * bounds.step;
+            string = format_number(value.clamp(bounds.min, bounds.max), bounds.step);
+        }
+
+        if done {
+            match string.parse::<f64>() {
+                Ok(parsed) if (bounds.min ..= bounds.max).contains(&parsed) => {
+                    return Some(parsed);
+                },
+                _ => { change_to_color(minibuffer, ERROR_COLOR); },
+            }
+        }
+    }
+}
+
+fn enter_u32(minibuffer: WINDOW, prompt: &str,
+             min: u32, max: u32, default: Option<u32>) -> Option<u32> {
+    let bounds = NumberBounds {
+        min: min as f64,
+        max: max as f64,
+        default: default.map(|value| value as f64),
+        step: 1.0,
+        integer: true,
+    };
+    enter_number(minibuffer, prompt, bounds).map(|value| value as u32)
+}
+
+fn enter_i32(minibuffer: WINDOW, prompt: &str,
+             min: i32, max: i32, default: Option<i32>) -> Option<i32> {
+    let bounds = NumberBounds {
+        min: min as f64,
+        max: max as f64,
+        default: default.map(|value| value as f64),
+        step: 1.0,
+        integer: true,
+    };
+    enter_number(minibuffer, prompt, bounds).map(|value| value as i32)
+}
+
+fn enter_f64(minibuffer: WINDOW, prompt: &str,
+             min: f64, max: f64, default: Option<f64>, step: f64) -> Option<f64> {
+    let bounds = NumberBounds { min, max, default, step, integer: false };
+    enter_number(minibuffer, prompt, bounds)
+}
+
+// NOTE(erick): A list of numbers separated by spaces, typed like the
+// arguments of the commands and checked by 'parse' when confirmed.
+// Errors are shown inline and the numbers kept so they can be fixed.
+fn enter_numbers<T, F>(minibuffer: WINDOW, prompt: &str, parse: F) -> Option<T>
+    where F: Fn(&[String]) -> Result<T, String> {
     let mut string = String::new();
+    let mut error_message: Option<String> = None;
     loop {
         wclear(minibuffer);
         wmove(minibuffer, 0, 0);
         wprintw(minibuffer, prompt);
         wprintw(minibuffer, string.as_str());
+        if let Some(ref error_message) = error_message {
+            wprintw(minibuffer, "  [");
+            wprintw(minibuffer, error_message.as_str());
+            wprintw(minibuffer, "]");
+            wmove(minibuffer, 0, (prompt.len() + string.len()) as i32);
+        }
         wrefresh(minibuffer);
 
         change_to_color(minibuffer, NORMAL_COLOR);
+        error_message = None;
 
         let mut char_to_push = None;
         let mut done = false;
 
         let ch = getch();
         match ch {
-            KEY_ENTER     => { done = true; },
-            KEY_ESC       => { return None; },
-            KEY_Q         => { return None; },
+            _ if keys::is(ch, Action::Confirm) => { done = true; },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
             KEY_BACKSPACE => { string.pop(); },
+            KEY_MOUSE     => { get_mouse_event(); },
             _             => { char_to_push = Some(ch) },
         };
 
-        if char_to_push.is_some() {
-            let char_to_push = get_char(char_to_push.unwrap());
+        if let Some(char_to_push) = char_to_push {
+            let char_to_push = get_char(char_to_push);
             match char_to_push {
-                ch @ '0' ... '9' => { string.push(ch); },
-                _               => { change_to_color(minibuffer, ERROR_COLOR); },
+                ch @ '0' ..= '9' | ch @ '.' | ch @ '-' | ch @ ' ' => { string.push(ch); },
+                _ => { change_to_color(minibuffer, ERROR_COLOR); },
             }
         }
 
         if done {
-            let parsed = string.parse::<u32>();
-            if parsed.is_ok() {
-                return Some(parsed.unwrap());
-            } else {
+            let tokens = string.split_whitespace().map(|token| token.to_string())
+                .collect::<Vec<_> >();
+            match parse(tokens.as_slice()) {
+                Ok(value)    => { return Some(value); },
+                Err(message) => {
+                    change_to_color(minibuffer, ERROR_COLOR);
+                    error_message = Some(message);
+                },
+            }
+        }
+    }
+}
+
+// NOTE(erick): Palette files aren't images, so they are typed here with
+// tab completion instead of going through open_file and the browser.
+// The file is read when confirmed and errors are shown inline.
+fn enter_palette_file(minibuffer: WINDOW) -> Option<PathBuf> {
+    let prompt = "Palette file: ";
+    let mut string = get_current_path();
+    let mut error_message: Option<String> = None;
+    loop {
+        wclear(minibuffer);
+        wmove(minibuffer, 0, 0);
+        wprintw(minibuffer, prompt);
+        wprintw(minibuffer, string.as_str());
+        if let Some(ref error_message) = error_message {
+            wprintw(minibuffer, "  [");
+            wprintw(minibuffer, error_message.as_str());
+            wprintw(minibuffer, "]");
+            wmove(minibuffer, 0, (prompt.len() + string.len()) as i32);
+        }
+        wrefresh(minibuffer);
+
+        change_to_color(minibuffer, NORMAL_COLOR);
+        error_message = None;
+
+        let mut auto_complete = false;
+        let mut done = false;
+
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => { done = true; },
+            _ if keys::is(ch, Action::Cancel)  => { return None; },
+            KEY_TAB       => { auto_complete = true; },
+            KEY_BACKSPACE => { string.pop(); },
+            KEY_MOUSE     => { get_mouse_event(); },
+            _             => {
+                if is_printable(ch) {
+                    string.push(get_char(ch));
+                }
+            },
+        };
+
+        if auto_complete {
+            string = completion::expand_path(string.as_str());
+            let completed = select_completion(minibuffer, prompt, &mut string,
+                                              &get_maximum_path_matching);
+            if !completed {
                 change_to_color(minibuffer, ERROR_COLOR);
             }
         }
+
+        if done {
+            let path = PathBuf::from(completion::expand_path(string.as_str()));
+            match palette::read_file(&path) {
+                Ok(_)        => { return Some(path); },
+                Err(message) => {
+                    change_to_color(minibuffer, ERROR_COLOR);
+                    error_message = Some(message);
+                },
+            }
+        }
     }
 }
 
-#[allow(unused_variables, unused_assignments)]
+// NOTE(erick): Only the file browser can return more than one file,
+// and only when opening.
+#[allow(unused_assignments, clippy::unnecessary_unwrap)]
 fn open_file(win: WINDOW, screen_height: i32, screen_width: i32,
-             file_must_exists: bool) -> Option<PathBuf> {
+             file_must_exists: bool) -> Option<Vec<PathBuf> > {
     let mut string = get_current_path();
 
     let mut done = false;
     let mut do_open_file = false;
-    let mut is_autocompleting = false;
-    let mut completion = None;
-    let mut completion_index = 0;
 
     loop {
         wclear(win);
@@ -472,44 +1914,64 @@ fn open_file(win: WINDOW, screen_height: i32, screen_width: i32,
         change_to_color(win, NORMAL_COLOR);
 
         let mut auto_complete = false;
-        let mut restart_state = true;
+        let mut show_browser = false;
+        let mut show_places = false;
 
         let ch = getch();
         match ch {
-            KEY_ENTER     => { done = true; do_open_file = true; },
-            KEY_ESC       => { done = true; do_open_file = false; },
-            KEY_TAB       => { auto_complete = true; restart_state = false; },
+            _ if keys::is(ch, Action::Confirm) => { done = true; do_open_file = true; },
+            _ if keys::is(ch, Action::Cancel)  => { done = true; do_open_file = false; },
+            _ if keys::is(ch, Action::Browse)  => { show_browser = true; },
+            _ if keys::is(ch, Action::Places)  => { show_places = true; },
+            KEY_TAB       => { auto_complete = true; },
             KEY_BACKSPACE => { string.pop(); },
-            _             => { string.push(get_char(ch)); },
+            KEY_MOUSE     => { get_mouse_event(); },
+            _             => {
+                if is_printable(ch) {
+                    string.push(get_char(ch));
+                }
+            },
         };
 
         if auto_complete {
-            if !is_autocompleting {
-                is_autocompleting = true;
-                completion = get_maximum_path_matching(string.as_ref());
-                if completion.is_some() {
-                    let completion = completion.as_ref().unwrap();
-                    if completion.len() > 0 {
-                        string = maximum_prefix(completion);
-                    }  else {
-                        change_to_color(win, ERROR_COLOR);
-                    }
-                }
-            } else {
-                if completion.is_some() {
-                    let options = completion.as_ref().unwrap();
-                    if options.len() > 0 {
-                        string = options[completion_index].clone();
-                        completion_index += 1;
-                        if completion_index >= options.len() {
-                            completion_index = 0;
+            string = completion::expand_path(string.as_str());
+            let completed = select_completion(win, "File: ", &mut string,
+                                              &get_maximum_path_matching);
+            if !completed {
+                change_to_color(win, ERROR_COLOR);
+            }
+        }
+
+        if show_browser {
+            let start_directory = browser::start_directory(string.as_str());
+            let result = browser::browse(screen_height, screen_width,
+                                         start_directory, file_must_exists);
+            match result {
+                BrowseResult::Selected(paths) => {
+                    let paths = paths.iter()
+                        .map(|path| handle_file_opening(&path.to_string_lossy().into_owned(),
+                                                        file_must_exists))
+                        .collect::<Result<Vec<_>, _> >();
+                    if paths.is_ok() {
+                        let paths = paths.unwrap();
+                        for path in paths.iter() {
+                            let _ = places::add_recent(path);
                         }
-                    } else {
-                        change_to_color(win, ERROR_COLOR);
+                        return Some(paths);
                     }
-                } else {
                     change_to_color(win, ERROR_COLOR);
-                }
+                },
+                BrowseResult::EditPath(path) => { string = path; },
+                BrowseResult::Cancelled      => { },
+            }
+        }
+
+        if show_places {
+            let current_directory = browser::start_directory(string.as_str());
+            let result = places::choose(screen_height, screen_width, &current_directory);
+            match result {
+                PlacesResult::Selected(path) => { string = path; },
+                PlacesResult::Cancelled      => { },
             }
         }
 
@@ -517,9 +1979,11 @@ fn open_file(win: WINDOW, screen_height: i32, screen_width: i32,
             if !do_open_file {
                 return None;
             } else {
-                if let Ok(path_buf) = handle_file_opening(&string,
+                let expanded = completion::expand_path(string.as_str());
+                if let Ok(path_buf) = handle_file_opening(&expanded,
                                                           file_must_exists) {
-                    return Some(path_buf);
+                    let _ = places::add_recent(&path_buf);
+                    return Some(vec![path_buf]);
                 } else {
                     change_to_color(win, ERROR_COLOR);
                     done = false;
@@ -527,16 +1991,13 @@ fn open_file(win: WINDOW, screen_height: i32, screen_width: i32,
             }
         }
 
-        if restart_state {
-            is_autocompleting = false;
-            completion_index = 0;
-        }
     }
 }
 
 // NOTE(erick): Since we don't have a goto statement
 // this function was extracted from the code above so
 // we can do early-outs an keep the code more readable.
+#[allow(clippy::ptr_arg)]
 fn handle_file_opening(string: &String,
                        file_must_exists: bool) -> Result<PathBuf, ()> {
     // TODO(erick): We already had a PathBuf before,
@@ -558,18 +2019,14 @@ fn handle_file_opening(string: &String,
     let mut path_buf = PathBuf::new();
     path_buf.push(path);
 
-    // NOTE(erick): Isolating the scope of
-    // extension to make the compiler happy.
-    {
-        let extension = path_buf.extension();
-        if extension.is_none() {
-            return Err ( () )
-        }
-
-        let extension = extension.unwrap();
-        if extension != "bmp" {
+    // NOTE(erick): Existing files are recognized by their contents, new
+    // ones need an extension to pick the encoder.
+    if file_must_exists {
+        if !codec::has_supported_signature(&path_buf) {
             return Err ( () )
         }
+    } else if !codec::has_supported_extension(&path_buf) {
+        return Err ( () )
     }
 
     Ok(path_buf)
@@ -590,6 +2047,7 @@ fn get_current_path() -> String {
     result.unwrap()
 }
 
+#[allow(clippy::len_zero, clippy::question_mark)]
 fn get_maximum_path_matching(to_complete: &str) -> Option<Vec<String> > {
     let last_slash_index = to_complete.rfind('/');
     if last_slash_index.is_none() {
@@ -597,13 +2055,9 @@ fn get_maximum_path_matching(to_complete: &str) -> Option<Vec<String> > {
     }
 
     let last_slash_index = last_slash_index.unwrap();
-    let path_to_search = &to_complete[0 .. last_slash_index];
+    let path_to_search = if last_slash_index == 0 { "/" } else { &to_complete[0 .. last_slash_index] };
     let string_to_match = &to_complete[last_slash_index + 1 ..];
 
-    if string_to_match.len() == 0 {
-        return None;
-    }
-
     let dir_iterator = read_dir(path_to_search);
     if dir_iterator.is_err() {
         return None;
@@ -611,9 +2065,6 @@ fn get_maximum_path_matching(to_complete: &str) -> Option<Vec<String> > {
 
     let mut matching_files = Vec::new();
     let dir_iterator = dir_iterator.unwrap();
-    // NOTE(erick): We could do a fuzzy search or
-    // try to correct the string using Levenshtein distance
-    // but I will keep it simple for now.
     for dir in dir_iterator {
         if dir.is_err() {
             // TODO(erick): Log this?
@@ -629,15 +2080,33 @@ fn get_maximum_path_matching(to_complete: &str) -> Option<Vec<String> > {
         }
 
         let filename = filename.unwrap().to_string_lossy();
-        if filename.starts_with(string_to_match) {
-            matching_files.push(path.clone().
-                                into_os_string().into_string().unwrap_or_default());
+        // NOTE(erick): Hidden files only show up when asked for.
+        if filename.starts_with('.') && !string_to_match.starts_with('.') {
+            continue;
+        }
+
+        let score = if string_to_match.len() == 0 {
+            Some(0)
+        } else {
+            completion::match_score(filename.as_ref(), string_to_match)
+        };
+        if score.is_none() {
+            continue;
         }
+
+        let mut path_string = path.clone().into_os_string().into_string().unwrap_or_default();
+        if path.is_dir() {
+            path_string.push('/');
+        }
+
+        matching_files.push((score.unwrap(), path_string));
     }
 
-    Some(matching_files)
+    matching_files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Some(matching_files.into_iter().map(|(_, path)| path).collect())
 }
 
+#[allow(clippy::len_zero, clippy::needless_range_loop)]
 fn maximum_prefix(strings: &Vec<String>) -> String {
     if strings.len() == 0 {
         return "".to_string();
@@ -669,15 +2138,139 @@ fn maximum_prefix(strings: &Vec<String>) -> String {
     result
 }
 
-fn clear_window(win: WINDOW) {
-    change_to_color(win, NORMAL_COLOR);
-    wclear(win);
-    wrefresh(win);
+// NOTE(erick): Completes 'string' in place. A single candidate is taken
+// right away, otherwise the common prefix is filled in and the candidates
+// are listed in a popup above the minibuffer. Typing keeps filtering the
+// list. Returns false if nothing matched.
+fn select_completion(minibuffer: WINDOW, prompt: &str, string: &mut String,
+                     complete: &dyn Fn(&str) -> Option<Vec<String> >) -> bool {
+    let candidates = complete(string.as_str());
+    if candidates.is_none() || candidates.as_ref().unwrap().is_empty() {
+        return false;
+    }
+
+    let mut candidates = candidates.unwrap();
+    if candidates.len() == 1 {
+        *string = candidates.remove(0);
+        return true;
+    }
+
+    let prefix = maximum_prefix(&candidates);
+    if prefix.len() > string.len() && prefix.starts_with(string.as_str()) {
+        *string = prefix;
+    }
+
+    let max_rows = getbegy(minibuffer).min(10);
+    if max_rows <= 0 {
+        return true;
+    }
+
+    let rows = (candidates.len() as i32).min(max_rows);
+    let popup = newwin(rows, getmaxx(minibuffer), getbegy(minibuffer) - rows, 0);
+    defer! {{
+        wclear(popup);
+        wrefresh(popup);
+        delwin(popup);
+    }}
+
+    let mut selected = 0;
+    let mut first_visible = 0;
+    loop {
+        if selected < first_visible {
+            first_visible = selected;
+        } else if selected >= first_visible + rows as usize {
+            first_visible = selected + 1 - rows as usize;
+        }
+
+        change_to_color(popup, QUESTION_COLOR);
+        wclear(popup);
+        for (row, candidate) in candidates.iter().enumerate()
+            .skip(first_visible).take(rows as usize) {
+            // NOTE(erick): Only the last path component is interesting,
+            // the rest is what's been typed already.
+            let trimmed = candidate.trim_end_matches('/');
+            let name_start = trimmed.rfind(['/', ' ']).map(|i| i + 1).unwrap_or(0);
+            let name = &candidate[name_start ..];
+
+            wmove(popup, (row - first_visible) as i32, 0);
+            if row == selected {
+                wattron(popup, theme::attribute(HIGHLIGHT_COLOR));
+                wprintw(popup, format!(" {} ", name).as_str());
+                wattroff(popup, theme::attribute(HIGHLIGHT_COLOR));
+            } else {
+                wprintw(popup, format!(" {} ", name).as_str());
+            }
+        }
+        wrefresh(popup);
+
+        wclear(minibuffer);
+        wmove(minibuffer, 0, 0);
+        wprintw(minibuffer, prompt);
+        wprintw(minibuffer, string.as_str());
+        wrefresh(minibuffer);
+
+        let mut refilter = false;
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => {
+                *string = candidates[selected].clone();
+                return true;
+            },
+            _ if keys::is(ch, Action::Cancel)  => { return true; },
+            KEY_TAB | KEY_DOWN => { selected = (selected + 1) % candidates.len(); },
+            KEY_UP             => {
+                selected = if selected == 0 { candidates.len() - 1 } else { selected - 1 };
+            },
+            KEY_BACKSPACE      => { string.pop(); refilter = true; },
+            KEY_MOUSE          => {
+                let event = get_mouse_event();
+                if event.is_none() { continue; }
+
+                let event = event.unwrap();
+                let direction = wheel_direction(&event);
+                if direction != 0 {
+                    let moved = selected as isize + direction;
+                    selected = moved.max(0).min(candidates.len() as isize - 1) as usize;
+                    continue;
+                }
+
+                let is_click = event.bstate & BUTTON1_CLICKED as mmask_t != 0;
+                if is_click && wenclose(popup, event.y, event.x) {
+                    let row = first_visible + (event.y - getbegy(popup)) as usize;
+                    if row < candidates.len() {
+                        *string = candidates[row].clone();
+                        return true;
+                    }
+                }
+            },
+            _                  => {
+                if is_printable(ch) {
+                    string.push(get_char(ch));
+                    refilter = true;
+                }
+            },
+        }
+
+        if refilter {
+            let filtered = complete(string.as_str());
+            if filtered.is_none() || filtered.as_ref().unwrap().is_empty() {
+                return false;
+            }
+
+            candidates = filtered.unwrap();
+            selected = 0;
+            first_visible = 0;
+            if candidates.len() == 1 {
+                *string = candidates.remove(0);
+                return true;
+            }
+        }
+    }
 }
 
 #[inline]
-fn change_to_color(window: WINDOW, color: i16) {
-    wbkgd(window, COLOR_PAIR(color));
+fn is_printable(ch: i32) -> bool {
+    (0x20 .. KEY_BACKSPACE).contains(&ch)
 }
 
 #[inline]
@@ -695,7 +2288,7 @@ fn wprint_i32_char_debug(win: WINDOW, ch: i32) {
     wprintw(win, format!("{}: {:08x}", get_char(ch), ch).as_ref());
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::explicit_counter_loop)]
 fn wprint_strings(win: WINDOW, strings: &Vec<String>) {
     let mut line_numer = 0;
     for string in strings {
@@ -706,25 +2299,28 @@ fn wprint_strings(win: WINDOW, strings: &Vec<String>) {
     }
 }
 
-fn wprint_files(window: WINDOW, files: &Vec<PathBuf>) {
+#[allow(clippy::explicit_counter_loop, clippy::ptr_arg)]
+fn wprint_files(window: WINDOW, files: &Vec<PathBuf>, first_visible: usize) {
     wmove(window, 0, 0);
     wprintw(window, "Opened files:");
 
-    let mut file_number = 1;
-    for file in files {
-        wmove(window, file_number, 0);
+    let mut line_number = 1;
+    for (file_index, file) in files.iter().enumerate()
+        .skip(first_visible).take(visible_rows(window)) {
+        wmove(window, line_number, 0);
 
         wprintw(window, format!("{}: {}",
-                                file_number, file_stem(file)).as_str());
+                                file_index + 1, file_stem(file)).as_str());
 
-        file_number += 1;
+        line_number += 1;
     }
 }
 
-#[allow(unused_variables)]
+#[allow(unused_variables, clippy::explicit_counter_loop, clippy::match_ref_pats,
+        clippy::ptr_arg)]
 fn wprint_operations(window: WINDOW,
                      operations: &Vec<Operation>, opened_files: &Vec<PathBuf>,
-                     selected_operation: isize) {
+                     selected_operation: isize, first_visible: usize) {
     wmove(window, 0, 0);
     change_to_color(window, NORMAL_COLOR);
     wprintw(window, "Operations:");
@@ -732,10 +2328,10 @@ fn wprint_operations(window: WINDOW,
     // NOTE(erick): If selected_operation is -1 (meaning no operation is
     // selected) selected_number will be zero an no entry will be highlighted.
     let selected_number = selected_operation + 1;
-    let mut operation_number = 1;
+    let mut operation_number = first_visible as isize + 1;
 
-    for operation in operations {
-        wmove(window, operation_number as i32, 0);
+    for operation in operations.iter().skip(first_visible).take(visible_rows(window)) {
+        wmove(window, (operation_number - first_visible as isize) as i32, 0);
 
         if operation_number == selected_number {
             change_to_color(window, HIGHLIGHT_COLOR);
@@ -751,10 +2347,54 @@ fn wprint_operations(window: WINDOW,
                                         file_stem(&opened_files[file_index])).as_str());
 
             },
-            &Operation::Save(op_index, file_index) => {
-                wprintw(window, format!("Save({}: {})", op_index,
+            &Operation::Save(op_index, file_index, ref options) => {
+                let path = &opened_files[file_index];
+                let format = codec::format_from_extension(path);
+                if format == Some(Format::Jpeg) {
+                    wprintw(window, format!("Save({}: {}, {} {})", op_index, file_stem(path),
+                                            options.quality, options.subsampling).as_str());
+                } else if format == Some(Format::Bmp) && options.palette_bits.is_some() {
+                    wprintw(window, format!("Save({}: {}, {}-bit)", op_index, file_stem(path),
+                                            options.palette_bits.unwrap()).as_str());
+                } else if codec::is_netpbm(format) {
+                    let mut details = Vec::new();
+                    if options.plain.is_some() {
+                        let encoding = if options.plain.unwrap() { "plain" } else { "raw" };
+                        details.push(encoding.to_string());
+                    }
+                    if options.sample_bits != 8 {
+                        details.push(format!("{}-bit", options.sample_bits));
+                    }
+                    if details.is_empty() {
+                        wprintw(window, format!("Save({}: {})", op_index,
+                                                file_stem(path)).as_str());
+                    } else {
+                        wprintw(window, format!("Save({}: {}, {})", op_index, file_stem(path),
+                                                details.join(" ")).as_str());
+                    }
+                } else {
+                    wprintw(window, format!("Save({}: {})", op_index,
+                                            file_stem(path)).as_str());
+                }
+            },
+            &Operation::Frame(file_index, frame) => {
+                wprintw(window, format!("Frame({}, {})",
+                                        file_stem(&opened_files[file_index]), frame + 1).as_str());
+            },
+            &Operation::Animate(ref frames, _, file_index) => {
+                let ops = frames.iter().map(|&(op, _)| op.to_string()).collect::<Vec<_> >();
+                wprintw(window, format!("Animate({}: {})", ops.join(", "),
+                                        file_stem(&opened_files[file_index])).as_str());
+            },
+            &Operation::SaveIcon(ref ops, file_index) => {
+                let ops = ops.iter().map(|op| op.to_string()).collect::<Vec<_> >();
+                wprintw(window, format!("SaveIcon({}: {})", ops.join(", "),
                                         file_stem(&opened_files[file_index])).as_str());
             },
+            // NOTE(erick): The level Otsu found is only known after a run.
+            &Operation::Threshold(_, Threshold::Otsu(Some(level))) => {
+                wprintw(window, format!("{} -> {}", operation, level).as_str());
+            },
             _  => {
                 wprintw(window, format!("{}", operation).as_str());
             },
@@ -764,6 +2404,23 @@ fn wprint_operations(window: WINDOW,
     }
 }
 
+// NOTE(erick): The first line of the panes is their title.
+fn visible_rows(window: WINDOW) -> usize {
+    (getmaxy(window) - 1).max(1) as usize
+}
+
+fn scroll_position(first_visible: usize, increment: isize,
+                   item_count: usize, window: WINDOW) -> usize {
+    let rows = visible_rows(window);
+    let max_first_visible = item_count.saturating_sub(rows);
+
+    let position = first_visible as isize + increment;
+    if position < 0 { return 0; }
+
+    (position as usize).min(max_first_visible)
+}
+
+#[allow(clippy::ptr_arg)]
 fn file_stem (path: &PathBuf) -> String {
     let file_stem = path.file_stem();
     if file_stem.is_none() {
@@ -778,3 +2435,50 @@ fn file_stem (path: &PathBuf) -> String {
 
     result.unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Image;
+
+    #[test]
+    fn write_and_source_pipeline() {
+        let directory = std::env::temp_dir().join(format!("climp-pipeline-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // NOTE(erick): Opening and saving add recent files, keep them out
+        // of the user's list.
+        std::env::set_var("XDG_DATA_HOME", &directory);
+
+        let input = directory.join("input.png");
+        codec::write_file(&input, &Image::new(4, 4), &SaveOptions::default()).unwrap();
+        let lines = [format!("open {}", input.display()),
+                     "crop 1 0 0 2 2".to_string(),
+                     format!("save 2 {}", directory.join("output.png").display())];
+
+        let mut operations = Vec::new();
+        let mut opened_files = Vec::new();
+        for line in lines.iter() {
+            let command = command::parse_command(line, operations.len()).unwrap();
+            execute_command(command, &mut operations, &mut opened_files).unwrap();
+        }
+
+        let pipeline = directory.join("pipeline.climp");
+        write_pipeline(&pipeline, &operations, &opened_files).unwrap();
+        source_pipeline(&pipeline, &mut operations, &mut opened_files).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(operations.len(), 6);
+        match operations[4] {
+            Operation::Crop(op, 0, 0, 2, 2) => assert_eq!(op, 3),
+            _ => panic!("the crop was not sourced"),
+        }
+        match operations[5] {
+            Operation::Save(op, file, _) => assert_eq!((op, file), (4, 3)),
+            _ => panic!("the save was not sourced"),
+        }
+
+        assert!(source_pipeline(&directory.join("missing.climp"),
+                                &mut operations, &mut opened_files).is_err());
+        assert_eq!(operations.len(), 6);
+    }
+}
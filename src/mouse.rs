@@ -0,0 +1,33 @@
+use ncurses::*;
+
+pub fn get_mouse_event() -> Option<MEVENT> {
+    let mut event = MEVENT { id: 0, x: 0, y: 0, z: 0, bstate: 0 };
+    if getmouse(&mut event) != OK {
+        return None;
+    }
+
+    Some(event)
+}
+
+// NOTE(erick): -1 for wheel up, 1 for wheel down and 0 for anything else.
+pub fn wheel_direction(event: &MEVENT) -> isize {
+    if event.bstate & BUTTON4_PRESSED as mmask_t != 0 { return -1; }
+    if event.bstate & BUTTON5_PRESSED as mmask_t != 0 { return  1; }
+
+    0
+}
+
+// NOTE(erick): The clicked line of a pane, not counting the title.
+pub fn clicked_row(window: WINDOW, event: &MEVENT) -> Option<usize> {
+    let is_click = event.bstate & (BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED) as mmask_t != 0;
+    if !is_click || !wenclose(window, event.y, event.x) {
+        return None;
+    }
+
+    let row = event.y - getbegy(window) - 1;
+    if row < 0 {
+        return None;
+    }
+
+    Some(row as usize)
+}
@@ -0,0 +1,606 @@
+use std::sync::OnceLock;
+
+use config::Section;
+use image::Image;
+
+// NOTE(erick): Reference:
+// https://netpbm.sourceforge.net/doc/pbm.html (and pgm, ppm, pam)
+//
+// P1-P3 are the plain (ASCII) bitmap, graymap and pixmap, P4-P6 their
+// binary versions and P7 is PAM, which is always binary.
+
+// NOTE(erick): Which flavour of the format gets written, picked by the
+// file extension. Anymap is a graymap or a pixmap, whichever holds the
+// image without loss.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Bitmap,
+    Graymap,
+    Pixmap,
+    Anymap,
+    Arbitrary,
+}
+
+pub struct NetpbmOptions {
+    // NOTE(erick): Write P1-P3 instead of P4-P6. PAM has no plain version.
+    pub plain: bool,
+}
+
+impl NetpbmOptions {
+    pub fn default() -> NetpbmOptions {
+        NetpbmOptions { plain: false }
+    }
+
+    // NOTE(erick): [netpbm]
+    //              plain = yes | no
+    pub fn from_section(section: Option<&Section>) -> Result<NetpbmOptions, String> {
+        let mut result = NetpbmOptions::default();
+        if section.is_none() {
+            return Ok(result);
+        }
+
+        for entry in section.unwrap().entries.iter() {
+            match (entry.name.as_str(), entry.value.as_str()) {
+                ("plain", "yes") => { result.plain = true; },
+                ("plain", "no")  => { result.plain = false; },
+                ("plain", _)     => {
+                    return Err(format!("{}: invalid value '{}' for '{}'",
+                                       entry.line, entry.value, entry.name));
+                },
+                _ => return Err(format!("{}: unknown option '{}'", entry.line, entry.name)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+static NETPBM_OPTIONS : OnceLock<NetpbmOptions> = OnceLock::new();
+
+pub fn set_options(options: NetpbmOptions) {
+    let _ = NETPBM_OPTIONS.set(options);
+}
+
+pub fn options() -> &'static NetpbmOptions {
+    NETPBM_OPTIONS.get_or_init(NetpbmOptions::default)
+}
+
+pub fn is_netpbm(bytes: &[u8]) -> bool {
+    bytes.len() >= 3 && bytes[0] == b'P' &&
+        (b'1' ..= b'7').contains(&bytes[1]) && bytes[2].is_ascii_whitespace()
+}
+
+// NOTE(erick): The magic number, '1' to '7'.
+pub fn magic(bytes: &[u8]) -> Option<u8> {
+    if !is_netpbm(bytes) {
+        return None;
+    }
+
+    Some(bytes[1] - b'0')
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TupleType {
+    BlackAndWhite,
+    Grayscale,
+    Rgb,
+    BlackAndWhiteAlpha,
+    GrayscaleAlpha,
+    RgbAlpha,
+}
+
+struct Header {
+    magic: u8,
+    width: usize,
+    height: usize,
+    depth: usize,
+    maxval: u32,
+    tuple_type: TupleType,
+    data_offset: usize,
+}
+
+// NOTE(erick): Reads the whitespace separated tokens of the P1-P6 headers,
+// skipping '#' comments.
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while self.position < self.bytes.len() {
+            let byte = self.bytes[self.position];
+            if byte == b'#' {
+                while self.position < self.bytes.len() && self.bytes[self.position] != b'\n' {
+                    self.position += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, String> {
+        self.skip_whitespace_and_comments();
+        let start = self.position;
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_digit() {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            return Err("Netpbm: expected a number".to_string());
+        }
+
+        let digits = std::str::from_utf8(&self.bytes[start .. self.position]).unwrap();
+        digits.parse::<u32>().map_err(|_| "Netpbm: number too big".to_string())
+    }
+
+    // NOTE(erick): P1 allows bits with no whitespace between them.
+    fn bit(&mut self) -> Result<u32, String> {
+        self.skip_whitespace_and_comments();
+        if self.position >= self.bytes.len() {
+            return Err("Netpbm: unexpected end of file".to_string());
+        }
+
+        let byte = self.bytes[self.position];
+        self.position += 1;
+        match byte {
+            b'0' => Ok(0),
+            b'1' => Ok(1),
+            _    => Err("Netpbm: expected 0 or 1".to_string()),
+        }
+    }
+}
+
+fn read_header(bytes: &[u8]) -> Result<Header, String> {
+    let magic = magic(bytes);
+    if magic.is_none() {
+        return Err("Netpbm: bad signature".to_string());
+    }
+
+    let magic = magic.unwrap();
+    if magic == 7 {
+        return read_pam_header(bytes);
+    }
+
+    let mut tokens = Tokens { bytes, position: 2 };
+    let width = tokens.number()? as usize;
+    let height = tokens.number()? as usize;
+    let is_bitmap = magic == 1 || magic == 4;
+    let maxval = if is_bitmap { 1 } else { tokens.number()? };
+
+    // NOTE(erick): Binary data starts after exactly one whitespace byte.
+    let mut data_offset = tokens.position;
+    if magic >= 4 {
+        data_offset += 1;
+    }
+
+    let (depth, tuple_type) = match magic {
+        1 | 4 => (1, TupleType::BlackAndWhite),
+        2 | 5 => (1, TupleType::Grayscale),
+        _     => (3, TupleType::Rgb),
+    };
+
+    let header = Header { magic, width, height, depth, maxval, tuple_type, data_offset };
+    check_header(&header)?;
+    Ok(header)
+}
+
+fn read_pam_header(bytes: &[u8]) -> Result<Header, String> {
+    let mut width = None;
+    let mut height = None;
+    let mut depth = None;
+    let mut maxval = None;
+    let mut tuple_type = None;
+
+    let mut position = 3;
+    loop {
+        let line_end = bytes[position ..].iter().position(|byte| *byte == b'\n');
+        if line_end.is_none() {
+            return Err("Netpbm: missing ENDHDR".to_string());
+        }
+
+        let line_end = position + line_end.unwrap();
+        let line = String::from_utf8_lossy(&bytes[position .. line_end]);
+        position = line_end + 1;
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        if line == "ENDHDR" { break; }
+
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap();
+        let value = words.collect::<Vec<_> >().join(" ");
+        let number = || value.parse::<u32>().map_err(|_| format!("Netpbm: bad {} '{}'", name, value));
+        match name {
+            "WIDTH"    => { width = Some(number()? as usize); },
+            "HEIGHT"   => { height = Some(number()? as usize); },
+            "DEPTH"    => { depth = Some(number()? as usize); },
+            "MAXVAL"   => { maxval = Some(number()?); },
+            "TUPLTYPE" => { tuple_type = Some(value.clone()); },
+            _          => return Err(format!("Netpbm: unknown PAM header line '{}'", name)),
+        }
+    }
+
+    if width.is_none() || height.is_none() || depth.is_none() || maxval.is_none() {
+        return Err("Netpbm: incomplete PAM header".to_string());
+    }
+
+    let depth = depth.unwrap();
+    // NOTE(erick): Unknown tuple types are guessed from the depth, which
+    // is what most programs that write them mean anyway.
+    let tuple_type = match (tuple_type.as_deref(), depth) {
+        (Some("BLACKANDWHITE"), 1)       => TupleType::BlackAndWhite,
+        (Some("GRAYSCALE"), 1)           => TupleType::Grayscale,
+        (Some("RGB"), 3)                 => TupleType::Rgb,
+        (Some("BLACKANDWHITE_ALPHA"), 2) => TupleType::BlackAndWhiteAlpha,
+        (Some("GRAYSCALE_ALPHA"), 2)     => TupleType::GrayscaleAlpha,
+        (Some("RGB_ALPHA"), 4)           => TupleType::RgbAlpha,
+        (_, 1) => TupleType::Grayscale,
+        (_, 2) => TupleType::GrayscaleAlpha,
+        (_, 3) => TupleType::Rgb,
+        (_, 4) => TupleType::RgbAlpha,
+        _      => return Err(format!("Netpbm: unsupported PAM depth {}", depth)),
+    };
+
+    let header = Header {
+        magic: 7,
+        width: width.unwrap(),
+        height: height.unwrap(),
+        depth,
+        maxval: maxval.unwrap(),
+        tuple_type,
+        data_offset: position,
+    };
+    check_header(&header)?;
+    Ok(header)
+}
+
+fn check_header(header: &Header) -> Result<(), String> {
+    if header.width == 0 || header.height == 0 ||
+        header.width > 0x00ff_ffff || header.height > 0x00ff_ffff {
+        return Err("Netpbm: invalid dimensions".to_string());
+    }
+    if header.maxval == 0 || header.maxval > 65535 {
+        return Err(format!("Netpbm: invalid maxval {}", header.maxval));
+    }
+
+    Ok(())
+}
+
+// NOTE(erick): Only needs the header, so it works on a truncated file.
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let header = read_header(bytes).ok()?;
+    Some((header.width as u32, header.height as u32))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    let header = read_header(bytes)?;
+    let sample_count = header.width * header.height * header.depth;
+
+    let samples = match header.magic {
+        1 => {
+            let mut tokens = Tokens { bytes, position: header.data_offset };
+            let mut samples = Vec::with_capacity(sample_count.min(bytes.len()));
+            for _ in 0 .. sample_count {
+                samples.push(tokens.bit()?);
+            }
+            samples
+        },
+        2 | 3 => {
+            let mut tokens = Tokens { bytes, position: header.data_offset };
+            let mut samples = Vec::with_capacity(sample_count.min(bytes.len()));
+            for _ in 0 .. sample_count {
+                let sample = tokens.number()?;
+                if sample > header.maxval {
+                    return Err("Netpbm: sample bigger than maxval".to_string());
+                }
+                samples.push(sample);
+            }
+            samples
+        },
+        4 => read_packed_bits(bytes, &header)?,
+        _ => read_binary_samples(bytes, &header)?,
+    };
+
+    let scale = |sample: u32| -> u8 {
+        ((sample * 255 + header.maxval / 2) / header.maxval) as u8
+    };
+
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    for (index, tuple) in samples.chunks(header.depth).enumerate() {
+        let pixel = match header.tuple_type {
+            // NOTE(erick): In PBM files 1 is black, in PAM files it's white.
+            TupleType::BlackAndWhite if header.magic != 7 => {
+                let value = if tuple[0] == 1 { 0 } else { 255 };
+                [value, value, value, 255]
+            },
+            TupleType::BlackAndWhite | TupleType::Grayscale => {
+                let value = scale(tuple[0]);
+                [value, value, value, 255]
+            },
+            TupleType::BlackAndWhiteAlpha | TupleType::GrayscaleAlpha => {
+                let value = scale(tuple[0]);
+                [value, value, value, scale(tuple[1])]
+            },
+            TupleType::Rgb      => [scale(tuple[0]), scale(tuple[1]), scale(tuple[2]), 255],
+            TupleType::RgbAlpha => [scale(tuple[0]), scale(tuple[1]), scale(tuple[2]),
+                                    scale(tuple[3])],
+        };
+
+        let x = index % header.width;
+        let y = index / header.width;
+        image.set_pixel(x as u32, y as u32, pixel);
+    }
+
+    Ok(image)
+}
+
+fn read_packed_bits(bytes: &[u8], header: &Header) -> Result<Vec<u32>, String> {
+    let row_size = header.width.div_ceil(8);
+    if header.data_offset + row_size * header.height > bytes.len() {
+        return Err("Netpbm: truncated image data".to_string());
+    }
+
+    let mut samples = Vec::with_capacity(header.width * header.height);
+    for y in 0 .. header.height {
+        let row = &bytes[header.data_offset + y * row_size ..];
+        for x in 0 .. header.width {
+            samples.push(((row[x / 8] >> (7 - x % 8)) & 1) as u32);
+        }
+    }
+
+    Ok(samples)
+}
+
+// NOTE(erick): Samples take two big-endian bytes when maxval needs them.
+fn read_binary_samples(bytes: &[u8], header: &Header) -> Result<Vec<u32>, String> {
+    let sample_count = header.width * header.height * header.depth;
+    let sample_size = if header.maxval > 255 { 2 } else { 1 };
+    if header.data_offset + sample_count * sample_size > bytes.len() {
+        return Err("Netpbm: truncated image data".to_string());
+    }
+
+    let data = &bytes[header.data_offset ..];
+    let mut samples = Vec::with_capacity(sample_count);
+    for i in 0 .. sample_count {
+        let sample = if sample_size == 2 {
+            (data[i * 2] as u32) << 8 | data[i * 2 + 1] as u32
+        } else {
+            data[i] as u32
+        };
+        if sample > header.maxval {
+            return Err("Netpbm: sample bigger than maxval".to_string());
+        }
+        samples.push(sample);
+    }
+
+    Ok(samples)
+}
+
+#[inline]
+fn luma(pixel: &[u8]) -> u8 {
+    ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114 + 500) / 1000) as u8
+}
+
+// NOTE(erick): Every kind but PAM drops alpha. Bitmaps are thresholded
+// at half gray and graymaps use the Rec. 601 luma of colored pixels.
+// 'plain' picks P1-P3 over P4-P6 and is ignored for PAM.
+// 'sample_bits' is 8 or 16, 16 writes maxval 65535 and is ignored for
+// bitmaps.
+pub fn encode(image: &Image, kind: Kind, plain: bool, sample_bits: u32) -> Vec<u8> {
+    let is_gray = image.pixels.chunks(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let kind = match kind {
+        Kind::Anymap if is_gray => Kind::Graymap,
+        Kind::Anymap            => Kind::Pixmap,
+        _                       => kind,
+    };
+
+    if kind == Kind::Arbitrary {
+        return encode_pam(image, is_gray, sample_bits);
+    }
+
+    let magic = match kind {
+        Kind::Bitmap  => if plain { 1 } else { 4 },
+        Kind::Graymap => if plain { 2 } else { 5 },
+        _             => if plain { 3 } else { 6 },
+    };
+
+    let mut output = format!("P{}\n{} {}\n", magic, image.width, image.height).into_bytes();
+    if kind != Kind::Bitmap {
+        output.extend_from_slice(format!("{}\n", max_value(sample_bits)).as_bytes());
+    }
+
+    let width = image.width as usize;
+    match magic {
+        1 => {
+            for row in image.pixels.chunks(width * 4) {
+                let bits = row.chunks(4)
+                    .map(|pixel| if luma(pixel) < 128 { "1" } else { "0" })
+                    .collect::<Vec<_> >();
+                // NOTE(erick): Plain files should keep lines under 70 characters.
+                for line in bits.chunks(34) {
+                    output.extend_from_slice(line.join(" ").as_bytes());
+                    output.push(b'\n');
+                }
+            }
+        },
+        2 | 3 => {
+            let values_per_line = if sample_bits == 16 { 11 } else { 17 };
+            for row in image.pixels.chunks(width * 4) {
+                let mut values = Vec::new();
+                for pixel in row.chunks(4) {
+                    if magic == 2 {
+                        values.push(scale_sample(luma(pixel), sample_bits).to_string());
+                    } else {
+                        for &sample in pixel[0 .. 3].iter() {
+                            values.push(scale_sample(sample, sample_bits).to_string());
+                        }
+                    }
+                }
+                for line in values.chunks(values_per_line) {
+                    output.extend_from_slice(line.join(" ").as_bytes());
+                    output.push(b'\n');
+                }
+            }
+        },
+        4 => {
+            for row in image.pixels.chunks(width * 4) {
+                let mut packed = vec![0u8; width.div_ceil(8)];
+                for (x, pixel) in row.chunks(4).enumerate() {
+                    if luma(pixel) < 128 {
+                        packed[x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+                output.extend_from_slice(&packed);
+            }
+        },
+        5 => {
+            for pixel in image.pixels.chunks(4) {
+                push_sample(&mut output, luma(pixel), sample_bits);
+            }
+        },
+        _ => {
+            for pixel in image.pixels.chunks(4) {
+                for &sample in pixel[0 .. 3].iter() {
+                    push_sample(&mut output, sample, sample_bits);
+                }
+            }
+        },
+    }
+
+    output
+}
+
+fn max_value(sample_bits: u32) -> u32 {
+    if sample_bits == 16 { 65535 } else { 255 }
+}
+
+// NOTE(erick): 257 maps 255 to 65535 exactly and decodes back to the
+// same 8-bit value.
+fn scale_sample(sample: u8, sample_bits: u32) -> u32 {
+    if sample_bits == 16 { sample as u32 * 257 } else { sample as u32 }
+}
+
+fn push_sample(output: &mut Vec<u8>, sample: u8, sample_bits: u32) {
+    if sample_bits == 16 {
+        output.extend_from_slice(&(scale_sample(sample, sample_bits) as u16).to_be_bytes());
+    } else {
+        output.push(sample);
+    }
+}
+
+fn encode_pam(image: &Image, is_gray: bool, sample_bits: u32) -> Vec<u8> {
+    let has_alpha = image.pixels.chunks(4).any(|pixel| pixel[3] != 255);
+    let (depth, tuple_type) = match (is_gray, has_alpha) {
+        (true, false)  => (1, "GRAYSCALE"),
+        (true, true)   => (2, "GRAYSCALE_ALPHA"),
+        (false, false) => (3, "RGB"),
+        (false, true)  => (4, "RGB_ALPHA"),
+    };
+
+    let mut output = format!("P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+                             image.width, image.height, depth, max_value(sample_bits),
+                             tuple_type).into_bytes();
+    for pixel in image.pixels.chunks(4) {
+        let gray_alpha = [pixel[0], pixel[3]];
+        let samples = match depth {
+            1 => &pixel[0 .. 1],
+            2 => &gray_alpha[..],
+            3 => &pixel[0 .. 3],
+            _ => pixel,
+        };
+        for &sample in samples.iter() {
+            push_sample(&mut output, sample, sample_bits);
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(width: u32, height: u32, gray: bool) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let value = (x * 23 + y * 71) as u8;
+                let pixel = if gray { [value, value, value, 255] }
+                            else { [value, (x * 40) as u8, (y * 90) as u8, 255] };
+                image.set_pixel(x, y, pixel);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn round_trips() {
+        let color = test_image(5, 3, false);
+        let gray = test_image(5, 3, true);
+        let mut bitmap = Image::new(10, 3);
+        for (index, pixel) in bitmap.pixels.chunks_mut(4).enumerate() {
+            let value = if index % 3 == 0 { 0 } else { 255 };
+            pixel.copy_from_slice(&[value, value, value, 255]);
+        }
+
+        for &plain in [true, false].iter() {
+            for &sample_bits in [8, 16].iter() {
+                let cases = [(&bitmap, Kind::Bitmap, if plain { b'1' } else { b'4' }),
+                             (&gray, Kind::Graymap, if plain { b'2' } else { b'5' }),
+                             (&color, Kind::Pixmap, if plain { b'3' } else { b'6' }),
+                             (&gray, Kind::Anymap, if plain { b'2' } else { b'5' })];
+                for &(image, kind, magic) in cases.iter() {
+                    let bytes = encode(image, kind, plain, sample_bits);
+                    assert_eq!(&bytes[0 .. 2], &[b'P', magic]);
+
+                    let decoded = decode(&bytes).unwrap();
+                    assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+                    assert!(decoded.pixels == image.pixels, "P{} at {} bits",
+                            magic as char, sample_bits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pam_round_trip() {
+        let mut image = test_image(4, 4, false);
+        image.set_pixel(1, 2, [10, 20, 30, 40]);
+
+        for &sample_bits in [8, 16].iter() {
+            let bytes = encode(&image, Kind::Arbitrary, true, sample_bits);
+            assert_eq!(&bytes[0 .. 2], b"P7");
+            assert!(decode(&bytes).unwrap().pixels == image.pixels);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let image = test_image(5, 3, false);
+        let cases = [(Kind::Bitmap, false, 8), (Kind::Graymap, false, 16),
+                     (Kind::Pixmap, false, 8), (Kind::Pixmap, true, 8),
+                     (Kind::Arbitrary, false, 16)];
+        for &(kind, plain, sample_bits) in cases.iter() {
+            let bytes = encode(&image, kind, plain, sample_bits);
+            let cut = if plain { bytes.len() - 4 } else { bytes.len() - 1 };
+            assert!(decode(&bytes[.. cut]).is_err());
+        }
+
+        assert!(decode(b"P3\n2 1\n255\n1 2 3 4 5").is_err());
+        assert!(decode(b"P1\n3 1\n0 1").is_err());
+    }
+
+    #[test]
+    fn out_of_range_samples() {
+        assert!(decode(b"P2\n2 1\n255\n0 256\n").is_err());
+        assert!(decode(b"P3\n1 1\n100\n100 101 0\n").is_err());
+        assert!(decode(b"P5\n2 1\n200\n\x00\xc9").is_err());
+        assert!(decode(b"P5\n1 1\n1000\n\x03\xe9").is_err());
+        assert!(decode(b"P2\n2 1\n255\n0 255\n").is_ok());
+        assert!(decode(b"P5\n1 1\n1000\n\x03\xe8").is_ok());
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+
+#[allow(dead_code)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Direction::Horizontal => write!(f, "Hor"),
+            Direction::Vertical   => write!(f, "Ver"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Subsampling {
+    Chroma420,
+    Chroma444,
+}
+
+impl Display for Subsampling {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Subsampling::Chroma420 => write!(f, "4:2:0"),
+            Subsampling::Chroma444 => write!(f, "4:4:4"),
+        }
+    }
+}
+
+// NOTE(erick): The gray presets weight the stored (gamma encoded) values,
+// so they compute luma, not luminance. Desaturate is the HSL lightness,
+// the average of the largest and smallest channel. A matrix has one row
+// per output channel (R, G, B, A) and one column per input channel.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mixer {
+    Rec601,
+    Rec709,
+    Average,
+    Desaturate,
+    Matrix([[f64; 4]; 4]),
+}
+
+pub const IDENTITY_MATRIX : [[f64; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+impl Display for Mixer {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Mixer::Rec601     => write!(f, "Rec.601"),
+            Mixer::Rec709     => write!(f, "Rec.709"),
+            Mixer::Average    => write!(f, "Average"),
+            Mixer::Desaturate => write!(f, "Desaturate"),
+            Mixer::Matrix(_)  => write!(f, "Matrix"),
+        }
+    }
+}
+
+// NOTE(erick): The channels an adjustment changes. All is the three
+// color channels, alpha has to be asked for on its own.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channels {
+    All,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Display for Channels {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Channels::All   => write!(f, "RGB"),
+            Channels::Red   => write!(f, "R"),
+            Channels::Green => write!(f, "G"),
+            Channels::Blue  => write!(f, "B"),
+            Channels::Alpha => write!(f, "A"),
+        }
+    }
+}
+
+// NOTE(erick): Brightness and contrast go from -100 to 100 (percent),
+// zero leaves the image alone. Gamma above one brightens the midtones.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Adjustment {
+    pub brightness: f64,
+    pub contrast: f64,
+    pub gamma: f64,
+}
+
+// NOTE(erick): Input black and white are stretched to output black and
+// white, the midtone is a gamma applied in between. Output black above
+// output white inverts the channel.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Levels {
+    pub input_black: u8,
+    pub input_white: u8,
+    pub midtone: f64,
+    pub output_black: u8,
+    pub output_white: u8,
+}
+
+// NOTE(erick): What convolutions read outside the image: the nearest
+// edge pixel, the opposite side, the image reflected at its edges or
+// transparent black.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EdgeMode {
+    Clamp,
+    Wrap,
+    Mirror,
+    Constant,
+}
+
+impl Display for EdgeMode {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            EdgeMode::Clamp    => write!(f, "Clamp"),
+            EdgeMode::Wrap     => write!(f, "Wrap"),
+            EdgeMode::Mirror   => write!(f, "Mirror"),
+            EdgeMode::Constant => write!(f, "Constant"),
+        }
+    }
+}
+
+// NOTE(erick): The Gaussian radius is the standard deviation, like the
+// radius of most editors. Unsharp mask adds 'amount' times the detail
+// removed by a Gaussian blur. Custom kernels are square with an odd size,
+// row by row, and every sum is divided by 'divisor' before adding 'bias'.
+// A divisor of 0 is the sum of the weights, or 1 when they add up to 0.
+#[derive(Clone, PartialEq)]
+pub enum Kernel {
+    BoxBlur(u32),
+    Gaussian(f64),
+    Sharpen,
+    UnsharpMask(f64, f64),
+    Emboss,
+    Sobel,
+    Prewitt,
+    Laplacian,
+    Custom(Vec<f64>, f64, f64),
+}
+
+impl Display for Kernel {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            &Kernel::BoxBlur(radius)              => write!(f, "Box {}", radius),
+            &Kernel::Gaussian(radius)             => write!(f, "Gaussian {}", radius),
+            &Kernel::Sharpen                      => write!(f, "Sharpen"),
+            &Kernel::UnsharpMask(radius, amount)  => write!(f, "Unsharp {} {}", radius, amount),
+            &Kernel::Emboss                       => write!(f, "Emboss"),
+            &Kernel::Sobel                        => write!(f, "Sobel"),
+            &Kernel::Prewitt                      => write!(f, "Prewitt"),
+            &Kernel::Laplacian                    => write!(f, "Laplacian"),
+            Kernel::Custom(values, _, _)     => {
+                let size = (values.len() as f64).sqrt() as usize;
+                write!(f, "Kernel {}x{}", size, size)
+            },
+        }
+    }
+}
+
+// NOTE(erick): The neighbourhood of median and morphology filters, all
+// the pixels at most 'radius' away in both directions for a square,
+// along the row or the column for a cross and in a circle for a disk.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Shape {
+    Square,
+    Cross,
+    Disk,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Window {
+    pub shape: Shape,
+    pub radius: u32,
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.shape {
+            Shape::Square => write!(f, "Square {}", self.radius),
+            Shape::Cross  => write!(f, "Cross {}", self.radius),
+            Shape::Disk   => write!(f, "Disk {}", self.radius),
+        }
+    }
+}
+
+// NOTE(erick): Erode is a minimum filter and dilate a maximum filter.
+// Open erodes then dilates, close dilates then erodes, and the gradient
+// is the dilated minus the eroded image.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Morphology {
+    Erode,
+    Dilate,
+    Open,
+    Close,
+    Gradient,
+}
+
+impl Display for Morphology {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Morphology::Erode    => write!(f, "Erode"),
+            Morphology::Dilate   => write!(f, "Dilate"),
+            Morphology::Open     => write!(f, "Open"),
+            Morphology::Close    => write!(f, "Close"),
+            Morphology::Gradient => write!(f, "Gradient"),
+        }
+    }
+}
+
+// NOTE(erick): Pixels brighter than the threshold turn white and the
+// rest black. Otsu picks the level that best splits the histogram in
+// two, and keeps the one found by the last run so it can be shown.
+// Adaptive thresholds compare every pixel with the mean (or Gaussian
+// weighted mean) of the SIZE by SIZE window around it minus an offset.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Fixed(u8),
+    Otsu(Option<u8>),
+    Mean(u32, f64),
+    Gaussian(u32, f64),
+}
+
+impl Display for Threshold {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Threshold::Fixed(level)           => write!(f, "{}", level),
+            Threshold::Otsu(_)                => write!(f, "Otsu"),
+            Threshold::Mean(size, offset)     => write!(f, "Mean {} {}", size, offset),
+            Threshold::Gaussian(size, offset) => write!(f, "Gaussian {} {}", size, offset),
+        }
+    }
+}
+
+// NOTE(erick): The colors an image is reduced to. Median cut and octree
+// pick at most that many colors from the image itself, the others are
+// fixed: the 216 web-safe colors, evenly spaced grays or the colors of a
+// GPL, ACT or JASC palette file, read when the operation runs.
+#[derive(Clone, PartialEq)]
+pub enum ColorPalette {
+    MedianCut(u32),
+    Octree(u32),
+    WebSafe,
+    Gray(u32),
+    File(PathBuf),
+}
+
+impl Display for ColorPalette {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            &ColorPalette::MedianCut(count) => write!(f, "Median cut {}", count),
+            &ColorPalette::Octree(count)    => write!(f, "Octree {}", count),
+            &ColorPalette::WebSafe          => write!(f, "Web-safe"),
+            &ColorPalette::Gray(count)      => write!(f, "Gray {}", count),
+            ColorPalette::File(path)   => {
+                let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+                write!(f, "{}", name.unwrap_or_default())
+            },
+        }
+    }
+}
+
+// NOTE(erick): Bayer dithering adds a fixed pattern of the given size
+// before picking the nearest color. The others spread the error of each
+// pixel over the neighbours that haven't been picked yet.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    Bayer(u32),
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+}
+
+impl Display for Dither {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Dither::None           => write!(f, "No dither"),
+            Dither::Bayer(size)    => write!(f, "Bayer {}", size),
+            Dither::FloydSteinberg => write!(f, "Floyd-Steinberg"),
+            Dither::Atkinson       => write!(f, "Atkinson"),
+            Dither::Sierra         => write!(f, "Sierra"),
+        }
+    }
+}
+
+// NOTE(erick): Encoder settings picked when the save is added. JPEG uses
+// the quality and subsampling, BMP the palette bits (None is true
+// color), Netpbm plain or binary (None follows the config) and the
+// sample bits, and the other formats ignore them.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SaveOptions {
+    pub quality: u32,
+    pub subsampling: Subsampling,
+    pub palette_bits: Option<u32>,
+    pub plain: Option<bool>,
+    pub sample_bits: u32,
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions { quality: 90, subsampling: Subsampling::Chroma420, palette_bits: None,
+                      plain: None, sample_bits: 8 }
+    }
+}
+
+pub fn points_string(points: &[(u8, u8)]) -> String {
+    points.iter().map(|&(x, y)| format!("{}:{}", x, y)).collect::<Vec<_> >().join(" ")
+}
+
+#[allow(dead_code)]
+pub enum Operation {
+    Open(usize),
+    Save(usize, usize, SaveOptions),
+    Merge(usize, usize, Direction),
+    Crop(usize, u32, u32, i32, i32),
+    // NOTE(erick): (file, frame) of an animated file.
+    Frame(usize, usize),
+    // NOTE(erick): (op, delay in milliseconds) for every frame, the loop
+    // count (0 loops forever) and the file.
+    Animate(Vec<(usize, u32)>, u16, usize),
+    // NOTE(erick): Every op is one size of the icon written to the file.
+    SaveIcon(Vec<usize>, usize),
+    Mix(usize, Mixer),
+    Adjust(usize, Channels, Adjustment),
+    Levels(usize, Channels, Levels),
+    // NOTE(erick): Control points (input, output) sorted by input.
+    Curves(usize, Channels, Vec<(u8, u8)>),
+    Convolve(usize, Kernel, EdgeMode),
+    Median(usize, Channels, Window),
+    Morphology(usize, Channels, Morphology, Window),
+    Threshold(usize, Threshold),
+    Quantize(usize, ColorPalette, Dither),
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Operation::Open(file)
+                => write!(f, "Open({})", file),
+            Operation::Save(op, file, options)
+                => write!(f, "Save({}, {}, {} {})", op, file, options.quality, options.subsampling),
+            Operation::Crop(op, x0, y0, w, h)
+                => write!(f, "Crop({}, {}, {}, {}, {})", op, x0, y0, w, h),
+            Operation::Merge(op0, op1, dir)
+                => write!(f, "Merge({}, {}, {})", op0, op1, dir),
+            Operation::Frame(file, frame)
+                => write!(f, "Frame({}, {})", file, frame),
+            Operation::Animate(frames, loops, file) => {
+                let ops = frames.iter().map(|&(op, _)| op.to_string()).collect::<Vec<_> >();
+                write!(f, "Animate({}, {}, {})", ops.join(" "), loops, file)
+            },
+            Operation::SaveIcon(ops, file) => {
+                let ops = ops.iter().map(|op| op.to_string()).collect::<Vec<_> >();
+                write!(f, "SaveIcon({}, {})", ops.join(" "), file)
+            },
+            Operation::Mix(op, mixer)
+                => write!(f, "Mix({}, {})", op, mixer),
+            Operation::Adjust(op, channels, adjustment)
+                => write!(f, "Adjust({}, {}, {}, {}, {})", op, channels, adjustment.brightness,
+                          adjustment.contrast, adjustment.gamma),
+            Operation::Levels(op, channels, levels)
+                => write!(f, "Levels({}, {}, {}, {}, {}, {}, {})", op, channels,
+                          levels.input_black, levels.input_white, levels.midtone,
+                          levels.output_black, levels.output_white),
+            Operation::Curves(op, channels, points)
+                => write!(f, "Curves({}, {}, {})", op, channels, points_string(points)),
+            Operation::Convolve(op, kernel, edge)
+                => write!(f, "Convolve({}, {}, {})", op, kernel, edge),
+            Operation::Median(op, channels, window)
+                => write!(f, "Median({}, {}, {})", op, channels, window),
+            Operation::Morphology(op, channels, morphology, window)
+                => write!(f, "{}({}, {}, {})", morphology, op, channels, window),
+            Operation::Threshold(op, threshold)
+                => write!(f, "Threshold({}, {})", op, threshold),
+            Operation::Quantize(op, palette, dither)
+                => write!(f, "Quantize({}, {}, {})", op, palette, dither),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use codec;
+
+// NOTE(erick): Fixed palettes and the palette files of other programs:
+// GIMP's GPL, Photoshop's ACT and Paint Shop Pro's JASC-PAL. The files
+// are recognized by their contents like the images. None of them is
+// used for alpha, ACT's transparent index is ignored.
+
+pub const MAX_COLORS : usize = 256;
+
+const ACT_SIZE : usize = MAX_COLORS * 3;
+const ACT_SIZE_WITH_COUNT : usize = ACT_SIZE + 4;
+
+// NOTE(erick): Every channel goes through 0, 51, 102, 153, 204 and 255.
+pub fn web_safe() -> Vec<[u8; 3]> {
+    let mut colors = Vec::with_capacity(216);
+    for r in 0 .. 6 {
+        for g in 0 .. 6 {
+            for b in 0 .. 6 {
+                colors.push([r * 51, g * 51, b * 51]);
+            }
+        }
+    }
+
+    colors
+}
+
+// NOTE(erick): 'count' grays from black to white, evenly spaced.
+pub fn gray(count: u32) -> Vec<[u8; 3]> {
+    (0 .. count).map(|index| {
+        let value = (index as f64 * 255.0 / (count - 1) as f64).round() as u8;
+        [value, value, value]
+    }).collect()
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<[u8; 3]>, String> {
+    let bytes = codec::read_bytes(path, None)?;
+    let colors = if bytes.starts_with(b"GIMP Palette") {
+        read_gpl(&bytes)?
+    } else if bytes.starts_with(b"JASC-PAL") {
+        read_jasc(&bytes)?
+    } else if bytes.len() == ACT_SIZE || bytes.len() == ACT_SIZE_WITH_COUNT {
+        read_act(&bytes)
+    } else {
+        return Err(format!("{} is not a GPL, ACT or JASC palette", path.display()));
+    };
+
+    if colors.is_empty() {
+        return Err(format!("{} has no colors", path.display()));
+    }
+    if colors.len() > MAX_COLORS {
+        return Err(format!("{} has more than {} colors", path.display(), MAX_COLORS));
+    }
+
+    Ok(colors)
+}
+
+fn parse_color(line: &str) -> Option<[u8; 3]> {
+    let mut values = line.split_whitespace().map(|token| token.parse::<u8>());
+    let r = values.next();
+    let g = values.next();
+    let b = values.next();
+    if r.is_none() || g.is_none() || b.is_none() {
+        return None;
+    }
+
+    match (r.unwrap(), g.unwrap(), b.unwrap()) {
+        (Ok(r), Ok(g), Ok(b)) => Some([r, g, b]),
+        _                     => None,
+    }
+}
+
+// NOTE(erick): After the first line come fields like 'Name: ...' and
+// comments, then one 'R G B name' line per color.
+fn read_gpl(bytes: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate().skip(1) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(color) = parse_color(line) {
+            colors.push(color);
+        } else if !line.contains(':') {
+            return Err(format!("GPL: bad color on line {}", number + 1));
+        }
+    }
+
+    Ok(colors)
+}
+
+// NOTE(erick): The signature, the version (0100), the color count and
+// one 'R G B' line per color.
+fn read_jasc(bytes: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+    let text = String::from_utf8_lossy(bytes);
+    let lines = text.lines().map(|line| line.trim()).collect::<Vec<_> >();
+    let count = lines.get(2).and_then(|line| line.parse::<usize>().ok());
+    if count.is_none() {
+        return Err("JASC: bad color count".to_string());
+    }
+
+    let count = count.unwrap();
+    let end = count.checked_add(3);
+    if end.is_none() || end.unwrap() > lines.len() {
+        return Err("JASC: unexpected end of file".to_string());
+    }
+
+    let mut colors = Vec::with_capacity(count);
+    for (number, line) in lines.iter().enumerate().skip(3).take(count) {
+        let color = parse_color(line);
+        if color.is_none() {
+            return Err(format!("JASC: bad color on line {}", number + 1));
+        }
+        colors.push(color.unwrap());
+    }
+
+    Ok(colors)
+}
+
+// NOTE(erick): 256 RGB triplets, optionally followed by the number of
+// colors used and the transparent index, both big endian.
+fn read_act(bytes: &[u8]) -> Vec<[u8; 3]> {
+    let mut count = MAX_COLORS;
+    if bytes.len() == ACT_SIZE_WITH_COUNT {
+        let used = (bytes[ACT_SIZE] as usize) << 8 | bytes[ACT_SIZE + 1] as usize;
+        if used > 0 && used <= MAX_COLORS {
+            count = used;
+        }
+    }
+
+    bytes[.. count * 3].chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn read_contents(name: &str, contents: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+        let path = env::temp_dir().join(format!("climp-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let result = read_file(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn fixed_palettes() {
+        let colors = web_safe();
+        assert_eq!(colors.len(), 216);
+        assert_eq!((colors[0], colors[215]), ([0, 0, 0], [255, 255, 255]));
+
+        assert_eq!(gray(2), vec![[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(gray(3)[1], [128, 128, 128]);
+        let colors = gray(MAX_COLORS as u32);
+        assert!(colors.iter().enumerate().all(|(value, color)| color[0] as usize == value));
+    }
+
+    #[test]
+    fn palette_files() {
+        let gpl = b"GIMP Palette\nName: Test\nColumns: 2\n# comment\n255 0 0\tRed\n 0 0 255 Blue\n";
+        assert_eq!(read_contents("test.gpl", gpl).unwrap(), vec![[255, 0, 0], [0, 0, 255]]);
+
+        let jasc = b"JASC-PAL\r\n0100\r\n2\r\n1 2 3\r\n4 5 6\r\n";
+        assert_eq!(read_contents("test.pal", jasc).unwrap(), vec![[1, 2, 3], [4, 5, 6]]);
+
+        let mut act = (0 .. ACT_SIZE).map(|i| i as u8).collect::<Vec<_> >();
+        assert_eq!(read_contents("full.act", &act).unwrap().len(), MAX_COLORS);
+        act.extend_from_slice(&[0, 3, 0xff, 0xff]);
+        assert_eq!(read_contents("count.act", &act).unwrap(),
+                   vec![[0, 1, 2], [3, 4, 5], [6, 7, 8]]);
+    }
+
+    #[test]
+    fn palette_limits() {
+        let mut gpl = b"GIMP Palette\n".to_vec();
+        for index in 0 .. MAX_COLORS + 1 {
+            gpl.extend_from_slice(format!("{} {} 0\n", index % 256, index / 256).as_bytes());
+        }
+        assert!(read_contents("big.gpl", &gpl).is_err());
+
+        assert!(read_contents("empty.gpl", b"GIMP Palette\nName: Empty\n").is_err());
+        assert!(read_contents("empty.pal", b"JASC-PAL\n0100\n0\n").is_err());
+        assert!(read_contents("short.pal", b"JASC-PAL\n0100\n3\n1 2 3\n").is_err());
+        assert!(read_contents("bad.gpl", b"GIMP Palette\n1 2 300\n").is_err());
+        assert!(read_contents("short.act", &[0; ACT_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use codec;
+use color;
+use dither;
+use filter;
+use image::Image;
+use rank;
+use threshold;
+use operation::Direction;
+use operation::Operation;
+use operation::Threshold;
+
+// NOTE(erick): Operations only refer to the ones before them, so a single
+// pass in order computes everything. Every result is kept because any of
+// them may be used again later. Levels found by Otsu thresholds are
+// stored back in their operations. Returns how many files were written.
+pub fn run(operations: &mut [Operation], opened_files: &[PathBuf]) -> Result<usize, String> {
+    let mut results: Vec<Rc<Image>> = Vec::with_capacity(operations.len());
+    let mut saved_count = 0;
+
+    for (index, operation) in operations.iter_mut().enumerate() {
+        let mut found_level = None;
+        let shared = |op: usize| -> Result<&Rc<Image>, String> {
+            if op >= index {
+                return Err(format!("operation {} uses a later operation", index + 1));
+            }
+            Ok(&results[op])
+        };
+        let input = |op: usize| -> Result<&Image, String> { shared(op).map(|image| &**image) };
+
+        // NOTE(erick): Saves pass their input on, it's shared rather than
+        // copied.
+        let result = match *operation {
+            Operation::Open(file) => codec::read_file(&opened_files[file]).map(Rc::new),
+            Operation::Save(op, file, ref options) => {
+                codec::write_file(&opened_files[file], input(op)?, options)?;
+                saved_count += 1;
+                Ok(shared(op)?.clone())
+            },
+            Operation::Merge(op0, op1, ref direction)
+                => merge(input(op0)?, input(op1)?, direction).map(Rc::new),
+            Operation::Crop(op, x0, y0, width, height)
+                => crop(input(op)?, x0, y0, width, height).map(Rc::new),
+            Operation::Frame(file, frame)
+                => codec::read_frame(&opened_files[file], frame).map(Rc::new),
+            Operation::Mix(op, ref mixer) => Ok(Rc::new(color::mix(input(op)?, mixer))),
+            Operation::Adjust(op, channels, ref adjustment)
+                => Ok(Rc::new(color::adjust(input(op)?, channels, adjustment))),
+            Operation::Levels(op, channels, ref levels)
+                => Ok(Rc::new(color::levels(input(op)?, channels, levels))),
+            Operation::Curves(op, channels, ref points)
+                => Ok(Rc::new(color::curves(input(op)?, channels, points))),
+            Operation::Convolve(op, ref kernel, edge)
+                => Ok(Rc::new(filter::convolve(input(op)?, kernel, edge))),
+            Operation::Median(op, channels, ref window)
+                => Ok(Rc::new(rank::median(input(op)?, channels, window))),
+            Operation::Morphology(op, channels, morphology, ref window)
+                => Ok(Rc::new(rank::morphology(input(op)?, channels, morphology, window))),
+            Operation::Threshold(op, ref threshold) => {
+                let (image, level) = threshold::threshold(input(op)?, threshold);
+                found_level = level;
+                Ok(Rc::new(image))
+            },
+            Operation::Quantize(op, ref palette, dither)
+                => dither::quantize(input(op)?, palette, dither).map(Rc::new),
+            Operation::Animate(ref frames, loops, file) => {
+                let mut images = Vec::with_capacity(frames.len());
+                for &(op, delay) in frames.iter() {
+                    images.push((input(op)?, delay));
+                }
+                codec::write_animation(&opened_files[file], images.as_slice(), loops)?;
+                saved_count += 1;
+                Ok(shared(frames[0].0)?.clone())
+            },
+            Operation::SaveIcon(ref ops, file) => {
+                let mut images = Vec::with_capacity(ops.len());
+                for &op in ops.iter() {
+                    images.push(input(op)?);
+                }
+                codec::write_icon(&opened_files[file], images.as_slice())?;
+                saved_count += 1;
+                Ok(shared(ops[0])?.clone())
+            },
+        };
+
+        if found_level.is_some() {
+            if let Operation::Threshold(_, ref mut threshold) = *operation {
+                *threshold = Threshold::Otsu(found_level);
+            }
+        }
+
+        if result.is_err() {
+            return Err(format!("operation {}: {}", index + 1, result.err().unwrap()));
+        }
+        results.push(result.unwrap());
+    }
+
+    Ok(saved_count)
+}
+
+// NOTE(erick): Horizontal puts the images side by side, vertical puts the
+// second one below the first. The uncovered area is transparent.
+fn merge(first: &Image, second: &Image, direction: &Direction) -> Result<Image, String> {
+    let (width, height, x1, y1) = match *direction {
+        Direction::Horizontal => (first.width.checked_add(second.width),
+                                   Some(first.height.max(second.height)),
+                                   first.width, 0),
+        Direction::Vertical   => (Some(first.width.max(second.width)),
+                                   first.height.checked_add(second.height),
+                                   0, first.height),
+    };
+    if width.is_none() || height.is_none() {
+        return Err("merged image is too big".to_string());
+    }
+
+    let mut result = Image::new(width.unwrap(), height.unwrap());
+    blit(&mut result, first, 0, 0);
+    blit(&mut result, second, x1, y1);
+    Ok(result)
+}
+
+fn blit(destination: &mut Image, source: &Image, x0: u32, y0: u32) {
+    let row_size = source.width as usize * 4;
+    for y in 0 .. source.height as usize {
+        let from = y * row_size;
+        let to = ((y0 as usize + y) * destination.width as usize + x0 as usize) * 4;
+        destination.pixels[to .. to + row_size]
+            .copy_from_slice(&source.pixels[from .. from + row_size]);
+    }
+}
+
+// NOTE(erick): A negative width or height takes the pixels to the left
+// of or above (x0, y0). The rectangle is clipped to the image.
+fn crop(image: &Image, x0: u32, y0: u32, width: i32, height: i32) -> Result<Image, String> {
+    let span = |start: u32, size: i32, limit: u32| -> (i64, i64) {
+        let start = start as i64;
+        let (low, high) = if size < 0 { (start + size as i64, start) } else { (start, start + size as i64) };
+        (low.max(0), high.min(limit as i64))
+    };
+
+    let (left, right) = span(x0, width, image.width);
+    let (top, bottom) = span(y0, height, image.height);
+    if left >= right || top >= bottom {
+        return Err("crop is outside the image".to_string());
+    }
+
+    let mut result = Image::new((right - left) as u32, (bottom - top) as u32);
+    let row_size = result.width as usize * 4;
+    for y in 0 .. result.height as usize {
+        let from = ((top as usize + y) * image.width as usize + left as usize) * 4;
+        result.pixels[y * row_size .. (y + 1) * row_size]
+            .copy_from_slice(&image.pixels[from .. from + row_size]);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, value: u8) -> Image {
+        Image { width, height, pixels: vec![value; width as usize * height as usize * 4] }
+    }
+
+    #[test]
+    fn merges() {
+        let first = filled(2, 1, 10);
+        let second = filled(1, 2, 20);
+
+        let result = merge(&first, &second, &Direction::Horizontal).unwrap();
+        assert_eq!((result.width, result.height), (3, 2));
+        assert_eq!(result.pixel(1, 0), [10; 4]);
+        assert_eq!(result.pixel(1, 1), [0; 4]);
+        assert_eq!(result.pixel(2, 1), [20; 4]);
+
+        let result = merge(&first, &second, &Direction::Vertical).unwrap();
+        assert_eq!((result.width, result.height), (2, 3));
+        assert_eq!(result.pixel(1, 0), [10; 4]);
+        assert_eq!(result.pixel(0, 2), [20; 4]);
+        assert_eq!(result.pixel(1, 2), [0; 4]);
+    }
+
+    #[test]
+    fn oversized_merge() {
+        let wide = Image { width: u32::MAX, height: 0, pixels: Vec::new() };
+        let tall = Image { width: 0, height: u32::MAX, pixels: Vec::new() };
+        assert!(merge(&wide, &filled(1, 1, 0), &Direction::Horizontal).is_err());
+        assert!(merge(&tall, &filled(1, 1, 0), &Direction::Vertical).is_err());
+    }
+}
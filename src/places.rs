@@ -0,0 +1,306 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use ncurses::*;
+use ncurses::CURSOR_VISIBILITY::CURSOR_INVISIBLE;
+
+use config;
+use keys;
+use keys::Action;
+use keys::KEY_DOWN;
+use keys::KEY_END;
+use keys::KEY_HOME;
+use keys::KEY_NPAGE;
+use keys::KEY_PPAGE;
+use keys::KEY_UP;
+use mouse;
+use theme;
+use theme::HIGHLIGHT_COLOR;
+use theme::QUESTION_COLOR;
+
+const KEY_ADD_BOOKMARK : i32 = 'a' as i32;
+const KEY_REMOVE       : i32 = 'd' as i32;
+
+const RECENT_LIMIT : usize = 50;
+
+// NOTE(erick): Both lists live under $XDG_DATA_HOME/climp, one absolute
+// path per line, most recent first. They are small, so they are read and
+// written as a whole every time.
+fn data_file_path(name: &str) -> Option<PathBuf> {
+    config::xdg_directory("XDG_DATA_HOME", ".local/share")
+        .map(|mut path| { path.push(name); path })
+}
+
+fn read_list(name: &str) -> Vec<PathBuf> {
+    let path = data_file_path(name);
+    if path.is_none() {
+        return Vec::new();
+    }
+
+    let mut contents = String::new();
+    let file = File::open(path.unwrap());
+    if file.is_err() || file.unwrap().read_to_string(&mut contents).is_err() {
+        return Vec::new();
+    }
+
+    contents.lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn write_list(name: &str, paths: &[PathBuf]) -> Result<(), String> {
+    let path = data_file_path(name);
+    if path.is_none() {
+        return Err("HOME is not set".to_string());
+    }
+
+    let path = path.unwrap();
+    if fs::create_dir_all(path.parent().unwrap()).is_err() {
+        return Err(format!("cannot create {}", path.parent().unwrap().display()));
+    }
+
+    let mut contents = String::new();
+    for entry in paths {
+        // NOTE(erick): A newline would split the entry in two when read back.
+        let entry = entry.to_string_lossy();
+        if entry.contains('\n') { continue; }
+
+        contents.push_str(entry.as_ref());
+        contents.push('\n');
+    }
+
+    let file = File::create(&path);
+    if file.is_err() || file.unwrap().write_all(contents.as_bytes()).is_err() {
+        return Err(format!("cannot write {}", path.display()));
+    }
+
+    Ok(())
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    let mut result = std::env::current_dir().unwrap_or_default();
+    result.push(path);
+    result
+}
+
+pub fn recent_files() -> Vec<PathBuf> {
+    read_list("recent")
+}
+
+pub fn bookmarks() -> Vec<PathBuf> {
+    read_list("bookmarks")
+}
+
+// NOTE(erick): Failing to remember a file isn't worth interrupting the
+// user for, so callers are free to ignore the error.
+pub fn add_recent(path: &Path) -> Result<(), String> {
+    let path = absolute(path);
+    let mut recent = recent_files();
+    recent.retain(|entry| *entry != path);
+    recent.insert(0, path);
+    recent.truncate(RECENT_LIMIT);
+
+    write_list("recent", &recent)
+}
+
+pub fn add_bookmark(directory: &Path) -> Result<(), String> {
+    let directory = absolute(directory);
+    let mut bookmarks = bookmarks();
+    if bookmarks.contains(&directory) {
+        return Ok(());
+    }
+
+    bookmarks.push(directory);
+    write_list("bookmarks", &bookmarks)
+}
+
+pub enum PlacesResult {
+    Selected(String),
+    Cancelled,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Bookmark,
+    Recent,
+}
+
+struct Place {
+    kind: Kind,
+    path: PathBuf,
+}
+
+fn read_places() -> Vec<Place> {
+    let mut places = Vec::new();
+    for path in bookmarks() {
+        places.push(Place { kind: Kind::Bookmark, path });
+    }
+    for path in recent_files() {
+        places.push(Place { kind: Kind::Recent, path });
+    }
+
+    places
+}
+
+fn remove_place(place: &Place) -> Result<(), String> {
+    let (name, mut paths) = match place.kind {
+        Kind::Bookmark => ("bookmarks", bookmarks()),
+        Kind::Recent   => ("recent", recent_files()),
+    };
+
+    paths.retain(|path| *path != place.path);
+    write_list(name, &paths)
+}
+
+// NOTE(erick): Lists the bookmarks followed by the recent files. Picking
+// one returns it as prompt text, directories with a trailing slash so the
+// user can keep typing or completing. 'current_directory' is what gets
+// bookmarked.
+pub fn choose(screen_height: i32, screen_width: i32,
+              current_directory: &Path) -> PlacesResult {
+    let window = newwin(screen_height, screen_width, 0, 0);
+    defer! {{ delwin(window); }}
+
+    let old_cursor = curs_set(CURSOR_INVISIBLE);
+    defer! {
+        if let Some(cursor) = old_cursor {
+            curs_set(cursor);
+        }
+    }
+
+    let mut places = read_places();
+    let mut selected = 0;
+    let mut first_visible = 0;
+    let mut message: Option<String> = None;
+
+    // NOTE(erick): The first line is the title, the last one the keys.
+    let list_rows = (screen_height - 2).max(1) as usize;
+
+    loop {
+        if !places.is_empty() && selected >= places.len() {
+            selected = places.len() - 1;
+        }
+        if selected < first_visible {
+            first_visible = selected;
+        }
+        if selected >= first_visible + list_rows {
+            first_visible = selected + 1 - list_rows;
+        }
+
+        theme::clear_window(window);
+        wmove(window, 0, 0);
+        wattron(window, theme::attribute(QUESTION_COLOR));
+        wprintw(window, "Bookmarks and recent files");
+        wattroff(window, theme::attribute(QUESTION_COLOR));
+
+        if places.is_empty() {
+            wmove(window, 1, 0);
+            wprintw(window, "  Nothing here yet.");
+        }
+
+        for (index, place) in places.iter().enumerate().skip(first_visible).take(list_rows) {
+            let kind = match place.kind {
+                Kind::Bookmark => "bookmark",
+                Kind::Recent   => "recent  ",
+            };
+            let line = format!("  {}  {}", kind, place.path.display());
+            let line = line.chars().take(screen_width as usize).collect::<String>();
+
+            wmove(window, (index - first_visible) as i32 + 1, 0);
+            if index == selected {
+                wattron(window, theme::attribute(HIGHLIGHT_COLOR));
+                wprintw(window, line.as_str());
+                wattroff(window, theme::attribute(HIGHLIGHT_COLOR));
+            } else {
+                wprintw(window, line.as_str());
+            }
+        }
+
+        wmove(window, screen_height - 1, 0);
+        if message.is_some() {
+            wprintw(window, message.take().unwrap().as_str());
+        } else {
+            let bindings = keys::bindings();
+            let key_name = |action: Action| {
+                bindings.keys(action).first().map(|key| keys::key_name(*key)).unwrap_or_default()
+            };
+            wprintw(window, format!("{}: pick  {}: cancel  a: bookmark {}  d: remove",
+                                    key_name(Action::Confirm), key_name(Action::Cancel),
+                                    current_directory.display()).as_str());
+        }
+        wrefresh(window);
+
+        let ch = getch();
+        match ch {
+            _ if keys::is(ch, Action::Confirm) => {
+                if places.is_empty() { continue; }
+                return PlacesResult::Selected(prompt_text(&places[selected].path));
+            },
+            _ if keys::is(ch, Action::Cancel) || keys::is(ch, Action::Places) => {
+                return PlacesResult::Cancelled;
+            },
+            KEY_UP    => { selected = selected.saturating_sub(1); },
+            KEY_DOWN  => { selected += 1; },
+            KEY_PPAGE => { selected = selected.saturating_sub(list_rows); },
+            KEY_NPAGE => { selected += list_rows; },
+            KEY_HOME  => { selected = 0; },
+            KEY_END   => { selected = places.len(); },
+            KEY_ADD_BOOKMARK => {
+                let result = add_bookmark(current_directory);
+                if result.is_err() {
+                    message = Some(result.err().unwrap());
+                }
+                places = read_places();
+            },
+            KEY_REMOVE => {
+                if places.is_empty() { continue; }
+
+                let result = remove_place(&places[selected]);
+                if result.is_err() {
+                    message = Some(result.err().unwrap());
+                }
+                places = read_places();
+            },
+            KEY_MOUSE => {
+                let event = mouse::get_mouse_event();
+                if event.is_none() { continue; }
+
+                let event = event.unwrap();
+                let moved = selected as isize + mouse::wheel_direction(&event);
+                selected = moved.max(0) as usize;
+
+                let row = event.y - getbegy(window) - 1;
+                let is_click = event.bstate &
+                    (BUTTON1_CLICKED | BUTTON1_DOUBLE_CLICKED) as mmask_t != 0;
+                if !is_click || row < 0 || row as usize >= list_rows { continue; }
+
+                let clicked = first_visible + row as usize;
+                if clicked >= places.len() { continue; }
+                selected = clicked;
+
+                if event.bstate & BUTTON1_DOUBLE_CLICKED as mmask_t != 0 {
+                    return PlacesResult::Selected(prompt_text(&places[clicked].path));
+                }
+            },
+            _ => { },
+        }
+    }
+}
+
+fn prompt_text(path: &Path) -> String {
+    let mut text = path.to_string_lossy().into_owned();
+    if path.is_dir() && !text.ends_with('/') {
+        text.push('/');
+    }
+
+    text
+}
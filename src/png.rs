@@ -0,0 +1,573 @@
+use image::Image;
+use zlib;
+
+// NOTE(erick): Reference:
+// https://www.w3.org/TR/png/
+
+const SIGNATURE : [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+const COLOR_GRAY       : u8 = 0;
+const COLOR_RGB        : u8 = 2;
+const COLOR_PALETTE    : u8 = 3;
+const COLOR_GRAY_ALPHA : u8 = 4;
+const COLOR_RGBA       : u8 = 6;
+
+// NOTE(erick): (x0, y0, dx, dy) of each Adam7 pass.
+const ADAM7 : [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4),
+    (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE : [u32; 256] = make_crc_table();
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
+
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && bytes[0 .. 8] == SIGNATURE
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    if offset + 4 > bytes.len() {
+        return Err("PNG: unexpected end of file".to_string());
+    }
+    Ok((bytes[offset] as u32) << 24 | (bytes[offset + 1] as u32) << 16 |
+       (bytes[offset + 2] as u32) << 8 | bytes[offset + 3] as u32)
+}
+
+// NOTE(erick): IHDR is always the first chunk, so this works on a
+// truncated file.
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if !is_png(bytes) || bytes.len() < 24 || &bytes[12 .. 16] != b"IHDR" {
+        return None;
+    }
+
+    let width = read_u32(bytes, 16).ok()?;
+    let height = read_u32(bytes, 20).ok()?;
+    Some((width, height))
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_GRAY       => 1,
+            COLOR_RGB        => 3,
+            COLOR_PALETTE    => 1,
+            COLOR_GRAY_ALPHA => 2,
+            _                => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // NOTE(erick): The filter's idea of a pixel, at least one byte.
+    fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    fn row_size(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // NOTE(erick): Every row of every pass with its filter byte. None when
+    // it doesn't fit in memory anyway.
+    fn data_size(&self) -> Option<usize> {
+        if !self.interlaced {
+            return self.pass_size(self.width, self.height);
+        }
+
+        let mut total = 0usize;
+        for &(x0, y0, dx, dy) in ADAM7.iter() {
+            if x0 >= self.width || y0 >= self.height { continue; }
+
+            let pass_width = (self.width - x0).div_ceil(dx);
+            let pass_height = (self.height - y0).div_ceil(dy);
+            total = total.checked_add(self.pass_size(pass_width, pass_height)?)?;
+        }
+        Some(total)
+    }
+
+    fn pass_size(&self, width: usize, height: usize) -> Option<usize> {
+        let row_size = width.checked_mul(self.bits_per_pixel())?.div_ceil(8);
+        row_size.checked_add(1)?.checked_mul(height)
+    }
+}
+
+fn read_header(data: &[u8]) -> Result<Header, String> {
+    if data.len() != 13 {
+        return Err("PNG: bad IHDR size".to_string());
+    }
+
+    let width = read_u32(data, 0)? as usize;
+    let height = read_u32(data, 4)? as usize;
+    let bit_depth = data[8];
+    let color_type = data[9];
+    if width == 0 || height == 0 || width > 0x7fff_ffff || height > 0x7fff_ffff {
+        return Err("PNG: invalid dimensions".to_string());
+    }
+
+    let valid_depth = match color_type {
+        COLOR_GRAY       => [1, 2, 4, 8, 16].contains(&bit_depth),
+        COLOR_PALETTE    => [1, 2, 4, 8].contains(&bit_depth),
+        COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => bit_depth == 8 || bit_depth == 16,
+        _ => return Err(format!("PNG: invalid color type {}", color_type)),
+    };
+    if !valid_depth {
+        return Err(format!("PNG: invalid bit depth {} for color type {}", bit_depth, color_type));
+    }
+
+    if data[10] != 0 || data[11] != 0 {
+        return Err("PNG: unknown compression or filter method".to_string());
+    }
+    if data[12] > 1 {
+        return Err("PNG: unknown interlace method".to_string());
+    }
+
+    Ok(Header { width, height, bit_depth, color_type, interlaced: data[12] == 1 })
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    if !is_png(bytes) {
+        return Err("PNG: bad signature".to_string());
+    }
+
+    let mut header: Option<Header> = None;
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut transparency: Option<Vec<u8>> = None;
+    let mut compressed = Vec::new();
+    let mut seen_end = false;
+
+    let mut offset = 8;
+    while offset < bytes.len() {
+        let length = read_u32(bytes, offset)? as usize;
+        if offset + 12 + length > bytes.len() {
+            return Err("PNG: truncated chunk".to_string());
+        }
+
+        let chunk_type = &bytes[offset + 4 .. offset + 8];
+        let data = &bytes[offset + 8 .. offset + 8 + length];
+        let crc = read_u32(bytes, offset + 8 + length)?;
+        if crc32(&bytes[offset + 4 .. offset + 8 + length]) != crc {
+            return Err(format!("PNG: bad CRC in {} chunk", String::from_utf8_lossy(chunk_type)));
+        }
+        offset += 12 + length;
+
+        if header.is_none() && chunk_type != b"IHDR" {
+            return Err("PNG: missing IHDR".to_string());
+        }
+
+        match chunk_type {
+            b"IHDR" => { header = Some(read_header(data)?); },
+            b"PLTE" => {
+                if !length.is_multiple_of(3) || length / 3 > 256 {
+                    return Err("PNG: bad palette size".to_string());
+                }
+                palette = data.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect();
+            },
+            b"tRNS" => { transparency = Some(data.to_vec()); },
+            b"IDAT" => { compressed.extend_from_slice(data); },
+            b"IEND" => { seen_end = true; break; },
+            _ => {
+                // NOTE(erick): Uppercase first letter means we can't
+                // decode the image correctly without understanding it.
+                if chunk_type[0] & 0x20 == 0 {
+                    return Err(format!("PNG: unknown critical chunk {}",
+                                       String::from_utf8_lossy(chunk_type)));
+                }
+            },
+        }
+    }
+
+    if header.is_none() {
+        return Err("PNG: missing IHDR".to_string());
+    }
+    if !seen_end {
+        return Err("PNG: missing IEND".to_string());
+    }
+
+    let header = header.unwrap();
+    if header.color_type == COLOR_PALETTE {
+        if palette.is_empty() {
+            return Err("PNG: missing palette".to_string());
+        }
+        if let Some(ref transparency) = transparency {
+            for (entry, alpha) in palette.iter_mut().zip(transparency.iter()) {
+                entry[3] = *alpha;
+            }
+        }
+    }
+
+    let expected = header.data_size();
+    if expected.is_none() {
+        return Err("PNG: image too big".to_string());
+    }
+
+    let expected = expected.unwrap();
+    let data = zlib::decompress(&compressed, expected)
+        .map_err(|message| format!("PNG: {}", message))?;
+    if data.len() != expected {
+        return Err("PNG: not enough image data".to_string());
+    }
+
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    let mut consumed = 0;
+    if header.interlaced {
+        for &(x0, y0, dx, dy) in ADAM7.iter() {
+            if x0 >= header.width || y0 >= header.height { continue; }
+
+            let pass_width = (header.width - x0).div_ceil(dx);
+            let pass_height = (header.height - y0).div_ceil(dy);
+            let rows = unfilter(&header, &data[consumed ..], pass_width, pass_height)?;
+            consumed += pass_height * (header.row_size(pass_width) + 1);
+
+            for (row_index, row) in rows.chunks(header.row_size(pass_width)).enumerate() {
+                for x in 0 .. pass_width {
+                    let pixel = read_pixel(&header, row, x, &palette, transparency.as_ref());
+                    image.set_pixel((x0 + x * dx) as u32, (y0 + row_index * dy) as u32, pixel);
+                }
+            }
+        }
+    } else {
+        let rows = unfilter(&header, &data, header.width, header.height)?;
+        for (y, row) in rows.chunks(header.row_size(header.width)).enumerate() {
+            for x in 0 .. header.width {
+                let pixel = read_pixel(&header, row, x, &palette, transparency.as_ref());
+                image.set_pixel(x as u32, y as u32, pixel);
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[inline]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// NOTE(erick): Returns the raw rows without their filter bytes.
+fn unfilter(header: &Header, data: &[u8], width: usize, height: usize) -> Result<Vec<u8>, String> {
+    let row_size = header.row_size(width);
+    let stride = header.filter_stride();
+    if data.len() < height * (row_size + 1) {
+        return Err("PNG: not enough image data".to_string());
+    }
+
+    let mut rows = vec![0u8; row_size * height];
+    for y in 0 .. height {
+        let filter = data[y * (row_size + 1)];
+        let source = &data[y * (row_size + 1) + 1 .. (y + 1) * (row_size + 1)];
+        let (previous_rows, current_rows) = rows.split_at_mut(y * row_size);
+        let previous = if y > 0 { &previous_rows[(y - 1) * row_size ..] } else { &[][..] };
+        let current = &mut current_rows[.. row_size];
+
+        for i in 0 .. row_size {
+            let a = if i >= stride { current[i - stride] } else { 0 };
+            let b = if y > 0 { previous[i] } else { 0 };
+            let c = if y > 0 && i >= stride { previous[i - stride] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("PNG: invalid filter type {}", filter)),
+            };
+            current[i] = source[i].wrapping_add(predicted);
+        }
+    }
+
+    Ok(rows)
+}
+
+// NOTE(erick): Sample 'index' of a row, at the image's bit depth.
+#[inline]
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => (row[index * 2] as u16) << 8 | row[index * 2 + 1] as u16,
+        8  => row[index] as u16,
+        _  => {
+            let bit_offset = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit_offset % 8;
+            ((row[bit_offset / 8] >> shift) & ((1u16 << bit_depth) - 1) as u8) as u16
+        },
+    }
+}
+
+#[inline]
+fn scale_to_u8(value: u16, bit_depth: u8) -> u8 {
+    match bit_depth {
+        16 => (value >> 8) as u8,
+        8  => value as u8,
+        _  => (value as u32 * 255 / ((1u32 << bit_depth) - 1)) as u8,
+    }
+}
+
+fn read_pixel(header: &Header, row: &[u8], x: usize,
+              palette: &[[u8; 4]], transparency: Option<&Vec<u8>>) -> [u8; 4] {
+    let depth = header.bit_depth;
+    let channels = header.channels();
+    let base = x * channels;
+
+    // NOTE(erick): tRNS holds 16-bit values compared at the file's depth.
+    let transparent_key = |index: usize| -> Option<u16> {
+        let transparency = transparency?;
+        if transparency.len() < index * 2 + 2 { return None; }
+        Some((transparency[index * 2] as u16) << 8 | transparency[index * 2 + 1] as u16)
+    };
+
+    match header.color_type {
+        COLOR_GRAY => {
+            let gray = sample(row, base, depth);
+            let value = scale_to_u8(gray, depth);
+            let alpha = if transparent_key(0) == Some(gray) { 0 } else { 255 };
+            [value, value, value, alpha]
+        },
+        COLOR_RGB => {
+            let r = sample(row, base, depth);
+            let g = sample(row, base + 1, depth);
+            let b = sample(row, base + 2, depth);
+            let is_transparent = transparent_key(0) == Some(r) &&
+                transparent_key(1) == Some(g) && transparent_key(2) == Some(b);
+            [scale_to_u8(r, depth), scale_to_u8(g, depth), scale_to_u8(b, depth),
+             if is_transparent { 0 } else { 255 }]
+        },
+        COLOR_PALETTE => {
+            let index = sample(row, base, depth) as usize;
+            if index < palette.len() { palette[index] } else { [0, 0, 0, 255] }
+        },
+        COLOR_GRAY_ALPHA => {
+            let value = scale_to_u8(sample(row, base, depth), depth);
+            [value, value, value, scale_to_u8(sample(row, base + 1, depth), depth)]
+        },
+        _ => {
+            [scale_to_u8(sample(row, base, depth), depth),
+             scale_to_u8(sample(row, base + 1, depth), depth),
+             scale_to_u8(sample(row, base + 2, depth), depth),
+             scale_to_u8(sample(row, base + 3, depth), depth)]
+        },
+    }
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(data);
+    let crc = crc32(&output[start ..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+// NOTE(erick): Always 8 bits per sample. The color type is the smallest
+// one that holds the image without loss: gray if every pixel is gray,
+// and alpha only if some pixel isn't opaque.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let pixels = image.pixels.chunks(4);
+    let is_gray = pixels.clone().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let has_alpha = pixels.clone().any(|pixel| pixel[3] != 255);
+    let (color_type, channels) = match (is_gray, has_alpha) {
+        (true, false)  => (COLOR_GRAY, 1),
+        (true, true)   => (COLOR_GRAY_ALPHA, 2),
+        (false, false) => (COLOR_RGB, 3),
+        (false, true)  => (COLOR_RGBA, 4),
+    };
+
+    let width = image.width as usize;
+    let row_size = width * channels;
+    let mut raw = Vec::with_capacity(row_size * image.height as usize);
+    for pixel in pixels {
+        match channels {
+            1 => raw.push(pixel[0]),
+            2 => raw.extend_from_slice(&[pixel[0], pixel[3]]),
+            3 => raw.extend_from_slice(&pixel[0 .. 3]),
+            _ => raw.extend_from_slice(pixel),
+        }
+    }
+
+    let mut filtered = Vec::with_capacity((row_size + 1) * image.height as usize);
+    for y in 0 .. image.height as usize {
+        let current = &raw[y * row_size .. (y + 1) * row_size];
+        let previous = if y > 0 { &raw[(y - 1) * row_size .. y * row_size] } else { &[][..] };
+        filter_row(current, previous, channels, &mut filtered);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let mut output = SIGNATURE.to_vec();
+    write_chunk(&mut output, b"IHDR", &header);
+    write_chunk(&mut output, b"IDAT", &zlib::compress(&filtered));
+    write_chunk(&mut output, b"IEND", &[]);
+    output
+}
+
+// NOTE(erick): Tries every filter and keeps the one with the smallest sum
+// of absolute values, the usual heuristic from the PNG spec.
+fn filter_row(current: &[u8], previous: &[u8], stride: usize, output: &mut Vec<u8>) {
+    let mut best_filter = 0;
+    let mut best_row = Vec::new();
+    let mut best_score = u64::MAX;
+
+    for filter in 0 .. 5u8 {
+        let mut row = Vec::with_capacity(current.len());
+        for i in 0 .. current.len() {
+            let a = if i >= stride { current[i - stride] } else { 0 };
+            let b = if !previous.is_empty() { previous[i] } else { 0 };
+            let c = if !previous.is_empty() && i >= stride { previous[i - stride] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                _ => paeth(a, b, c),
+            };
+            row.push(current[i].wrapping_sub(predicted));
+        }
+
+        let score = row.iter().map(|byte| (*byte as i8).unsigned_abs() as u64).sum();
+        if score < best_score {
+            best_score = score;
+            best_filter = filter;
+            best_row = row;
+        }
+    }
+
+    output.push(best_filter);
+    output.extend_from_slice(&best_row);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_image(width: u32, height: u32, gray: bool, alpha: bool) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let r = (x * 255 / width) as u8;
+                let g = if gray { r } else { (y * 255 / height) as u8 };
+                let b = if gray { r } else { (x ^ y) as u8 };
+                let a = if alpha { ((x + y) * 17) as u8 } else { 255 };
+                image.set_pixel(x, y, [r, g, b, a]);
+            }
+        }
+        image
+    }
+
+    // NOTE(erick): An 8-bit gray file with unfiltered rows, Adam7 passes
+    // one after the other when interlaced.
+    fn gray_file(image: &Image, interlaced: bool, extra: usize) -> Vec<u8> {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let passes = if interlaced { ADAM7.to_vec() } else { vec![(0, 0, 1, 1)] };
+        let mut raw = Vec::new();
+        for &(x0, y0, dx, dy) in passes.iter() {
+            if x0 >= width || y0 >= height { continue; }
+            for y in (y0 .. height).step_by(dy) {
+                raw.push(0);
+                for x in (x0 .. width).step_by(dx) {
+                    raw.push(image.pixel(x as u32, y as u32)[0]);
+                }
+            }
+        }
+        raw.extend(std::iter::repeat_n(0, extra));
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&image.width.to_be_bytes());
+        header.extend_from_slice(&image.height.to_be_bytes());
+        header.extend_from_slice(&[8, COLOR_GRAY, 0, 0, interlaced as u8]);
+
+        let mut output = SIGNATURE.to_vec();
+        write_chunk(&mut output, b"IHDR", &header);
+        write_chunk(&mut output, b"IDAT", &zlib::compress(&raw));
+        write_chunk(&mut output, b"IEND", &[]);
+        output
+    }
+
+    #[test]
+    fn round_trip() {
+        for &(gray, alpha) in [(true, false), (true, true), (false, false), (false, true)].iter() {
+            let image = sample_image(37, 21, gray, alpha);
+            let decoded = decode(&encode(&image)).unwrap();
+            assert_eq!((decoded.width, decoded.height), (37, 21));
+            assert_eq!(decoded.pixels, image.pixels);
+        }
+    }
+
+    #[test]
+    fn interlaced() {
+        let image = sample_image(13, 11, true, false);
+        let decoded = decode(&gray_file(&image, true, 0)).unwrap();
+        assert_eq!(decoded.pixels, image.pixels);
+        assert_eq!(decode(&gray_file(&image, false, 0)).unwrap().pixels, image.pixels);
+    }
+
+    #[test]
+    fn wrong_data_size() {
+        let image = sample_image(13, 11, true, false);
+        assert!(decode(&gray_file(&image, false, 1)).is_err());
+        assert!(decode(&gray_file(&image, true, 1)).is_err());
+
+        let short = Image::new(13, 10);
+        let mut file = gray_file(&short, false, 0);
+        file[20 .. 24].copy_from_slice(&11u32.to_be_bytes());
+        let crc = crc32(&file[12 .. 29]);
+        file[29 .. 33].copy_from_slice(&crc.to_be_bytes());
+        assert!(decode(&file).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let file = encode(&sample_image(20, 10, false, true));
+        for length in 0 .. file.len() {
+            assert!(decode(&file[.. length]).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = encode(&sample_image(20, 10, false, true));
+        for index in 0 .. file.len() {
+            let mut corrupt = file.clone();
+            corrupt[index] ^= 0x10;
+            assert!(decode(&corrupt).is_err());
+        }
+    }
+}
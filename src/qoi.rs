@@ -0,0 +1,248 @@
+use image::Image;
+
+// NOTE(erick): Reference:
+// https://qoiformat.org/qoi-specification.pdf
+
+const HEADER_SIZE : usize = 14;
+const END_MARKER : [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX : u8 = 0x00;
+const OP_DIFF  : u8 = 0x40;
+const OP_LUMA  : u8 = 0x80;
+const OP_RUN   : u8 = 0xc0;
+const OP_RGB   : u8 = 0xfe;
+const OP_RGBA  : u8 = 0xff;
+const OP_MASK  : u8 = 0xc0;
+
+// NOTE(erick): The spec caps images at 400 million pixels.
+const MAX_PIXELS : usize = 400_000_000;
+
+pub fn is_qoi(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && &bytes[0 .. 4] == b"qoif"
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (bytes[offset] as u32) << 24 | (bytes[offset + 1] as u32) << 16 |
+    (bytes[offset + 2] as u32) << 8 | bytes[offset + 3] as u32
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if !is_qoi(bytes) || bytes.len() < HEADER_SIZE {
+        return None;
+    }
+
+    Some((read_u32(bytes, 4), read_u32(bytes, 8)))
+}
+
+#[inline]
+fn index_position(pixel: [u8; 4]) -> usize {
+    (pixel[0] as usize * 3 + pixel[1] as usize * 5 +
+     pixel[2] as usize * 7 + pixel[3] as usize * 11) % 64
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    if !is_qoi(bytes) {
+        return Err("QOI: bad signature".to_string());
+    }
+    if bytes.len() < HEADER_SIZE + END_MARKER.len() {
+        return Err("QOI: file too short".to_string());
+    }
+
+    let width = read_u32(bytes, 4);
+    let height = read_u32(bytes, 8);
+    let channels = bytes[12];
+    if width == 0 || height == 0 || width as usize * height as usize > MAX_PIXELS {
+        return Err("QOI: invalid dimensions".to_string());
+    }
+    if channels != 3 && channels != 4 {
+        return Err(format!("QOI: invalid channel count {}", channels));
+    }
+
+    let mut image = Image::new(width, height);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0u8, 0, 0, 255];
+    let mut run = 0;
+
+    let data_end = bytes.len() - END_MARKER.len();
+    let mut position = HEADER_SIZE;
+    for output in image.pixels.chunks_mut(4) {
+        if run > 0 {
+            run -= 1;
+        } else {
+            if position >= data_end {
+                return Err("QOI: truncated image data".to_string());
+            }
+
+            let op = bytes[position];
+            position += 1;
+
+            if op == OP_RGB || op == OP_RGBA {
+                let size = if op == OP_RGB { 3 } else { 4 };
+                if position + size > data_end {
+                    return Err("QOI: truncated image data".to_string());
+                }
+                pixel[0 .. size].copy_from_slice(&bytes[position .. position + size]);
+                position += size;
+            } else {
+                match op & OP_MASK {
+                    OP_INDEX => { pixel = index[op as usize]; },
+                    OP_DIFF  => {
+                        pixel[0] = pixel[0].wrapping_add(((op >> 4) & 3).wrapping_sub(2));
+                        pixel[1] = pixel[1].wrapping_add(((op >> 2) & 3).wrapping_sub(2));
+                        pixel[2] = pixel[2].wrapping_add((op & 3).wrapping_sub(2));
+                    },
+                    OP_LUMA  => {
+                        if position >= data_end {
+                            return Err("QOI: truncated image data".to_string());
+                        }
+                        let second = bytes[position];
+                        position += 1;
+
+                        let green = (op & 0x3f).wrapping_sub(32);
+                        pixel[0] = pixel[0].wrapping_add(green.wrapping_sub(8).wrapping_add(second >> 4));
+                        pixel[1] = pixel[1].wrapping_add(green);
+                        pixel[2] = pixel[2].wrapping_add(green.wrapping_sub(8).wrapping_add(second & 0x0f));
+                    },
+                    _        => { run = (op & 0x3f) as usize; },
+                }
+            }
+
+            index[index_position(pixel)] = pixel;
+        }
+
+        output.copy_from_slice(&pixel);
+    }
+
+    Ok(image)
+}
+
+// NOTE(erick): Three channels unless some pixel isn't opaque. The
+// colorspace byte says sRGB with linear alpha, which is what we mean.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let has_alpha = image.pixels.chunks(4).any(|pixel| pixel[3] != 255);
+
+    let mut output = Vec::with_capacity(HEADER_SIZE + image.pixels.len() / 2);
+    output.extend_from_slice(b"qoif");
+    output.extend_from_slice(&image.width.to_be_bytes());
+    output.extend_from_slice(&image.height.to_be_bytes());
+    output.push(if has_alpha { 4 } else { 3 });
+    output.push(0);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0u8, 0, 0, 255];
+    let mut run = 0;
+
+    let pixel_count = image.pixels.len() / 4;
+    for (i, chunk) in image.pixels.chunks(4).enumerate() {
+        let pixel = [chunk[0], chunk[1], chunk[2], chunk[3]];
+
+        if pixel == previous {
+            run += 1;
+            if run == 62 || i + 1 == pixel_count {
+                output.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            output.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let position = index_position(pixel);
+        if index[position] == pixel {
+            output.push(OP_INDEX | position as u8);
+        } else {
+            index[position] = pixel;
+
+            if pixel[3] == previous[3] {
+                let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+                let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+                let db = pixel[2].wrapping_sub(previous[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+
+                if (-2 ..= 1).contains(&dr) && (-2 ..= 1).contains(&dg) && (-2 ..= 1).contains(&db) {
+                    output.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                } else if (-32 ..= 31).contains(&dg) &&
+                    (-8 ..= 7).contains(&dr_dg) && (-8 ..= 7).contains(&db_dg) {
+                    output.push(OP_LUMA | (dg + 32) as u8);
+                    output.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    output.extend_from_slice(&[OP_RGB, pixel[0], pixel[1], pixel[2]]);
+                }
+            } else {
+                output.extend_from_slice(&[OP_RGBA, pixel[0], pixel[1], pixel[2], pixel[3]]);
+            }
+        }
+
+        previous = pixel;
+    }
+
+    output.extend_from_slice(&END_MARKER);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(erick): Gradients for the diff and luma ops, a flat block for
+    // runs longer than 62, repeated colors for the index and some
+    // transparency for RGBA.
+    fn test_image() -> Image {
+        let mut image = Image::new(40, 8);
+        for y in 0 .. 8 {
+            for x in 0 .. 40 {
+                let pixel = match y {
+                    0        => [x as u8, x as u8, x as u8, 255],
+                    1        => [(x * 9) as u8, (x * 7) as u8, (x * 5) as u8, 255],
+                    2 ..= 4  => [200, 100, 50, 255],
+                    5        => if x % 2 == 0 { [1, 2, 3, 255] } else { [250, 10, 90, 255] },
+                    6        => [(x * 61) as u8, (x * 113) as u8, (x * 29) as u8, 255],
+                    _        => [x as u8, 0, 0, (x * 6) as u8],
+                };
+                image.set_pixel(x, y, pixel);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = test_image();
+        let bytes = encode(&image);
+        assert_eq!(bytes[12], 4);
+        assert!(bytes.ends_with(&END_MARKER));
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!((decoded.width, decoded.height), (40, 8));
+        assert!(decoded.pixels == image.pixels);
+
+        let mut opaque = test_image();
+        opaque.pixels.truncate(40 * 7 * 4);
+        opaque.height = 7;
+        let bytes = encode(&opaque);
+        assert_eq!(bytes[12], 3);
+        assert!(decode(&bytes).unwrap().pixels == opaque.pixels);
+    }
+
+    #[test]
+    fn malformed() {
+        let bytes = encode(&test_image());
+        assert!(decode(&bytes[.. bytes.len() / 2]).is_err());
+        assert!(decode(&bytes[.. HEADER_SIZE + END_MARKER.len()]).is_err());
+        assert!(decode(&bytes[.. HEADER_SIZE]).is_err());
+        assert!(decode(b"qoi").is_err());
+
+        let mut zero_width = bytes.clone();
+        zero_width[4 .. 8].copy_from_slice(&[0; 4]);
+        assert!(decode(&zero_width).is_err());
+
+        let mut bad_channels = bytes.clone();
+        bad_channels[12] = 2;
+        assert!(decode(&bad_channels).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+// NOTE(erick): Palettes for formats that can't store every color. Images
+// with few enough colors keep them exactly, the others go through median
+// cut on a 5 bits per channel histogram. The quantize operation can also
+// use an octree or a fixed list of colors.
+//
+// Pixels with alpha below 128 are left out, the formats that need a
+// palette store transparency as a single index anyway.
+
+const BUCKET_BITS : u32 = 5;
+const BUCKET_COUNT : usize = 1 << (3 * BUCKET_BITS);
+const UNKNOWN : u16 = u16::MAX;
+const OCTREE_DEPTH : usize = 8;
+
+pub struct Palette {
+    pub colors: Vec<[u8; 3]>,
+    exact: HashMap<[u8; 3], u8>,
+    lookup: Vec<u16>,
+}
+
+#[inline]
+fn bucket(color: [u8; 3]) -> usize {
+    let shift = 8 - BUCKET_BITS;
+    ((color[0] >> shift) as usize) << (2 * BUCKET_BITS) |
+    ((color[1] >> shift) as usize) << BUCKET_BITS |
+    (color[2] >> shift) as usize
+}
+
+#[inline]
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    let dr = a[0] as i32 - b[0] as i32;
+    let dg = a[1] as i32 - b[1] as i32;
+    let db = a[2] as i32 - b[2] as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+#[inline]
+pub fn is_transparent(pixel: &[u8]) -> bool {
+    pixel[3] < 128
+}
+
+impl Palette {
+    pub fn build(pixels: &[u8], max_colors: usize) -> Palette {
+        let mut exact = HashMap::new();
+        let mut colors = Vec::new();
+        for pixel in pixels.chunks(4).filter(|pixel| !is_transparent(pixel)) {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            if let Entry::Vacant(entry) = exact.entry(color) {
+                if colors.len() == max_colors {
+                    return Palette::median_cut(pixels, max_colors);
+                }
+                entry.insert(colors.len() as u8);
+                colors.push(color);
+            }
+        }
+
+        Palette { colors, exact, lookup: vec![UNKNOWN; BUCKET_COUNT] }
+    }
+
+    // NOTE(erick): At most 256 colors, the first of the repeated ones is
+    // the one indices point to.
+    pub fn from_colors(colors: Vec<[u8; 3]>) -> Palette {
+        let mut exact = HashMap::new();
+        for (index, &color) in colors.iter().enumerate() {
+            exact.entry(color).or_insert(index as u8);
+        }
+
+        Palette { colors, exact, lookup: vec![UNKNOWN; BUCKET_COUNT] }
+    }
+
+    // NOTE(erick): Every color is a path of 8 levels down the tree, one
+    // bit of each channel per level, and every node counts the pixels
+    // under it. While there are too many leaves, the nodes closest to the
+    // leaves with the fewest pixels take the place of their children.
+    pub fn octree(pixels: &[u8], max_colors: usize) -> Palette {
+        let mut nodes = vec![OctreeNode::new()];
+        let mut levels = vec![Vec::new(); OCTREE_DEPTH];
+        let mut leaf_count = 0;
+        for pixel in pixels.chunks(4).filter(|pixel| !is_transparent(pixel)) {
+            if nodes[0].count == 0 {
+                levels[0].push(0);
+            }
+
+            let mut node = 0;
+            for level in 0 ..= OCTREE_DEPTH {
+                nodes[node].add(pixel);
+                if level == OCTREE_DEPTH {
+                    break;
+                }
+
+                let shift = 7 - level;
+                let child = ((pixel[0] >> shift) & 1) << 2 |
+                            ((pixel[1] >> shift) & 1) << 1 |
+                            ((pixel[2] >> shift) & 1);
+                let child = child as usize;
+                if nodes[node].children[child] == 0 {
+                    let mut new_node = OctreeNode::new();
+                    if level + 1 == OCTREE_DEPTH {
+                        new_node.is_leaf = true;
+                        leaf_count += 1;
+                    } else {
+                        levels[level + 1].push(nodes.len());
+                    }
+                    nodes[node].children[child] = nodes.len();
+                    nodes.push(new_node);
+                }
+                node = nodes[node].children[child];
+            }
+        }
+
+        for level in levels.iter_mut().rev() {
+            level.sort_by_key(|&node| ::std::cmp::Reverse(nodes[node].count));
+            while leaf_count > max_colors && !level.is_empty() {
+                let node = level.pop().unwrap();
+                let merged = nodes[node].children.iter().filter(|&&child| child != 0).count();
+                nodes[node].children = [0; 8];
+                nodes[node].is_leaf = true;
+                leaf_count = leaf_count + 1 - merged;
+            }
+        }
+
+        let mut colors = Vec::with_capacity(leaf_count);
+        let mut stack = vec![0];
+        while !stack.is_empty() {
+            let node = &nodes[stack.pop().unwrap()];
+            if node.is_leaf {
+                let half = node.count / 2;
+                colors.push([((node.sums[0] + half) / node.count) as u8,
+                             ((node.sums[1] + half) / node.count) as u8,
+                             ((node.sums[2] + half) / node.count) as u8]);
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != 0));
+            }
+        }
+
+        Palette::from_colors(colors)
+    }
+
+    fn median_cut(pixels: &[u8], max_colors: usize) -> Palette {
+        // NOTE(erick): Per bucket: pixel count and the sums of the real
+        // channel values, so the palette isn't snapped to the 5 bit grid.
+        let mut histogram = vec![[0u64; 4]; BUCKET_COUNT];
+        for pixel in pixels.chunks(4).filter(|pixel| !is_transparent(pixel)) {
+            let entry = &mut histogram[bucket([pixel[0], pixel[1], pixel[2]])];
+            entry[0] += 1;
+            entry[1] += pixel[0] as u64;
+            entry[2] += pixel[1] as u64;
+            entry[3] += pixel[2] as u64;
+        }
+
+        let entries = histogram.iter().enumerate()
+            .filter(|&(_, entry)| entry[0] > 0)
+            .map(|(index, entry)| (index, *entry))
+            .collect::<Vec<_> >();
+
+        let mut boxes = vec![entries];
+        while boxes.len() < max_colors {
+            let widest = boxes.iter().enumerate()
+                .filter(|&(_, entries)| entries.len() > 1)
+                .map(|(index, entries)| (index, widest_channel(entries)))
+                .max_by_key(|&(_, (_, range))| range);
+            if widest.is_none() {
+                break;
+            }
+
+            let (index, (channel, _)) = widest.unwrap();
+            let mut entries = boxes.swap_remove(index);
+            entries.sort_by_key(|&(_, entry)| entry[channel + 1] / entry[0]);
+
+            let total = entries.iter().map(|&(_, entry)| entry[0]).sum::<u64>();
+            let mut count = 0;
+            let mut split = 1;
+            for (i, &(_, entry)) in entries.iter().enumerate() {
+                count += entry[0];
+                if count * 2 >= total {
+                    split = (i + 1).min(entries.len() - 1);
+                    break;
+                }
+            }
+
+            let second = entries.split_off(split);
+            boxes.push(entries);
+            boxes.push(second);
+        }
+
+        let colors = boxes.iter().map(|entries| {
+            let mut sums = [0u64; 4];
+            for &(_, entry) in entries.iter() {
+                for channel in 0 .. 4 {
+                    sums[channel] += entry[channel];
+                }
+            }
+            [(sums[1] / sums[0]) as u8, (sums[2] / sums[0]) as u8, (sums[3] / sums[0]) as u8]
+        }).collect();
+
+        Palette { colors, exact: HashMap::new(), lookup: vec![UNKNOWN; BUCKET_COUNT] }
+    }
+
+    // NOTE(erick): Colors outside the palette get the nearest entry to
+    // their bucket, computed the first time the bucket is used.
+    pub fn index(&mut self, color: [u8; 3]) -> u8 {
+        if let Some(&index) = self.exact.get(&color) {
+            return index;
+        }
+
+        let bucket = bucket(color);
+        if self.lookup[bucket] == UNKNOWN {
+            let shift = 8 - BUCKET_BITS;
+            let half = 1 << (shift - 1);
+            let center = [color[0] >> shift << shift | half,
+                          color[1] >> shift << shift | half,
+                          color[2] >> shift << shift | half];
+            let nearest = self.colors.iter().enumerate()
+                .min_by_key(|&(_, &entry)| distance(entry, center))
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+            self.lookup[bucket] = nearest as u16;
+        }
+
+        self.lookup[bucket] as u8
+    }
+
+    // NOTE(erick): Searches the whole palette, index() is faster but
+    // only looks for the nearest color to the center of the bucket.
+    pub fn nearest(&self, color: [u8; 3]) -> u8 {
+        if let Some(&index) = self.exact.get(&color) {
+            return index;
+        }
+
+        self.colors.iter().enumerate()
+            .min_by_key(|&(_, &entry)| distance(entry, color))
+            .map(|(index, _)| index as u8)
+            .unwrap_or(0)
+    }
+}
+
+struct OctreeNode {
+    // NOTE(erick): 0 is no child, the root is nobody's child.
+    children: [usize; 8],
+    count: u64,
+    sums: [u64; 3],
+    is_leaf: bool,
+}
+
+impl OctreeNode {
+    fn new() -> OctreeNode {
+        OctreeNode { children: [0; 8], count: 0, sums: [0; 3], is_leaf: false }
+    }
+
+    #[inline]
+    fn add(&mut self, pixel: &[u8]) {
+        self.count += 1;
+        self.sums[0] += pixel[0] as u64;
+        self.sums[1] += pixel[1] as u64;
+        self.sums[2] += pixel[2] as u64;
+    }
+}
+
+// NOTE(erick): Returns the channel with the largest spread and the spread.
+fn widest_channel(entries: &[(usize, [u64; 4])]) -> (usize, u64) {
+    let mut result = (0, 0);
+    for channel in 0 .. 3 {
+        let values = entries.iter().map(|&(_, entry)| entry[channel + 1] / entry[0]);
+        let low = values.clone().min().unwrap_or(0);
+        let high = values.max().unwrap_or(0);
+        if high - low >= result.1 {
+            result = (channel, high - low);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(pixel_count: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0 .. pixel_count * 4).map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if i % 4 == 3 { 255 } else { state as u8 }
+        }).collect()
+    }
+
+    #[test]
+    fn palette_sizes() {
+        let pixels = noise(4096);
+        for &max_colors in [1, 2, 16, 255, 256].iter() {
+            let median_cut = Palette::build(&pixels, max_colors);
+            assert!(!median_cut.colors.is_empty() && median_cut.colors.len() <= max_colors);
+
+            let octree = Palette::octree(&pixels, max_colors);
+            assert!(!octree.colors.is_empty() && octree.colors.len() <= max_colors);
+        }
+
+        let transparent = vec![0u8; 16 * 4];
+        assert!(Palette::build(&transparent, 16).colors.is_empty());
+        assert!(Palette::octree(&transparent, 16).colors.is_empty());
+    }
+
+    #[test]
+    fn exact_colors() {
+        let pixels = [10, 20, 30, 255,  40, 50, 60, 255,  10, 20, 30, 255,  1, 2, 3, 0,
+                      70, 80, 90, 200];
+        let mut palette = Palette::build(&pixels, 3);
+        assert_eq!(palette.colors, vec![[10, 20, 30], [40, 50, 60], [70, 80, 90]]);
+        assert_eq!(palette.index([40, 50, 60]), 1);
+        assert_eq!(palette.nearest([68, 82, 90]), 2);
+        assert_eq!(palette.index([12, 18, 30]), 0);
+
+        let octree = Palette::octree(&pixels, 8);
+        assert_eq!(octree.colors.len(), 3);
+        assert!(octree.colors.contains(&[70, 80, 90]));
+
+        // NOTE(erick): Repeated colors point to the first of them.
+        let palette = Palette::from_colors(vec![[0, 0, 0], [9, 9, 9], [0, 0, 0]]);
+        assert_eq!(palette.nearest([0, 0, 0]), 0);
+        assert_eq!(palette.nearest([200, 200, 200]), 1);
+    }
+}
@@ -0,0 +1,279 @@
+use color;
+use image::Image;
+use operation::Channels;
+use operation::Morphology;
+use operation::Shape;
+use operation::Window;
+
+const COARSE_BINS : usize = 16;
+const FINE_PER_COARSE : usize = 256 / COARSE_BINS;
+
+#[derive(Clone, Copy)]
+enum Rank {
+    Minimum,
+    Median,
+    Maximum,
+}
+
+// NOTE(erick): The values inside the window of one channel. The coarse
+// bins count 16 values each, so finding a rank skips most of the fine
+// ones.
+struct Histogram {
+    coarse: [u32; COARSE_BINS],
+    fine: [u32; 256],
+    count: u32,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            coarse: [0; COARSE_BINS],
+            fine: [0; 256],
+            count: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.coarse = [0; COARSE_BINS];
+        self.fine = [0; 256];
+        self.count = 0;
+    }
+
+    #[inline]
+    fn add(&mut self, value: u8) {
+        self.coarse[value as usize / FINE_PER_COARSE] += 1;
+        self.fine[value as usize] += 1;
+        self.count += 1;
+    }
+
+    #[inline]
+    fn remove(&mut self, value: u8) {
+        self.coarse[value as usize / FINE_PER_COARSE] -= 1;
+        self.fine[value as usize] -= 1;
+        self.count -= 1;
+    }
+
+    // NOTE(erick): The value with 'rank' smaller (or equal) values before
+    // it, 0 is the minimum.
+    fn value(&self, rank: u32) -> u8 {
+        let mut rank = rank;
+        let mut bin = 0;
+        while rank >= self.coarse[bin] {
+            rank -= self.coarse[bin];
+            bin += 1;
+        }
+
+        let mut value = bin * FINE_PER_COARSE;
+        while rank >= self.fine[value] {
+            rank -= self.fine[value];
+            value += 1;
+        }
+
+        value as u8
+    }
+}
+
+pub fn median(image: &Image, channels: Channels, window: &Window) -> Image {
+    rank_filter(image, channels, window, Rank::Median)
+}
+
+pub fn morphology(image: &Image, channels: Channels, morphology: Morphology,
+                  window: &Window) -> Image {
+    match morphology {
+        Morphology::Erode    => rank_filter(image, channels, window, Rank::Minimum),
+        Morphology::Dilate   => rank_filter(image, channels, window, Rank::Maximum),
+        Morphology::Open     => {
+            let eroded = rank_filter(image, channels, window, Rank::Minimum);
+            rank_filter(&eroded, channels, window, Rank::Maximum)
+        },
+        Morphology::Close    => {
+            let dilated = rank_filter(image, channels, window, Rank::Maximum);
+            rank_filter(&dilated, channels, window, Rank::Minimum)
+        },
+        Morphology::Gradient => {
+            let affected = color::affected_channels(channels);
+            let eroded = rank_filter(image, channels, window, Rank::Minimum);
+            let mut result = rank_filter(image, channels, window, Rank::Maximum);
+            for (pixel, eroded) in result.pixels.chunks_mut(4).zip(eroded.pixels.chunks(4)) {
+                for channel in 0 .. 4 {
+                    if affected[channel] {
+                        pixel[channel] -= eroded[channel];
+                    }
+                }
+            }
+            result
+        },
+    }
+}
+
+// NOTE(erick): How far the window reaches left and right on each of its
+// rows, from 'radius' rows above the pixel to 'radius' rows below.
+fn half_widths(window: &Window) -> Vec<i64> {
+    let radius = window.radius as i64;
+    (-radius ..= radius).map(|dy| {
+        match window.shape {
+            Shape::Square => radius,
+            Shape::Cross  => if dy == 0 { radius } else { 0 },
+            Shape::Disk   => ((radius * radius - dy * dy) as f64).sqrt() as i64,
+        }
+    }).collect()
+}
+
+// NOTE(erick): Pixels outside the image are left out, so the windows are
+// smaller at the edges instead of seeing made up values. Along a row the
+// window slides by taking out the pixels that leave it and adding the
+// ones that enter it, one per window row.
+fn rank_filter(image: &Image, channels: Channels, window: &Window, rank: Rank) -> Image {
+    let affected = color::affected_channels(channels);
+    let affected = (0 .. 4).filter(|&channel| affected[channel]).collect::<Vec<_> >();
+    let width = image.width as i64;
+    let height = image.height as i64;
+    let radius = window.radius as i64;
+    let half_widths = half_widths(window);
+
+    let pixel_offset = |x: i64, y: i64| ((y * width + x) * 4) as usize;
+
+    let mut histograms = affected.iter().map(|_| Histogram::new()).collect::<Vec<_> >();
+    let mut result = image.clone();
+    for y in 0 .. height {
+        for histogram in histograms.iter_mut() {
+            histogram.clear();
+        }
+
+        for (row, &half_width) in half_widths.iter().enumerate() {
+            let window_y = y + row as i64 - radius;
+            if window_y < 0 || window_y >= height {
+                continue;
+            }
+            for window_x in 0 ..= half_width.min(width - 1) {
+                let offset = pixel_offset(window_x, window_y);
+                for (histogram, &channel) in histograms.iter_mut().zip(affected.iter()) {
+                    histogram.add(image.pixels[offset + channel]);
+                }
+            }
+        }
+
+        for x in 0 .. width {
+            let offset = pixel_offset(x, y);
+            for (histogram, &channel) in histograms.iter().zip(affected.iter()) {
+                let rank = match rank {
+                    Rank::Minimum => 0,
+                    Rank::Median  => histogram.count / 2,
+                    Rank::Maximum => histogram.count - 1,
+                };
+                result.pixels[offset + channel] = histogram.value(rank);
+            }
+
+            for (row, &half_width) in half_widths.iter().enumerate() {
+                let window_y = y + row as i64 - radius;
+                if window_y < 0 || window_y >= height {
+                    continue;
+                }
+
+                let leaving = x - half_width;
+                if leaving >= 0 {
+                    let offset = pixel_offset(leaving, window_y);
+                    for (histogram, &channel) in histograms.iter_mut().zip(affected.iter()) {
+                        histogram.remove(image.pixels[offset + channel]);
+                    }
+                }
+
+                let entering = x + half_width + 1;
+                if entering < width {
+                    let offset = pixel_offset(entering, window_y);
+                    for (histogram, &channel) in histograms.iter_mut().zip(affected.iter()) {
+                        histogram.add(image.pixels[offset + channel]);
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_image(width: u32, height: u32, values: &[u8]) -> Image {
+        let mut image = Image::new(width, height);
+        for (pixel, &value) in image.pixels.chunks_mut(4).zip(values.iter()) {
+            pixel.copy_from_slice(&[value, value, value, 255]);
+        }
+        image
+    }
+
+    fn reds(image: &Image) -> Vec<u8> {
+        image.pixels.chunks(4).map(|pixel| pixel[0]).collect()
+    }
+
+    // NOTE(erick): Sorts every window, the slow way.
+    fn reference(image: &Image, window: &Window, rank: Rank) -> Vec<u8> {
+        let half_widths = half_widths(window);
+        let radius = window.radius as i64;
+        let (width, height) = (image.width as i64, image.height as i64);
+        let mut result = Vec::new();
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let mut values = Vec::new();
+                for (row, &half_width) in half_widths.iter().enumerate() {
+                    let window_y = y + row as i64 - radius;
+                    for window_x in x - half_width ..= x + half_width {
+                        if window_x >= 0 && window_x < width && window_y >= 0 && window_y < height {
+                            values.push(image.pixel(window_x as u32, window_y as u32)[0]);
+                        }
+                    }
+                }
+                values.sort();
+                result.push(match rank {
+                    Rank::Minimum => values[0],
+                    Rank::Median  => values[values.len() / 2],
+                    Rank::Maximum => values[values.len() - 1],
+                });
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn median_window() {
+        let image = gray_image(3, 3, &[90, 10, 80,
+                                       20, 255, 30,
+                                       70, 40, 0]);
+        let square = Window { shape: Shape::Square, radius: 1 };
+        assert_eq!(median(&image, Channels::All, &square).pixel(1, 1), [40, 40, 40, 255]);
+
+        let cross = Window { shape: Shape::Cross, radius: 1 };
+        assert_eq!(median(&image, Channels::All, &cross).pixel(1, 1), [30, 30, 30, 255]);
+
+        // NOTE(erick): The corner sees 90, 10, 20 and 255.
+        assert_eq!(median(&image, Channels::Red, &square).pixel(0, 0), [90, 90, 90, 255]);
+    }
+
+    #[test]
+    fn matches_sorting() {
+        let values = (0 .. 13 * 9).map(|i| (i * 97 % 251) as u8).collect::<Vec<_> >();
+        let image = gray_image(13, 9, &values);
+        for &shape in [Shape::Square, Shape::Cross, Shape::Disk].iter() {
+            for &radius in [1, 2, 5].iter() {
+                let window = Window { shape, radius };
+                for &rank in [Rank::Minimum, Rank::Median, Rank::Maximum].iter() {
+                    let result = rank_filter(&image, Channels::All, &window, rank);
+                    assert!(reds(&result) == reference(&image, &window, rank));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn morphology_gradient() {
+        let image = gray_image(4, 1, &[0, 0, 200, 200]);
+        let window = Window { shape: Shape::Square, radius: 1 };
+        let result = morphology(&image, Channels::All, Morphology::Gradient, &window);
+        assert_eq!(reds(&result), vec![0, 200, 200, 0]);
+
+        let result = morphology(&image, Channels::All, Morphology::Open, &window);
+        assert_eq!(reds(&result), vec![0, 0, 200, 200]);
+    }
+}
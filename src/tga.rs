@@ -0,0 +1,481 @@
+use std::sync::OnceLock;
+
+use config::Section;
+use image::Image;
+
+// NOTE(erick): Reference: Truevision TGA File Format Specification 2.0.
+//
+// TGA has no signature at the start of the file, so is_tga() checks that
+// the header makes sense. Codecs with a real signature are tried first.
+
+const HEADER_SIZE : usize = 18;
+
+const TYPE_COLOR_MAPPED     : u8 = 1;
+const TYPE_TRUE_COLOR       : u8 = 2;
+const TYPE_GRAYSCALE        : u8 = 3;
+const TYPE_RLE_COLOR_MAPPED : u8 = 9;
+const TYPE_RLE_TRUE_COLOR   : u8 = 10;
+const TYPE_RLE_GRAYSCALE    : u8 = 11;
+
+const DESCRIPTOR_RIGHT_TO_LEFT : u8 = 0x10;
+const DESCRIPTOR_TOP_TO_BOTTOM : u8 = 0x20;
+
+// NOTE(erick): The header stores the size in 16 bits.
+pub const MAX_SIZE : u32 = 65535;
+
+pub struct TgaOptions {
+    pub rle: bool,
+    pub top_to_bottom: bool,
+    // NOTE(erick): 0 picks the smallest depth that loses nothing. 8 is
+    // grayscale, 16 keeps one bit of alpha.
+    pub bits_per_pixel: u8,
+}
+
+impl TgaOptions {
+    pub fn default() -> TgaOptions {
+        TgaOptions { rle: true, top_to_bottom: false, bits_per_pixel: 0 }
+    }
+
+    // NOTE(erick): [tga]
+    //              compression = rle | none
+    //              origin = bottom-left | top-left
+    //              bits = auto | 8 | 16 | 24 | 32
+    pub fn from_section(section: Option<&Section>) -> Result<TgaOptions, String> {
+        let mut result = TgaOptions::default();
+        if section.is_none() {
+            return Ok(result);
+        }
+
+        for entry in section.unwrap().entries.iter() {
+            match (entry.name.as_str(), entry.value.as_str()) {
+                ("compression", "rle")  => { result.rle = true; },
+                ("compression", "none") => { result.rle = false; },
+                ("origin", "bottom-left") => { result.top_to_bottom = false; },
+                ("origin", "top-left")    => { result.top_to_bottom = true; },
+                ("bits", "auto") => { result.bits_per_pixel = 0; },
+                ("bits", "8")    => { result.bits_per_pixel = 8; },
+                ("bits", "16")   => { result.bits_per_pixel = 16; },
+                ("bits", "24")   => { result.bits_per_pixel = 24; },
+                ("bits", "32")   => { result.bits_per_pixel = 32; },
+                ("compression", _) | ("origin", _) | ("bits", _) => {
+                    return Err(format!("{}: invalid value '{}' for '{}'",
+                                       entry.line, entry.value, entry.name));
+                },
+                _ => return Err(format!("{}: unknown option '{}'", entry.line, entry.name)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+static TGA_OPTIONS : OnceLock<TgaOptions> = OnceLock::new();
+
+pub fn set_options(options: TgaOptions) {
+    let _ = TGA_OPTIONS.set(options);
+}
+
+pub fn options() -> &'static TgaOptions {
+    TGA_OPTIONS.get_or_init(TgaOptions::default)
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+struct Header {
+    id_length: usize,
+    color_map_type: u8,
+    image_type: u8,
+    color_map_first: usize,
+    color_map_length: usize,
+    color_map_entry_size: u8,
+    width: usize,
+    height: usize,
+    bits_per_pixel: u8,
+    descriptor: u8,
+}
+
+fn read_header(bytes: &[u8]) -> Option<Header> {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let header = Header {
+        id_length: bytes[0] as usize,
+        color_map_type: bytes[1],
+        image_type: bytes[2],
+        color_map_first: read_u16(bytes, 3) as usize,
+        color_map_length: read_u16(bytes, 5) as usize,
+        color_map_entry_size: bytes[7],
+        width: read_u16(bytes, 12) as usize,
+        height: read_u16(bytes, 14) as usize,
+        bits_per_pixel: bytes[16],
+        descriptor: bytes[17],
+    };
+
+    let is_color_mapped = header.image_type == TYPE_COLOR_MAPPED ||
+        header.image_type == TYPE_RLE_COLOR_MAPPED;
+    let valid = match header.image_type {
+        TYPE_COLOR_MAPPED | TYPE_RLE_COLOR_MAPPED =>
+            header.bits_per_pixel == 8 || header.bits_per_pixel == 16,
+        TYPE_TRUE_COLOR | TYPE_RLE_TRUE_COLOR =>
+            [15, 16, 24, 32].contains(&header.bits_per_pixel),
+        TYPE_GRAYSCALE | TYPE_RLE_GRAYSCALE =>
+            header.bits_per_pixel == 8 || header.bits_per_pixel == 16,
+        _ => false,
+    };
+    let valid_color_map = match header.color_map_type {
+        0 => !is_color_mapped,
+        1 => [15, 16, 24, 32].contains(&header.color_map_entry_size),
+        _ => false,
+    };
+
+    if !valid || !valid_color_map || header.width == 0 || header.height == 0 ||
+        header.descriptor & 0xc0 != 0 {
+        return None;
+    }
+
+    Some(header)
+}
+
+pub fn is_tga(bytes: &[u8]) -> bool {
+    read_header(bytes).is_some()
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let header = read_header(bytes)?;
+    Some((header.width as u32, header.height as u32))
+}
+
+// NOTE(erick): 15/16-bit pixels are ARRRRRGGGGGBBBBB, little-endian.
+#[inline]
+fn color_from_bytes(bytes: &[u8], bits_per_pixel: u8, use_alpha: bool) -> [u8; 4] {
+    match bits_per_pixel {
+        15 | 16 => {
+            let value = bytes[0] as u16 | (bytes[1] as u16) << 8;
+            let expand = |five_bits: u16| ((five_bits * 255 + 15) / 31) as u8;
+            let alpha = if use_alpha && value & 0x8000 == 0 { 0 } else { 255 };
+            [expand((value >> 10) & 0x1f), expand((value >> 5) & 0x1f), expand(value & 0x1f), alpha]
+        },
+        24 => [bytes[2], bytes[1], bytes[0], 255],
+        _  => [bytes[2], bytes[1], bytes[0], if use_alpha { bytes[3] } else { 255 }],
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    let header = read_header(bytes);
+    if header.is_none() {
+        return Err("TGA: invalid header".to_string());
+    }
+
+    let header = header.unwrap();
+    let alpha_bits = header.descriptor & 0x0f;
+
+    let mut offset = HEADER_SIZE + header.id_length;
+    let mut color_map = Vec::new();
+    if header.color_map_type == 1 {
+        let entry_size = (header.color_map_entry_size as usize).div_ceil(8);
+        if offset + header.color_map_length * entry_size > bytes.len() {
+            return Err("TGA: truncated color map".to_string());
+        }
+
+        for i in 0 .. header.color_map_length {
+            let entry = &bytes[offset + i * entry_size ..];
+            color_map.push(color_from_bytes(entry, header.color_map_entry_size, alpha_bits > 0));
+        }
+        offset += header.color_map_length * entry_size;
+    }
+    if offset > bytes.len() {
+        return Err("TGA: truncated image data".to_string());
+    }
+
+    let pixel_size = (header.bits_per_pixel as usize).div_ceil(8);
+    let pixel_count = header.width * header.height;
+    let is_rle = header.image_type >= TYPE_RLE_COLOR_MAPPED;
+    let data = if is_rle {
+        decompress_rle(&bytes[offset ..], pixel_count, pixel_size)?
+    } else {
+        if offset + pixel_count * pixel_size > bytes.len() {
+            return Err("TGA: truncated image data".to_string());
+        }
+        bytes[offset .. offset + pixel_count * pixel_size].to_vec()
+    };
+
+    // NOTE(erick): Plenty of writers leave the alpha bits at zero even
+    // though the alpha channel is there. If it's all zero we assume it's
+    // padding rather than an invisible image.
+    let mut use_alpha = alpha_bits > 0;
+    if !use_alpha && header.bits_per_pixel == 32 {
+        use_alpha = data.chunks(4).any(|pixel| pixel[3] != 0);
+    }
+
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    for (index, pixel_bytes) in data.chunks(pixel_size).enumerate() {
+        let pixel = match header.image_type {
+            TYPE_COLOR_MAPPED | TYPE_RLE_COLOR_MAPPED => {
+                let value = if pixel_size == 2 {
+                    read_u16(pixel_bytes, 0) as usize
+                } else {
+                    pixel_bytes[0] as usize
+                };
+                let entry = value.wrapping_sub(header.color_map_first);
+                if entry >= color_map.len() {
+                    return Err("TGA: color index out of the color map".to_string());
+                }
+                color_map[entry]
+            },
+            TYPE_GRAYSCALE | TYPE_RLE_GRAYSCALE => {
+                let value = pixel_bytes[0];
+                let alpha = if pixel_size == 2 { pixel_bytes[1] } else { 255 };
+                [value, value, value, alpha]
+            },
+            _ => color_from_bytes(pixel_bytes, header.bits_per_pixel, use_alpha),
+        };
+
+        let column = index % header.width;
+        let row = index / header.width;
+        let x = if header.descriptor & DESCRIPTOR_RIGHT_TO_LEFT != 0 {
+            header.width - 1 - column
+        } else {
+            column
+        };
+        let y = if header.descriptor & DESCRIPTOR_TOP_TO_BOTTOM != 0 {
+            row
+        } else {
+            header.height - 1 - row
+        };
+        image.set_pixel(x as u32, y as u32, pixel);
+    }
+
+    Ok(image)
+}
+
+// NOTE(erick): Packets may cross scanlines. The spec says they shouldn't
+// but enough writers do it that everyone accepts it. No packet expands
+// to more than 128 times its size.
+fn decompress_rle(bytes: &[u8], pixel_count: usize, pixel_size: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity((pixel_count * pixel_size).min(bytes.len() * 128));
+    let mut position = 0;
+    while output.len() < pixel_count * pixel_size {
+        if position >= bytes.len() {
+            return Err("TGA: truncated RLE data".to_string());
+        }
+
+        let packet = bytes[position];
+        position += 1;
+        let count = (packet & 0x7f) as usize + 1;
+        let is_run = packet & 0x80 != 0;
+        let packet_size = if is_run { pixel_size } else { pixel_size * count };
+        if position + packet_size > bytes.len() {
+            return Err("TGA: truncated RLE data".to_string());
+        }
+
+        if is_run {
+            for _ in 0 .. count {
+                output.extend_from_slice(&bytes[position .. position + pixel_size]);
+            }
+        } else {
+            output.extend_from_slice(&bytes[position .. position + packet_size]);
+        }
+        position += packet_size;
+    }
+
+    output.truncate(pixel_count * pixel_size);
+    Ok(output)
+}
+
+#[inline]
+fn luma(pixel: &[u8]) -> u8 {
+    ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114 + 500) / 1000) as u8
+}
+
+fn pixel_to_bytes(pixel: &[u8], bits_per_pixel: u8, output: &mut Vec<u8>) {
+    match bits_per_pixel {
+        8  => output.push(luma(pixel)),
+        16 => {
+            let five_bits = |value: u8| (value as u16 * 31 + 127) / 255;
+            let value = if pixel[3] >= 128 { 0x8000 } else { 0 } |
+                five_bits(pixel[0]) << 10 | five_bits(pixel[1]) << 5 | five_bits(pixel[2]);
+            output.extend_from_slice(&value.to_le_bytes());
+        },
+        24 => output.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]),
+        _  => output.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]),
+    }
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    let options = options();
+
+    let bits_per_pixel = if options.bits_per_pixel != 0 {
+        options.bits_per_pixel
+    } else {
+        let is_gray = image.pixels.chunks(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+        let has_alpha = image.pixels.chunks(4).any(|pixel| pixel[3] != 255);
+        match (is_gray, has_alpha) {
+            (true, false) => 8,
+            (_, true)     => 32,
+            _             => 24,
+        }
+    };
+
+    let image_type = match (bits_per_pixel, options.rle) {
+        (8, false) => TYPE_GRAYSCALE,
+        (8, true)  => TYPE_RLE_GRAYSCALE,
+        (_, false) => TYPE_TRUE_COLOR,
+        (_, true)  => TYPE_RLE_TRUE_COLOR,
+    };
+    let alpha_bits = match bits_per_pixel {
+        16 => 1,
+        32 => 8,
+        _  => 0,
+    };
+    let descriptor = alpha_bits | if options.top_to_bottom { DESCRIPTOR_TOP_TO_BOTTOM } else { 0 };
+
+    let mut output = vec![0u8; HEADER_SIZE];
+    output[2] = image_type;
+    output[12 .. 14].copy_from_slice(&(image.width as u16).to_le_bytes());
+    output[14 .. 16].copy_from_slice(&(image.height as u16).to_le_bytes());
+    output[16] = bits_per_pixel;
+    output[17] = descriptor;
+
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let pixel_size = (bits_per_pixel / 8) as usize;
+    for row in 0 .. height {
+        let y = if options.top_to_bottom { row } else { height - 1 - row };
+        let pixels = &image.pixels[y * width * 4 .. (y + 1) * width * 4];
+
+        let mut row_bytes = Vec::with_capacity(width * pixel_size);
+        for pixel in pixels.chunks(4) {
+            pixel_to_bytes(pixel, bits_per_pixel, &mut row_bytes);
+        }
+
+        if options.rle {
+            compress_rle_row(&row_bytes, pixel_size, &mut output);
+        } else {
+            output.extend_from_slice(&row_bytes);
+        }
+    }
+
+    // NOTE(erick): TGA 2.0 footer with no extension or developer areas.
+    output.extend_from_slice(&[0; 8]);
+    output.extend_from_slice(b"TRUEVISION-XFILE.\0");
+    output
+}
+
+// NOTE(erick): Runs of two or more equal pixels become run packets and
+// everything in between goes into raw packets. Rows are compressed on
+// their own, as the spec asks.
+fn compress_rle_row(row: &[u8], pixel_size: usize, output: &mut Vec<u8>) {
+    let pixels = row.chunks(pixel_size).collect::<Vec<_> >();
+    let mut i = 0;
+    while i < pixels.len() {
+        let mut run = 1;
+        while i + run < pixels.len() && run < 128 && pixels[i + run] == pixels[i] {
+            run += 1;
+        }
+
+        if run >= 2 {
+            output.push(0x80 | (run - 1) as u8);
+            output.extend_from_slice(pixels[i]);
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < pixels.len() && i - start < 128 &&
+            (i + 1 >= pixels.len() || pixels[i + 1] != pixels[i]) {
+            i += 1;
+        }
+        if i == start {
+            i += 1;
+        }
+
+        output.push((i - start - 1) as u8);
+        for pixel in &pixels[start .. i] {
+            output.extend_from_slice(pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOOTER_SIZE : usize = 26;
+
+    // NOTE(erick): Long runs and noise, so RLE writes both kinds of packets.
+    fn test_image(width: u32, height: u32, gray: bool, alpha: bool) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let value = if x < width / 2 { y as u8 * 40 } else { (x * 37 + y * 11) as u8 };
+                let pixel = [value,
+                             if gray { value } else { 255 - value },
+                             if gray { value } else { (y * 60) as u8 },
+                             if alpha { (y * 50) as u8 } else { 255 }];
+                image.set_pixel(x, y, pixel);
+            }
+        }
+        image
+    }
+
+    fn uncompressed(image: &Image, bits_per_pixel: u8) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        bytes[2] = if bits_per_pixel == 8 { TYPE_GRAYSCALE } else { TYPE_TRUE_COLOR };
+        bytes[12 .. 14].copy_from_slice(&(image.width as u16).to_le_bytes());
+        bytes[14 .. 16].copy_from_slice(&(image.height as u16).to_le_bytes());
+        bytes[16] = bits_per_pixel;
+        bytes[17] = if bits_per_pixel == 32 { 8 } else { 0 };
+
+        let row_size = image.width as usize * 4;
+        for row in image.pixels.chunks(row_size).rev() {
+            for pixel in row.chunks(4) {
+                pixel_to_bytes(pixel, bits_per_pixel, &mut bytes);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        let cases = [(true, false, TYPE_RLE_GRAYSCALE, 8),
+                     (false, false, TYPE_RLE_TRUE_COLOR, 24),
+                     (false, true, TYPE_RLE_TRUE_COLOR, 32)];
+        for &(gray, alpha, image_type, bits_per_pixel) in cases.iter() {
+            let image = test_image(300, 4, gray, alpha);
+            let bytes = encode(&image);
+            assert_eq!((bytes[2], bytes[16]), (image_type, bits_per_pixel));
+
+            let uncompressed_size = image.pixels.len() / 4 * bits_per_pixel as usize / 8;
+            assert!(bytes.len() < HEADER_SIZE + uncompressed_size + FOOTER_SIZE);
+            assert!(decode(&bytes).unwrap().pixels == image.pixels);
+        }
+    }
+
+    #[test]
+    fn uncompressed_round_trips() {
+        for &(gray, alpha, bits_per_pixel) in [(true, false, 8), (false, false, 24),
+                                               (false, true, 32)].iter() {
+            let image = test_image(5, 3, gray, alpha);
+            let decoded = decode(&uncompressed(&image, bits_per_pixel)).unwrap();
+            assert_eq!((decoded.width, decoded.height), (5, 3));
+            assert!(decoded.pixels == image.pixels);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let image = test_image(40, 3, false, false);
+
+        let bytes = encode(&image);
+        assert!(decode(&bytes[.. bytes.len() - FOOTER_SIZE - 1]).is_err());
+
+        let bytes = uncompressed(&image, 24);
+        assert!(decode(&bytes[.. bytes.len() - 1]).is_err());
+        assert!(decode(&bytes[.. HEADER_SIZE]).is_err());
+        assert!(decode(&bytes[.. HEADER_SIZE - 1]).is_err());
+    }
+}
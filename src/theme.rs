@@ -0,0 +1,296 @@
+use std::sync::OnceLock;
+
+use ncurses::*;
+
+use config::Config;
+use config::Section;
+
+// NOTE(erick): These are both the ncurses color pair numbers and the
+// indices (minus one) into Theme::styles.
+pub const NORMAL_COLOR    : i16 = 1;
+pub const ERROR_COLOR     : i16 = 2;
+pub const HIGHLIGHT_COLOR : i16 = 3;
+pub const QUESTION_COLOR  : i16 = 4;
+
+const ELEMENT_COUNT : usize = 4;
+const ELEMENT_NAMES : [&str; ELEMENT_COUNT] = ["normal", "error", "highlight", "question"];
+
+// NOTE(erick): -1 is the terminal's default color, see use_default_colors(3).
+const COLOR_DEFAULT : i16 = -1;
+
+#[derive(Clone, Copy)]
+pub struct Style {
+    pub foreground: i16,
+    pub background: i16,
+    pub attributes: attr_t,
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub styles: [Style; ELEMENT_COUNT],
+}
+
+fn style(foreground: i16, background: i16, attributes: attr_t) -> Style {
+    Style { foreground, background, attributes }
+}
+
+pub fn builtin_theme(name: &str) -> Option<Theme> {
+    let styles = match name {
+        "dark" => [
+            style(COLOR_WHITE, COLOR_BLACK, A_NORMAL()),
+            style(COLOR_WHITE, COLOR_RED,   A_NORMAL()),
+            style(COLOR_BLACK, COLOR_WHITE, A_NORMAL()),
+            style(COLOR_WHITE, COLOR_BLUE,  A_NORMAL()),
+        ],
+        "light" => [
+            style(COLOR_BLACK, COLOR_WHITE, A_NORMAL()),
+            style(COLOR_WHITE, COLOR_RED,   A_NORMAL()),
+            style(COLOR_WHITE, COLOR_BLACK, A_NORMAL()),
+            style(COLOR_WHITE, COLOR_BLUE,  A_NORMAL()),
+        ],
+        "high-contrast" => [
+            style(COLOR_WHITE,  COLOR_BLACK,  A_BOLD()),
+            style(COLOR_YELLOW, COLOR_RED,    A_BOLD()),
+            style(COLOR_BLACK,  COLOR_YELLOW, A_BOLD()),
+            style(COLOR_BLACK,  COLOR_CYAN,   A_BOLD()),
+        ],
+        _ => return None,
+    };
+
+    Some(Theme { styles })
+}
+
+// NOTE(erick): Used when the terminal has no colors at all. Every element
+// still has to be distinguishable from normal text.
+fn monochrome_attribute(color: i16) -> attr_t {
+    match color {
+        ERROR_COLOR     => A_BOLD() | A_REVERSE(),
+        HIGHLIGHT_COLOR => A_REVERSE(),
+        QUESTION_COLOR  => A_BOLD() | A_UNDERLINE(),
+        _               => A_NORMAL(),
+    }
+}
+
+// NOTE(erick): The theme is picked in the [ui] section and user themes
+// are defined in [theme NAME] sections:
+//
+//     [ui]
+//     theme = paper
+//
+//     [theme paper]
+//     base = light
+//     highlight = black yellow bold
+//
+// Elements not listed are taken from the base theme (dark by default).
+pub fn from_config(config: &Config) -> Result<Theme, String> {
+    let mut theme_name = "dark".to_string();
+    let mut theme_line = 0;
+
+    if let Some(ui) = config.section("ui") {
+        for entry in ui.entries.iter() {
+            match entry.name.as_str() {
+                "theme" => {
+                    theme_name = entry.value.clone();
+                    theme_line = entry.line;
+                },
+                _ => return Err(format!("{}: unknown option '{}'", entry.line, entry.name)),
+            }
+        }
+    }
+
+    load_theme(config, theme_name.as_str(), theme_line, 0)
+}
+
+fn load_theme(config: &Config, name: &str, line: usize, depth: usize) -> Result<Theme, String> {
+    // NOTE(erick): User themes may shadow the built-in ones.
+    let section_name = format!("theme {}", name);
+    let section = config.section(section_name.as_str());
+    if section.is_none() {
+        let builtin = builtin_theme(name);
+        if builtin.is_none() {
+            return Err(format!("{}: unknown theme '{}'", line, name));
+        }
+        return Ok(builtin.unwrap());
+    }
+
+    if depth > 8 {
+        return Err(format!("{}: theme '{}' is part of an inheritance loop", line, name));
+    }
+
+    user_theme(config, name, section.unwrap(), depth)
+}
+
+fn user_theme(config: &Config, name: &str, section: &Section,
+              depth: usize) -> Result<Theme, String> {
+    let base = section.entries.iter().find(|entry| entry.name == "base");
+    let mut theme = if let Some(base) = base {
+        if base.value == name && builtin_theme(name).is_some() {
+            builtin_theme(name).unwrap()
+        } else {
+            load_theme(config, base.value.as_str(), base.line, depth + 1)?
+        }
+    } else {
+        builtin_theme("dark").unwrap()
+    };
+
+    for entry in section.entries.iter() {
+        if entry.name == "base" { continue; }
+
+        let element = ELEMENT_NAMES.iter().position(|element| *element == entry.name);
+        if element.is_none() {
+            return Err(format!("{}: unknown element '{}'", entry.line, entry.name));
+        }
+
+        let parsed = parse_style(entry.value.as_str());
+        if parsed.is_err() {
+            return Err(format!("{}: {}", entry.line, parsed.err().unwrap()));
+        }
+
+        theme.styles[element.unwrap()] = parsed.unwrap();
+    }
+
+    Ok(theme)
+}
+
+// NOTE(erick): 'FOREGROUND BACKGROUND [ATTRIBUTE ...]'
+fn parse_style(value: &str) -> Result<Style, String> {
+    let words = value.split_whitespace().collect::<Vec<_> >();
+    if words.len() < 2 {
+        return Err("expected 'foreground background [attributes]'".to_string());
+    }
+
+    let foreground = parse_color(words[0])?;
+    let background = parse_color(words[1])?;
+
+    let mut attributes = A_NORMAL();
+    for word in &words[2 ..] {
+        attributes |= match *word {
+            "bold"      => A_BOLD(),
+            "reverse"   => A_REVERSE(),
+            "underline" => A_UNDERLINE(),
+            _ => return Err(format!("unknown attribute '{}'", word)),
+        };
+    }
+
+    Ok(Style { foreground, background, attributes })
+}
+
+fn parse_color(name: &str) -> Result<i16, String> {
+    let color = match name {
+        "default" => COLOR_DEFAULT,
+        "black"   => COLOR_BLACK,
+        "red"     => COLOR_RED,
+        "green"   => COLOR_GREEN,
+        "yellow"  => COLOR_YELLOW,
+        "blue"    => COLOR_BLUE,
+        "magenta" => COLOR_MAGENTA,
+        "cyan"    => COLOR_CYAN,
+        "white"   => COLOR_WHITE,
+        _ => {
+            let number = name.parse::<i16>();
+            if number.is_err() || number.as_ref().unwrap() < &0 ||
+                number.as_ref().unwrap() > &255 {
+                return Err(format!("unknown color '{}'", name));
+            }
+            number.unwrap()
+        },
+    };
+
+    Ok(color)
+}
+
+// NOTE(erick): Themes may use any of 256 colors but the terminal may have
+// fewer, which is only known after start_color(). Bright colors fall back
+// to their normal versions and the rest to the terminal's default.
+fn fit_color(color: i16, available: i32) -> i16 {
+    if (color as i32) < available {
+        return color;
+    }
+    if (8 .. 16).contains(&color) && available >= 8 {
+        return color - 8;
+    }
+
+    COLOR_DEFAULT
+}
+
+struct ActiveTheme {
+    theme: Theme,
+    has_colors: bool,
+}
+
+static ACTIVE_THEME : OnceLock<ActiveTheme> = OnceLock::new();
+
+// NOTE(erick): Must be called after start_color().
+pub fn apply(theme: Theme) {
+    let mut theme = theme;
+    let has_colors = has_colors();
+    if has_colors {
+        for style in theme.styles.iter_mut() {
+            style.foreground = fit_color(style.foreground, COLORS());
+            style.background = fit_color(style.background, COLORS());
+        }
+
+
+        let uses_default = theme.styles.iter().any(|style| {
+            style.foreground == COLOR_DEFAULT || style.background == COLOR_DEFAULT
+        });
+        if uses_default {
+            use_default_colors();
+        }
+
+        for (index, style) in theme.styles.iter().enumerate() {
+            init_pair(index as i16 + 1, style.foreground, style.background);
+        }
+    }
+
+    let _ = ACTIVE_THEME.set(ActiveTheme { theme, has_colors });
+}
+
+pub fn attribute(color: i16) -> attr_t {
+    let active = ACTIVE_THEME.get();
+    if active.is_none() || !active.unwrap().has_colors {
+        return monochrome_attribute(color);
+    }
+
+    let style = active.unwrap().theme.styles[(color - 1) as usize];
+    COLOR_PAIR(color) | style.attributes
+}
+
+pub fn clear_window(win: WINDOW) {
+    change_to_color(win, NORMAL_COLOR);
+    wclear(win);
+    wrefresh(win);
+}
+
+#[inline]
+pub fn change_to_color(window: WINDOW, color: i16) {
+    wbkgd(window, attribute(color));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles() {
+        let style = parse_style("default 12 bold underline").unwrap();
+        assert_eq!((style.foreground, style.background), (COLOR_DEFAULT, 12));
+        assert_eq!(style.attributes, A_BOLD() | A_UNDERLINE());
+
+        assert!(parse_style("red").is_err());
+        assert!(parse_style("red 256").is_err());
+        assert!(parse_style("red -2").is_err());
+        assert!(parse_style("red blue blink").is_err());
+    }
+
+    #[test]
+    fn fitted_colors() {
+        assert_eq!(fit_color(COLOR_DEFAULT, 8), COLOR_DEFAULT);
+        assert_eq!(fit_color(COLOR_WHITE, 8), COLOR_WHITE);
+        assert_eq!(fit_color(9, 8), COLOR_RED);
+        assert_eq!(fit_color(9, 16), 9);
+        assert_eq!(fit_color(200, 16), COLOR_DEFAULT);
+        assert_eq!(fit_color(200, 256), 200);
+        assert_eq!(fit_color(12, 0), COLOR_DEFAULT);
+    }
+}
@@ -0,0 +1,160 @@
+use blur;
+use color;
+use image::Image;
+use operation::EdgeMode;
+use operation::Threshold;
+
+// NOTE(erick): Returns the black and white image and, for Otsu, the
+// level it found. Alpha is kept.
+pub fn threshold(image: &Image, threshold: &Threshold) -> (Image, Option<u8>) {
+    match *threshold {
+        Threshold::Fixed(level)           => (global(image, level), None),
+        Threshold::Otsu(_)                => {
+            let level = otsu_level(image);
+            (global(image, level), Some(level))
+        },
+        Threshold::Mean(size, offset)     => {
+            let gray = gray_image(image);
+            let mean = blur::box_blur(&gray, size / 2, EdgeMode::Clamp);
+            (adaptive(image, &gray, &mean, offset), None)
+        },
+        Threshold::Gaussian(size, offset) => {
+            let gray = gray_image(image);
+            let mean = blur::gaussian_blur(&gray, window_sigma(size), EdgeMode::Clamp);
+            (adaptive(image, &gray, &mean, offset), None)
+        },
+    }
+}
+
+#[inline]
+fn black_or_white(pixel: &mut [u8], white: bool) {
+    let value = if white { 255 } else { 0 };
+    pixel[0] = value;
+    pixel[1] = value;
+    pixel[2] = value;
+}
+
+fn global(image: &Image, level: u8) -> Image {
+    let mut result = image.clone();
+    for pixel in result.pixels.chunks_mut(4) {
+        let white = color::luma(pixel) > level;
+        black_or_white(pixel, white);
+    }
+
+    result
+}
+
+// NOTE(erick): The level that maximizes the variance between the pixels
+// at or below it and the ones above it.
+fn otsu_level(image: &Image) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels.chunks(4) {
+        histogram[color::luma(pixel) as usize] += 1;
+    }
+
+    let total = histogram.iter().sum::<u64>() as f64;
+    let total_sum = histogram.iter().enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum::<f64>();
+
+    let mut level = 0;
+    let mut best_variance = -1.0;
+    let mut below_count = 0.0;
+    let mut below_sum = 0.0;
+    for (value, &count) in histogram.iter().enumerate() {
+        below_count += count as f64;
+        below_sum += value as f64 * count as f64;
+        let above_count = total - below_count;
+        if below_count == 0.0 || above_count == 0.0 {
+            continue;
+        }
+
+        let below_mean = below_sum / below_count;
+        let above_mean = (total_sum - below_sum) / above_count;
+        let variance = below_count * above_count * (below_mean - above_mean).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            level = value as u8;
+        }
+    }
+
+    level
+}
+
+fn gray_image(image: &Image) -> Image {
+    let mut gray = image.clone();
+    for pixel in gray.pixels.chunks_mut(4) {
+        let value = color::luma(pixel);
+        pixel.copy_from_slice(&[value, value, value, 255]);
+    }
+
+    gray
+}
+
+// NOTE(erick): The standard deviation OpenCV uses for a Gaussian window
+// of this size.
+fn window_sigma(size: u32) -> f64 {
+    0.3 * ((size as f64 - 1.0) * 0.5 - 1.0) + 0.8
+}
+
+fn adaptive(image: &Image, gray: &Image, mean: &Image, offset: f64) -> Image {
+    let mut result = image.clone();
+    let pixels = result.pixels.chunks_mut(4)
+        .zip(gray.pixels.chunks(4))
+        .zip(mean.pixels.chunks(4));
+    for ((pixel, gray), mean) in pixels {
+        let white = gray[0] as f64 > mean[0] as f64 - offset;
+        black_or_white(pixel, white);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_values(width: u32, values: &[u8]) -> Image {
+        let mut image = Image::new(width, values.len() as u32 / width);
+        for (pixel, &value) in image.pixels.chunks_mut(4).zip(values.iter()) {
+            pixel.copy_from_slice(&[value, value, value, 128]);
+        }
+        image
+    }
+
+    fn whites(image: &Image) -> Vec<bool> {
+        image.pixels.chunks(4).map(|pixel| pixel[0] == 255).collect()
+    }
+
+    #[test]
+    fn cut_point() {
+        let image = gray_values(4, &[0, 100, 101, 255]);
+        let (result, level) = threshold(&image, &Threshold::Fixed(100));
+        assert_eq!(whites(&result), vec![false, false, true, true]);
+        assert_eq!(result.pixel(1, 0), [0, 0, 0, 128]);
+        assert_eq!(level, None);
+
+        let (result, _) = threshold(&image, &Threshold::Fixed(255));
+        assert_eq!(whites(&result), vec![false; 4]);
+
+        let image = gray_values(6, &[30, 200, 30, 30, 200, 30]);
+        let (result, level) = threshold(&image, &Threshold::Otsu(None));
+        assert_eq!(level, Some(30));
+        assert_eq!(whites(&result), vec![false, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn adaptive_windows() {
+        // NOTE(erick): A dot darker than its neighbours on a ramp.
+        let mut values = (0 .. 49).map(|i| 40 + (i % 7) as u8 * 30).collect::<Vec<_> >();
+        values[24] = 90;
+        let image = gray_values(7, &values);
+
+        for &kind in [Threshold::Mean(3, 5.0), Threshold::Gaussian(3, 5.0)].iter() {
+            let (result, _) = threshold(&image, &kind);
+            assert!(!whites(&result)[24]);
+            assert!(whites(&result)[23]);
+            assert_eq!(result.pixel(0, 0)[3], 128);
+        }
+    }
+}
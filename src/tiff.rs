@@ -0,0 +1,1157 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use config::Section;
+use image::Image;
+
+// NOTE(erick): Reference: TIFF Revision 6.0, baseline plus the LZW and
+// predictor parts of section 2.
+//
+// Only strips are read, tiles and the JPEG and CCITT compressions are
+// rejected. Pages are the IFDs in the main chain, reduced resolution
+// copies (thumbnails) are skipped.
+
+const TAG_NEW_SUBFILE_TYPE     : u16 = 254;
+const TAG_IMAGE_WIDTH          : u16 = 256;
+const TAG_IMAGE_LENGTH         : u16 = 257;
+const TAG_BITS_PER_SAMPLE      : u16 = 258;
+const TAG_COMPRESSION          : u16 = 259;
+const TAG_PHOTOMETRIC          : u16 = 262;
+const TAG_STRIP_OFFSETS        : u16 = 273;
+const TAG_ORIENTATION          : u16 = 274;
+const TAG_SAMPLES_PER_PIXEL    : u16 = 277;
+const TAG_ROWS_PER_STRIP       : u16 = 278;
+const TAG_STRIP_BYTE_COUNTS    : u16 = 279;
+const TAG_X_RESOLUTION         : u16 = 282;
+const TAG_Y_RESOLUTION         : u16 = 283;
+const TAG_PLANAR_CONFIGURATION : u16 = 284;
+const TAG_RESOLUTION_UNIT      : u16 = 296;
+const TAG_PAGE_NUMBER          : u16 = 297;
+const TAG_PREDICTOR            : u16 = 317;
+const TAG_COLOR_MAP            : u16 = 320;
+const TAG_TILE_WIDTH           : u16 = 322;
+const TAG_EXTRA_SAMPLES        : u16 = 338;
+const TAG_SAMPLE_FORMAT        : u16 = 339;
+
+const TYPE_BYTE      : u16 = 1;
+const TYPE_SHORT     : u16 = 3;
+const TYPE_LONG      : u16 = 4;
+const TYPE_RATIONAL  : u16 = 5;
+const TYPE_UNDEFINED : u16 = 7;
+
+const COMPRESSION_NONE     : u16 = 1;
+const COMPRESSION_LZW      : u16 = 5;
+const COMPRESSION_PACKBITS : u16 = 32773;
+
+const PHOTOMETRIC_WHITE_IS_ZERO : u16 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO : u16 = 1;
+const PHOTOMETRIC_RGB           : u16 = 2;
+const PHOTOMETRIC_PALETTE       : u16 = 3;
+
+const EXTRA_SAMPLE_ASSOCIATED_ALPHA   : u32 = 1;
+const EXTRA_SAMPLE_UNASSOCIATED_ALPHA : u32 = 2;
+
+const PREDICTOR_HORIZONTAL : u16 = 2;
+
+const LZW_CLEAR : usize = 256;
+const LZW_END : usize = 257;
+const LZW_MAX_CODE_SIZE : u32 = 12;
+const LZW_MAX_CODES : usize = 1 << LZW_MAX_CODE_SIZE;
+
+// NOTE(erick): How many bytes one compressed byte can become at most. A
+// PackBits run is two bytes for 128, an LZW code is at least 9 bits for
+// at most LZW_MAX_CODES bytes. The strips are checked against these
+// before the planes are allocated.
+const PACKBITS_MAX_EXPANSION : usize = 64;
+const LZW_MAX_EXPANSION : usize = LZW_MAX_CODES * 8 / 9;
+
+const MAX_PIXELS : usize = 400_000_000;
+
+// NOTE(erick): Written strips hold about this many bytes before
+// compression, which is what the specification recommends.
+const STRIP_SIZE : usize = 8 * 1024;
+
+pub struct TiffOptions {
+    pub compression: u16,
+    pub bits_per_sample: u8,
+}
+
+impl TiffOptions {
+    pub fn default() -> TiffOptions {
+        TiffOptions { compression: COMPRESSION_LZW, bits_per_sample: 8 }
+    }
+
+    // NOTE(erick): [tiff]
+    //              compression = lzw | packbits | none
+    //              bits = 8 | 16
+    pub fn from_section(section: Option<&Section>) -> Result<TiffOptions, String> {
+        let mut result = TiffOptions::default();
+        if section.is_none() {
+            return Ok(result);
+        }
+
+        for entry in section.unwrap().entries.iter() {
+            match (entry.name.as_str(), entry.value.as_str()) {
+                ("compression", "lzw")      => { result.compression = COMPRESSION_LZW; },
+                ("compression", "packbits") => { result.compression = COMPRESSION_PACKBITS; },
+                ("compression", "none")     => { result.compression = COMPRESSION_NONE; },
+                ("bits", "8")  => { result.bits_per_sample = 8; },
+                ("bits", "16") => { result.bits_per_sample = 16; },
+                ("compression", _) | ("bits", _) => {
+                    return Err(format!("{}: invalid value '{}' for '{}'",
+                                       entry.line, entry.value, entry.name));
+                },
+                _ => return Err(format!("{}: unknown option '{}'", entry.line, entry.name)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+static TIFF_OPTIONS : OnceLock<TiffOptions> = OnceLock::new();
+
+pub fn set_options(options: TiffOptions) {
+    let _ = TIFF_OPTIONS.set(options);
+}
+
+pub fn options() -> &'static TiffOptions {
+    TIFF_OPTIONS.get_or_init(TiffOptions::default)
+}
+
+pub fn is_tiff(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*")
+}
+
+fn truncated() -> String {
+    "TIFF: unexpected end of file".to_string()
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.bytes.get(offset .. offset + 2).ok_or_else(truncated)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.bytes.get(offset .. offset + 4).ok_or_else(truncated)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
+
+// NOTE(erick): 'offset' is where the values are, inside the entry when
+// they fit in 4 bytes.
+struct Field {
+    field_type: u16,
+    count: usize,
+    offset: usize,
+}
+
+type Fields = HashMap<u16, Field>;
+
+fn reader<'a>(bytes: &'a [u8]) -> Result<Reader<'a>, String> {
+    if !is_tiff(bytes) {
+        return Err("TIFF: bad signature".to_string());
+    }
+    Ok(Reader { bytes, big_endian: bytes[0] == b'M' })
+}
+
+// NOTE(erick): Returns the fields of the IFD at 'offset' and the offset
+// of the next one, 0 for the last.
+fn read_ifd(reader: &Reader, offset: usize) -> Result<(Fields, usize), String> {
+    let count = reader.u16(offset)? as usize;
+    let mut fields = HashMap::new();
+    for index in 0 .. count {
+        let entry = offset + 2 + index * 12;
+        let tag = reader.u16(entry)?;
+        let field_type = reader.u16(entry + 2)?;
+        let value_count = reader.u32(entry + 4)? as usize;
+        let size = match field_type {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8         => 2,
+            4 | 9 | 11    => 4,
+            5 | 10 | 12   => 8,
+            _             => continue,
+        };
+        let value_offset = if size * value_count <= 4 {
+            entry + 8
+        } else {
+            reader.u32(entry + 8)? as usize
+        };
+        fields.insert(tag, Field { field_type, count: value_count, offset: value_offset });
+    }
+
+    let next = reader.u32(offset + 2 + count * 12)? as usize;
+    Ok((fields, next))
+}
+
+fn values(reader: &Reader, fields: &Fields, tag: u16) -> Result<Option<Vec<u32>>, String> {
+    let field = fields.get(&tag);
+    if field.is_none() {
+        return Ok(None);
+    }
+
+    // NOTE(erick): Callers index the first value, a tag with no values is
+    // as broken as a missing one.
+    let field = field.unwrap();
+    if field.count == 0 {
+        return Err(format!("TIFF: tag {} has no values", tag));
+    }
+
+    let mut result = Vec::with_capacity(field.count.min(reader.bytes.len()));
+    for index in 0 .. field.count {
+        let value = match field.field_type {
+            TYPE_BYTE | TYPE_UNDEFINED => {
+                *reader.bytes.get(field.offset + index).ok_or_else(truncated)? as u32
+            },
+            TYPE_SHORT => reader.u16(field.offset + index * 2)? as u32,
+            TYPE_LONG  => reader.u32(field.offset + index * 4)?,
+            _ => return Err(format!("TIFF: unexpected type for tag {}", tag)),
+        };
+        result.push(value);
+    }
+
+    Ok(Some(result))
+}
+
+fn value(reader: &Reader, fields: &Fields, tag: u16, default: u32) -> Result<u32, String> {
+    let values = values(reader, fields, tag)?;
+    Ok(values.and_then(|values| values.first().cloned()).unwrap_or(default))
+}
+
+// NOTE(erick): The IFD offsets of every page, in order.
+fn page_offsets(reader: &Reader) -> Result<Vec<usize>, String> {
+    let mut offsets = Vec::new();
+    let mut visited = HashSet::new();
+    let mut offset = reader.u32(4)? as usize;
+    while offset != 0 && visited.insert(offset) {
+        let (fields, next) = read_ifd(reader, offset)?;
+        let subfile_type = value(reader, &fields, TAG_NEW_SUBFILE_TYPE, 0)?;
+        if subfile_type & 1 == 0 {
+            offsets.push(offset);
+        }
+        offset = next;
+    }
+
+    Ok(offsets)
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let reader = reader(bytes).ok()?;
+    let offset = reader.u32(4).ok()? as usize;
+    let (fields, _) = read_ifd(&reader, offset).ok()?;
+    let width = value(&reader, &fields, TAG_IMAGE_WIDTH, 0).ok()?;
+    let height = value(&reader, &fields, TAG_IMAGE_LENGTH, 0).ok()?;
+    let orientation = value(&reader, &fields, TAG_ORIENTATION, 1).ok()?;
+    if (5 ..= 8).contains(&orientation) {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+pub fn page_count(bytes: &[u8]) -> Result<usize, String> {
+    let reader = reader(bytes)?;
+    Ok(page_offsets(&reader)?.len())
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    decode_page(bytes, 0)
+}
+
+struct Page {
+    width: usize,
+    height: usize,
+    bits_per_sample: usize,
+    samples_per_pixel: usize,
+    compression: u16,
+    photometric: u16,
+    is_planar: bool,
+    predictor: u16,
+    orientation: u16,
+    rows_per_strip: usize,
+    strip_offsets: Vec<u32>,
+    strip_byte_counts: Option<Vec<u32>>,
+    color_map: Vec<u32>,
+    alpha: Option<u32>,
+}
+
+pub fn decode_page(bytes: &[u8], wanted: usize) -> Result<Image, String> {
+    let reader = reader(bytes)?;
+    let offsets = page_offsets(&reader)?;
+    if wanted >= offsets.len() {
+        return Err(format!("TIFF: no page {}", wanted + 1));
+    }
+
+    let (fields, _) = read_ifd(&reader, offsets[wanted])?;
+    let page = read_page(&reader, &fields)?;
+
+    let planes = read_planes(&reader, &page)?;
+    let color_channels = if page.photometric == PHOTOMETRIC_RGB { 3 } else { 1 };
+    let samples_in_plane = if page.is_planar { 1 } else { page.samples_per_pixel };
+    let row_bytes = row_bytes(&page)?;
+    let bits = page.bits_per_sample;
+    let big_endian = reader.big_endian;
+
+    let channel = |x: usize, y: usize, channel: usize| -> u32 {
+        if page.is_planar {
+            sample(&planes[channel], row_bytes, bits, big_endian, x, y)
+        } else {
+            sample(&planes[0], row_bytes, bits, big_endian, x * samples_in_plane + channel, y)
+        }
+    };
+
+    let mut image = Image::new(page.width as u32, page.height as u32);
+    let palette_size = 1 << bits;
+    for y in 0 .. page.height {
+        for x in 0 .. page.width {
+            let mut pixel = match page.photometric {
+                PHOTOMETRIC_WHITE_IS_ZERO => {
+                    let gray = 255 - to_u8(channel(x, y, 0), bits);
+                    [gray, gray, gray, 255]
+                },
+                PHOTOMETRIC_BLACK_IS_ZERO => {
+                    let gray = to_u8(channel(x, y, 0), bits);
+                    [gray, gray, gray, 255]
+                },
+                PHOTOMETRIC_RGB => [to_u8(channel(x, y, 0), bits),
+                                    to_u8(channel(x, y, 1), bits),
+                                    to_u8(channel(x, y, 2), bits), 255],
+                _ => {
+                    let index = channel(x, y, 0) as usize;
+                    [(page.color_map[index] >> 8) as u8,
+                     (page.color_map[palette_size + index] >> 8) as u8,
+                     (page.color_map[2 * palette_size + index] >> 8) as u8, 255]
+                },
+            };
+
+            if page.alpha.is_some() {
+                let alpha = to_u8(channel(x, y, color_channels), bits);
+                pixel[3] = alpha;
+                if page.alpha == Some(EXTRA_SAMPLE_ASSOCIATED_ALPHA) && alpha != 0 {
+                    for value in pixel[0 .. 3].iter_mut() {
+                        *value = (*value as u32 * 255 / alpha as u32).min(255) as u8;
+                    }
+                }
+            }
+
+            image.set_pixel(x as u32, y as u32, pixel);
+        }
+    }
+
+    Ok(image.oriented(page.orientation))
+}
+
+fn read_page(reader: &Reader, fields: &Fields) -> Result<Page, String> {
+    if fields.contains_key(&TAG_TILE_WIDTH) {
+        return Err("TIFF: tiled images are not supported".to_string());
+    }
+
+    let width = value(reader, fields, TAG_IMAGE_WIDTH, 0)? as usize;
+    let height = value(reader, fields, TAG_IMAGE_LENGTH, 0)? as usize;
+    if width == 0 || height == 0 {
+        return Err("TIFF: missing image size".to_string());
+    }
+
+    let pixels = width.checked_mul(height);
+    if pixels.is_none() || pixels.unwrap() > MAX_PIXELS {
+        return Err(format!("TIFF: {}x{} is too big", width, height));
+    }
+
+    let samples_per_pixel = value(reader, fields, TAG_SAMPLES_PER_PIXEL, 1)? as usize;
+    if !(1 ..= 4).contains(&samples_per_pixel) {
+        return Err(format!("TIFF: {} samples per pixel are not supported", samples_per_pixel));
+    }
+    let bits = values(reader, fields, TAG_BITS_PER_SAMPLE)?.unwrap_or_else(|| vec![1]);
+    if bits.iter().any(|&value| value != bits[0]) {
+        return Err("TIFF: samples of different sizes are not supported".to_string());
+    }
+    let bits_per_sample = bits[0] as usize;
+    if ![1, 2, 4, 8, 16].contains(&bits_per_sample) {
+        return Err(format!("TIFF: {} bit samples are not supported", bits_per_sample));
+    }
+    if value(reader, fields, TAG_SAMPLE_FORMAT, 1)? != 1 {
+        return Err("TIFF: only unsigned integer samples are supported".to_string());
+    }
+
+    let compression = value(reader, fields, TAG_COMPRESSION, COMPRESSION_NONE as u32)? as u16;
+    if ![COMPRESSION_NONE, COMPRESSION_LZW, COMPRESSION_PACKBITS].contains(&compression) {
+        return Err(format!("TIFF: compression {} is not supported", compression));
+    }
+
+    let photometric = value(reader, fields, TAG_PHOTOMETRIC, PHOTOMETRIC_BLACK_IS_ZERO as u32)? as u16;
+    let color_channels = match photometric {
+        PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO | PHOTOMETRIC_PALETTE => 1,
+        PHOTOMETRIC_RGB => 3,
+        _ => return Err(format!("TIFF: photometric interpretation {} is not supported",
+                                photometric)),
+    };
+    if samples_per_pixel < color_channels {
+        return Err("TIFF: too few samples per pixel".to_string());
+    }
+    if photometric == PHOTOMETRIC_RGB && bits_per_sample < 8 {
+        return Err(format!("TIFF: {} bit RGB is not supported", bits_per_sample));
+    }
+
+    let color_map = if photometric == PHOTOMETRIC_PALETTE {
+        let color_map = values(reader, fields, TAG_COLOR_MAP)?.unwrap_or_default();
+        if color_map.len() < 3 << bits_per_sample {
+            return Err("TIFF: bad color map".to_string());
+        }
+        color_map
+    } else {
+        Vec::new()
+    };
+
+    // NOTE(erick): Only the first extra sample can be alpha, the others
+    // are ignored. Unspecified extra samples aren't alpha either.
+    let extra_samples = values(reader, fields, TAG_EXTRA_SAMPLES)?.unwrap_or_default();
+    let alpha = if samples_per_pixel > color_channels {
+        extra_samples.first().cloned()
+            .filter(|&kind| kind == EXTRA_SAMPLE_ASSOCIATED_ALPHA ||
+                            kind == EXTRA_SAMPLE_UNASSOCIATED_ALPHA)
+    } else {
+        None
+    };
+
+    let predictor = value(reader, fields, TAG_PREDICTOR, 1)? as u16;
+    if predictor != 1 && predictor != PREDICTOR_HORIZONTAL {
+        return Err(format!("TIFF: predictor {} is not supported", predictor));
+    }
+
+    let strip_offsets = values(reader, fields, TAG_STRIP_OFFSETS)?;
+    if strip_offsets.is_none() {
+        return Err("TIFF: missing strip offsets".to_string());
+    }
+
+    let rows_per_strip = value(reader, fields, TAG_ROWS_PER_STRIP, u32::MAX)? as usize;
+    Ok(Page {
+        width,
+        height,
+        bits_per_sample,
+        samples_per_pixel,
+        compression,
+        photometric,
+        is_planar: value(reader, fields, TAG_PLANAR_CONFIGURATION, 1)? == 2,
+        predictor,
+        orientation: value(reader, fields, TAG_ORIENTATION, 1)? as u16,
+        rows_per_strip: if rows_per_strip == 0 { height } else { rows_per_strip.min(height) },
+        strip_offsets: strip_offsets.unwrap(),
+        strip_byte_counts: values(reader, fields, TAG_STRIP_BYTE_COUNTS)?,
+        color_map,
+        alpha,
+    })
+}
+
+fn too_big() -> String {
+    "TIFF: the image is too big for the file".to_string()
+}
+
+fn row_bytes(page: &Page) -> Result<usize, String> {
+    let samples_in_plane = if page.is_planar { 1 } else { page.samples_per_pixel };
+    let bits = page.width.checked_mul(samples_in_plane * page.bits_per_sample);
+    if bits.is_none() {
+        return Err(too_big());
+    }
+
+    Ok(bits.unwrap().div_ceil(8))
+}
+
+// NOTE(erick): Decompresses the strips into one buffer per plane. Chunky
+// images have a single plane with every sample interleaved.
+fn read_planes(reader: &Reader, page: &Page) -> Result<Vec<Vec<u8>>, String> {
+    let plane_count = if page.is_planar { page.samples_per_pixel } else { 1 };
+    let samples_in_plane = if page.is_planar { 1 } else { page.samples_per_pixel };
+    let row_bytes = row_bytes(page)?;
+    let strips_per_plane = page.height.div_ceil(page.rows_per_strip);
+    if page.strip_offsets.len() < strips_per_plane * plane_count {
+        return Err("TIFF: missing strips".to_string());
+    }
+
+    // NOTE(erick): Uncompressed files without byte counts can't hold more
+    // than the whole file.
+    let plane_size = row_bytes.checked_mul(page.height).ok_or_else(too_big)?;
+    let stored = match page.strip_byte_counts {
+        Some(ref counts) => counts.iter()
+            .fold(0usize, |sum, &count| sum.saturating_add(count as usize)),
+        None             => reader.bytes.len(),
+    };
+    let expansion = match page.compression {
+        COMPRESSION_LZW      => LZW_MAX_EXPANSION,
+        COMPRESSION_PACKBITS => PACKBITS_MAX_EXPANSION,
+        _                    => 1,
+    };
+    let total = plane_size.checked_mul(plane_count);
+    if total.is_none() || total.unwrap() > stored.saturating_mul(expansion) {
+        return Err(too_big());
+    }
+
+    let mut planes = Vec::with_capacity(plane_count);
+    for plane in 0 .. plane_count {
+        let mut data = Vec::with_capacity(plane_size);
+        for strip in 0 .. strips_per_plane {
+            let index = plane * strips_per_plane + strip;
+            let rows = page.rows_per_strip.min(page.height - strip * page.rows_per_strip);
+            let expected = rows * row_bytes;
+
+            // NOTE(erick): Old writers leave the byte counts out of
+            // uncompressed files, the size is known anyway.
+            let start = page.strip_offsets[index] as usize;
+            let count = match page.strip_byte_counts {
+                Some(ref counts) if index < counts.len() => counts[index] as usize,
+                _ if page.compression == COMPRESSION_NONE => expected,
+                _ => return Err("TIFF: missing strip byte counts".to_string()),
+            };
+            let end = start.saturating_add(count).min(reader.bytes.len());
+            let compressed = reader.bytes.get(start .. end).ok_or_else(truncated)?;
+
+            let mut decoded = match page.compression {
+                COMPRESSION_LZW      => lzw_decode(compressed, expected)?,
+                COMPRESSION_PACKBITS => packbits_decode(compressed, expected),
+                _                    => compressed[.. compressed.len().min(expected)].to_vec(),
+            };
+            decoded.resize(expected, 0);
+
+            if page.predictor == PREDICTOR_HORIZONTAL {
+                undo_predictor(&mut decoded, row_bytes, samples_in_plane,
+                               page.bits_per_sample, reader.big_endian);
+            }
+            data.extend_from_slice(&decoded);
+        }
+        planes.push(data);
+    }
+
+    Ok(planes)
+}
+
+// NOTE(erick): 'index' counts samples from the start of the row.
+#[inline]
+fn sample(data: &[u8], row_bytes: usize, bits: usize, big_endian: bool,
+          index: usize, y: usize) -> u32 {
+    let row = y * row_bytes;
+    match bits {
+        8  => data[row + index] as u32,
+        16 => {
+            let bytes = [data[row + index * 2], data[row + index * 2 + 1]];
+            if big_endian {
+                u16::from_be_bytes(bytes) as u32
+            } else {
+                u16::from_le_bytes(bytes) as u32
+            }
+        },
+        _  => {
+            let bit = index * bits;
+            let byte = data[row + bit / 8] as u32;
+            (byte >> (8 - bits - bit % 8)) & ((1 << bits) - 1)
+        },
+    }
+}
+
+#[inline]
+fn to_u8(value: u32, bits: usize) -> u8 {
+    match bits {
+        16 => (value >> 8) as u8,
+        8  => value as u8,
+        _  => (value * 255 / ((1 << bits) - 1)) as u8,
+    }
+}
+
+// NOTE(erick): Every sample is stored as the difference from the same
+// sample of the pixel to its left. Only defined for 8 and 16 bits.
+fn undo_predictor(data: &mut [u8], row_bytes: usize, samples_per_pixel: usize,
+                  bits: usize, big_endian: bool) {
+    for row in data.chunks_mut(row_bytes) {
+        match bits {
+            8 => {
+                for i in samples_per_pixel .. row.len() {
+                    row[i] = row[i].wrapping_add(row[i - samples_per_pixel]);
+                }
+            },
+            16 => {
+                let read = |row: &[u8], i: usize| {
+                    let bytes = [row[i * 2], row[i * 2 + 1]];
+                    if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+                };
+                for i in samples_per_pixel .. row.len() / 2 {
+                    let value = read(row, i).wrapping_add(read(row, i - samples_per_pixel));
+                    let bytes = if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+                    row[i * 2 .. i * 2 + 2].copy_from_slice(&bytes);
+                }
+            },
+            _ => { },
+        }
+    }
+}
+
+fn packbits_decode(data: &[u8], expected: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(expected);
+    let mut position = 0;
+    while position < data.len() && output.len() < expected {
+        let header = data[position] as i8;
+        position += 1;
+        match header {
+            0 ..= 127 => {
+                let end = (position + header as usize + 1).min(data.len());
+                output.extend_from_slice(&data[position .. end]);
+                position = end;
+            },
+            -127 ..= -1 if position < data.len() => {
+                let count = 1 - header as isize;
+                output.extend(std::iter::repeat_n(data[position], count as usize));
+                position += 1;
+            },
+            _ => { },
+        }
+    }
+
+    output.truncate(expected);
+    output
+}
+
+// NOTE(erick): Unlike GIF, codes are packed from the most significant bit
+// and the code size grows one code early.
+fn lzw_decode(data: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    let mut prefix = vec![0u16; LZW_MAX_CODES];
+    let mut suffix = vec![0u8; LZW_MAX_CODES];
+    let mut length = vec![0u16; LZW_MAX_CODES];
+    for code in 0 .. LZW_CLEAR {
+        suffix[code] = code as u8;
+        length[code] = 1;
+    }
+
+    let mut output = Vec::with_capacity(expected);
+    let mut size = 9;
+    let mut next = LZW_END + 1;
+    let mut previous: Option<usize> = None;
+
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut position = 0;
+    while output.len() < expected {
+        while bit_count < size && position < data.len() {
+            bits = bits << 8 | data[position] as u32;
+            bit_count += 8;
+            position += 1;
+        }
+        if bit_count < size {
+            break;
+        }
+
+        let code = ((bits >> (bit_count - size)) & ((1 << size) - 1)) as usize;
+        bit_count -= size;
+
+        if code == LZW_CLEAR {
+            size = 9;
+            next = LZW_END + 1;
+            previous = None;
+            continue;
+        }
+        if code == LZW_END {
+            break;
+        }
+
+        if previous.is_none() {
+            if code > LZW_CLEAR {
+                return Err("TIFF: invalid LZW code".to_string());
+            }
+            output.push(code as u8);
+            previous = Some(code);
+            continue;
+        }
+
+        let previous_code = previous.unwrap();
+        if code > next || (code == next && next == LZW_MAX_CODES) {
+            return Err("TIFF: invalid LZW code".to_string());
+        }
+
+        let string_code = if code == next { previous_code } else { code };
+        let start = output.len();
+        let string_length = length[string_code] as usize;
+        output.resize(start + string_length, 0);
+        let mut current = string_code;
+        for i in (0 .. string_length).rev() {
+            output[start + i] = suffix[current];
+            current = prefix[current] as usize;
+        }
+        let first = output[start];
+        if code == next {
+            output.push(first);
+        }
+
+        if next < LZW_MAX_CODES {
+            prefix[next] = previous_code as u16;
+            suffix[next] = first;
+            length[next] = length[previous_code] + 1;
+            next += 1;
+            if next == (1 << size) - 1 && size < LZW_MAX_CODE_SIZE {
+                size += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    output.truncate(expected);
+    Ok(output)
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: usize, size: u32) {
+        self.bits = self.bits << size | code as u32;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push((self.bits >> (self.bit_count - 8)) as u8);
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push((self.bits << (8 - self.bit_count)) as u8);
+        }
+        self.bytes
+    }
+}
+
+// NOTE(erick): Follows libtiff: the table starts over two codes before
+// it's full, and the code after the last one is counted as if it was
+// added, because decoders add an entry for the last code too.
+fn lzw_encode(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { bytes: Vec::new(), bits: 0, bit_count: 0 };
+    let mut table: HashMap<(usize, u8), usize> = HashMap::new();
+    let mut size = 9;
+    let mut next = LZW_END + 1;
+
+    writer.write(LZW_CLEAR, size);
+    if data.is_empty() {
+        writer.write(LZW_END, size);
+        return writer.finish();
+    }
+
+    let mut current = data[0] as usize;
+    for &byte in &data[1 ..] {
+        if let Some(&entry) = table.get(&(current, byte)) {
+            current = entry;
+            continue;
+        }
+
+        writer.write(current, size);
+        table.insert((current, byte), next);
+        next += 1;
+        if next == LZW_MAX_CODES - 2 {
+            writer.write(LZW_CLEAR, size);
+            table.clear();
+            size = 9;
+            next = LZW_END + 1;
+        } else if next > (1 << size) - 1 {
+            size += 1;
+        }
+        current = byte as usize;
+    }
+
+    writer.write(current, size);
+    next += 1;
+    if next > (1 << size) - 1 && size < LZW_MAX_CODE_SIZE {
+        size += 1;
+    }
+    writer.write(LZW_END, size);
+    writer.finish()
+}
+
+// NOTE(erick): Rows are packed on their own, runs don't cross them.
+fn packbits_encode(data: &[u8], row_bytes: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 128 + 1);
+    for row in data.chunks(row_bytes) {
+        let mut position = 0;
+        while position < row.len() {
+            let mut run = 1;
+            while position + run < row.len() && run < 128 && row[position + run] == row[position] {
+                run += 1;
+            }
+            if run >= 3 {
+                output.push((1 - run as isize) as u8);
+                output.push(row[position]);
+                position += run;
+                continue;
+            }
+
+            let start = position;
+            while position < row.len() && position - start < 128 {
+                let starts_run = position + 2 < row.len() &&
+                    row[position] == row[position + 1] && row[position] == row[position + 2];
+                if starts_run {
+                    break;
+                }
+                position += 1;
+            }
+            output.push((position - start - 1) as u8);
+            output.extend_from_slice(&row[start .. position]);
+        }
+    }
+    output
+}
+
+fn apply_predictor(data: &mut [u8], row_bytes: usize, samples_per_pixel: usize, bits: usize) {
+    for row in data.chunks_mut(row_bytes) {
+        if bits == 16 {
+            for i in (samples_per_pixel .. row.len() / 2).rev() {
+                let value = u16::from_le_bytes([row[i * 2], row[i * 2 + 1]]);
+                let left_index = (i - samples_per_pixel) * 2;
+                let left = u16::from_le_bytes([row[left_index], row[left_index + 1]]);
+                row[i * 2 .. i * 2 + 2].copy_from_slice(&value.wrapping_sub(left).to_le_bytes());
+            }
+        } else {
+            for i in (samples_per_pixel .. row.len()).rev() {
+                row[i] = row[i].wrapping_sub(row[i - samples_per_pixel]);
+            }
+        }
+    }
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+fn shorts(tag: u16, values: &[u16]) -> IfdEntry {
+    let value = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    IfdEntry { tag, field_type: TYPE_SHORT, count: values.len() as u32, value }
+}
+
+fn longs(tag: u16, values: &[u32]) -> IfdEntry {
+    let value = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    IfdEntry { tag, field_type: TYPE_LONG, count: values.len() as u32, value }
+}
+
+fn rational(tag: u16, numerator: u32, denominator: u32) -> IfdEntry {
+    let mut value = numerator.to_le_bytes().to_vec();
+    value.extend_from_slice(&denominator.to_le_bytes());
+    IfdEntry { tag, field_type: TYPE_RATIONAL, count: 1, value }
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    encode_pages(&[image])
+}
+
+// NOTE(erick): Files are little endian with every IFD before the strips
+// it points to, so the size of the first page is near the start of the
+// file for the browser.
+pub fn encode_pages(images: &[&Image]) -> Vec<u8> {
+    let mut output = b"II*\0\0\0\0\0".to_vec();
+    let mut next_pointer = 4;
+    for (number, image) in images.iter().enumerate() {
+        if output.len() % 2 == 1 {
+            output.push(0);
+        }
+        let offset = output.len() as u32;
+        output[next_pointer .. next_pointer + 4].copy_from_slice(&offset.to_le_bytes());
+
+        let page = if images.len() > 1 { Some((number, images.len())) } else { None };
+        next_pointer = write_page(&mut output, image, page);
+    }
+    output
+}
+
+// NOTE(erick): Gray images are written as gray and images with few enough
+// colors get a palette, when nothing is lost that way. Returns where the
+// offset of the next IFD goes.
+fn write_page(output: &mut Vec<u8>, image: &Image, page: Option<(usize, usize)>) -> usize {
+    let options = options();
+    let bits = options.bits_per_sample as usize;
+    let is_gray = image.pixels.chunks(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let has_alpha = image.pixels.chunks(4).any(|pixel| pixel[3] != 255);
+    let palette = if !is_gray && !has_alpha && bits == 8 { exact_palette(image) } else { None };
+
+    let (photometric, color_channels) = if palette.is_some() {
+        (PHOTOMETRIC_PALETTE, 1)
+    } else if is_gray {
+        (PHOTOMETRIC_BLACK_IS_ZERO, 1)
+    } else {
+        (PHOTOMETRIC_RGB, 3)
+    };
+    let samples_per_pixel = color_channels + if has_alpha { 1 } else { 0 };
+
+    let mut samples = Vec::with_capacity(image.pixels.len() * bits / 8);
+    for pixel in image.pixels.chunks(4) {
+        if let Some(ref palette) = palette {
+            samples.push(palette[&[pixel[0], pixel[1], pixel[2]]]);
+            continue;
+        }
+
+        let channels = if is_gray { &[0, 3][.. samples_per_pixel] } else { &[0, 1, 2, 3][.. samples_per_pixel] };
+        for &channel in channels {
+            if bits == 16 {
+                samples.extend_from_slice(&(pixel[channel] as u16 * 257).to_le_bytes());
+            } else {
+                samples.push(pixel[channel]);
+            }
+        }
+    }
+
+    let row_bytes = image.width as usize * samples_per_pixel * bits / 8;
+    let rows_per_strip = (STRIP_SIZE / row_bytes.max(1)).clamp(1, image.height.max(1) as usize);
+    let use_predictor = options.compression == COMPRESSION_LZW && palette.is_none();
+    let strips = samples.chunks(rows_per_strip * row_bytes.max(1)).map(|strip| {
+        match options.compression {
+            COMPRESSION_LZW if use_predictor => {
+                let mut strip = strip.to_vec();
+                apply_predictor(&mut strip, row_bytes, samples_per_pixel, bits);
+                lzw_encode(&strip)
+            },
+            COMPRESSION_LZW      => lzw_encode(strip),
+            COMPRESSION_PACKBITS => packbits_encode(strip, row_bytes),
+            _                    => strip.to_vec(),
+        }
+    }).collect::<Vec<_> >();
+
+    let mut entries = Vec::new();
+    if page.is_some() {
+        entries.push(longs(TAG_NEW_SUBFILE_TYPE, &[2]));
+    }
+    entries.push(longs(TAG_IMAGE_WIDTH, &[image.width]));
+    entries.push(longs(TAG_IMAGE_LENGTH, &[image.height]));
+    entries.push(shorts(TAG_BITS_PER_SAMPLE, &vec![bits as u16; samples_per_pixel]));
+    entries.push(shorts(TAG_COMPRESSION, &[options.compression]));
+    entries.push(shorts(TAG_PHOTOMETRIC, &[photometric]));
+    entries.push(longs(TAG_STRIP_OFFSETS, &vec![0; strips.len()]));
+    entries.push(shorts(TAG_SAMPLES_PER_PIXEL, &[samples_per_pixel as u16]));
+    entries.push(longs(TAG_ROWS_PER_STRIP, &[rows_per_strip as u32]));
+    entries.push(longs(TAG_STRIP_BYTE_COUNTS,
+                       &strips.iter().map(|strip| strip.len() as u32).collect::<Vec<_> >()));
+    entries.push(rational(TAG_X_RESOLUTION, 72, 1));
+    entries.push(rational(TAG_Y_RESOLUTION, 72, 1));
+    entries.push(shorts(TAG_PLANAR_CONFIGURATION, &[1]));
+    entries.push(shorts(TAG_RESOLUTION_UNIT, &[2]));
+    if let Some((number, total)) = page {
+        entries.push(shorts(TAG_PAGE_NUMBER, &[number as u16, total as u16]));
+    }
+    if use_predictor {
+        entries.push(shorts(TAG_PREDICTOR, &[PREDICTOR_HORIZONTAL]));
+    }
+    if let Some(ref palette) = palette {
+        let mut color_map = vec![0u16; 3 * 256];
+        for (color, &index) in palette.iter() {
+            for channel in 0 .. 3 {
+                color_map[channel * 256 + index as usize] = color[channel] as u16 * 257;
+            }
+        }
+        entries.push(shorts(TAG_COLOR_MAP, &color_map));
+    }
+    if has_alpha {
+        entries.push(shorts(TAG_EXTRA_SAMPLES, &[EXTRA_SAMPLE_UNASSOCIATED_ALPHA as u16]));
+    }
+
+    // NOTE(erick): Values that don't fit in their entry go right after
+    // the IFD, padded to even offsets, and the strips after them.
+    let ifd_offset = output.len();
+    let ifd_size = 2 + entries.len() * 12 + 4;
+    let values_size = entries.iter()
+        .filter(|entry| entry.value.len() > 4)
+        .map(|entry| entry.value.len() + entry.value.len() % 2)
+        .sum::<usize>();
+    let mut strip_offset = (ifd_offset + ifd_size + values_size) as u32;
+    let offsets = strips.iter().map(|strip| {
+        let offset = strip_offset;
+        strip_offset += strip.len() as u32;
+        offset
+    }).collect::<Vec<_> >();
+    for entry in entries.iter_mut().filter(|entry| entry.tag == TAG_STRIP_OFFSETS) {
+        *entry = longs(TAG_STRIP_OFFSETS, &offsets);
+    }
+
+    let mut values = Vec::with_capacity(values_size);
+    output.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for entry in entries.iter() {
+        output.extend_from_slice(&entry.tag.to_le_bytes());
+        output.extend_from_slice(&entry.field_type.to_le_bytes());
+        output.extend_from_slice(&entry.count.to_le_bytes());
+        if entry.value.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[.. entry.value.len()].copy_from_slice(&entry.value);
+            output.extend_from_slice(&inline);
+        } else {
+            let offset = (ifd_offset + ifd_size + values.len()) as u32;
+            output.extend_from_slice(&offset.to_le_bytes());
+            values.extend_from_slice(&entry.value);
+            if values.len() % 2 == 1 {
+                values.push(0);
+            }
+        }
+    }
+
+    let next_pointer = output.len();
+    output.extend_from_slice(&[0, 0, 0, 0]);
+    output.extend_from_slice(&values);
+    for strip in strips.iter() {
+        output.extend_from_slice(strip);
+    }
+
+    next_pointer
+}
+
+// NOTE(erick): Maps every color to its index, None with more than 256.
+fn exact_palette(image: &Image) -> Option<HashMap<[u8; 3], u8>> {
+    let mut palette = HashMap::new();
+    for pixel in image.pixels.chunks(4) {
+        let index = palette.len();
+        if let Entry::Vacant(entry) = palette.entry([pixel[0], pixel[1], pixel[2]]) {
+            if index == 256 {
+                return None;
+            }
+            entry.insert(index as u8);
+        }
+    }
+    Some(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_image(width: u32, height: u32, gray: bool, alpha: bool) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let r = (x * 255 / width) as u8;
+                let g = if gray { r } else { (y * 255 / height) as u8 };
+                let b = if gray { r } else { (x ^ y) as u8 };
+                let a = if alpha { ((x + y) * 17) as u8 } else { 255 };
+                image.set_pixel(x, y, [r, g, b, a]);
+            }
+        }
+        image
+    }
+
+    fn sample_data() -> Vec<u8> {
+        (0 .. 5000u32)
+            .map(|index| if index % 300 < 100 { 7 } else { (index * 31 / 7) as u8 })
+            .collect()
+    }
+
+    // NOTE(erick): A little endian file with one IFD of LONG fields and
+    // 'data' after it.
+    fn file_with_fields(fields: &[(u16, u32)], data: &[u8]) -> Vec<u8> {
+        let mut output = b"II*\0\x08\0\0\0".to_vec();
+        output.extend_from_slice(&(fields.len() as u16).to_le_bytes());
+        for &(tag, value) in fields.iter() {
+            output.extend_from_slice(&tag.to_le_bytes());
+            output.extend_from_slice(&TYPE_LONG.to_le_bytes());
+            output.extend_from_slice(&1u32.to_le_bytes());
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+        output.extend_from_slice(&[0; 4]);
+        output.extend_from_slice(data);
+        output
+    }
+
+    fn data_offset(field_count: usize) -> u32 {
+        (8 + 2 + field_count * 12 + 4) as u32
+    }
+
+    #[test]
+    fn round_trip() {
+        for &(gray, alpha) in [(true, false), (true, true), (false, false), (false, true)].iter() {
+            let image = sample_image(37, 21, gray, alpha);
+            let decoded = decode(&encode(&image)).unwrap();
+            assert_eq!((decoded.width, decoded.height), (37, 21));
+            assert_eq!(decoded.pixels, image.pixels);
+        }
+    }
+
+    #[test]
+    fn palette_round_trip() {
+        let mut image = Image::new(9, 5);
+        for (index, pixel) in image.pixels.chunks_mut(4).enumerate() {
+            pixel.copy_from_slice(&[(index % 3 * 100) as u8, 20, (index % 2 * 200) as u8, 255]);
+        }
+        assert_eq!(decode(&encode(&image)).unwrap().pixels, image.pixels);
+    }
+
+    #[test]
+    fn pages() {
+        let first = sample_image(10, 10, false, false);
+        let second = sample_image(7, 3, true, true);
+        let file = encode_pages(&[&first, &second]);
+        assert_eq!(page_count(&file).unwrap(), 2);
+        assert_eq!(decode_page(&file, 1).unwrap().pixels, second.pixels);
+        assert!(decode_page(&file, 2).is_err());
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let data = sample_data();
+        assert_eq!(lzw_decode(&lzw_encode(&data), data.len()).unwrap(), data);
+        assert_eq!(packbits_decode(&packbits_encode(&data, 1000), data.len()), data);
+    }
+
+    // NOTE(erick): Short strips are padded, but everything before the
+    // first strip has to be there.
+    #[test]
+    fn truncated() {
+        let file = encode(&sample_image(20, 10, false, true));
+        let reader = reader(&file).unwrap();
+        let (fields, _) = read_ifd(&reader, page_offsets(&reader).unwrap()[0]).unwrap();
+        let strip = values(&reader, &fields, TAG_STRIP_OFFSETS).unwrap().unwrap()[0] as usize;
+        for length in 0 .. file.len() {
+            let result = decode(&file[.. length]);
+            if length < strip {
+                assert!(result.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = encode(&sample_image(20, 10, false, true));
+        for index in 0 .. file.len() {
+            let mut corrupt = file.clone();
+            corrupt[index] ^= 0x10;
+            let _ = decode(&corrupt);
+        }
+    }
+
+    #[test]
+    fn bad_sizes() {
+        let fields = |width: u32, height: u32, samples: u32| {
+            file_with_fields(&[(TAG_IMAGE_WIDTH, width), (TAG_IMAGE_LENGTH, height),
+                               (TAG_BITS_PER_SAMPLE, 8), (TAG_PHOTOMETRIC, 1),
+                               (TAG_STRIP_OFFSETS, data_offset(7)),
+                               (TAG_SAMPLES_PER_PIXEL, samples),
+                               (TAG_STRIP_BYTE_COUNTS, 100)], &[0; 100])
+        };
+        assert!(decode(&fields(10, 10, 1)).is_ok());
+        assert!(decode(&fields(10, 10, 5000)).is_err());
+        assert!(decode(&fields(20000, 20000, 1)).is_err());
+        assert!(decode(&fields(u32::MAX, u32::MAX, 4)).is_err());
+    }
+
+    #[test]
+    fn empty_fields() {
+        let fields = [(TAG_IMAGE_WIDTH, 10), (TAG_IMAGE_LENGTH, 10), (TAG_BITS_PER_SAMPLE, 8),
+                      (TAG_PHOTOMETRIC, 1), (TAG_STRIP_OFFSETS, data_offset(5))];
+        let file = file_with_fields(&fields, &[0; 100]);
+        assert!(decode(&file).is_ok());
+        for index in 0 .. fields.len() {
+            let mut empty = file.clone();
+            let count = 8 + 2 + index * 12 + 4;
+            empty[count .. count + 4].copy_from_slice(&[0; 4]);
+            assert!(decode(&empty).is_err());
+        }
+    }
+}
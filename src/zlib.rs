@@ -0,0 +1,778 @@
+// NOTE(erick): zlib (RFC 1950) around DEFLATE (RFC 1951). Just enough
+// for the codecs: a complete inflater and a compressor that does LZ77
+// with hash chains and dynamic Huffman blocks.
+
+const MAX_BITS : usize = 15;
+
+const LENGTH_BASE : [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA : [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE : [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA : [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// NOTE(erick): The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER : [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // NOTE(erick): 5552 is the most bytes we can add before b overflows.
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn reverse_bits(code: u32, length: usize) -> u32 {
+    let mut result = 0;
+    for i in 0 .. length {
+        result |= ((code >> i) & 1) << (length - 1 - i);
+    }
+    result
+}
+
+// NOTE(erick): Codes are assigned as in RFC 1951 section 3.2.2.
+fn canonical_codes(lengths: &[u8]) -> Result<Vec<u32>, String> {
+    let mut length_count = [0u32; MAX_BITS + 1];
+    for length in lengths {
+        length_count[*length as usize] += 1;
+    }
+    length_count[0] = 0;
+
+    let mut next_code = [0u32; MAX_BITS + 2];
+    let mut code = 0;
+    for bits in 1 ..= MAX_BITS {
+        code = (code + length_count[bits - 1]) << 1;
+        next_code[bits] = code;
+        if code + length_count[bits] > (1 << bits) {
+            return Err("zlib: over-subscribed Huffman code".to_string());
+        }
+    }
+
+    let mut codes = vec![0; lengths.len()];
+    for (symbol, length) in lengths.iter().enumerate() {
+        if *length == 0 { continue; }
+        codes[symbol] = next_code[*length as usize];
+        next_code[*length as usize] += 1;
+    }
+
+    Ok(codes)
+}
+
+// NOTE(erick): A single lookup table indexed by the next 'max_bits' bits
+// of the stream. Each entry is 'symbol << 4 | length', zero meaning no code.
+struct Huffman {
+    table: Vec<u16>,
+    max_bits: usize,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let max_bits = (*lengths.iter().max().unwrap_or(&0) as usize).max(1);
+        let codes = canonical_codes(lengths)?;
+
+        let mut table = vec![0u16; 1 << max_bits];
+        for (symbol, length) in lengths.iter().enumerate() {
+            let length = *length as usize;
+            if length == 0 { continue; }
+
+            let reversed = reverse_bits(codes[symbol], length) as usize;
+            let mut index = reversed;
+            while index < table.len() {
+                table[index] = (symbol << 4 | length) as u16;
+                index += 1 << length;
+            }
+        }
+
+        Ok(Huffman { table, max_bits })
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn refill(&mut self) {
+        while self.count <= 56 && self.position < self.bytes.len() {
+            self.buffer |= (self.bytes[self.position] as u64) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+    }
+
+    fn bits(&mut self, count: usize) -> Result<u32, String> {
+        if count == 0 { return Ok(0); }
+
+        if self.count < count {
+            self.refill();
+            if self.count < count {
+                return Err("zlib: unexpected end of data".to_string());
+            }
+        }
+
+        let value = (self.buffer & ((1u64 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<usize, String> {
+        if self.count < huffman.max_bits {
+            self.refill();
+        }
+
+        let index = (self.buffer & ((1u64 << huffman.max_bits) - 1)) as usize;
+        let entry = huffman.table[index] as usize;
+        let length = entry & 0xf;
+        if length == 0 || length > self.count {
+            return Err("zlib: invalid Huffman code".to_string());
+        }
+
+        self.buffer >>= length;
+        self.count -= length;
+        Ok(entry >> 4)
+    }
+
+    fn align_to_byte(&mut self) {
+        let extra = self.count % 8;
+        self.buffer >>= extra;
+        self.count -= extra;
+    }
+
+    // NOTE(erick): Bytes consumed so far, not counting the whole bytes
+    // still sitting in the bit buffer.
+    fn byte_position(&self) -> usize {
+        self.position - self.count / 8
+    }
+}
+
+// NOTE(erick): Decompresses a zlib stream and checks its Adler-32. Streams
+// that inflate to more than 'limit' bytes are rejected as they go.
+pub fn decompress(bytes: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if bytes.len() < 2 {
+        return Err("zlib: stream too short".to_string());
+    }
+
+    let cmf = bytes[0];
+    let flags = bytes[1];
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+        return Err("zlib: unsupported compression method".to_string());
+    }
+    if !((cmf as u16) << 8 | flags as u16).is_multiple_of(31) {
+        return Err("zlib: bad header checksum".to_string());
+    }
+    if flags & 0x20 != 0 {
+        return Err("zlib: preset dictionaries are not supported".to_string());
+    }
+
+    let (output, used) = inflate(&bytes[2 ..], limit)?;
+    let trailer = 2 + used;
+    if trailer + 4 > bytes.len() {
+        return Err("zlib: missing checksum".to_string());
+    }
+
+    let expected = (bytes[trailer] as u32) << 24 | (bytes[trailer + 1] as u32) << 16 |
+                   (bytes[trailer + 2] as u32) << 8 | bytes[trailer + 3] as u32;
+    if adler32(&output) != expected {
+        return Err("zlib: checksum mismatch".to_string());
+    }
+
+    Ok(output)
+}
+
+// NOTE(erick): Raw DEFLATE. Returns the data and the number of input
+// bytes the stream took.
+pub fn inflate(bytes: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader { bytes, position: 0, buffer: 0, count: 0 };
+    let mut output = Vec::with_capacity(bytes.len().saturating_mul(4).min(limit));
+
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        let block_type = reader.bits(2)?;
+        match block_type {
+            0 => inflate_stored(&mut reader, &mut output, limit)?,
+            1 => {
+                let (literals, distances) = fixed_huffman();
+                inflate_block(&mut reader, &mut output, &literals, &distances, limit)?;
+            },
+            2 => {
+                let (literals, distances) = read_dynamic_huffman(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances, limit)?;
+            },
+            _ => return Err("zlib: invalid block type".to_string()),
+        }
+
+        if is_final { break; }
+    }
+
+    Ok((output, reader.byte_position()))
+}
+
+fn too_much_data() -> String {
+    "zlib: more data than expected".to_string()
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>,
+                  limit: usize) -> Result<(), String> {
+    reader.align_to_byte();
+    let length = reader.bits(16)? as usize;
+    let complement = reader.bits(16)? as usize;
+    if length != !complement & 0xffff {
+        return Err("zlib: corrupt stored block".to_string());
+    }
+    if output.len() + length > limit {
+        return Err(too_much_data());
+    }
+
+    let mut remaining = length;
+    while remaining > 0 && reader.count >= 8 {
+        output.push(reader.bits(8)? as u8);
+        remaining -= 1;
+    }
+
+    if reader.position + remaining > reader.bytes.len() {
+        return Err("zlib: unexpected end of data".to_string());
+    }
+    output.extend_from_slice(&reader.bytes[reader.position .. reader.position + remaining]);
+    reader.position += remaining;
+
+    Ok(())
+}
+
+fn fixed_huffman() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0 ..= 143   => 8,
+            144 ..= 255 => 9,
+            256 ..= 279 => 7,
+            _           => 8,
+        };
+    }
+
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5u8; 30]).unwrap())
+}
+
+fn read_dynamic_huffman(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for i in 0 .. code_length_count {
+        code_length_lengths[CODE_LENGTH_ORDER[i]] = reader.bits(3)? as u8;
+    }
+    let code_length_huffman = Huffman::new(&code_length_lengths)?;
+
+    // NOTE(erick): Repeats may cross from the literal to the distance
+    // lengths, so both are read as one sequence.
+    let total = literal_count + distance_count;
+    let mut lengths = Vec::with_capacity(total);
+    while lengths.len() < total {
+        let symbol = reader.decode(&code_length_huffman)?;
+        let (value, repeat) = match symbol {
+            0 ..= 15 => (symbol as u8, 1),
+            16 => {
+                if lengths.is_empty() {
+                    return Err("zlib: repeat with no previous length".to_string());
+                }
+                (*lengths.last().unwrap(), 3 + reader.bits(2)? as usize)
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _  => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if lengths.len() + repeat > total {
+            return Err("zlib: too many code lengths".to_string());
+        }
+        for _ in 0 .. repeat {
+            lengths.push(value);
+        }
+    }
+
+    if lengths[256] == 0 {
+        return Err("zlib: missing end of block code".to_string());
+    }
+
+    let literals = Huffman::new(&lengths[0 .. literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count ..])?;
+    Ok((literals, distances))
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>,
+                 literals: &Huffman, distances: &Huffman, limit: usize) -> Result<(), String> {
+    loop {
+        let symbol = reader.decode(literals)?;
+        if symbol < 256 {
+            if output.len() == limit {
+                return Err(too_much_data());
+            }
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let length_index = symbol - 257;
+        if length_index >= LENGTH_BASE.len() {
+            return Err("zlib: invalid length code".to_string());
+        }
+        let length = LENGTH_BASE[length_index] as usize +
+            reader.bits(LENGTH_EXTRA[length_index] as usize)? as usize;
+
+        let distance_index = reader.decode(distances)?;
+        if distance_index >= DISTANCE_BASE.len() {
+            return Err("zlib: invalid distance code".to_string());
+        }
+        let distance = DISTANCE_BASE[distance_index] as usize +
+            reader.bits(DISTANCE_EXTRA[distance_index] as usize)? as usize;
+        if distance > output.len() {
+            return Err("zlib: distance too far back".to_string());
+        }
+        if output.len() + length > limit {
+            return Err(too_much_data());
+        }
+
+        // NOTE(erick): The copy may overlap what it's writing.
+        let start = output.len() - distance;
+        for i in 0 .. length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: usize) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, length: u8) {
+        self.write(reverse_bits(code, length as usize), length as usize);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+const WINDOW_SIZE    : usize = 32768;
+const HASH_BITS      : usize = 15;
+const MIN_MATCH      : usize = 3;
+const MAX_MATCH      : usize = 258;
+const MAX_CHAIN      : usize = 64;
+const BLOCK_TOKENS   : usize = 1 << 16;
+
+#[inline]
+fn hash(bytes: &[u8], position: usize) -> usize {
+    let value = (bytes[position] as u32) << 16 |
+                (bytes[position + 1] as u32) << 8 |
+                bytes[position + 2] as u32;
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+#[inline]
+fn insert_position(bytes: &[u8], head: &mut [usize], previous: &mut [usize], position: usize) {
+    if position + MIN_MATCH > bytes.len() { return; }
+
+    let h = hash(bytes, position);
+    previous[position % WINDOW_SIZE] = head[h];
+    head[h] = position;
+}
+
+// NOTE(erick): Greedy LZ77 over hash chains. 'previous' only keeps the
+// last window worth of positions, which is all DEFLATE can refer to.
+fn find_matches(bytes: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(bytes.len() / 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut position = 0;
+    while position < bytes.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if position + MIN_MATCH <= bytes.len() {
+            let max_length = (bytes.len() - position).min(MAX_MATCH);
+            let mut candidate = head[hash(bytes, position)];
+            let mut chain = 0;
+            while candidate != usize::MAX && chain < MAX_CHAIN {
+                if position - candidate > WINDOW_SIZE { break; }
+
+                if bytes[candidate + best_length] == bytes[position + best_length] {
+                    let mut length = 0;
+                    while length < max_length && bytes[candidate + length] == bytes[position + length] {
+                        length += 1;
+                    }
+                    if length > best_length {
+                        best_length = length;
+                        best_distance = position - candidate;
+                        if length == max_length { break; }
+                    }
+                }
+
+                let next = previous[candidate % WINDOW_SIZE];
+                // NOTE(erick): Entries older than the window were overwritten
+                // and may point forward.
+                if next == usize::MAX || next >= candidate { break; }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            tokens.push(Token::Match(best_length as u16, best_distance as u16));
+            for i in 0 .. best_length {
+                insert_position(bytes, &mut head, &mut previous, position + i);
+            }
+            position += best_length;
+        } else {
+            tokens.push(Token::Literal(bytes[position]));
+            insert_position(bytes, &mut head, &mut previous, position);
+            position += 1;
+        }
+    }
+
+    tokens
+}
+
+fn length_symbol(length: usize) -> (usize, u32, u8) {
+    let mut index = LENGTH_BASE.len() - 1;
+    while LENGTH_BASE[index] as usize > length {
+        index -= 1;
+    }
+    (257 + index, (length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index])
+}
+
+fn distance_symbol(distance: usize) -> (usize, u32, u8) {
+    let mut index = DISTANCE_BASE.len() - 1;
+    while DISTANCE_BASE[index] as usize > distance {
+        index -= 1;
+    }
+    (index, (distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index])
+}
+
+// NOTE(erick): Plain Huffman construction. When the tree gets deeper than
+// 'limit' the frequencies are flattened and we try again, which costs a
+// little compression but is much simpler than package-merge.
+fn code_lengths(frequencies: &[u32], limit: usize) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+
+    // NOTE(erick): Some decoders reject codes with a single symbol.
+    let used = frequencies.iter().filter(|frequency| **frequency > 0).count();
+    if used < 2 {
+        for frequency in frequencies.iter_mut().take(2) {
+            if *frequency == 0 { *frequency = 1; }
+        }
+    }
+
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|length| *length as usize <= limit) {
+            return lengths;
+        }
+
+        for frequency in frequencies.iter_mut() {
+            if *frequency > 0 {
+                *frequency = (*frequency >> 1).max(1);
+            }
+        }
+    }
+}
+
+fn huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    // NOTE(erick): Nodes below frequencies.len() are leaves.
+    let mut parent = vec![usize::MAX; frequencies.len() * 2];
+    let mut heap = BinaryHeap::new();
+    for (symbol, frequency) in frequencies.iter().enumerate() {
+        if *frequency > 0 {
+            heap.push(Reverse((*frequency as u64, symbol)));
+        }
+    }
+
+    let mut next_node = frequencies.len();
+    while heap.len() > 1 {
+        let Reverse((frequency0, node0)) = heap.pop().unwrap();
+        let Reverse((frequency1, node1)) = heap.pop().unwrap();
+        parent[node0] = next_node;
+        parent[node1] = next_node;
+        heap.push(Reverse((frequency0 + frequency1, next_node)));
+        next_node += 1;
+    }
+
+    let mut lengths = vec![0u8; frequencies.len()];
+    for symbol in 0 .. frequencies.len() {
+        if frequencies[symbol] == 0 { continue; }
+
+        let mut depth = 0;
+        let mut node = symbol;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(255) as u8;
+    }
+
+    lengths
+}
+
+// NOTE(erick): Run-length encodes the code lengths with symbols 16-18.
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == value {
+            run += 1;
+        }
+
+        if value == 0 && run >= 3 {
+            let run = run.min(138);
+            if run <= 10 {
+                result.push((17, (run - 3) as u8));
+            } else {
+                result.push((18, (run - 11) as u8));
+            }
+            i += run;
+        } else if value != 0 && run >= 4 {
+            result.push((value, 0));
+            let run = (run - 1).min(6);
+            result.push((16, (run - 3) as u8));
+            i += run + 1;
+        } else {
+            result.push((value, 0));
+            i += 1;
+        }
+    }
+
+    result
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], is_final: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => { literal_frequencies[byte as usize] += 1; },
+            Token::Match(length, distance) => {
+                literal_frequencies[length_symbol(length as usize).0] += 1;
+                distance_frequencies[distance_symbol(distance as usize).0] += 1;
+            },
+        }
+    }
+    literal_frequencies[256] = 1;
+
+    let literal_lengths = code_lengths(&literal_frequencies, MAX_BITS);
+    let distance_lengths = code_lengths(&distance_frequencies, MAX_BITS);
+    let literal_codes = canonical_codes(&literal_lengths).unwrap();
+    let distance_codes = canonical_codes(&distance_lengths).unwrap();
+
+    let mut literal_count = 286;
+    while literal_count > 257 && literal_lengths[literal_count - 1] == 0 {
+        literal_count -= 1;
+    }
+    let mut distance_count = 30;
+    while distance_count > 1 && distance_lengths[distance_count - 1] == 0 {
+        distance_count -= 1;
+    }
+
+    let mut all_lengths = literal_lengths[0 .. literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[0 .. distance_count]);
+    let encoded_lengths = encode_code_lengths(&all_lengths);
+
+    let mut code_length_frequencies = [0u32; 19];
+    for &(symbol, _) in encoded_lengths.iter() {
+        code_length_frequencies[symbol as usize] += 1;
+    }
+    let code_length_lengths = code_lengths(&code_length_frequencies, 7);
+    let code_length_codes = canonical_codes(&code_length_lengths).unwrap();
+
+    let mut code_length_count = 19;
+    while code_length_count > 4 &&
+        code_length_lengths[CODE_LENGTH_ORDER[code_length_count - 1]] == 0 {
+        code_length_count -= 1;
+    }
+
+    writer.write(is_final as u32, 1);
+    writer.write(2, 2);
+    writer.write((literal_count - 257) as u32, 5);
+    writer.write((distance_count - 1) as u32, 5);
+    writer.write((code_length_count - 4) as u32, 4);
+    for i in 0 .. code_length_count {
+        writer.write(code_length_lengths[CODE_LENGTH_ORDER[i]] as u32, 3);
+    }
+
+    for &(symbol, extra) in encoded_lengths.iter() {
+        let symbol = symbol as usize;
+        writer.write_code(code_length_codes[symbol], code_length_lengths[symbol]);
+        match symbol {
+            16 => writer.write(extra as u32, 2),
+            17 => writer.write(extra as u32, 3),
+            18 => writer.write(extra as u32, 7),
+            _  => { },
+        }
+    }
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                let byte = byte as usize;
+                writer.write_code(literal_codes[byte], literal_lengths[byte]);
+            },
+            Token::Match(length, distance) => {
+                let (symbol, extra, extra_bits) = length_symbol(length as usize);
+                writer.write_code(literal_codes[symbol], literal_lengths[symbol]);
+                writer.write(extra, extra_bits as usize);
+
+                let (symbol, extra, extra_bits) = distance_symbol(distance as usize);
+                writer.write_code(distance_codes[symbol], distance_lengths[symbol]);
+                writer.write(extra, extra_bits as usize);
+            },
+        }
+    }
+
+    writer.write_code(literal_codes[256], literal_lengths[256]);
+}
+
+pub fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let tokens = find_matches(bytes);
+    let mut writer = BitWriter { bytes: Vec::with_capacity(bytes.len() / 2), buffer: 0, count: 0 };
+
+    if tokens.is_empty() {
+        write_block(&mut writer, &[], true);
+    }
+
+    let block_count = tokens.len().div_ceil(BLOCK_TOKENS);
+    for (index, block) in tokens.chunks(BLOCK_TOKENS).enumerate() {
+        write_block(&mut writer, block, index + 1 == block_count);
+    }
+
+    writer.flush();
+    writer.bytes
+}
+
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    // NOTE(erick): 32K window, deflate, default compression level.
+    let mut result = vec![0x78, 0x9c];
+    result.append(&mut deflate(bytes));
+
+    let checksum = adler32(bytes);
+    result.extend_from_slice(&[(checksum >> 24) as u8, (checksum >> 16) as u8,
+                               (checksum >> 8) as u8, checksum as u8]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(erick): Noise with long repeats in it, so the compressor finds
+    // both literals and matches.
+    fn sample_data(size: usize) -> Vec<u8> {
+        let mut state = 12345u32;
+        (0 .. size).map(|index| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if index % 1000 < 600 { (index % 7) as u8 } else { (state >> 24) as u8 }
+        }).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for &size in [0, 1, 100, 70_000].iter() {
+            let data = sample_data(size);
+            let compressed = compress(&data);
+            assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn stored_block() {
+        let stored = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        let (data, used) = inflate(&stored, 5).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(used, stored.len());
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let compressed = compress(&sample_data(5000));
+        for length in 0 .. compressed.len() {
+            assert!(decompress(&compressed[.. length], 5000).is_err());
+        }
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut compressed = compress(&sample_data(100));
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress(&compressed, 100).is_err());
+    }
+
+    #[test]
+    fn corrupt_stream() {
+        let compressed = compress(&sample_data(5000));
+        for index in 2 .. compressed.len() {
+            let mut corrupt = compressed.clone();
+            corrupt[index] ^= 0x55;
+            let _ = decompress(&corrupt, 5000);
+        }
+    }
+
+    #[test]
+    fn over_limit() {
+        let compressed = compress(&vec![0; 100_000]);
+        assert!(decompress(&compressed, 99_999).is_err());
+        assert_eq!(decompress(&compressed, 100_000).unwrap().len(), 100_000);
+    }
+}